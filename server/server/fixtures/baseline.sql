@@ -0,0 +1,163 @@
+-- The schema of a database created before migrations existed (schema version 0),
+-- with a few rows to check that data survives the migrations
+
+create table users (
+    id              integer primary key autoincrement,
+    name            VARCHAR(20) not null unique,
+    elo             float  default 1500.0,
+    password_hash   varchar(64) not null,
+    uuid            varchar(36) not null,
+    user_role       smallint
+);
+
+create table matches (
+    id              integer primary key autoincrement,
+    epoch           bigint not null,
+    elo_diff        integer,
+    winner_elo      float,
+    loser_elo       float,
+    winner          integer,
+    loser           integer,
+    foreign key(winner) references users(id),
+    foreign key(loser) references users(id)
+);
+
+create table old_matches (
+    id              integer primary key autoincrement,
+    epoch           bigint not null,
+    elo_diff        integer,
+    winner_elo      float,
+    loser_elo       float,
+    winner          integer,
+    loser           integer,
+    season           integer,
+    foreign key(winner) references users(id),
+    foreign key(loser) references users(id)
+    foreign key(season) references seasons(id)
+);
+
+create table offseason_matches (
+    id              integer primary key autoincrement,
+    epoch           bigint not null,
+    elo_diff        integer,
+    winner_elo      float,
+    loser_elo       float,
+    winner          integer,
+    loser           integer,
+    foreign key(winner) references users(id),
+    foreign key(loser) references users(id)
+);
+
+create table match_notification (
+    id              integer primary key autoincrement,
+    winner_accept   smallint default 0,
+    loser_accept    smallint default 0,
+    epoch           bigint not null,
+    winner          integer,
+    loser           integer,
+    foreign key(winner) references users(id),
+    foreign key(loser) references users(id)
+);
+
+create table new_user_notification (
+    id              integer primary key autoincrement,
+    name            VARCHAR(20) not null unique,
+    password_hash   varchar(64) not null
+);
+
+create table reset_password_notification (
+    id                integer primary key autoincrement,
+    user              integer not null unique,
+    foreign key(user) references users(id)
+);
+
+create table badges (
+    id              integer primary key autoincrement,
+    season_id       integer,
+    badge_index     integer,
+    pid             integer,
+    foreign key(pid) references users(id),
+    foreign key(season_id) references seasons(id)
+);
+
+create table tournament_badges (
+    id              integer primary key autoincrement,
+    image           integer,
+    pid             integer,
+    tid             integer,
+    foreign key(tid) references tournaments(id),
+    foreign key(pid) references users(id),
+    foreign key(image) references images(id)
+);
+
+create table seasons (
+    id              integer primary key autoincrement,
+    start_epoch     integer
+);
+
+create table variables (
+    id              integer primary key,
+    value           integer not null
+);
+
+create table tournaments (
+    id              integer primary key autoincrement,
+    name            varchar(36),
+    prize           integer,
+    state           smallint,
+    ttype           smallint,
+    player_count    integer,
+    organizer       integer
+);
+
+create table tournament_lists (
+    id                      integer primary key autoincrement,
+    player                  integer,
+    tournament              integer,
+    foreign key(player)     references users(id),
+    foreign key(tournament) references tournaments(id)
+);
+
+create table tournament_games (
+    id                      integer primary key autoincrement,
+    bucket                   integer,
+    player1                  integer,
+    player2                  integer,
+    tournament              integer,
+    foreign key(tournament) references tournaments(id)
+);
+
+create table tournament_matches (
+    id                      integer primary key autoincrement,
+    game                    integer,
+    winner                  integer,
+    loser                   integer,
+    foreign key(game) references tournament_games(id)
+);
+
+create table tournament_winners (
+    id                      integer primary key autoincrement,
+    player                  integer,
+    tournament              integer,
+    foreign key(player)     references users(id),
+    foreign key(tournament) references tournaments(id)
+);
+
+create table images (
+    id              integer primary key autoincrement,
+    name            varchar(10) not null
+);
+
+create table tournament_lookup (
+    id              integer primary key autoincrement,
+    tournament      integer,
+    _table           blob,
+    foreign key(tournament) references tournaments(id)
+);
+
+insert into users (name, elo, password_hash, uuid, user_role) values ('Sivert', 1516.0, 'hash', 'uuid-sivert', 0);
+insert into users (name, elo, password_hash, uuid, user_role) values ('Lars', 1484.0, 'hash', 'uuid-lars', 0);
+insert into matches (epoch, elo_diff, winner_elo, loser_elo, winner, loser) values (1606310183219, 16, 1516.0, 1484.0, 1, 2);
+insert into match_notification (epoch, winner, loser, winner_accept) values (1606310283219, 2, 1, 1);
+insert into seasons (id, start_epoch) values (1, 1606310083219);
+insert into variables (id, value) values (1, 1);
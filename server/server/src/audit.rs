@@ -0,0 +1,366 @@
+use rusqlite::{named_params, params};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use server_core::types::*;
+use server_macro::Sql;
+
+use crate::{
+    _named_params,
+    score::scores_from_sql,
+    server::{DataBase, ParamsType},
+    validate::{Validate, Validator},
+    SQL_TUPLE_NAMED,
+};
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+const MAX_PAGE_SIZE: i64 = 200;
+// Keeps the offset of the last page far from overflowing
+const MAX_PAGE: i64 = 1_000_000;
+
+fn default_page_size() -> i64
+{
+    50
+}
+
+// Every filter is optional, the newest entries come first
+#[derive(Deserialize)]
+pub struct AuditLogQuery
+{
+    pub actor:     Option<String>,
+    pub action:    Option<String>,
+    pub target:    Option<String>,
+    // Epochs in milliseconds, from is inclusive and to is exclusive
+    pub from:      Option<i64>,
+    pub to:        Option<i64>,
+    #[serde(default)]
+    pub page:      i64,
+    #[serde(default = "default_page_size")]
+    pub page_size: i64,
+}
+
+impl Validate for AuditLogQuery
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new()
+            .check((0..=MAX_PAGE).contains(&self.page), "page", "Must be between 0 and 1000000")
+            .check(
+                (1..=MAX_PAGE_SIZE).contains(&self.page_size),
+                "page_size",
+                "Must be between 1 and 200",
+            )
+            .finish()
+    }
+}
+
+#[derive(Sql)]
+struct AuditRow
+{
+    id:         i64,
+    actor_name: String,
+    action:     String,
+    target:     String,
+    before:     String,
+    after:      String,
+    epoch:      i64,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct AuditEntry
+{
+    pub id:     i64,
+    // Empty for changes made by the server itself
+    pub actor:  String,
+    pub action: String,
+    pub target: String,
+    pub before: Value,
+    pub after:  Value,
+    pub epoch:  i64,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(test, derive(Debug))]
+pub struct AuditLogPage
+{
+    pub entries: Vec<AuditEntry>,
+    // The number of entries matching the filters, on every page
+    pub total:   i64,
+}
+
+impl From<AuditRow> for AuditEntry
+{
+    fn from(row: AuditRow) -> Self
+    {
+        let json = |text: &str| serde_json::from_str(text).unwrap_or(Value::Null);
+        AuditEntry {
+            id:     row.id,
+            actor:  row.actor_name,
+            action: row.action,
+            target: row.target,
+            before: json(&row.before),
+            after:  json(&row.after),
+            epoch:  row.epoch,
+        }
+    }
+}
+
+const AUDIT_FILTER: &str = "from audit_log a left join users u on a.actor = u.id
+    where (:actor is null or u.name = :actor)
+    and (:action is null or a.action = :action)
+    and (:target is null or a.target = :target)
+    and (:from is null or a.epoch >= :from)
+    and (:to is null or a.epoch < :to)";
+
+// Every change to the database made through the api is written to the audit
+// log, with the state of what changed before and after. The log can not be
+// changed, old entries are removed once they are past the retention period.
+impl DataBase
+{
+    pub(crate) fn audit(
+        &self,
+        actor: i64,
+        action: &str,
+        target: &str,
+        before: Value,
+        after: Value,
+    ) -> ServerResult<()>
+    {
+        self.conn.execute(
+            "insert into audit_log (actor, action, target, before, after, epoch)
+             values (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![actor, action, target, before.to_string(), after.to_string(), self.epoch()],
+        )?;
+        Ok(())
+    }
+
+    // The players, time and scores of a match, or null if there is no such match
+    pub(crate) fn match_snapshot(&self, id: i64) -> ServerResult<Value>
+    {
+        Ok(SQL_TUPLE_NAMED!(
+            self,
+            "select w.name, l.name, m.epoch, m.scores from matches m
+             join users w on m.winner = w.id
+             join users l on m.loser = l.id
+             where m.id = :id",
+            named_params! {":id": id},
+            String,
+            String,
+            i64,
+            String
+        )?
+        .pop()
+        .map_or(Value::Null, |(winner, loser, epoch, scores)| {
+            json!({
+                "winner": winner,
+                "loser": loser,
+                "epoch": epoch,
+                "scores": scores_from_sql(&scores)
+            })
+        }))
+    }
+
+    pub fn get_audit_log(&self, token: String, query: AuditLogQuery) -> ServerResult<AuditLogPage>
+    {
+        if !self.get_is_admin(token)?
+        {
+            return Err(ServerError::Unauthorized);
+        }
+
+        let total = SQL_TUPLE_NAMED!(
+            self,
+            format!("select count(*) {}", AUDIT_FILTER),
+            named_params! {
+                ":actor": query.actor,
+                ":action": query.action,
+                ":target": query.target,
+                ":from": query.from,
+                ":to": query.to,
+            },
+            i64
+        )?
+        .pop()
+        .map_or(0, |(count,)| count);
+
+        let entries = self.sql_many::<AuditRow, _>(
+            format!(
+                "select a.*, coalesce(u.name, '') as actor_name {}
+                 order by a.id desc limit :limit offset :offset",
+                AUDIT_FILTER
+            ),
+            _named_params! {
+                ":actor": query.actor,
+                ":action": query.action,
+                ":target": query.target,
+                ":from": query.from,
+                ":to": query.to,
+                ":limit": query.page_size,
+                ":offset": query.page * query.page_size,
+            },
+        )?;
+
+        Ok(AuditLogPage {
+            entries: entries.into_iter().map(AuditEntry::from).collect(),
+            total,
+        })
+    }
+
+    // Removes the entries older than audit_retention_days, 0 keeps every entry
+    pub fn prune_audit_log(&self) -> ServerResult<()>
+    {
+        let days = self.get_variable("audit_retention_days".to_string())?;
+        if days > 0
+        {
+            self.conn.execute("delete from audit_log where epoch <= ?1", params![
+                self.epoch() - days * DAY_MS
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use rusqlite::NO_PARAMS;
+
+    use super::*;
+    use crate::{
+        r#match::{DeleteMatchInfo, NewEditMatchInfo},
+        test_util::*,
+    };
+
+    fn query(filters: Value) -> AuditLogQuery
+    {
+        serde_json::from_value(filters).unwrap()
+    }
+
+    fn insert_entry(s: &DataBase, epoch: i64)
+    {
+        s.conn
+            .execute(
+                "insert into audit_log (actor, action, target, before, after, epoch)
+                 values (0, 'test', 'none', 'null', 'null', ?1)",
+                params![epoch],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_audit_log_has_match_changes()
+    {
+        let db_file = "tempA1.db";
+        let s = DataBase::new(db_file);
+        let admin = create_user(&s, "Admin");
+        let token = create_user(&s, "Winner");
+        create_user(&s, "Loser");
+        make_user_admin(&s, "Admin".to_string()).unwrap();
+
+        s.register_match("Winner".to_string(), "Loser".to_string(), token.clone())
+            .unwrap();
+        respond_to_match(&s, "Loser", 1);
+        let epoch = s.match_snapshot(1).unwrap()["epoch"].as_i64().unwrap();
+        s.edit_match(NewEditMatchInfo {
+            winner: "Loser".to_string(),
+            loser: "Winner".to_string(),
+            epoch,
+            id: 1,
+            token: admin.clone(),
+        })
+        .unwrap();
+        s.delete_match(DeleteMatchInfo {
+            id: 1, token: admin.clone()
+        })
+        .unwrap();
+
+        let not_admin = s.get_audit_log(token, query(json!({})));
+        let edit = s.get_audit_log(admin.clone(), query(json!({"action": "edit_match"}))).unwrap();
+        let delete = s.get_audit_log(admin.clone(), query(json!({"target": "match:1"}))).unwrap();
+        let by_winner = s.get_audit_log(admin.clone(), query(json!({"actor": "Winner"}))).unwrap();
+        let first_page = s.get_audit_log(admin.clone(), query(json!({"page_size": 2}))).unwrap();
+        let last_page =
+            s.get_audit_log(admin, query(json!({"page_size": 2, "page": 1000}))).unwrap();
+        let update = s.conn.execute("update audit_log set action = 'changed'", NO_PARAMS);
+
+        std::fs::remove_file(db_file).expect("Removing file tempA1");
+        assert!(matches!(not_admin, Err(ServerError::Unauthorized)));
+        assert_eq!(edit.total, 1);
+        assert_eq!(edit.entries[0].actor, "Admin");
+        assert_eq!(edit.entries[0].before["winner"], "Winner");
+        assert_eq!(edit.entries[0].after["winner"], "Loser");
+        // The newest entry comes first
+        assert_eq!(delete.total, 2);
+        assert_eq!(delete.entries[0].action, "delete_match");
+        assert_eq!(delete.entries[0].before["winner"], "Loser");
+        assert_eq!(delete.entries[0].after, Value::Null);
+        assert_eq!(by_winner.entries[0].action, "register_match");
+        assert_eq!(by_winner.entries[0].after["loser"], "Loser");
+        assert_eq!(first_page.entries.len(), 2);
+        assert!(first_page.total > 2);
+        assert!(last_page.entries.is_empty());
+        assert!(update.is_err());
+    }
+
+    #[test]
+    fn test_audit_log_is_pruned_after_retention()
+    {
+        let db_file = "tempA2.db";
+        let s = DataBase::new(db_file);
+        let admin = create_user(&s, "Admin");
+        make_user_admin(&s, "Admin".to_string()).unwrap();
+        let count = || get_table_size(&s, "audit_log");
+
+        let start = count();
+        insert_entry(&s, 0);
+        insert_entry(&s, s.epoch());
+        let delete_new = s
+            .conn
+            .execute("delete from audit_log where epoch > 0 and action = 'test'", NO_PARAMS);
+        s.prune_audit_log().unwrap();
+        let pruned = count();
+
+        s.set_variable(admin.clone(), "audit_retention_days".to_string(), 0).unwrap();
+        insert_entry(&s, 0);
+        let kept_before = count();
+        s.prune_audit_log().unwrap();
+        let delete_old = s.conn.execute("delete from audit_log where epoch = 0", NO_PARAMS);
+        let kept = count();
+        let negative = s.set_variable(admin, "audit_retention_days".to_string(), -1);
+
+        std::fs::remove_file(db_file).expect("Removing file tempA2");
+        assert!(delete_new.is_err());
+        // Only the old entry is removed
+        assert_eq!(pruned, start + 1);
+        assert_eq!(kept, kept_before);
+        assert!(delete_old.is_err());
+        assert!(matches!(negative, Err(ServerError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn test_logouts_are_audited()
+    {
+        let db_file = "tempA3.db";
+        let s = DataBase::new(db_file);
+        let admin = create_user(&s, "Admin");
+        let token = create_user(&s, "Player");
+        make_user_admin(&s, "Admin".to_string()).unwrap();
+
+        s.logout(token).unwrap();
+        let token = s.login("Player".to_string(), "password".to_string()).unwrap();
+        s.logout_everywhere(token.clone()).unwrap();
+        let not_admin = s.admin_rollback(token);
+
+        let player = s.get_audit_log(admin.clone(), query(json!({"actor": "Player"}))).unwrap();
+        let rollback = s.get_audit_log(admin, query(json!({"action": "roll_back"}))).unwrap();
+        let far_page = query(json!({ "page": i64::MAX })).validate();
+
+        std::fs::remove_file(db_file).expect("Removing file tempA3");
+        assert_eq!(player.entries[0].action, "logout_everywhere");
+        assert_eq!(player.entries[0].target, "user:Player");
+        assert_eq!(player.entries[1].action, "logout");
+        assert!(not_admin.is_err());
+        assert_eq!(rollback.total, 0);
+        assert!(matches!(far_page, Err(ServerError::InvalidRequest(_))));
+    }
+}
@@ -0,0 +1,75 @@
+use serde_derive::{Deserialize, Serialize};
+use server_core::types::{FromSql, ServerResult};
+use server_macro::Sql;
+
+use super::{
+    score::{serialize_scores, GameScore},
+    validate::{Validate, Validator},
+};
+
+
+// The elo fields without a number are the ratings of the pairs
+#[derive(Debug, Serialize, Sql)]
+pub struct DoublesMatch
+{
+    pub winner1:     String,
+    pub winner2:     String,
+    pub loser1:      String,
+    pub loser2:      String,
+    pub epoch:       i64,
+    pub elo_diff:    f64,
+    pub winner_elo:  f64,
+    pub loser_elo:   f64,
+    pub winner1_elo: f64,
+    pub winner2_elo: f64,
+    pub loser1_elo:  f64,
+    pub loser2_elo:  f64,
+    #[serde(serialize_with = "serialize_scores")]
+    pub scores:      String,
+}
+
+#[derive(Debug, Serialize, Sql)]
+pub struct Pair
+{
+    pub player1:   String,
+    pub player2:   String,
+    pub elo:       f64,
+    pub deviation: f64,
+    pub games:     i64,
+}
+
+#[derive(Debug, Serialize, Sql)]
+pub struct DoublesPlayer
+{
+    pub name:      String,
+    pub elo:       f64,
+    pub deviation: f64,
+    pub games:     i64,
+}
+
+// Doubles match info sent from the front end
+#[derive(Debug, Deserialize)]
+pub struct DoublesMatchInfo
+{
+    pub winners: [String; 2],
+    pub losers:  [String; 2],
+    #[serde(default)]
+    pub scores:  Vec<GameScore>,
+}
+
+impl Validate for DoublesMatchInfo
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        let mut validator = Validator::new();
+        for name in &self.winners
+        {
+            validator.username("winners", name);
+        }
+        for name in &self.losers
+        {
+            validator.username("losers", name);
+        }
+        validator.finish()
+    }
+}
@@ -1,31 +1,67 @@
+mod audit;
 mod badge;
+mod doubles;
 mod r#match;
 mod notification;
+mod password;
+mod pool;
 mod season;
 #[macro_use]
 mod server;
 mod macros;
+mod match_versions;
 mod process;
+mod rating;
+mod score;
+mod server_doubles;
 mod server_init;
+mod server_migrations;
 mod server_rollback;
 mod server_season;
+mod server_session;
 mod sql_executor;
 mod test_util;
 mod tournament;
+mod tournament_groups;
+mod tournament_notification;
+mod tournament_placements;
+mod tournament_rated;
+mod tournament_round_robin;
+mod tournament_schedule;
+mod tournament_seeding;
+mod tournament_start;
+mod tournament_swiss;
+mod tournament_withdrawal;
 mod user;
+mod validate;
 
 
+pub use audit::*;
 pub use badge::*;
+pub use doubles::*;
 pub use r#match::*;
+pub use match_versions::*;
 pub use notification::*;
+pub use password::*;
+pub use pool::*;
 pub use process::*;
+pub use rating::*;
+pub use score::*;
 pub use season::*;
 pub use server_init::*;
+pub use server_migrations::*;
 pub use server_rollback::*;
 pub use server_season::*;
 pub use sql_executor::*;
 pub use test_util::*;
 pub use tournament::*;
+pub use tournament_groups::*;
+pub use tournament_round_robin::*;
+pub use tournament_schedule::*;
+pub use tournament_seeding::*;
+pub use tournament_swiss::*;
+pub use tournament_withdrawal::*;
 pub use user::*;
+pub use validate::*;
 
 pub use crate::server::*;
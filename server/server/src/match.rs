@@ -1,7 +1,12 @@
 use serde_derive::{Deserialize, Serialize};
-use server_core::types::FromSql;
+use server_core::types::{FromSql, ServerResult};
 use server_macro::Sql;
 
+use super::{
+    score::{serialize_scores, GameScore},
+    validate::{Validate, Validator},
+};
+
 
 #[derive(Debug, Serialize, Sql)]
 pub struct Match
@@ -13,6 +18,8 @@ pub struct Match
     pub winner_elo: f64,
     pub loser_elo:  f64,
     pub season:     i64,
+    #[serde(serialize_with = "serialize_scores")]
+    pub scores:     String,
 }
 
 #[derive(Debug, Serialize, Sql)]
@@ -24,10 +31,21 @@ pub struct EditMatchInfo
     pub id:     i64,
 }
 
+// The epoch and players of a match. Changing the match changes the ratings of
+// its players from its epoch on.
+#[derive(Clone, Copy)]
+pub(crate) struct MatchFootprint
+{
+    pub epoch:   i64,
+    pub players: [i64; 2],
+}
+
 #[derive(Debug, Deserialize)]
 pub struct DeleteMatchInfo
 {
     pub id:    i64,
+    // Taken from the Authorization header, never from the body
+    #[serde(skip_deserializing)]
     pub token: String,
 }
 
@@ -38,6 +56,7 @@ pub struct NewEditMatchInfo
     pub loser:  String,
     pub epoch:  i64,
     pub id:     i64,
+    #[serde(skip_deserializing)]
     pub token:  String,
 }
 
@@ -47,5 +66,39 @@ pub struct MatchInfo
 {
     pub winner: String,
     pub loser:  String,
-    pub token:  String,
+    #[serde(default)]
+    pub scores: Vec<GameScore>,
+}
+
+impl Validate for MatchInfo
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new()
+            .username("winner", &self.winner)
+            .username("loser", &self.loser)
+            .check(self.winner != self.loser, "loser", "Can not be the same as the winner")
+            .finish()
+    }
+}
+
+impl Validate for NewEditMatchInfo
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new()
+            .username("winner", &self.winner)
+            .username("loser", &self.loser)
+            .check(self.winner != self.loser, "loser", "Can not be the same as the winner")
+            .check(self.epoch >= 0, "epoch", "Can not be negative")
+            .finish()
+    }
+}
+
+impl Validate for DeleteMatchInfo
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new().check(self.id > 0, "id", "Must be positive").finish()
+    }
 }
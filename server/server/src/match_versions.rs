@@ -0,0 +1,289 @@
+use rusqlite::params;
+use serde_derive::{Deserialize, Serialize};
+use server_core::types::*;
+use server_macro::Sql;
+
+use crate::{
+    _params,
+    server::{DataBase, ParamsType},
+    validate::{Validate, Validator},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct UndoMatchInfo
+{
+    // The id of the match, its latest change is undone
+    pub id:    i64,
+    #[serde(skip_deserializing)]
+    pub token: String,
+}
+
+impl Validate for UndoMatchInfo
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new().check(self.id > 0, "id", "Must be positive").finish()
+    }
+}
+
+#[derive(Sql)]
+struct MatchVersion
+{
+    id:       i64,
+    match_id: i64,
+    epoch:    i64,
+    winner:   i64,
+    loser:    i64,
+    scores:   String,
+    deleted:  bool,
+}
+
+// A change to a match that can still be undone, with the match as it was
+// before the change
+#[derive(Serialize, Sql)]
+#[cfg_attr(test, derive(Debug))]
+pub struct MatchChange
+{
+    pub id:         i64,
+    pub match_id:   i64,
+    pub winner:     String,
+    pub loser:      String,
+    pub epoch:      i64,
+    pub deleted:    bool,
+    pub changed_by: String,
+    pub changed:    i64,
+}
+
+// Admins edit and delete matches through versions. The match is stored as it
+// was before each change, and the changes of a match are undone newest first.
+// The ratings are replayed after every change, so undoing a change gives every
+// player the rating they had before it.
+impl DataBase
+{
+    pub(crate) fn save_match_version(&self, id: i64, deleted: bool, admin: i64)
+        -> ServerResult<()>
+    {
+        self.conn.execute(
+            "insert into match_versions (match, epoch, winner, loser, scores, deleted, \
+             changed_by, changed)
+             select id, epoch, winner, loser, scores, ?2, ?3, ?4 from matches where id = ?1",
+            params![id, deleted, admin, self.epoch()],
+        )?;
+        Ok(())
+    }
+
+    pub fn undo_match_change(&self, info: UndoMatchInfo) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let admin = self.get_admin(&info.token)?;
+            let version = self
+                .sql_one::<MatchVersion, _>(
+                    "select id, match as match_id, epoch, winner, loser, scores, deleted
+                     from match_versions where match = ?1 and undone = 0
+                     order by id desc limit 1",
+                    _params![info.id],
+                )
+                .map_err(|_| {
+                    ServerError::InvalidRequest(vec![FieldError::new(
+                        "id",
+                        "Has no changes to undo",
+                    )])
+                })?;
+            let target = format!("match:{}", version.match_id);
+            let before = self.match_snapshot(version.match_id)?;
+            let footprint = self.get_match_footprint(version.match_id)?;
+
+            if version.deleted
+            {
+                // The ratings of the match are set by the replay
+                self.conn.execute(
+                    "insert into matches (id, epoch, winner, loser, scores, elo_diff, winner_elo, \
+                     loser_elo)
+                     values (?1, ?2, ?3, ?4, ?5, 0, 0, 0)",
+                    params![
+                        version.match_id,
+                        version.epoch,
+                        version.winner,
+                        version.loser,
+                        version.scores
+                    ],
+                )?;
+            }
+            else
+            {
+                self.conn.execute(
+                    "update matches set epoch = ?1, winner = ?2, loser = ?3, scores = ?4 where id \
+                     = ?5",
+                    params![
+                        version.epoch,
+                        version.winner,
+                        version.loser,
+                        version.scores,
+                        version.match_id
+                    ],
+                )?;
+            }
+            self.conn
+                .execute("update match_versions set undone = 1 where id = ?1", params![
+                    version.id
+                ])?;
+            self.replay_changed_matches(&[footprint, self.get_match_footprint(version.match_id)?])?;
+
+            let after = self.match_snapshot(version.match_id)?;
+            self.audit(admin.id, "undo_match_change", &target, before, after)
+        })
+    }
+
+    // The changes that can be undone, newest first
+    pub fn get_match_changes(&self, token: String) -> ServerResult<Vec<MatchChange>>
+    {
+        self.get_admin(&token)?;
+        self.sql_many(
+            "select v.id, v.match as match_id, w.name as winner, l.name as loser, v.epoch,
+             v.deleted, a.name as changed_by, v.changed
+             from match_versions v
+             join users w on v.winner = w.id
+             join users l on v.loser = l.id
+             join users a on v.changed_by = a.id
+             where v.undone = 0
+             order by v.id desc",
+            None,
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use rusqlite::NO_PARAMS;
+
+    use super::*;
+    use crate::{
+        r#match::{DeleteMatchInfo, NewEditMatchInfo},
+        test_util::*,
+    };
+
+    const PLAYERS: [&str; 3] = ["Sivert", "Lars", "Markus"];
+
+    // Every player plays every other player twice
+    fn play_matches(s: &DataBase, tokens: &[String])
+    {
+        let mut notification = 0;
+        for _ in 0..2
+        {
+            for (i, winner) in PLAYERS.iter().enumerate()
+            {
+                for loser in PLAYERS.iter().skip(i + 1)
+                {
+                    s.register_match(winner.to_string(), loser.to_string(), tokens[i].clone())
+                        .unwrap();
+                    notification += 1;
+                    respond_to_match(s, loser, notification);
+                }
+            }
+        }
+    }
+
+    // The rating of every player, and the ratings stored with every match
+    fn ratings(s: &DataBase) -> (Vec<f64>, Vec<(i64, f64, f64, f64)>)
+    {
+        let users = PLAYERS.iter().map(|name| s.get_user(name).unwrap().elo).collect();
+        let matches = s
+            .conn
+            .prepare("select id, winner_elo, loser_elo, elo_diff from matches order by id")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        (users, matches)
+    }
+
+    fn undo(s: &DataBase, token: &str, id: i64) -> ServerResult<()>
+    {
+        s.undo_match_change(UndoMatchInfo {
+            id,
+            token: token.to_string(),
+        })
+    }
+
+    #[test]
+    fn test_undo_deleted_match_restores_ratings()
+    {
+        let db_file = "tempV1.db";
+        let s = DataBase::new(db_file);
+        let admin = create_user(&s, "Admin");
+        make_user_admin(&s, "Admin".to_string()).unwrap();
+        let tokens: Vec<String> = PLAYERS.iter().map(|name| create_user(&s, name)).collect();
+        play_matches(&s, &tokens);
+
+        let before_delete = ratings(&s);
+        s.delete_match(DeleteMatchInfo {
+            id: 2, token: admin.clone()
+        })
+        .unwrap();
+        let deleted = ratings(&s);
+        let changes = s.get_match_changes(admin.clone()).unwrap();
+        let not_admin = undo(&s, &tokens[0], 2);
+        undo(&s, &admin, 2).unwrap();
+        let restored = ratings(&s);
+        let nothing_left = undo(&s, &admin, 2);
+
+        std::fs::remove_file(db_file).expect("Removing file tempV1");
+        assert_eq!(deleted.1.len(), before_delete.1.len() - 1);
+        assert_ne!(deleted.0, before_delete.0);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].match_id, 2);
+        assert!(changes[0].deleted);
+        assert_eq!(changes[0].changed_by, "Admin");
+        assert!(matches!(not_admin, Err(ServerError::Unauthorized)));
+        assert_eq!(restored, before_delete);
+        assert!(matches!(nothing_left, Err(ServerError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn test_undo_edits_newest_first()
+    {
+        let db_file = "tempV2.db";
+        let s = DataBase::new(db_file);
+        let admin = create_user(&s, "Admin");
+        make_user_admin(&s, "Admin".to_string()).unwrap();
+        let tokens: Vec<String> = PLAYERS.iter().map(|name| create_user(&s, name)).collect();
+        play_matches(&s, &tokens);
+        let epoch = s.match_snapshot(1).unwrap()["epoch"].as_i64().unwrap();
+        let edit = |winner: &str, loser: &str| {
+            s.edit_match(NewEditMatchInfo {
+                winner: winner.to_string(),
+                loser: loser.to_string(),
+                epoch,
+                id: 1,
+                token: admin.clone(),
+            })
+            .unwrap()
+        };
+
+        let original = ratings(&s);
+        edit("Lars", "Sivert");
+        let first_edit = ratings(&s);
+        edit("Markus", "Sivert");
+        s.delete_match(DeleteMatchInfo {
+            id: 1, token: admin.clone()
+        })
+        .unwrap();
+
+        undo(&s, &admin, 1).unwrap();
+        let undeleted = s.match_snapshot(1).unwrap();
+        undo(&s, &admin, 1).unwrap();
+        let undone_once = ratings(&s);
+        undo(&s, &admin, 1).unwrap();
+        let undone_twice = ratings(&s);
+        let changes = s.get_match_changes(admin).unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempV2");
+        assert_eq!(undeleted["winner"], "Markus");
+        assert_eq!(undone_once, first_edit);
+        assert_eq!(undone_twice, original);
+        assert!(changes.is_empty());
+    }
+}
@@ -5,6 +5,8 @@ use serde_derive::{Deserialize, Serialize};
 use server_core::types::FromSql;
 use server_macro::Sql;
 
+use super::score::serialize_scores;
+
 
 #[derive(Serialize, Sql)]
 pub struct MatchNotification
@@ -13,6 +15,8 @@ pub struct MatchNotification
     pub winner: String,
     pub loser:  String,
     pub epoch:  i64,
+    #[serde(serialize_with = "serialize_scores")]
+    pub scores: String,
 }
 
 #[derive(Sql)]
@@ -24,6 +28,65 @@ pub struct MatchNotificationTable
     pub epoch:         i64,
     pub winner:        i64,
     pub loser:         i64,
+    pub scores:        String,
+}
+
+#[derive(Serialize, Sql)]
+pub struct DoublesNotification
+{
+    pub id:      i64,
+    pub winner1: String,
+    pub winner2: String,
+    pub loser1:  String,
+    pub loser2:  String,
+    pub epoch:   i64,
+    #[serde(serialize_with = "serialize_scores")]
+    pub scores:  String,
+}
+
+#[derive(Sql)]
+pub struct DoublesNotificationTable
+{
+    pub id:             i64,
+    pub winner1_accept: u8,
+    pub winner2_accept: u8,
+    pub loser1_accept:  u8,
+    pub loser2_accept:  u8,
+    pub epoch:          i64,
+    pub winner1:        i64,
+    pub winner2:        i64,
+    pub loser1:         i64,
+    pub loser2:         i64,
+    pub scores:         String,
+}
+
+// A result one of the players of a tournament game has reported. The
+// organizer also sees the results the other player has declined.
+#[derive(Serialize, Sql)]
+pub struct TournamentNotification
+{
+    pub id:              i64,
+    pub tournament:      i64,
+    pub tournament_game: i64,
+    pub winner:          String,
+    pub loser:           String,
+    pub epoch:           i64,
+    #[serde(serialize_with = "serialize_scores")]
+    pub scores:          String,
+    pub disputed:        bool,
+}
+
+#[derive(Sql)]
+pub struct TournamentNotificationTable
+{
+    pub id:            i64,
+    pub game:          i64,
+    pub winner_accept: u8,
+    pub loser_accept:  u8,
+    pub epoch:         i64,
+    pub winner:        i64,
+    pub loser:         i64,
+    pub scores:        String,
 }
 
 #[derive(Serialize, Sql)]
@@ -37,19 +100,22 @@ pub struct AdminNotification
 pub struct NotificationInfo
 {
     pub r#type: String,
-    pub token:  String,
 }
 
 pub enum NotificationType
 {
     Admin,
     Match,
+    Doubles,
+    Tournament,
 }
 
 pub enum Notification
 {
     Admin(HashMap<String, Vec<AdminNotification>>),
     Match(Vec<MatchNotification>),
+    Doubles(Vec<DoublesNotification>),
+    Tournament(Vec<TournamentNotification>),
 }
 
 impl serde::Serialize for Notification
@@ -78,6 +144,24 @@ impl serde::Serialize for Notification
                 }
                 seq.end()
             },
+            Notification::Doubles(n) =>
+            {
+                let mut seq = serializer.serialize_seq(Some(n.len()))?;
+                for e in n
+                {
+                    seq.serialize_element(e)?;
+                }
+                seq.end()
+            },
+            Notification::Tournament(n) =>
+            {
+                let mut seq = serializer.serialize_seq(Some(n.len()))?;
+                for e in n
+                {
+                    seq.serialize_element(e)?;
+                }
+                seq.end()
+            },
         }
     }
 }
@@ -92,6 +176,8 @@ impl std::convert::TryFrom<String> for NotificationType
         {
             "match" => Ok(NotificationType::Match),
             "admin" => Ok(NotificationType::Admin),
+            "doubles" => Ok(NotificationType::Doubles),
+            "tournament" => Ok(NotificationType::Tournament),
             _ => Err(()),
         }
     }
@@ -101,7 +187,6 @@ impl std::convert::TryFrom<String> for NotificationType
 struct _NotificationAns
 {
     id:     i64,
-    token:  String,
     ans:    u8,
     r#type: String,
 }
@@ -111,23 +196,28 @@ pub enum NotificationAns
     ResetPassword(i64, String, u8),
     NewUser(i64, String, u8),
     Match(i64, String, u8),
+    Doubles(i64, String, u8),
+    Tournament(i64, String, u8),
 }
 
 
-impl std::convert::TryFrom<String> for NotificationAns
+// The answer in the request body together with the token of who answered
+impl std::convert::TryFrom<(String, String)> for NotificationAns
 {
     type Error = ();
 
-    fn try_from(action: String) -> Result<Self, Self::Error>
+    fn try_from((action, token): (String, String)) -> Result<Self, Self::Error>
     {
         match serde_json::from_str::<_NotificationAns>(&action)
         {
             Err(_) => Err(()),
             Ok(not) => match not.r#type.as_str()
             {
-                "reset_password" => Ok(NotificationAns::ResetPassword(not.id, not.token, not.ans)),
-                "new_user" => Ok(NotificationAns::NewUser(not.id, not.token, not.ans)),
-                "match" => Ok(NotificationAns::Match(not.id, not.token, not.ans)),
+                "reset_password" => Ok(NotificationAns::ResetPassword(not.id, token, not.ans)),
+                "new_user" => Ok(NotificationAns::NewUser(not.id, token, not.ans)),
+                "match" => Ok(NotificationAns::Match(not.id, token, not.ans)),
+                "doubles" => Ok(NotificationAns::Doubles(not.id, token, not.ans)),
+                "tournament" => Ok(NotificationAns::Tournament(not.id, token, not.ans)),
                 _ => Err(()),
             },
         }
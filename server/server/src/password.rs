@@ -0,0 +1,89 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use server_core::constants::*;
+
+const TEMPORARY_PASSWORD_LENGTH: usize = 12;
+
+// Argon2id with a random salt, the salt and parameters are part of the
+// returned string
+pub fn hash_password(password: &str) -> String
+{
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Hashing password")
+        .to_string()
+}
+
+pub fn verify_password(password: &str, hash: &str, algorithm: u8) -> bool
+{
+    match algorithm
+    {
+        HASH_SHA256 => sha256(password) == hash,
+        HASH_ARGON2ID => PasswordHash::new(hash)
+            .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+// Only used to check passwords stored before Argon2id, they are re-hashed at
+// the next login
+fn sha256(word: &str) -> String
+{
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(word);
+    let result = hasher.finalize();
+    format!("{:x}", result)
+}
+
+pub fn temporary_password() -> String
+{
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TEMPORARY_PASSWORD_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_argon2_hashes_are_salted()
+    {
+        let a = hash_password("password");
+        let b = hash_password("password");
+
+        assert_ne!(a, b);
+        assert!(a.starts_with("$argon2id$"));
+        assert!(verify_password("password", &a, HASH_ARGON2ID));
+        assert!(verify_password("password", &b, HASH_ARGON2ID));
+        assert!(!verify_password("passwork", &a, HASH_ARGON2ID));
+    }
+
+    #[test]
+    fn test_verifies_old_sha256_hashes()
+    {
+        let hash = "5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8";
+
+        assert!(verify_password("password", hash, HASH_SHA256));
+        assert!(!verify_password("password", hash, HASH_ARGON2ID));
+    }
+
+    #[test]
+    fn test_temporary_passwords_differ()
+    {
+        let a = temporary_password();
+
+        assert_eq!(a.len(), TEMPORARY_PASSWORD_LENGTH);
+        assert_ne!(a, temporary_password());
+    }
+}
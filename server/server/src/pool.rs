@@ -0,0 +1,158 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex, MutexGuard, TryLockError,
+};
+
+use rusqlite::NO_PARAMS;
+use server_core::types::ServerResult;
+
+use super::server::DataBase;
+
+// All writes go through a single connection, while reads are spread over a set
+// of read only connections. With the database in WAL mode a read never has to
+// wait for a write to finish, so a slow write does not hold up the leaderboard.
+pub struct DataBasePool
+{
+    // The readers are dropped first, so that the writer is the last connection
+    // to close and can clean up the WAL files
+    readers: Vec<Mutex<DataBase>>,
+    writer:  Mutex<DataBase>,
+    next:    AtomicUsize,
+}
+
+impl DataBasePool
+{
+    pub fn new(file: &str, readers: usize) -> Self
+    {
+        // The writer creates and migrates the database, so it has to be opened
+        // before any of the readers
+        let writer = DataBase::new(file);
+        writer
+            .conn
+            .query_row("pragma journal_mode = wal", NO_PARAMS, |row| row.get::<_, String>(0))
+            .expect("Setting journal mode");
+        writer.create_missing_variables().expect("Creating variables");
+
+        DataBasePool {
+            readers: (0..readers).map(|_| Mutex::new(DataBase::init_reader(file))).collect(),
+            writer:  Mutex::new(writer),
+            next:    AtomicUsize::new(0),
+        }
+    }
+
+    pub fn write(&self) -> MutexGuard<'_, DataBase>
+    {
+        lock(&self.writer)
+    }
+
+    // Takes the first reader that is not in use, and waits for one if they
+    // are all busy. Falls back to the writer if there are no readers.
+    pub fn read(&self) -> MutexGuard<'_, DataBase>
+    {
+        if self.readers.is_empty()
+        {
+            return self.write();
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % self.readers.len();
+        for i in 0..self.readers.len()
+        {
+            match self.readers[(start + i) % self.readers.len()].try_lock()
+            {
+                Ok(guard) => return guard,
+                Err(TryLockError::Poisoned(p_err)) => return p_err.into_inner(),
+                Err(TryLockError::WouldBlock) => continue,
+            }
+        }
+        lock(&self.readers[start])
+    }
+
+    // Moves everything in the WAL file into the database file, so that the
+    // database file can be copied on its own
+    pub fn checkpoint(&self) -> ServerResult<MutexGuard<'_, DataBase>>
+    {
+        let writer = self.write();
+        writer.conn.query_row("pragma wal_checkpoint(truncate)", NO_PARAMS, |_| Ok(()))?;
+        Ok(writer)
+    }
+}
+
+// A thread panicking while holding the lock leaves the connection as it was,
+// so a poisoned lock is still usable
+fn lock(mutex: &Mutex<DataBase>) -> MutexGuard<'_, DataBase>
+{
+    match mutex.lock()
+    {
+        Ok(guard) => guard,
+        Err(p_err) => p_err.into_inner(),
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::test_util::*;
+
+    #[test]
+    fn test_readers_see_writes()
+    {
+        let db_file = "tempP1.db";
+        let pool = DataBasePool::new(db_file, 2);
+
+        create_user(&pool.write(), "Sivert");
+        let user = pool.read().get_user_without_matches("Sivert");
+        let is_season = pool.read().get_is_season();
+
+        drop(pool);
+        std::fs::remove_file(db_file).expect("Removing file tempP1");
+        assert!(user.is_ok());
+        assert!(is_season.is_ok());
+    }
+
+    #[test]
+    fn test_readers_can_not_write()
+    {
+        let db_file = "tempP2.db";
+        let pool = DataBasePool::new(db_file, 1);
+
+        let res = pool.read().create_user("Sivert".to_string(), "password".to_string());
+
+        drop(pool);
+        std::fs::remove_file(db_file).expect("Removing file tempP2");
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_reads_are_not_blocked_by_the_writer()
+    {
+        let db_file = "tempP3.db";
+        let pool = Arc::new(DataBasePool::new(db_file, 2));
+        create_user(&pool.write(), "Sivert");
+
+        // Hold the writer in the middle of a write while reading from another
+        // thread
+        let writer = pool.write();
+        writer.conn.execute("begin immediate", NO_PARAMS).unwrap();
+        writer.conn.execute("update users set elo = 2000.0", NO_PARAMS).unwrap();
+
+        let reader_pool = pool.clone();
+        let elo = std::thread::spawn(move || {
+            reader_pool.read().get_user_without_matches("Sivert").unwrap().elo
+        })
+        .join()
+        .unwrap();
+
+        writer.conn.execute("commit", NO_PARAMS).unwrap();
+        drop(writer);
+        let after = pool.read().get_user_without_matches("Sivert").unwrap().elo;
+
+        drop(pool);
+        std::fs::remove_file(db_file).expect("Removing file tempP3");
+        assert_eq!(elo, 1500.0);
+        assert_eq!(after, 2000.0);
+    }
+}
@@ -1,13 +1,16 @@
-use std::sync::{Arc, Mutex};
+use std::{sync::Arc, time::Duration};
 
 use chrono::prelude::*;
+use serde_json::Value;
 use server_core::constants::DATABASE_FILE;
 
-use super::{season::Season, server::DataBase};
+use super::{pool::DataBasePool, season::Season};
 
-fn get_ars(data: &Arc<Mutex<DataBase>>) -> (bool, i64, u32)
+const TOURNAMENT_START_INTERVAL: Duration = Duration::from_secs(60);
+
+fn get_ars(data: &Arc<DataBasePool>) -> (bool, i64, u32)
 {
-    let s = data.lock().expect("Getting mutex");
+    let s = data.read();
 
     // Assume this means that this is the first season
     let season =
@@ -21,22 +24,23 @@ fn get_ars(data: &Arc<Mutex<DataBase>>) -> (bool, i64, u32)
     (running, season_length, start_month)
 }
 
-fn start_new_season(data: &Arc<Mutex<DataBase>>)
+fn start_new_season(data: &Arc<DataBasePool>)
 {
-    let s = data.lock().expect("Getting mutex");
+    let s = data.write();
     s.end_season(true).expect("Endig season");
     s.start_new_season().expect("starting new season");
+    s.audit(0, "start_season", "season", Value::Null, Value::Null).expect("Auditing new season");
 }
 
-fn backup(data: &Arc<Mutex<DataBase>>)
+fn backup(data: &Arc<DataBasePool>)
 {
-    let s = data.lock().expect("Getting mutex");
+    let s = data.checkpoint().expect("Checkpointing DB");
     std::fs::copy(DATABASE_FILE, format!("db/backup/{}.db", Utc::now().format("%d_%m_%y_%H%M%S")))
         .expect("Backing DB up");
     drop(s);
 }
 
-pub fn spawn_season_checker(data: Arc<Mutex<DataBase>>)
+pub fn spawn_season_checker(data: Arc<DataBasePool>)
 {
     std::thread::spawn(move || {
         loop
@@ -46,6 +50,7 @@ pub fn spawn_season_checker(data: Arc<Mutex<DataBase>>)
             let duration = next_3.signed_duration_since(now).to_std().unwrap();
             std::thread::sleep(duration);
             backup(&data);
+            data.write().prune_audit_log().expect("Pruning audit log");
 
             let (is_season, len, start) = get_ars(&data);
             if is_season && Utc::now().month0() == (start + len as u32) % 12
@@ -55,3 +60,19 @@ pub fn spawn_season_checker(data: Arc<Mutex<DataBase>>)
         }
     });
 }
+
+// Starts the tournaments whose registration deadline has passed
+pub fn spawn_tournament_starter(data: Arc<DataBasePool>)
+{
+    std::thread::spawn(move || {
+        loop
+        {
+            std::thread::sleep(TOURNAMENT_START_INTERVAL);
+            // A failed attempt is retried on the next tick
+            if let Err(e) = data.write().start_due_tournaments()
+            {
+                eprintln!("Starting tournaments: {:?}", e);
+            }
+        }
+    });
+}
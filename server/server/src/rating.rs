@@ -0,0 +1,509 @@
+use std::f64::consts::PI;
+
+use elo::EloRank;
+
+pub const DEFAULT_RATING: f64 = 1500.0;
+pub const DEFAULT_DEVIATION: f64 = 350.0;
+pub const DEFAULT_VOLATILITY: f64 = 0.06;
+pub const DEFAULT_K: i64 = 32;
+pub const MAX_K: i64 = 100;
+
+// Glicko-2 works on its own scale, this converts to and from the Elo scale
+const GLICKO2_SCALE: f64 = 173.7178;
+const GLICKO2_TAU: f64 = 0.5;
+const GLICKO2_EPSILON: f64 = 0.000001;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rating
+{
+    pub rating:     f64,
+    pub deviation:  f64,
+    pub volatility: f64,
+    pub games:      i64,
+}
+
+impl Default for Rating
+{
+    fn default() -> Self
+    {
+        Rating {
+            rating:     DEFAULT_RATING,
+            deviation:  DEFAULT_DEVIATION,
+            volatility: DEFAULT_VOLATILITY,
+            games:      0,
+        }
+    }
+}
+
+impl Rating
+{
+    pub fn new(rating: f64, games: i64) -> Self
+    {
+        Rating {
+            rating, games, ..Default::default()
+        }
+    }
+
+    // Two players seen as a single opponent, used to rate each player of a
+    // doubles match against the other team
+    pub fn team(a: &Rating, b: &Rating) -> Self
+    {
+        Rating {
+            rating:     (a.rating + b.rating) / 2.0,
+            deviation:  ((a.deviation.powi(2) + b.deviation.powi(2)) / 2.0).sqrt(),
+            volatility: (a.volatility + b.volatility) / 2.0,
+            games:      a.games.min(b.games),
+        }
+    }
+
+    // Same player, one more game played
+    fn played(&self, rating: f64, deviation: f64, volatility: f64) -> Self
+    {
+        Rating {
+            rating,
+            deviation,
+            volatility,
+            games: self.games + 1,
+        }
+    }
+}
+
+pub trait RatingSystem
+{
+    // Returns the new (winner, loser) ratings
+    fn rate(&self, winner: &Rating, loser: &Rating) -> (Rating, Rating);
+
+    // Like `rate`, but the change in rating is scaled by `margin`
+    fn rate_by_margin(&self, winner: &Rating, loser: &Rating, margin: f64) -> (Rating, Rating)
+    {
+        let (w, l) = self.rate(winner, loser);
+        (
+            Rating {
+                rating: winner.rating + (w.rating - winner.rating) * margin, ..w
+            },
+            Rating {
+                rating: loser.rating + (l.rating - loser.rating) * margin, ..l
+            },
+        )
+    }
+
+    // A rating period went by without the player playing any matches
+    fn idle(&self, rating: &Rating) -> Rating
+    {
+        *rating
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RatingSystemType
+{
+    Elo,
+    DynamicElo,
+    Glicko2,
+    TrueSkill,
+}
+
+impl std::convert::TryFrom<i64> for RatingSystemType
+{
+    type Error = ();
+
+    fn try_from(n: i64) -> Result<Self, Self::Error>
+    {
+        use RatingSystemType::*;
+        match n
+        {
+            0 => Ok(Elo),
+            1 => Ok(DynamicElo),
+            2 => Ok(Glicko2),
+            3 => Ok(TrueSkill),
+            _ => Err(()),
+        }
+    }
+}
+
+impl RatingSystemType
+{
+    pub fn create(self, k: i64) -> Box<dyn RatingSystem>
+    {
+        match self
+        {
+            RatingSystemType::Elo => Box::new(ClassicElo {
+                k: k as i32
+            }),
+            RatingSystemType::DynamicElo => Box::new(DynamicElo::new(k)),
+            RatingSystemType::Glicko2 => Box::new(Glicko2),
+            RatingSystemType::TrueSkill => Box::new(TrueSkill::default()),
+        }
+    }
+}
+
+pub struct ClassicElo
+{
+    pub k: i32,
+}
+
+impl RatingSystem for ClassicElo
+{
+    fn rate(&self, winner: &Rating, loser: &Rating) -> (Rating, Rating)
+    {
+        let elo = EloRank {
+            k: self.k
+        };
+        let (w, l) = elo.calculate(winner.rating, loser.rating);
+        (
+            winner.played(w, winner.deviation, winner.volatility),
+            loser.played(l, loser.deviation, loser.volatility),
+        )
+    }
+}
+
+// Elo where new players move faster than established ones. The K factor
+// starts at twice the configured K and settles at K after
+// `provisional_games` games.
+pub struct DynamicElo
+{
+    pub k:                 i64,
+    pub provisional_games: i64,
+}
+
+impl DynamicElo
+{
+    pub fn new(k: i64) -> Self
+    {
+        DynamicElo {
+            k, provisional_games: 20
+        }
+    }
+
+    fn k_for(&self, rating: &Rating) -> i32
+    {
+        if rating.games >= self.provisional_games
+        {
+            return self.k as i32;
+        }
+        let left = (self.provisional_games - rating.games) as f64 / self.provisional_games as f64;
+        (self.k as f64 * (1.0 + left)).round() as i32
+    }
+}
+
+impl RatingSystem for DynamicElo
+{
+    fn rate(&self, winner: &Rating, loser: &Rating) -> (Rating, Rating)
+    {
+        let (w, _) = EloRank {
+            k: self.k_for(winner)
+        }
+        .calculate(winner.rating, loser.rating);
+        let (_, l) = EloRank {
+            k: self.k_for(loser)
+        }
+        .calculate(winner.rating, loser.rating);
+        (
+            winner.played(w, winner.deviation, winner.volatility),
+            loser.played(l, loser.deviation, loser.volatility),
+        )
+    }
+}
+
+// Every match is treated as its own rating period
+pub struct Glicko2;
+
+impl Glicko2
+{
+    fn g(phi: f64) -> f64
+    {
+        1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+    }
+
+    fn volatility(phi: f64, sigma: f64, delta: f64, v: f64) -> f64
+    {
+        let a = (sigma * sigma).ln();
+        let f = |x: f64| {
+            let ex = x.exp();
+            let d = phi * phi + v + ex;
+            ex * (delta * delta - phi * phi - v - ex) / (2.0 * d * d)
+                - (x - a) / (GLICKO2_TAU * GLICKO2_TAU)
+        };
+
+        let mut big_a = a;
+        let mut big_b = if delta * delta > phi * phi + v
+        {
+            (delta * delta - phi * phi - v).ln()
+        }
+        else
+        {
+            let mut k = 1.0;
+            while f(a - k * GLICKO2_TAU) < 0.0
+            {
+                k += 1.0;
+            }
+            a - k * GLICKO2_TAU
+        };
+
+        let mut f_a = f(big_a);
+        let mut f_b = f(big_b);
+        while (big_b - big_a).abs() > GLICKO2_EPSILON
+        {
+            let big_c = big_a + (big_a - big_b) * f_a / (f_b - f_a);
+            let f_c = f(big_c);
+            if f_c * f_b <= 0.0
+            {
+                big_a = big_b;
+                f_a = f_b;
+            }
+            else
+            {
+                f_a /= 2.0;
+            }
+            big_b = big_c;
+            f_b = f_c;
+        }
+        (big_a / 2.0).exp()
+    }
+
+    fn update(player: &Rating, opponent: &Rating, score: f64) -> Rating
+    {
+        let mu = (player.rating - DEFAULT_RATING) / GLICKO2_SCALE;
+        let phi = player.deviation / GLICKO2_SCALE;
+        let mu_j = (opponent.rating - DEFAULT_RATING) / GLICKO2_SCALE;
+        let phi_j = opponent.deviation / GLICKO2_SCALE;
+
+        let g = Self::g(phi_j);
+        let e = 1.0 / (1.0 + (-g * (mu - mu_j)).exp());
+        let v = 1.0 / (g * g * e * (1.0 - e));
+        let delta = v * g * (score - e);
+
+        let sigma = Self::volatility(phi, player.volatility, delta, v);
+        let phi_star = (phi * phi + sigma * sigma).sqrt();
+        let new_phi = 1.0 / (1.0 / (phi_star * phi_star) + 1.0 / v).sqrt();
+        let new_mu = mu + new_phi * new_phi * g * (score - e);
+
+        player.played(
+            new_mu * GLICKO2_SCALE + DEFAULT_RATING,
+            new_phi * GLICKO2_SCALE,
+            sigma,
+        )
+    }
+}
+
+impl RatingSystem for Glicko2
+{
+    fn rate(&self, winner: &Rating, loser: &Rating) -> (Rating, Rating)
+    {
+        (Self::update(winner, loser, 1.0), Self::update(loser, winner, 0.0))
+    }
+
+    fn idle(&self, rating: &Rating) -> Rating
+    {
+        let phi = rating.deviation / GLICKO2_SCALE;
+        let phi_star = (phi * phi + rating.volatility * rating.volatility).sqrt();
+        Rating {
+            deviation: (phi_star * GLICKO2_SCALE).min(DEFAULT_DEVIATION),
+            ..*rating
+        }
+    }
+}
+
+// Two player TrueSkill without draws, scaled so that mu starts at 1500 and
+// sigma at the default deviation
+pub struct TrueSkill
+{
+    pub beta: f64,
+    pub tau:  f64,
+}
+
+impl Default for TrueSkill
+{
+    fn default() -> Self
+    {
+        TrueSkill {
+            beta: DEFAULT_DEVIATION / 2.0, tau: DEFAULT_DEVIATION / 100.0
+        }
+    }
+}
+
+impl RatingSystem for TrueSkill
+{
+    fn rate(&self, winner: &Rating, loser: &Rating) -> (Rating, Rating)
+    {
+        let w_var = winner.deviation.powi(2) + self.tau.powi(2);
+        let l_var = loser.deviation.powi(2) + self.tau.powi(2);
+        let c = (2.0 * self.beta.powi(2) + w_var + l_var).sqrt();
+
+        let t = (winner.rating - loser.rating) / c;
+        let v = win_v(t);
+        let w = (v * (v + t)).clamp(0.0, 1.0);
+
+        let w_sigma = (w_var * (1.0 - w_var / (c * c) * w)).sqrt();
+        let l_sigma = (l_var * (1.0 - l_var / (c * c) * w)).sqrt();
+        (
+            winner.played(winner.rating + w_var / c * v, w_sigma, winner.volatility),
+            loser.played(loser.rating - l_var / c * v, l_sigma, loser.volatility),
+        )
+    }
+
+    fn idle(&self, rating: &Rating) -> Rating
+    {
+        Rating {
+            deviation: (rating.deviation.powi(2) + self.tau.powi(2))
+                .sqrt()
+                .min(DEFAULT_DEVIATION),
+            ..*rating
+        }
+    }
+}
+
+// The density over the distribution of the standard normal at t. For an upset
+// both are too small to divide, but they share exp(-t^2 / 2), which cancels.
+fn win_v(t: f64) -> f64
+{
+    let z = -t / 2.0_f64.sqrt();
+    if z > 0.0
+    {
+        (2.0 / PI).sqrt() / erfcx(z)
+    }
+    else
+    {
+        let pdf = (-t * t / 2.0).exp() / (2.0 * PI).sqrt();
+        pdf / (1.0 - 0.5 * erfcx(-z) * (-z * z).exp())
+    }
+}
+
+// erfc(x) * exp(x^2) for x >= 0, from the erfc of Numerical Recipes, with a
+// relative error below 1.2e-7 even far in the tail
+fn erfcx(x: f64) -> f64
+{
+    let t = 1.0 / (1.0 + 0.5 * x);
+    t * (-1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807
+                            + t * (-1.13520398
+                                + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277)))))))))
+        .exp()
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    fn all_systems() -> Vec<Box<dyn RatingSystem>>
+    {
+        (0..4)
+            .map(|n| RatingSystemType::try_from(n).unwrap().create(DEFAULT_K))
+            .collect()
+    }
+
+    #[test]
+    fn test_classic_elo_matches_elo_crate()
+    {
+        let system = RatingSystemType::Elo.create(32);
+        let (w, l) = system.rate(&Rating::new(1200.0, 0), &Rating::new(1400.0, 0));
+        assert_eq!(w.rating, 1224.3119016527346);
+        assert_eq!(l.rating, 1375.6880983472654);
+        assert_eq!(w.games, 1);
+        assert_eq!(l.games, 1);
+    }
+
+    #[test]
+    fn test_winner_gains_and_loser_loses()
+    {
+        for system in all_systems()
+        {
+            let (w, l) = system.rate(&Rating::default(), &Rating::default());
+            assert!(w.rating > DEFAULT_RATING);
+            assert!(l.rating < DEFAULT_RATING);
+        }
+    }
+
+    #[test]
+    fn test_dynamic_elo_settles()
+    {
+        let system = DynamicElo::new(32);
+        assert_eq!(system.k_for(&Rating::new(1500.0, 0)), 64);
+        assert_eq!(system.k_for(&Rating::new(1500.0, 20)), 32);
+        assert_eq!(system.k_for(&Rating::new(1500.0, 100)), 32);
+    }
+
+    #[test]
+    fn test_glicko2_example()
+    {
+        // One game from the example in Glickman's paper
+        let player = Rating {
+            rating:     1500.0,
+            deviation:  200.0,
+            volatility: 0.06,
+            games:      0,
+        };
+        let opponent = Rating {
+            rating:     1400.0,
+            deviation:  30.0,
+            volatility: 0.06,
+            games:      0,
+        };
+        let (w, l) = Glicko2.rate(&player, &opponent);
+        assert!((w.rating - 1563.56).abs() < 0.01);
+        assert!((w.deviation - 175.40).abs() < 0.01);
+        assert!(w.deviation < player.deviation);
+        assert!(l.rating < opponent.rating);
+    }
+
+    #[test]
+    fn test_idle_grows_deviation()
+    {
+        let rating = Rating {
+            rating:     1600.0,
+            deviation:  50.0,
+            volatility: 0.06,
+            games:      10,
+        };
+        let elo = RatingSystemType::Elo.create(DEFAULT_K).idle(&rating);
+        let glicko = Glicko2.idle(&rating);
+        let capped = Glicko2.idle(&Rating::default());
+
+        assert_eq!(elo, rating);
+        assert!(glicko.deviation > rating.deviation);
+        assert_eq!(glicko.rating, rating.rating);
+        assert_eq!(capped.deviation, DEFAULT_DEVIATION);
+    }
+
+    #[test]
+    fn test_trueskill_reduces_uncertainty()
+    {
+        let (w, l) = TrueSkill::default().rate(&Rating::default(), &Rating::default());
+        assert!(w.deviation < DEFAULT_DEVIATION);
+        assert!(l.deviation < DEFAULT_DEVIATION);
+        assert!((w.rating - DEFAULT_RATING - (DEFAULT_RATING - l.rating)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_trueskill_survives_a_large_upset()
+    {
+        let winner = Rating {
+            rating: 1000.0,
+            deviation: 30.0,
+            ..Default::default()
+        };
+        let loser = Rating {
+            rating: 3000.0,
+            deviation: 30.0,
+            ..Default::default()
+        };
+        let (w, l) = TrueSkill::default().rate(&winner, &loser);
+        assert!(w.rating.is_finite() && w.deviation.is_finite());
+        assert!(l.rating.is_finite() && l.deviation.is_finite());
+        assert!(w.rating > winner.rating);
+        assert!(l.rating < loser.rating);
+        assert!(w.deviation > 0.0 && w.deviation <= winner.deviation);
+        // Far in the tail v is close to -t
+        let t = -20.0;
+        assert!((win_v(t) + t).abs() < 0.1);
+        assert!((win_v(0.0) - (2.0 / PI).sqrt()).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,125 @@
+use serde::Serializer;
+use server_core::types::{ServerError, ServerResult};
+
+// Points in a single game, from the match winner's point of view
+pub type GameScore = [u32; 2];
+
+// Scores are stored as json text in the match tables, e.g "[[11,9],[8,11],[11,4]]"
+pub fn scores_to_sql(scores: &[GameScore]) -> String
+{
+    if scores.is_empty() { String::new() } else { serde_json::to_string(scores).unwrap() }
+}
+
+pub fn scores_from_sql(scores: &str) -> Vec<GameScore>
+{
+    serde_json::from_str(scores).unwrap_or_default()
+}
+
+pub fn serialize_scores<S>(scores: &str, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serde::Serialize::serialize(&scores_from_sql(scores), serializer)
+}
+
+fn valid_game(game: &GameScore, points: u32) -> bool
+{
+    let (high, low) = if game[0] > game[1] { (game[0], game[1]) } else { (game[1], game[0]) };
+    if high < points || high - low < 2
+    {
+        return false;
+    }
+    // Either the game ended right at `points`, or in extra points when the lead
+    // became two
+    high == points || high - low == 2
+}
+
+// A best of N match where the winner of the match won the last game, and
+// needed (N + 1) / 2 games to win. Every game is played to 11 or to 21 and
+// must be won by two.
+pub fn validate_scores(scores: &[GameScore]) -> ServerResult<()>
+{
+    if scores.is_empty()
+    {
+        return Ok(());
+    }
+
+    if ![11, 21].iter().any(|&points| scores.iter().all(|g| valid_game(g, points)))
+    {
+        return Err(ServerError::InvalidScore);
+    }
+
+    let winner_games = scores.iter().filter(|g| g[0] > g[1]).count();
+    let loser_games = scores.len() - winner_games;
+    let last = scores[scores.len() - 1];
+    if last[0] < last[1] || loser_games >= winner_games || winner_games > 4
+    {
+        return Err(ServerError::InvalidScore);
+    }
+    Ok(())
+}
+
+// How convincing a win was, 1.0 for matches without scores. A clean sweep gives
+// 1.5 while a win in the deciding game gives a bit less than 1.
+pub fn margin_multiplier(scores: &[GameScore]) -> f64
+{
+    let winner_games = scores.iter().filter(|g| g[0] > g[1]).count() as f64;
+    if winner_games == 0.0
+    {
+        return 1.0;
+    }
+    let loser_games = scores.len() as f64 - winner_games;
+    0.5 + (winner_games - loser_games) / winner_games
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_valid_scores()
+    {
+        assert!(validate_scores(&[]).is_ok());
+        assert!(validate_scores(&[[11, 9]]).is_ok());
+        assert!(validate_scores(&[[11, 9], [8, 11], [11, 4]]).is_ok());
+        assert!(validate_scores(&[[11, 9], [12, 14], [15, 13]]).is_ok());
+        assert!(validate_scores(&[[21, 19], [23, 21]]).is_ok());
+        assert!(validate_scores(&[[11, 0], [11, 0], [3, 11], [11, 5]]).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_scores()
+    {
+        // Not won by two
+        assert!(validate_scores(&[[11, 10]]).is_err());
+        // Game went on after it was over
+        assert!(validate_scores(&[[15, 9]]).is_err());
+        // Match winner lost the last game
+        assert!(validate_scores(&[[11, 9], [11, 4], [4, 11]]).is_err());
+        // Loser won as many games as the winner
+        assert!(validate_scores(&[[11, 9], [4, 11]]).is_err());
+        // Mixing 11 and 21 point games
+        assert!(validate_scores(&[[21, 9], [11, 4]]).is_err());
+    }
+
+    #[test]
+    fn test_margin_multiplier()
+    {
+        assert_eq!(margin_multiplier(&[]), 1.0);
+        assert_eq!(margin_multiplier(&[[11, 9], [11, 4]]), 1.5);
+        assert_eq!(margin_multiplier(&[[11, 9], [8, 11], [11, 4]]), 1.0);
+        assert!(margin_multiplier(&[[11, 9], [8, 11], [9, 11], [11, 4], [11, 5]]) < 1.0);
+    }
+
+    #[test]
+    fn test_scores_round_trip()
+    {
+        let scores = vec![[11, 9], [8, 11], [11, 4]];
+        let sql = scores_to_sql(&scores);
+        assert_eq!(sql, "[[11,9],[8,11],[11,4]]");
+        assert_eq!(scores_from_sql(&sql), scores);
+        assert_eq!(scores_from_sql(""), Vec::<GameScore>::new());
+    }
+}
@@ -1,10 +1,9 @@
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, convert::TryFrom, str::FromStr};
 
 use chrono::prelude::*;
-use elo::EloRank;
 use lazy_static::lazy_static;
-use regex::Regex;
 use rusqlite::{named_params, params, Connection, ToSql, NO_PARAMS};
+use serde_json::{json, Value};
 use server_core::{constants::*, types::*};
 use uuid::Uuid;
 
@@ -16,8 +15,12 @@ use super::{
         AdminNotification, MatchNotification, MatchNotificationTable, Notification,
         NotificationAns, NotificationType,
     },
+    password::*,
+    rating::*,
+    score::*,
     tournament::*,
     user::{StatsUsers, User},
+    validate::valid_username,
     GET_OR_CREATE_DB_VAR, SQL_TUPLE_NAMED,
 };
 
@@ -38,6 +41,10 @@ lazy_static! {
         m.insert("is_season", (IS_SEASON_ID as i64, 1));
         m.insert("season_length", (N_SEASON_ID as i64, 1));
         m.insert("user_conf", (REQUIRE_CONFIRMATION_ID as i64, 0));
+        m.insert("rating_system", (RATING_SYSTEM_ID as i64, RatingSystemType::Elo as i64));
+        m.insert("elo_k", (ELO_K_ID as i64, DEFAULT_K));
+        m.insert("margin_of_victory", (MARGIN_OF_VICTORY_ID as i64, 0));
+        m.insert("audit_retention_days", (AUDIT_RETENTION_DAYS_ID as i64, 365));
         m
     };
 }
@@ -57,119 +64,196 @@ impl DataBase
 
     pub fn create_superuser(&self, name: String) -> ServerResult<()>
     {
-        let password = self.hash(&"password".to_string());
-        self.create_user_with_password_hash(name.clone(), password)?;
-        self.make_user_admin(name)?;
-        Ok(())
+        self.atomically(|| {
+            let password = hash_password("password");
+            self.create_user_with_password_hash(name.clone(), password, HASH_ARGON2ID)?;
+            self.make_user_admin(name)?;
+            Ok(())
+        })
     }
 
     pub fn get_is_admin(&self, token: String) -> ServerResult<bool>
     {
-        let user = self.get_user_without_matches_by("uuid", "=", &token)?;
+        let user = self.get_user_from_token(&token)?;
         Ok(user.user_role & USER_ROLE_SUPERUSER == USER_ROLE_SUPERUSER)
     }
 
-    pub fn admin_rollback(&self, token: String) -> ServerResult<()>
+    // Runs `f` in a savepoint, which is a transaction of its own unless one is
+    // already open. Everything `f` did is undone if it fails.
+    pub(crate) fn atomically<T>(&self, f: impl FnOnce() -> ServerResult<T>) -> ServerResult<T>
     {
-        if self.get_is_admin(token)?
+        self.conn.execute_batch("savepoint atomically")?;
+        match f()
         {
-            self.roll_back(-1)?;
+            Ok(value) =>
+            {
+                self.conn.execute_batch("release atomically")?;
+                Ok(value)
+            },
+            Err(e) =>
+            {
+                self.conn.execute_batch("rollback to atomically; release atomically")?;
+                Err(e)
+            },
         }
-        Ok(())
     }
 
-    pub fn delete_match(&self, info: DeleteMatchInfo) -> ServerResult<()>
+    // The user owning the token, if they are an admin
+    pub(crate) fn get_admin(&self, token: &str) -> ServerResult<User>
     {
-        if !self.get_is_admin(info.token.clone())?
+        let user = self.get_user_from_token(token)?;
+        if user.user_role & USER_ROLE_SUPERUSER != USER_ROLE_SUPERUSER
         {
             return Err(ServerError::Unauthorized);
         }
+        Ok(user)
+    }
 
-        self.try_delete_match(info)?;
-        self.roll_back(-1)?;
-        Ok(())
+    pub fn admin_rollback(&self, token: String) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let admin = self.get_admin(&token)?;
+            self.roll_back(-1)?;
+            self.audit(admin.id, "roll_back", "season", Value::Null, Value::Null)
+        })
+    }
+
+    pub fn delete_match(&self, info: DeleteMatchInfo) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let admin = self.get_admin(&info.token)?;
+            let target = format!("match:{}", info.id);
+            let before = self.match_snapshot(info.id)?;
+            let footprint = self.get_match_footprint(info.id)?;
+
+            self.save_match_version(info.id, true, admin.id)?;
+            self.try_delete_match(info)?;
+            self.replay_changed_matches(&[footprint])?;
+            self.audit(admin.id, "delete_match", &target, before, Value::Null)
+        })
     }
 
     pub fn edit_match(&self, info: NewEditMatchInfo) -> ServerResult<()>
     {
-        if !self.get_is_admin(info.token.clone())?
-        {
-            return Err(ServerError::Unauthorized);
-        }
+        self.atomically(|| {
+            let admin = self.get_admin(&info.token)?;
+            let (id, target) = (info.id, format!("match:{}", info.id));
+            let before = self.match_snapshot(id)?;
+            let footprint = self.get_match_footprint(id)?;
 
-        self.update_match(info)?;
-        self.roll_back(-1)?;
-        Ok(())
+            self.save_match_version(id, false, admin.id)?;
+            self.update_match(info)?;
+            self.replay_changed_matches(&[footprint, self.get_match_footprint(id)?])?;
+            self.audit(admin.id, "edit_match", &target, before, self.match_snapshot(id)?)
+        })
     }
 
-    pub fn login(&self, name: String, password: String) -> ServerResult<String> // String = Uuid
+    pub fn login(&self, name: String, password: String) -> ServerResult<String> // String = session token
     {
-        let mut info = SQL_TUPLE_NAMED!(
-            self,
-            "select password_hash, uuid, user_role from users where name = :name;",
-            named_params! {":name" : name},
-            String,
-            String,
-            u8
-        )?;
+        self.login_on_device(name, password, "")
+    }
 
-        if info.is_empty()
-        {
-            let count = SQL_TUPLE_NAMED!(
+    pub fn login_on_device(
+        &self,
+        name: String,
+        password: String,
+        device: &str,
+    ) -> ServerResult<String>
+    {
+        // A required password change still commits the upgraded hash
+        let session = self.atomically(|| {
+            let mut info = SQL_TUPLE_NAMED!(
                 self,
-                "select count(*) from new_user_notification where name = :name",
-                named_params! {":name": &name},
-                i64
+                "select id, password_hash, hash_algorithm, must_change_password, user_role
+                 from users where name = :name;",
+                named_params! {":name" : name},
+                i64,
+                String,
+                u8,
+                bool,
+                u8
             )?;
-            if let Some((c, ..)) = count.get(0)
+
+            if info.is_empty()
             {
-                if c == &1
+                let count = SQL_TUPLE_NAMED!(
+                    self,
+                    "select count(*) from new_user_notification where name = :name",
+                    named_params! {":name": &name},
+                    i64
+                )?;
+                if let Some((c, ..)) = count.get(0)
                 {
-                    return Err(ServerError::WaitingForAdmin);
+                    if c == &1
+                    {
+                        return Err(ServerError::WaitingForAdmin);
+                    }
                 }
+
+                return Err(ServerError::UserNotExist);
             }
 
-            return Err(ServerError::UserNotExist);
-        }
+            let (id, p, algorithm, must_change, r) = info.pop().expect("Getting user stuff");
 
-        let (p, u, r) = info.pop().expect("Getting user stuff");
+            if r & USER_ROLE_INACTIVE == USER_ROLE_INACTIVE
+            {
+                return Err(ServerError::InactiveUser);
+            }
 
-        if r & USER_ROLE_INACTIVE == USER_ROLE_INACTIVE
-        {
-            return Err(ServerError::InactiveUser);
-        }
+            if !verify_password(&password, &p, algorithm)
+            {
+                return Err(ServerError::WrongUsernameOrPassword);
+            }
 
-        if self.hash(&password) == p
-        {
-            return Ok(u);
-        }
+            // Old hashes can only be upgraded while we know the password
+            if algorithm != HASH_ARGON2ID
+            {
+                self.set_password_hash(id, hash_password(&password), must_change)?;
+            }
 
-        Err(ServerError::WrongUsernameOrPassword)
+            if must_change
+            {
+                return Ok(None);
+            }
+
+            self.delete_expired_sessions()?;
+            self.create_session(id, device).map(Some)
+        })?;
+        session.ok_or(ServerError::PasswordChangeRequired)
     }
 
     pub fn respond_to_match(&self, id: i64, ans: u8, token: String) -> ServerResult<()>
     {
-        let user = self.get_user_without_matches_by("uuid", "=", token.as_str())?;
-        let sql = "select id, winner_accept, loser_accept, epoch, winner, loser
-                  from match_notification where id = :id";
+        self.atomically(|| {
+            let user = self.get_user_from_token(&token)?;
+            let sql = "select id, winner_accept, loser_accept, epoch, winner, loser, scores
+                      from match_notification where id = :id";
 
-        let mut match_notification: MatchNotificationTable =
-            self.sql_one(sql, _named_params! {":id": id})?;
+            let mut match_notification: MatchNotificationTable =
+                self.sql_one(sql, _named_params! {":id": id})?;
 
-        if user.id != match_notification.winner && user.id != match_notification.loser
-        {
-            return Err(ServerError::Unauthorized);
-        }
+            if user.id != match_notification.winner && user.id != match_notification.loser
+            {
+                return Err(ServerError::Unauthorized);
+            }
 
-        if user.id == match_notification.winner
-        {
-            match_notification.winner_accept = ans;
-        }
-        else
-        {
-            match_notification.loser_accept = ans;
-        }
-        self.handle_notification_answer(&user, ans, &match_notification)
+            if user.id == match_notification.winner
+            {
+                match_notification.winner_accept = ans;
+            }
+            else
+            {
+                match_notification.loser_accept = ans;
+            }
+            self.handle_notification_answer(&user, ans, &match_notification)?;
+            self.audit(
+                user.id,
+                "respond_to_match",
+                &format!("match_notification:{}", id),
+                Value::Null,
+                json!({ "answer": ans }),
+            )
+        })
     }
 
     pub fn get_variable(&self, variable: String) -> ServerResult<i64>
@@ -177,7 +261,13 @@ impl DataBase
         let (id, default) = match HASHMAP.get(variable.as_str())
         {
             Some(t) => t,
-            None => return Err(ServerError::Critical(format!("No varialbe named {}", variable))),
+            None =>
+            {
+                return Err(ServerError::InvalidRequest(vec![FieldError::new(
+                    "variable",
+                    format!("No variable named {}", variable),
+                )]))
+            },
         };
 
         let res: Result<i64, rusqlite::Error> = GET_OR_CREATE_DB_VAR!(&self.conn, *id, *default);
@@ -190,48 +280,144 @@ impl DataBase
 
     pub fn set_variable(&self, token: String, varialbe: String, val: i64) -> ServerResult<()>
     {
-        if !self.get_is_admin(token)?
-        {
-            return Err(ServerError::Unauthorized);
-        }
+        self.atomically(|| {
+            let admin = self.get_admin(&token)?;
 
-        let id = match HASHMAP.get(varialbe.as_str())
-        {
-            Some((id, _)) => id,
-            None => return Err(ServerError::Critical(format!("No variable named: {}", varialbe))),
-        };
+            let id = match HASHMAP.get(varialbe.as_str())
+            {
+                Some((id, _)) => id,
+                None =>
+                {
+                    return Err(ServerError::InvalidRequest(vec![FieldError::new(
+                        "variable",
+                        format!("No variable named {}", varialbe),
+                    )]))
+                },
+            };
 
-        self.conn
-            .execute("replace into variables(id, value) values (?1, ?2);", params![id, val])?;
-        Ok(())
+            if varialbe == "rating_system" && RatingSystemType::try_from(val).is_err()
+            {
+                return Err(ServerError::InvalidRequest(vec![FieldError::new(
+                    "new_val",
+                    format!("No rating system with id {}", val),
+                )]));
+            }
+            if varialbe == "elo_k" && !(1..=MAX_K).contains(&val)
+            {
+                return Err(ServerError::InvalidRequest(vec![FieldError::new(
+                    "new_val",
+                    format!("Must be between 1 and {}", MAX_K),
+                )]));
+            }
+            if varialbe == "audit_retention_days" && val < 0
+            {
+                return Err(ServerError::InvalidRequest(vec![FieldError::new(
+                    "new_val",
+                    "Can not be negative",
+                )]));
+            }
+
+            let before = self.get_variable(varialbe.clone())?;
+            self.conn
+                .execute("replace into variables(id, value) values (?1, ?2);", params![id, val])?;
+            self.audit(
+                admin.id,
+                "set_variable",
+                &format!("variable:{}", varialbe),
+                json!(before),
+                json!(val),
+            )?;
+
+            // The current season should be rated by whatever engine is configured
+            if varialbe == "rating_system" || varialbe == "elo_k"
+            {
+                self.set_season_rating_system()?;
+            }
+            if varialbe == "rating_system" || varialbe == "elo_k" || varialbe == "margin_of_victory"
+            {
+                self.roll_back(-1)?;
+            }
+            Ok(())
+        })
     }
 
-    pub fn create_user(&self, new_user: String, password: String) -> ServerResult<String>
+    // Read only connections can not create variables, so every variable is
+    // given its default value up front
+    pub fn create_missing_variables(&self) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            self.get_is_season()?;
+            self.get_season_length()?;
+            for variable in HASHMAP.keys()
+            {
+                self.get_variable(variable.to_string())?;
+            }
+            Ok(())
+        })
+    }
+
+    // How much the score of a match should count, see `margin_multiplier`
+    pub fn get_margin(&self, scores: &str) -> ServerResult<f64>
     {
-        let re = Regex::new(r"^[a-zåA-ZæøåÆØÅ0-9_-]*$").unwrap();
-        if !re.is_match(&new_user) || new_user.is_empty()
+        if self.get_variable("margin_of_victory".to_string())? == 1
         {
-            return Err(ServerError::InvalidUsername);
+            Ok(margin_multiplier(&scores_from_sql(scores)))
         }
-
-        if !self.check_unique_name(&new_user)?
+        else
         {
-            return Err(ServerError::UsernameTaken);
+            Ok(1.0)
         }
+    }
 
-        if self.get_variable("user_conf".to_string())? == 1
+    // A season is rated by the engine stored on it, the variables choose the
+    // engine of new seasons and of the offseason
+    pub fn get_rating_system(&self) -> ServerResult<Box<dyn RatingSystem>>
+    {
+        let (id, k) = match self.get_season_rating_system()?
         {
-            self.create_new_user_notification(new_user, password)?;
-            Ok(" Now you have to wait for an admin to accept..!".to_string())
-        }
-        else
+            Some(system) => system,
+            None => (
+                self.get_variable("rating_system".to_string())?,
+                self.get_variable("elo_k".to_string())?,
+            ),
+        };
+        match RatingSystemType::try_from(id)
         {
-            let password_hash = self.hash(&password);
-            self.create_user_with_password_hash(new_user, password_hash)?;
-            Ok("".to_string())
+            Ok(t) => Ok(t.create(k)),
+            Err(_) => Err(ServerError::Critical(format!("No rating system with id: {}", id))),
         }
     }
 
+    pub fn create_user(&self, new_user: String, password: String) -> ServerResult<String>
+    {
+        self.atomically(|| {
+            if !valid_username(&new_user)
+            {
+                return Err(ServerError::InvalidUsername);
+            }
+
+            if !self.check_unique_name(&new_user)?
+            {
+                return Err(ServerError::UsernameTaken);
+            }
+
+            let target = format!("user:{}", new_user);
+            if self.get_variable("user_conf".to_string())? == 1
+            {
+                self.create_new_user_notification(new_user, password)?;
+                self.audit(0, "create_user", &target, Value::Null, json!({"approved": false}))?;
+                Ok(" Now you have to wait for an admin to accept..!".to_string())
+            }
+            else
+            {
+                let password_hash = hash_password(&password);
+                self.create_user_with_password_hash(new_user, password_hash, HASH_ARGON2ID)?;
+                self.audit(0, "create_user", &target, Value::Null, json!({"approved": true}))?;
+                Ok("".to_string())
+            }
+        })
+    }
+
     pub fn edit_users(
         &self,
         users: Vec<String>,
@@ -239,7 +425,7 @@ impl DataBase
         token: String,
     ) -> ServerResult<usize>
     {
-        self._edit_users(users, action, token)
+        self.atomically(|| self._edit_users(users, action, token))
     }
 
     pub fn get_user(&self, name: &str) -> ServerResult<User>
@@ -274,10 +460,14 @@ impl DataBase
 
     pub fn get_multiple_users(&self, users: Vec<String>, season: Option<u32>) -> ServerResult<Vec<User>>
     {
-        let list = format!("{:?}", users).as_str().replace("[", "(").replace("]", ")");
-        let sql = format!("select id, name, elo, user_role from users where name in {}", list);
+        let list = vec!["?"; users.len()].join(", ");
+        let sql = format!(
+            "select id, name, elo, deviation, volatility, user_role from users where name in ({})",
+            list
+        );
+        let names: Vec<&dyn ToSql> = users.iter().map(|name| name as &dyn ToSql).collect();
 
-        let mut users = self.sql_many::<User, _>(sql, None)?;
+        let mut users = self.sql_many::<User, _>(sql, Some(ParamsType::Params(&names)))?;
         for user in &mut users
         {
             user.match_history = if let Some(season) = season {
@@ -296,24 +486,42 @@ impl DataBase
         token: String,
     ) -> ServerResult<()>
     {
-        if self.get_user_without_matches_by("uuid", "=", &token).is_err()
-        {
-            return Err(ServerError::UserNotExist);
-        };
-
-        let (winner, loser) = (
-            self.get_user_without_matches(&winner_name)?,
-            self.get_user_without_matches(&loser_name)?,
-        );
+        self.register_match_with_scores(winner_name, loser_name, Vec::new(), token)
+    }
 
-        self.create_match_notification(&winner, &loser, token)
+    pub fn register_match_with_scores(
+        &self,
+        winner_name: String,
+        loser_name: String,
+        scores: Vec<GameScore>,
+        token: String,
+    ) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let user = self.get_user_from_token(&token)?;
+            validate_scores(&scores)?;
+
+            let (winner, loser) = (
+                self.get_user_without_matches(&winner_name)?,
+                self.get_user_without_matches(&loser_name)?,
+            );
+
+            self.create_match_notification(&winner, &loser, &scores, token)?;
+            self.audit(
+                user.id,
+                "register_match",
+                &format!("match_notification:{}", self.conn.last_insert_rowid()),
+                Value::Null,
+                json!({"winner": winner_name, "loser": loser_name, "scores": scores}),
+            )
+        })
     }
 
     pub fn get_history(&self) -> ServerResult<Vec<Match>>
     {
         let current_season = self.get_latest_season_number()?;
         let sql = format!(
-            "select a.name as winner, b.name as loser, elo_diff, winner_elo, loser_elo, epoch, {} \
+            "select a.name as winner, b.name as loser, elo_diff, winner_elo, loser_elo, epoch, scores, {} \
              as season from matches
              inner join users as a on a.id = winner
              inner join users as b on b.id = loser
@@ -352,13 +560,13 @@ impl DataBase
     ) -> ServerResult<Vec<Match>>
     {
         let sql = &format!(
-            "select u1.name as winner, u2.name as loser, elo_diff, winner_elo, loser_elo, epoch, \
+            "select u1.name as winner, u2.name as loser, elo_diff, winner_elo, loser_elo, epoch, scores, \
              {} t
              join users u1 on t.winner = u1.id
              join users u2 on t.loser = u2.id
              where t.winner = :user1 and t.loser = :user2
              union
-             select u2.name as winner, u1.name as loser, elo_diff, winner_elo, loser_elo, epoch, \
+             select u2.name as winner, u1.name as loser, elo_diff, winner_elo, loser_elo, epoch, scores, \
              {} t
              join users u1 on t.loser = u1.id
              join users u2 on t.winner = u2.id
@@ -385,49 +593,65 @@ impl DataBase
         new_password: String,
     ) -> ServerResult<()>
     {
-        let sql = "select id, password_hash from users where name = :name;";
-        let mut info = SQL_TUPLE_NAMED!(self, sql, named_params! {":name" : name}, i64, String)?;
-        if info.is_empty()
-        {
-            return Err(ServerError::UserNotExist);
-        }
+        self.atomically(|| {
+            let sql = "select id, password_hash, hash_algorithm from users where name = :name;";
+            let mut info =
+                SQL_TUPLE_NAMED!(self, sql, named_params! {":name" : name}, i64, String, u8)?;
+            if info.is_empty()
+            {
+                return Err(ServerError::UserNotExist);
+            }
 
-        let (id, p) = info.pop().unwrap();
-        if self.hash(&password) == p
-        {
-            return self.update_password(id, new_password);
-        }
+            let (id, p, algorithm) = info.pop().unwrap();
+            if verify_password(&password, &p, algorithm)
+            {
+                self.update_password(id, new_password)?;
+                self.revoke_sessions(id)?;
+                return self.audit(
+                    id,
+                    "change_password",
+                    &format!("user:{}", name),
+                    Value::Null,
+                    Value::Null,
+                );
+            }
 
-        Err(ServerError::PasswordNotMatch)
+            Err(ServerError::PasswordNotMatch)
+        })
     }
 
     pub fn request_reset_password(&self, name: String) -> ServerResult<()>
     {
-        let sql = "select user from reset_password_notification where user = :id;";
-        let user_id = self.get_user_without_matches(&name)?.id;
-        let params = named_params! {":id": user_id};
-        let notification = SQL_TUPLE_NAMED!(self, sql, params, i64)?;
-        if !notification.is_empty()
-        {
-            return Err(ServerError::ResetPasswordDuplicate);
-        }
+        self.atomically(|| {
+            let sql = "select user from reset_password_notification where user = :id;";
+            let user_id = self.get_user_without_matches(&name)?.id;
+            let params = named_params! {":id": user_id};
+            let notification = SQL_TUPLE_NAMED!(self, sql, params, i64)?;
+            if !notification.is_empty()
+            {
+                return Err(ServerError::ResetPasswordDuplicate);
+            }
 
-        self.conn
-            .execute_named("insert into reset_password_notification (user) values (:id)", params)?;
+            self.conn.execute_named(
+                "insert into reset_password_notification (user) values (:id)",
+                params,
+            )?;
 
-        Ok(())
+            let target = format!("user:{}", name);
+            self.audit(0, "request_reset_password", &target, Value::Null, Value::Null)
+        })
     }
 
     pub fn get_match_notifications(&self, token: String) -> ServerResult<Vec<MatchNotification>>
     {
-        let user = self.get_user_without_matches_by("uuid", "=", token.as_str())?;
+        let user = self.get_user_from_token(&token)?;
         let sql = "select * from
-            (select m.id, u1.name as winner, u2.name as loser, epoch from match_notification m
+            (select m.id, u1.name as winner, u2.name as loser, epoch, scores from match_notification m
             join users u1 on m.winner = u1.id
             join users u2 on m.loser = u2.id
             where m.winner = :id and m.winner_accept = 0
             union
-            select m.id, u1.name as winner, u2.name as loser, epoch from match_notification m
+            select m.id, u1.name as winner, u2.name as loser, epoch, scores from match_notification m
             join users u1 on m.winner = u1.id
             join users u2 on m.loser = u2.id
             where m.loser = :id and m.loser_accept = 0)
@@ -457,33 +681,67 @@ impl DataBase
 
     pub fn respond_to_new_user(&self, id: i64, ans: u8, token: String) -> ServerResult<()>
     {
-        if !self.get_is_admin(token)?
-        {
-            return Err(ServerError::Unauthorized);
-        }
-
-        // No match is being accepted, but the ans values are the same Xdd
-        if ans == ACCEPT_REQUEST
-        {
-            self.create_user_from_notification(id)?;
-        }
-        self.delete_new_user_notification(id)?;
-        Ok(())
+        self.atomically(|| {
+            let admin = self.get_admin(&token)?;
+            let name = SQL_TUPLE_NAMED!(
+                self,
+                "select name from new_user_notification where id = :id",
+                named_params! {":id": id},
+                String
+            )?
+            .pop()
+            .map_or(String::new(), |(name,)| name);
+
+            // No match is being accepted, but the ans values are the same Xdd
+            if ans == ACCEPT_REQUEST
+            {
+                self.create_user_from_notification(id)?;
+            }
+            self.delete_new_user_notification(id)?;
+            self.audit(
+                admin.id,
+                "respond_to_new_user",
+                &format!("user:{}", name),
+                Value::Null,
+                json!({ "answer": ans }),
+            )
+        })
     }
 
-    pub fn respond_to_reset_password(&self, id: i64, ans: u8, token: String) -> ServerResult<()>
+    // Returns the temporary password if the reset was accepted, the admin has
+    // to pass it on to the user
+    pub fn respond_to_reset_password(
+        &self,
+        id: i64,
+        ans: u8,
+        token: String,
+    ) -> ServerResult<Option<String>>
     {
-        if !self.get_is_admin(token)?
-        {
-            return Err(ServerError::Unauthorized);
-        }
-
-        if ans == ACCEPT_REQUEST
-        {
-            self.reset_password(id)?;
-        }
-        self.delete_reset_password_notification(id)?;
-        Ok(())
+        self.atomically(|| {
+            let admin = self.get_admin(&token)?;
+            let name = SQL_TUPLE_NAMED!(
+                self,
+                "select u.name from reset_password_notification n join users u on n.user = u.id
+                 where n.id = :id",
+                named_params! {":id": id},
+                String
+            )?
+            .pop()
+            .map_or(String::new(), |(name,)| name);
+
+            let password =
+                if ans == ACCEPT_REQUEST { Some(self.reset_password(id)?) } else { None };
+            self.delete_reset_password_notification(id)?;
+            // The temporary password is not logged
+            self.audit(
+                admin.id,
+                "respond_to_reset_password",
+                &format!("user:{}", name),
+                Value::Null,
+                json!({ "answer": ans }),
+            )?;
+            Ok(password)
+        })
     }
 
     pub fn sql_one<T, S>(&self, s: S, params: Params) -> ServerResult<T>
@@ -502,7 +760,7 @@ impl DataBase
         {
             Some(Ok(res)) => Ok(res),
             Some(Err(e)) => Err(ServerError::Rusqlite(e)),
-            None => Err(ServerError::Critical("Could not find any rows".to_string())),
+            None => Err(ServerError::NotFound),
         }
     }
 
@@ -538,20 +796,43 @@ impl DataBase
             {
                 Ok(Notification::Match(self.get_match_notifications(token)?))
             },
+            NotificationType::Doubles =>
+            {
+                Ok(Notification::Doubles(self.get_doubles_notifications(token)?))
+            },
+            NotificationType::Tournament =>
+            {
+                Ok(Notification::Tournament(self.get_tournament_notifications(token)?))
+            },
         }
     }
 
-    pub fn respond_to_notification(&self, not: NotificationAns) -> ServerResult<()>
+    // Only a reset password answer gives anything back, the temporary password
+    pub fn respond_to_notification(&self, not: NotificationAns) -> ServerResult<Option<String>>
     {
-        match not
+        self.atomically(|| match not
         {
-            NotificationAns::Match(id, token, ans) => self.respond_to_match(id, ans, token),
-            NotificationAns::NewUser(id, token, ans) => self.respond_to_new_user(id, ans, token),
+            NotificationAns::Match(id, token, ans) =>
+            {
+                self.respond_to_match(id, ans, token).map(|_| None)
+            },
+            NotificationAns::Doubles(id, token, ans) =>
+            {
+                self.respond_to_doubles_match(id, ans, token).map(|_| None)
+            },
+            NotificationAns::Tournament(id, token, ans) =>
+            {
+                self.respond_to_tournament_match(id, ans, token).map(|_| None)
+            },
+            NotificationAns::NewUser(id, token, ans) =>
+            {
+                self.respond_to_new_user(id, ans, token).map(|_| None)
+            },
             NotificationAns::ResetPassword(id, token, ans) =>
             {
                 self.respond_to_reset_password(id, ans, token)
             },
-        }
+        })
     }
 }
 
@@ -585,8 +866,9 @@ impl DataBase
     fn create_new_user_notification(&self, name: String, password: String) -> ServerResult<()>
     {
         self.conn.execute(
-            "insert into new_user_notification (name, password_hash) values (?1, ?2)",
-            params![name, self.hash(&password)],
+            "insert into new_user_notification (name, password_hash, hash_algorithm) values (?1, \
+             ?2, ?3)",
+            params![name, hash_password(&password), HASH_ARGON2ID],
         )?;
         Ok(())
     }
@@ -675,15 +957,19 @@ impl DataBase
     fn _edit_users(&self, users: Vec<String>, action: String, token: String)
         -> ServerResult<usize>
     {
-        if !self.get_is_admin(token)?
-        {
-            return Err(ServerError::Unauthorized);
-        }
+        let admin = self.get_admin(&token)?;
+        let action_name = action.clone();
 
         let action = match EditUserAction::from_str(&action)
         {
             Ok(a) => a,
-            _ => return Err(ServerError::Critical("Invalid action".into())),
+            _ =>
+            {
+                return Err(ServerError::InvalidRequest(vec![FieldError::new(
+                    "action",
+                    format!("Invalid action {}", action),
+                )]))
+            },
         };
 
         let func: Box<dyn Fn(String) -> ServerResult<usize>> = match action
@@ -710,11 +996,23 @@ impl DataBase
         let mut errors = Vec::<String>::new();
         for name in users
         {
-            let res = func(name);
+            let before = self.get_user_without_matches(&name).map(|u| u.user_role);
+            let res = func(name.clone());
             if res.is_err()
             {
                 errors.push(format!("{:?}", res.unwrap_err()));
             }
+            else if let Ok(before) = before
+            {
+                let after = self.get_user_without_matches(&name)?.user_role;
+                self.audit(
+                    admin.id,
+                    "edit_users",
+                    &format!("user:{}", name),
+                    json!({ "user_role": before }),
+                    json!({"user_role": after, "action": action_name}),
+                )?;
+            }
         }
 
         if !errors.is_empty()
@@ -763,29 +1061,38 @@ impl DataBase
     {
         let user = SQL_TUPLE_NAMED!(
             self,
-            "select name, password_hash from new_user_notification where id = :id",
+            "select name, password_hash, hash_algorithm from new_user_notification where id = :id",
             named_params! {":id": id},
             String,
-            String
+            String,
+            u8
         )?;
 
         if user.is_empty()
         {
             return Err(ServerError::Critical("err".into())); // Should not be possible to reach this
         }
-        self.create_user_with_password_hash(user[0].0.clone(), user[0].1.clone())
+        self.create_user_with_password_hash(user[0].0.clone(), user[0].1.clone(), user[0].2)
     }
 
     fn create_user_with_password_hash(
         &self,
         new_user: String,
         password_hash: String,
+        hash_algorithm: u8,
     ) -> ServerResult<String>
     {
         let uuid = format!("{}", Uuid::new_v4());
         self.conn.execute(
-            "insert into users (name, password_hash, uuid, user_role) values (?1, ?2, ?3, ?4)",
-            params![new_user, password_hash, uuid, USER_ROLE_SOFT_INACTIVE | USER_ROLE_REGULAR],
+            "insert into users (name, password_hash, hash_algorithm, uuid, user_role) values (?1, \
+             ?2, ?3, ?4, ?5)",
+            params![
+                new_user,
+                password_hash,
+                hash_algorithm,
+                uuid,
+                USER_ROLE_SOFT_INACTIVE | USER_ROLE_REGULAR
+            ],
         )?;
         Ok(uuid)
     }
@@ -865,39 +1172,74 @@ impl DataBase
         loser: &User,
     ) -> ServerResult<()>
     {
-        let elo = EloRank {
-            k: 32
-        };
-        let (new_winner_elo, new_loser_elo) = elo.calculate(winner.elo, loser.elo);
+        self.create_rated_match(m.epoch, winner, loser, &m.scores)?;
+        Ok(())
+    }
+
+    // Stores the match and updates the ratings of both players, returns the id
+    // of the match
+    pub(crate) fn create_rated_match(
+        &self,
+        epoch: i64,
+        winner: &User,
+        loser: &User,
+        scores: &str,
+    ) -> ServerResult<i64>
+    {
+        let rating_system = self.get_rating_system()?;
+        let (new_winner, new_loser) = rating_system.rate_by_margin(
+            &self.get_rating(winner)?,
+            &self.get_rating(loser)?,
+            self.get_margin(scores)?,
+        );
 
 
         self.conn.execute(
-            "insert into matches (epoch, winner, loser, elo_diff, winner_elo, loser_elo)
-            values (?1, ?2, ?3, ?4, ?5, ?6)",
+            "insert into matches (epoch, winner, loser, elo_diff, winner_elo, loser_elo,
+            winner_deviation, loser_deviation, winner_volatility, loser_volatility, scores)
+            values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
             params![
-                m.epoch,
-                m.winner,
-                m.loser,
-                new_winner_elo - winner.elo,
-                new_winner_elo,
-                new_loser_elo
+                epoch,
+                winner.id,
+                loser.id,
+                new_winner.rating - winner.elo,
+                new_winner.rating,
+                new_loser.rating,
+                new_winner.deviation,
+                new_loser.deviation,
+                new_winner.volatility,
+                new_loser.volatility,
+                scores
             ],
         )?;
+        let id = self.conn.last_insert_rowid();
 
 
-        if self.need_to_roll_back(m.epoch)?
+        if self.need_to_roll_back(epoch)?
         {
-            self.roll_back(m.epoch)?;
+            self.roll_back(epoch)?;
             self.make_user_active(winner.name.clone())?;
             self.make_user_active(loser.name.clone())?;
-            return Ok(());
+            return Ok(id);
         }
 
-        self.update_elo(winner.id, new_winner_elo)?;
-        self.update_elo(loser.id, new_loser_elo)?;
+        self.update_rating(winner.id, &new_winner)?;
+        self.update_rating(loser.id, &new_loser)?;
         self.make_user_active(winner.name.clone())?;
         self.make_user_active(loser.name.clone())?;
-        Ok(())
+        Ok(id)
+    }
+
+    fn get_rating(&self, user: &User) -> ServerResult<Rating>
+    {
+        let sql = "select count(*) from matches where winner = :id or loser = :id";
+        let count = SQL_TUPLE_NAMED!(self, sql, named_params! {":id": user.id}, i64)?;
+        Ok(Rating {
+            rating:     user.elo,
+            deviation:  user.deviation,
+            volatility: user.volatility,
+            games:      count[0].0,
+        })
     }
 
     fn need_to_roll_back(&self, epoch: i64) -> ServerResult<bool>
@@ -911,30 +1253,33 @@ impl DataBase
         &self,
         winner: &User,
         loser: &User,
+        scores: &[GameScore],
         token: String,
     ) -> ServerResult<()>
     {
+        let scores = scores_to_sql(scores);
         if self.user_have_token(winner.id, &token)?
         {
             self.conn.execute(
-                "insert into match_notification (epoch, winner, loser, winner_accept) values (?1, \
-                 ?2, ?3, ?4)",
-                params![self.epoch(), winner.id, loser.id, ACCEPT_REQUEST],
+                "insert into match_notification (epoch, winner, loser, scores, winner_accept) \
+                 values (?1, ?2, ?3, ?4, ?5)",
+                params![self.epoch(), winner.id, loser.id, scores, ACCEPT_REQUEST],
             )?;
         }
         else if self.user_have_token(loser.id, &token)?
         {
             self.conn.execute(
-                "insert into match_notification (epoch, winner, loser,  loser_accept) values (?1, \
-                 ?2, ?3, ?4)",
-                params![self.epoch(), winner.id, loser.id, ACCEPT_REQUEST],
+                "insert into match_notification (epoch, winner, loser, scores, loser_accept) \
+                 values (?1, ?2, ?3, ?4, ?5)",
+                params![self.epoch(), winner.id, loser.id, scores, ACCEPT_REQUEST],
             )?;
         }
         else
         {
             self.conn.execute(
-                "insert into match_notification (epoch, winner, loser) values (?1, ?2, ?3)",
-                params![self.epoch(), winner.id, loser.id],
+                "insert into match_notification (epoch, winner, loser, scores) values (?1, ?2, \
+                 ?3, ?4)",
+                params![self.epoch(), winner.id, loser.id, scores],
             )?;
         }
         Ok(())
@@ -942,21 +1287,33 @@ impl DataBase
 
     fn user_have_token(&self, user_id: i64, token: &str) -> ServerResult<bool>
     {
-        let sql = "select count(*) from users where id = :id and uuid = :token";
+        let sql = "select count(*) from sessions where user = :id and token = :token";
         let c = SQL_TUPLE_NAMED!(self, sql, named_params! {":id": user_id, ":token": token}, i64)?;
         Ok(c[0].0 == 1)
     }
 
     fn update_password(&self, id: i64, new_password: String) -> ServerResult<()>
     {
-        let hash = self.hash(&new_password);
-        let mut stmt =
-            self.conn.prepare("update users  set password_hash = :hash WHERE id = :id")?;
-        stmt.execute_named(named_params! {":hash": hash, ":id": id})?;
+        self.set_password_hash(id, hash_password(&new_password), false)
+    }
+
+    fn set_password_hash(&self, id: i64, hash: String, must_change: bool) -> ServerResult<()>
+    {
+        let mut stmt = self.conn.prepare(
+            "update users set password_hash = :hash, hash_algorithm = :algorithm,
+             must_change_password = :must_change WHERE id = :id",
+        )?;
+        stmt.execute_named(named_params! {
+            ":hash": hash,
+            ":algorithm": HASH_ARGON2ID,
+            ":must_change": must_change,
+            ":id": id
+        })?;
         Ok(())
     }
 
-    fn reset_password(&self, id: i64) -> ServerResult<()>
+    // The user gets a random password that has to be changed at the next login
+    fn reset_password(&self, id: i64) -> ServerResult<String>
     {
         let sql = "select user from reset_password_notification where id = :id;";
         let user_id = SQL_TUPLE_NAMED!(self, sql, named_params! {":id" : id}, i64)?;
@@ -964,34 +1321,30 @@ impl DataBase
         {
             return Err(ServerError::Critical("Something went wrong".into())); // Should not be possible to reach this
         }
-        let user_id = user_id[0].0;
-        let hash = self.hash(&"@uit".to_string());
-        self.conn.execute_named(
-            "update users set password_hash = :hash where id = :id",
-            named_params! {":hash": hash, ":id": user_id},
-        )?;
-        Ok(())
-    }
-
-    fn hash(&self, word: &str) -> String
-    {
-        use sha2::{Digest, Sha256};
-        let mut hasher = Sha256::new();
-        hasher.update(word);
-        let result = hasher.finalize();
-        format!("{:x}", result)
+        let password = temporary_password();
+        self.set_password_hash(user_id[0].0, hash_password(&password), true)?;
+        self.revoke_sessions(user_id[0].0)?;
+        Ok(password)
     }
 
-    fn update_elo(&self, id: i64, elo: f64) -> ServerResult<()>
+    pub(crate) fn update_rating(&self, id: i64, rating: &Rating) -> ServerResult<()>
     {
-        let mut stmt = self.conn.prepare("update users set elo = :elo WHERE id = :id")?;
-        stmt.execute_named(named_params! {":elo": elo, ":id": id})?;
+        let mut stmt = self.conn.prepare_cached(
+            "update users set elo = :elo, deviation = :deviation, volatility = :volatility
+             WHERE id = :id",
+        )?;
+        stmt.execute_named(named_params! {
+            ":elo": rating.rating,
+            ":deviation": rating.deviation,
+            ":volatility": rating.volatility,
+            ":id": id
+        })?;
         Ok(())
     }
 
     fn get_matches_from_season(&self, id: i64, season: u32) -> ServerResult<Vec<Match>> {
         let sql = format!(
-            "select a.name as winner, b.name as loser, elo_diff, winner_elo, loser_elo, epoch, {} \
+            "select a.name as winner, b.name as loser, elo_diff, winner_elo, loser_elo, epoch, scores, {} \
              as season
                 from old_matches
                 inner join users as a on a.id = winner
@@ -1006,7 +1359,7 @@ impl DataBase
     {
         let current_season = self.get_latest_season_number()?;
         let sql = format!(
-            "select a.name as winner, b.name as loser, elo_diff, winner_elo, loser_elo, epoch, {} \
+            "select a.name as winner, b.name as loser, elo_diff, winner_elo, loser_elo, epoch, scores, {} \
              as season
                 from matches
                 inner join users as a on a.id = winner
@@ -1031,7 +1384,9 @@ impl DataBase
             let season: i64 = row.get(1)?;
             let tooltip = format!("Season: {}", season);
             Ok(Badge {
-                id: row.get(0)?, tooltip: tooltip, name: BADGES[index].to_string()
+                id: row.get(0)?,
+                tooltip,
+                name: BADGES[index].to_string(),
             })
         })?;
 
@@ -1049,6 +1404,7 @@ impl DataBase
                 id: image.id, tooltip: tournament_name, name: image.name
             });
         }
+        vec.extend(self.get_placement_badges(pid)?);
         Ok(vec)
     }
 
@@ -1060,7 +1416,7 @@ impl DataBase
 
     fn get_users_with_user_role(&self, user_role: u8, val: u8) -> ServerResult<Vec<User>>
     {
-        let sql = "select id, name, elo, user_role from users
+        let sql = "select id, name, elo, deviation, volatility, user_role from users
              where user_role & :user_role = :val
              order by elo desc";
 
@@ -1085,13 +1441,15 @@ impl DataBase
         val: &str,
     ) -> ServerResult<User>
     {
-        let sql =
-            &format!("select id, name, elo, user_role from users where {} {} :val", col, comp);
+        let sql = &format!(
+            "select id, name, elo, deviation, volatility, user_role from users where {} {} :val",
+            col, comp
+        );
 
 
         self.sql_one(sql, _named_params! {":val": val}).map_err(|e| match e
         {
-            ServerError::Critical(_) => ServerError::UserNotExist,
+            ServerError::NotFound => ServerError::UserNotExist,
             _ => e,
         })
     }
@@ -1112,7 +1470,7 @@ impl DataBase
         reader.read_line(&mut buffer).expect("Reading line");
 
         let mut hasher = Sha256::new();
-        hasher.update(&buffer.trim_end_matches('\n'));
+        hasher.update(buffer.trim_end_matches('\n'));
         let result = hasher.finalize();
         format!("{:x}", result)
     }
@@ -1133,7 +1491,7 @@ mod test
     };
 
     use super::*;
-    use crate::{test_util::*, SQL_TUPLE};
+    use crate::{test_util::*, user::MultipleUsersInfo, validate::Validate, SQL_TUPLE};
 
 
     #[test]
@@ -1398,6 +1756,57 @@ mod test
         assert!(err.is_err() && (uuid.is_ok() && uuid.unwrap().len() == 36));
     }
 
+    #[test]
+    fn test_login_upgrades_old_password_hash()
+    {
+        let db_file = "temp8A.db";
+        let s = DataBase::new(db_file);
+        let siv = "Sivert".to_string();
+        create_user(&s, siv.as_str());
+
+        let first = s.login(siv.clone(), "password".to_string());
+        let (hash, algorithm): (String, u8) = s
+            .conn
+            .query_row(
+                "select password_hash, hash_algorithm from users where name = ?1",
+                params![siv],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        let second = s.login(siv.clone(), "password".to_string());
+        let wrong = s.login(siv, "passwork".to_string());
+
+        std::fs::remove_file(db_file).expect("Removing file temp8A");
+        assert!(first.is_ok());
+        assert_eq!(algorithm, HASH_ARGON2ID);
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(second.is_ok());
+        assert_ne!(first, second);
+        assert!(wrong.is_err());
+    }
+
+    #[test]
+    fn test_required_password_change_keeps_the_upgraded_hash()
+    {
+        let db_file = "temp8B.db";
+        let s = DataBase::new(db_file);
+        create_user(&s, "Sivert");
+        s.conn.execute("update users set must_change_password = 1", NO_PARAMS).unwrap();
+
+        let login = s.login("Sivert".to_string(), "password".to_string());
+        let (algorithm, must_change): (u8, bool) = s
+            .conn
+            .query_row("select hash_algorithm, must_change_password from users", NO_PARAMS, |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file temp8B");
+        assert!(matches!(login, Err(ServerError::PasswordChangeRequired)));
+        assert_eq!(algorithm, HASH_ARGON2ID);
+        assert!(must_change);
+    }
+
     #[test]
     fn test_unix_time_in_ms()
     {
@@ -1479,6 +1888,77 @@ mod test
         assert_eq!(bernt_user.elo, 1517.4577607770875);
     }
 
+    #[test]
+    fn test_changing_rating_system_replays_season()
+    {
+        let db_file = "tempC1.db";
+        let s = DataBase::new(db_file);
+
+        let siv = "Sivert".to_string();
+        let lars = "Lars".to_string();
+        let token = create_user(&s, siv.as_str());
+        create_user(&s, lars.as_str());
+        s.make_user_admin(siv.clone()).unwrap();
+
+        s.register_match(siv.clone(), lars.clone(), token.clone()).unwrap();
+        respond_to_match(&s, lars.as_str(), 1);
+        let k32 = s.get_user_without_matches(&siv).unwrap().elo;
+
+        s.set_variable(token.clone(), "elo_k".to_string(), 16).unwrap();
+        let k16 = s.get_user_without_matches(&siv).unwrap().elo;
+
+        s.set_variable(token.clone(), "rating_system".to_string(), 2).unwrap();
+        let glicko = s.get_user_without_matches(&siv).unwrap().elo;
+        let history = s.get_history().unwrap();
+
+        let invalid = s.set_variable(token.clone(), "rating_system".to_string(), 42);
+        let zero_k = s.set_variable(token.clone(), "elo_k".to_string(), 0);
+        let huge_k = s.set_variable(token.clone(), "elo_k".to_string(), MAX_K + 1);
+        let unknown = s.set_variable(token, "not_a_variable".to_string(), 1);
+
+        std::fs::remove_file(db_file).expect("Removing file tempC1");
+        assert_eq!(k32, 1516.0);
+        assert_eq!(k16, 1508.0);
+        assert!(glicko > k32);
+        assert_eq!(history[0].winner_elo, glicko);
+        assert_eq!(invalid.unwrap_err().details()[0].field, "new_val");
+        assert_eq!(zero_k.unwrap_err().details()[0].field, "new_val");
+        assert_eq!(huge_k.unwrap_err().details()[0].field, "new_val");
+        assert_eq!(unknown.unwrap_err().details()[0].field, "variable");
+    }
+
+    #[test]
+    fn test_match_scores_are_stored_and_weigh_rating()
+    {
+        let db_file = "tempC2.db";
+        let s = DataBase::new(db_file);
+
+        let siv = "Sivert".to_string();
+        let lars = "Lars".to_string();
+        let token = create_user(&s, siv.as_str());
+        create_user(&s, lars.as_str());
+        s.make_user_admin(siv.clone()).unwrap();
+
+        let invalid =
+            s.register_match_with_scores(siv.clone(), lars.clone(), vec![[11, 10]], token.clone());
+
+        let scores = vec![[11, 9], [11, 4], [11, 2]];
+        s.register_match_with_scores(siv.clone(), lars.clone(), scores.clone(), token.clone())
+            .unwrap();
+        respond_to_match(&s, lars.as_str(), 1);
+        let plain = s.get_user_without_matches(&siv).unwrap().elo;
+
+        s.set_variable(token, "margin_of_victory".to_string(), 1).unwrap();
+        let weighted = s.get_user_without_matches(&siv).unwrap().elo;
+        let history = s.get_history().unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempC2");
+        assert!(matches!(invalid, Err(ServerError::InvalidScore)));
+        assert_eq!(scores_from_sql(&history[0].scores), scores);
+        assert_eq!(plain, 1516.0);
+        assert_eq!(weighted, 1524.0);
+    }
+
     #[test]
     fn test_case_sensitive_user_search()
     {
@@ -1680,6 +2160,30 @@ mod test
         assert_eq!(m.len(), 0);
     }
 
+    #[test]
+    fn test_failed_match_response_changes_nothing()
+    {
+        let db_file = "tempJ5.db";
+        let s = DataBase::new(db_file);
+        let uuid = create_user(&s, "Sivert");
+        let lars = create_user(&s, "Lars");
+
+        s.register_match("Sivert".to_string(), "Lars".to_string(), uuid)
+            .expect("Creating match");
+        // The notification is deleted after the match is created
+        fail_on(&s, "delete", "match_notification");
+        let res = s.respond_to_match(1, ACCEPT_REQUEST, lars);
+        let matches = get_table_size(&s, "matches");
+        let notifications = get_table_size(&s, "match_notification");
+        let winner = s.get_user(&"Sivert".to_string()).unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempJ5");
+        assert!(res.is_err());
+        assert_eq!(matches, 0);
+        assert_eq!(notifications, 1);
+        assert_eq!(winner.elo, 1500.);
+    }
+
     #[test]
     fn test_can_get_multiple_users()
     {
@@ -1695,6 +2199,27 @@ mod test
         users.into_iter().for_each(|u| assert!(vec.contains(&u.name)));
     }
 
+    #[test]
+    fn test_multiple_users_names_are_not_sql()
+    {
+        let db_file = "tempJ6.db";
+        let s = DataBase::new(db_file);
+        create_user(&s, "Sivert");
+        create_user(&s, "Lars");
+
+        let names = vec!["Lars".to_string(), "Sivert\") or (\"1\" = \"1".to_string()];
+        let info = MultipleUsersInfo {
+            users: names.clone(), season: None
+        };
+        let invalid = info.validate();
+        let users = s.get_multiple_users(names, None).unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempJ6");
+        assert!(matches!(invalid, Err(ServerError::InvalidRequest(_))));
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].name, "Lars");
+    }
+
     #[test]
     fn test_reset_password_creates_notification()
     {
@@ -1723,11 +2248,16 @@ mod test
         s.request_reset_password(user.clone()).unwrap();
 
         let not = NotificationAns::ResetPassword(1, token, ACCEPT_REQUEST);
-        s.respond_to_notification(not).unwrap();
-        let res = s.login(user, "@uit".to_string());
+        let temporary = s.respond_to_notification(not).unwrap().unwrap();
+        let old = s.login(user.clone(), "password".to_string());
+        let first = s.login(user.clone(), temporary.clone());
+        s.change_password(user.clone(), temporary, "new password".to_string()).unwrap();
+        let res = s.login(user, "new password".to_string());
 
         std::fs::remove_file(db_file).expect("Removing file tempH");
 
+        assert_eq!(old, Err(ServerError::WrongUsernameOrPassword));
+        assert_eq!(first, Err(ServerError::PasswordChangeRequired));
         assert!(res.is_ok());
     }
 
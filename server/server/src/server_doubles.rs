@@ -0,0 +1,560 @@
+use std::collections::HashMap;
+
+use rusqlite::{named_params, params, NO_PARAMS};
+use serde_json::{json, Value};
+use server_core::{
+    constants::*,
+    types::{ServerError, ServerResult},
+};
+
+use super::{
+    _named_params,
+    doubles::{DoublesMatch, DoublesPlayer, Pair},
+    notification::{DoublesNotification, DoublesNotificationTable},
+    rating::{Rating, RatingSystem},
+    score::*,
+    server::{DataBase, ParamsType},
+};
+use crate::{SQL_TUPLE, SQL_TUPLE_NAMED};
+
+// Doubles are rated on their own, every player has a doubles rating and every
+// pair that has played together has a rating as a team. Doubles are not part
+// of the seasons, so the ratings carry over from season to season.
+impl DataBase
+{
+    pub fn register_doubles_match(
+        &self,
+        winners: [String; 2],
+        losers: [String; 2],
+        scores: Vec<GameScore>,
+        token: String,
+    ) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let user = self.get_user_from_token(&token)?;
+            validate_scores(&scores)?;
+
+            let mut ids = Vec::new();
+            for name in winners.iter().chain(losers.iter())
+            {
+                ids.push(self.get_user_without_matches(name)?.id);
+            }
+
+            for (i, id) in ids.iter().enumerate()
+            {
+                if ids[i + 1..].contains(id)
+                {
+                    return Err(ServerError::InvalidTeam);
+                }
+            }
+
+            // The player registering the match has already accepted it
+            let accept = |id: i64| if id == user.id { ACCEPT_REQUEST } else { 0 };
+            self.conn.execute(
+                "insert into doubles_notification (epoch, winner1, winner2, loser1, loser2, \
+                 winner1_accept, winner2_accept, loser1_accept, loser2_accept, scores)
+                 values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    self.epoch(),
+                    ids[0],
+                    ids[1],
+                    ids[2],
+                    ids[3],
+                    accept(ids[0]),
+                    accept(ids[1]),
+                    accept(ids[2]),
+                    accept(ids[3]),
+                    scores_to_sql(&scores)
+                ],
+            )?;
+            self.audit(
+                user.id,
+                "register_doubles_match",
+                &format!("doubles_notification:{}", self.conn.last_insert_rowid()),
+                Value::Null,
+                json!({"winners": winners, "losers": losers, "scores": scores}),
+            )
+        })
+    }
+
+    pub fn respond_to_doubles_match(&self, id: i64, ans: u8, token: String) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let user = self.get_user_from_token(&token)?;
+            let sql = "select id, winner1_accept, winner2_accept, loser1_accept, loser2_accept,
+                       epoch, winner1, winner2, loser1, loser2, scores
+                      from doubles_notification where id = :id";
+
+            let mut n: DoublesNotificationTable = self.sql_one(sql, _named_params! {":id": id})?;
+
+            let col = if user.id == n.winner1
+            {
+                n.winner1_accept = ans;
+                "winner1_accept"
+            }
+            else if user.id == n.winner2
+            {
+                n.winner2_accept = ans;
+                "winner2_accept"
+            }
+            else if user.id == n.loser1
+            {
+                n.loser1_accept = ans;
+                "loser1_accept"
+            }
+            else if user.id == n.loser2
+            {
+                n.loser2_accept = ans;
+                "loser2_accept"
+            }
+            else
+            {
+                return Err(ServerError::Unauthorized);
+            };
+
+            let accepts = [n.winner1_accept, n.winner2_accept, n.loser1_accept, n.loser2_accept];
+            if accepts.iter().all(|&a| a == ACCEPT_REQUEST)
+            {
+                self.create_doubles_match_from_notification(&n)?;
+                self.conn
+                    .execute("delete from doubles_notification where id = ?1", params![n.id])?;
+            }
+            else
+            {
+                self.conn.execute(
+                    &format!("update doubles_notification set {} = ?1 where id = ?2", col),
+                    params![ans, n.id],
+                )?;
+            }
+            self.audit(
+                user.id,
+                "respond_to_doubles_match",
+                &format!("doubles_notification:{}", id),
+                Value::Null,
+                json!({ "answer": ans }),
+            )
+        })
+    }
+
+    pub fn get_doubles_notifications(&self, token: String)
+        -> ServerResult<Vec<DoublesNotification>>
+    {
+        let user = self.get_user_from_token(&token)?;
+        let sql = "select m.id, a.name as winner1, b.name as winner2, c.name as loser1,
+            d.name as loser2, epoch, scores from doubles_notification m
+            join users a on m.winner1 = a.id
+            join users b on m.winner2 = b.id
+            join users c on m.loser1 = c.id
+            join users d on m.loser2 = d.id
+            where (m.winner1 = :id and m.winner1_accept = 0)
+            or (m.winner2 = :id and m.winner2_accept = 0)
+            or (m.loser1 = :id and m.loser1_accept = 0)
+            or (m.loser2 = :id and m.loser2_accept = 0)
+            order by epoch";
+
+        self.sql_many(sql, _named_params! {":id": user.id})
+    }
+
+    pub fn get_doubles_history(&self) -> ServerResult<Vec<DoublesMatch>>
+    {
+        let sql = "select a.name as winner1, b.name as winner2, c.name as loser1,
+            d.name as loser2, epoch, elo_diff, winner_elo, loser_elo, winner1_elo, winner2_elo,
+            loser1_elo, loser2_elo, scores from doubles_matches m
+            join users a on m.winner1 = a.id
+            join users b on m.winner2 = b.id
+            join users c on m.loser1 = c.id
+            join users d on m.loser2 = d.id
+            order by epoch desc";
+
+        self.sql_many(sql, None)
+    }
+
+    pub fn get_doubles_pairs(&self) -> ServerResult<Vec<Pair>>
+    {
+        let sql = "select a.name as player1, b.name as player2, p.elo, p.deviation, p.games
+            from pairs p
+            join users a on p.player1 = a.id
+            join users b on p.player2 = b.id
+            order by p.elo desc";
+
+        self.sql_many(sql, None)
+    }
+
+    pub fn get_doubles_players(&self) -> ServerResult<Vec<DoublesPlayer>>
+    {
+        let sql = format!(
+            "select u.name, r.elo, r.deviation, r.games from doubles_ratings r
+            join users u on r.user = u.id
+            where u.user_role & {} = 0
+            order by r.elo desc",
+            USER_ROLE_INACTIVE
+        );
+
+        self.sql_many(sql, None)
+    }
+
+    // Rates every doubles match again from the start, in the order they were
+    // played
+    pub fn replay_doubles(&self) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let rating_system = self.get_rating_system()?;
+            let matches = SQL_TUPLE!(
+                self,
+                "select id, winner1, winner2, loser1, loser2, scores from doubles_matches
+                 order by epoch",
+                i64,
+                i64,
+                i64,
+                i64,
+                i64,
+                String
+            )?;
+
+            let mut players: HashMap<i64, Rating> = HashMap::new();
+            let mut pairs: HashMap<(i64, i64), Rating> = HashMap::new();
+
+            for (id, w1, w2, l1, l2, scores) in matches
+            {
+                let get = |id: i64| players.get(&id).copied().unwrap_or_default();
+                let before = [get(w1), get(w2), get(l1), get(l2)];
+                let winner_pair = pairs.get(&pair_key(w1, w2)).copied().unwrap_or_default();
+                let loser_pair = pairs.get(&pair_key(l1, l2)).copied().unwrap_or_default();
+
+                let (after, new_winner_pair, new_loser_pair) = rate_doubles(
+                    rating_system.as_ref(),
+                    self.get_margin(&scores)?,
+                    before,
+                    winner_pair,
+                    loser_pair,
+                );
+
+                self.update_doubles_match(
+                    id,
+                    &after,
+                    &new_winner_pair,
+                    &new_loser_pair,
+                    winner_pair,
+                )?;
+                for (player, rating) in [w1, w2, l1, l2].iter().zip(after.iter())
+                {
+                    players.insert(*player, *rating);
+                }
+                pairs.insert(pair_key(w1, w2), new_winner_pair);
+                pairs.insert(pair_key(l1, l2), new_loser_pair);
+            }
+
+            self.conn.execute("delete from doubles_ratings", NO_PARAMS)?;
+            self.conn.execute("delete from pairs", NO_PARAMS)?;
+            for (id, rating) in players
+            {
+                self.update_doubles_rating(id, &rating)?;
+            }
+            for (key, rating) in pairs
+            {
+                self.update_pair_rating(key, &rating)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+
+// ~ private doubles functions
+impl DataBase
+{
+    fn create_doubles_match_from_notification(&self, n: &DoublesNotificationTable)
+        -> ServerResult<()>
+    {
+        let rating_system = self.get_rating_system()?;
+        let before = [
+            self.get_doubles_rating(n.winner1)?,
+            self.get_doubles_rating(n.winner2)?,
+            self.get_doubles_rating(n.loser1)?,
+            self.get_doubles_rating(n.loser2)?,
+        ];
+        let winner_pair = self.get_pair_rating(pair_key(n.winner1, n.winner2))?;
+        let loser_pair = self.get_pair_rating(pair_key(n.loser1, n.loser2))?;
+
+        let (after, new_winner_pair, new_loser_pair) = rate_doubles(
+            rating_system.as_ref(),
+            self.get_margin(&n.scores)?,
+            before,
+            winner_pair,
+            loser_pair,
+        );
+
+        self.conn.execute(
+            "insert into doubles_matches (epoch, winner1, winner2, loser1, loser2, scores)
+             values (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![n.epoch, n.winner1, n.winner2, n.loser1, n.loser2, n.scores],
+        )?;
+        let id = self.conn.last_insert_rowid();
+
+        // A match that was played before other doubles matches changes the
+        // ratings going into those, so everything has to be rated again
+        let sql = "select count(*) from doubles_matches where epoch > :epoch";
+        let later = SQL_TUPLE_NAMED!(self, sql, named_params! {":epoch": n.epoch}, i64)?;
+        if later[0].0 > 0
+        {
+            return self.replay_doubles();
+        }
+
+        self.update_doubles_match(id, &after, &new_winner_pair, &new_loser_pair, winner_pair)?;
+        for (player, rating) in [n.winner1, n.winner2, n.loser1, n.loser2].iter().zip(after.iter())
+        {
+            self.update_doubles_rating(*player, rating)?;
+        }
+        self.update_pair_rating(pair_key(n.winner1, n.winner2), &new_winner_pair)?;
+        self.update_pair_rating(pair_key(n.loser1, n.loser2), &new_loser_pair)?;
+        Ok(())
+    }
+
+    fn get_doubles_rating(&self, id: i64) -> ServerResult<Rating>
+    {
+        let sql = "select elo, deviation, volatility, games from doubles_ratings where user = :id";
+        let rating = SQL_TUPLE_NAMED!(self, sql, named_params! {":id": id}, f64, f64, f64, i64)?;
+        Ok(rating_from_row(rating.first()))
+    }
+
+    fn get_pair_rating(&self, (player1, player2): (i64, i64)) -> ServerResult<Rating>
+    {
+        let sql = "select elo, deviation, volatility, games from pairs
+                   where player1 = :player1 and player2 = :player2";
+        let rating = SQL_TUPLE_NAMED!(
+            self,
+            sql,
+            named_params! {":player1": player1, ":player2": player2},
+            f64,
+            f64,
+            f64,
+            i64
+        )?;
+        Ok(rating_from_row(rating.first()))
+    }
+
+    fn update_doubles_rating(&self, id: i64, rating: &Rating) -> ServerResult<()>
+    {
+        self.conn.execute(
+            "replace into doubles_ratings (user, elo, deviation, volatility, games)
+             values (?1, ?2, ?3, ?4, ?5)",
+            params![id, rating.rating, rating.deviation, rating.volatility, rating.games],
+        )?;
+        Ok(())
+    }
+
+    fn update_pair_rating(&self, (player1, player2): (i64, i64), rating: &Rating)
+        -> ServerResult<()>
+    {
+        self.conn.execute(
+            "replace into pairs (player1, player2, elo, deviation, volatility, games)
+             values (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                player1,
+                player2,
+                rating.rating,
+                rating.deviation,
+                rating.volatility,
+                rating.games
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn update_doubles_match(
+        &self,
+        id: i64,
+        players: &[Rating; 4],
+        winner_pair: &Rating,
+        loser_pair: &Rating,
+        old_winner_pair: Rating,
+    ) -> ServerResult<()>
+    {
+        self.conn.execute(
+            "update doubles_matches set elo_diff = ?1, winner_elo = ?2, loser_elo = ?3,
+             winner1_elo = ?4, winner2_elo = ?5, loser1_elo = ?6, loser2_elo = ?7
+             where id = ?8",
+            params![
+                winner_pair.rating - old_winner_pair.rating,
+                winner_pair.rating,
+                loser_pair.rating,
+                players[0].rating,
+                players[1].rating,
+                players[2].rating,
+                players[3].rating,
+                id
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+// Pairs are stored with the lowest user id first, so that a pair is the same
+// no matter who is entered first
+fn pair_key(a: i64, b: i64) -> (i64, i64)
+{
+    if a < b { (a, b) } else { (b, a) }
+}
+
+fn rating_from_row(row: Option<&(f64, f64, f64, i64)>) -> Rating
+{
+    match row
+    {
+        Some(&(rating, deviation, volatility, games)) => Rating {
+            rating,
+            deviation,
+            volatility,
+            games,
+        },
+        None => Rating::default(),
+    }
+}
+
+// Each player is rated against the other team as a whole, the pairs are rated
+// against each other like in a singles match
+fn rate_doubles(
+    rating_system: &dyn RatingSystem,
+    margin: f64,
+    players: [Rating; 4],
+    winner_pair: Rating,
+    loser_pair: Rating,
+) -> ([Rating; 4], Rating, Rating)
+{
+    let [w1, w2, l1, l2] = players;
+    let winners = Rating::team(&w1, &w2);
+    let losers = Rating::team(&l1, &l2);
+
+    let (new_winner_pair, new_loser_pair) =
+        rating_system.rate_by_margin(&winner_pair, &loser_pair, margin);
+    (
+        [
+            rating_system.rate_by_margin(&w1, &losers, margin).0,
+            rating_system.rate_by_margin(&w2, &losers, margin).0,
+            rating_system.rate_by_margin(&winners, &l1, margin).1,
+            rating_system.rate_by_margin(&winners, &l2, margin).1,
+        ],
+        new_winner_pair,
+        new_loser_pair,
+    )
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use rusqlite::NO_PARAMS;
+
+    use super::*;
+    use crate::test_util::*;
+
+    // Returns the tokens of Sivert, Lars, Markus and Ella
+    fn create_players(s: &DataBase) -> Vec<String>
+    {
+        ["Sivert", "Lars", "Markus", "Ella"].iter().map(|name| create_user(s, name)).collect()
+    }
+
+    fn register(s: &DataBase, token: &str) -> i64
+    {
+        s.register_doubles_match(
+            ["Sivert".to_string(), "Lars".to_string()],
+            ["Markus".to_string(), "Ella".to_string()],
+            vec![],
+            token.to_string(),
+        )
+        .unwrap();
+        s.conn
+            .query_row("select max(id) from doubles_notification", NO_PARAMS, |row| row.get(0))
+            .unwrap()
+    }
+
+    fn respond(s: &DataBase, token: &str, id: i64)
+    {
+        s.respond_to_doubles_match(id, ACCEPT_REQUEST, token.to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_doubles_match_needs_all_players_to_accept()
+    {
+        let db_file = "tempD1.db";
+        let s = DataBase::new(db_file);
+        let tokens = create_players(&s);
+
+        let id = register(&s, &tokens[0]);
+        let registered_by = s.get_doubles_notifications(tokens[0].clone()).unwrap().len();
+        let other = s.get_doubles_notifications(tokens[3].clone()).unwrap().len();
+
+        respond(&s, &tokens[1], id);
+        respond(&s, &tokens[2], id);
+        let before_last = s.get_doubles_history().unwrap().len();
+        respond(&s, &tokens[3], id);
+
+        let history = s.get_doubles_history().unwrap();
+        let pairs = s.get_doubles_pairs().unwrap();
+        let players = s.get_doubles_players().unwrap();
+        let singles = s.get_user_without_matches("Sivert").unwrap().elo;
+
+        std::fs::remove_file(db_file).expect("Removing file tempD1");
+        assert_eq!(registered_by, 0);
+        assert_eq!(other, 1);
+        assert_eq!(before_last, 0);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].winner_elo, 1516.0);
+        assert_eq!(history[0].loser1_elo, 1484.0);
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0].elo, 1516.0);
+        assert_eq!(players.len(), 4);
+        assert_eq!(players[0].elo, 1516.0);
+        assert_eq!(singles, 1500.0);
+    }
+
+    #[test]
+    fn test_doubles_team_must_be_four_players()
+    {
+        let db_file = "tempD2.db";
+        let s = DataBase::new(db_file);
+        let tokens = create_players(&s);
+
+        let res = s.register_doubles_match(
+            ["Sivert".to_string(), "Lars".to_string()],
+            ["Markus".to_string(), "Sivert".to_string()],
+            vec![],
+            tokens[0].clone(),
+        );
+        let outsider = s.respond_to_doubles_match(1, ACCEPT_REQUEST, tokens[3].clone());
+
+        std::fs::remove_file(db_file).expect("Removing file tempD2");
+        assert_eq!(res, Err(ServerError::InvalidTeam));
+        assert!(outsider.is_err());
+    }
+
+    #[test]
+    fn test_doubles_are_replayed_on_roll_back()
+    {
+        let db_file = "tempD3.db";
+        let s = DataBase::new(db_file);
+        let tokens = create_players(&s);
+        make_user_admin(&s, "Sivert".to_string()).unwrap();
+
+        for _ in 0..2
+        {
+            let id = register(&s, &tokens[0]);
+            for token in &tokens[1..]
+            {
+                respond(&s, token, id);
+            }
+        }
+        let played = s.get_doubles_pairs().unwrap()[0].elo;
+
+        s.conn.execute("update pairs set elo = 0", NO_PARAMS).unwrap();
+        s.roll_back(-1).unwrap();
+        let replayed = s.get_doubles_pairs().unwrap()[0].elo;
+
+        s.set_variable(tokens[0].clone(), "elo_k".to_string(), 16).unwrap();
+        let k16 = s.get_doubles_pairs().unwrap()[0].elo;
+
+        std::fs::remove_file(db_file).expect("Removing file tempD3");
+        assert_eq!(played, replayed);
+        assert!(k16 < played);
+        assert!(k16 > 1500.0);
+    }
+}
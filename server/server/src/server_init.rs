@@ -1,17 +1,24 @@
-use rusqlite::{Connection, NO_PARAMS};
+use std::time::Duration;
 
-use crate::server::DataBase;
+use rusqlite::{Connection, OpenFlags, NO_PARAMS};
+
+use crate::{server::DataBase, server_migrations::migrate};
+
+// How long to wait for a lock held by another connection
+const BUSY_TIMEOUT_SECS: u64 = 5;
 
 impl DataBase
 {
     pub fn init(file: &str) -> Self
     {
-        let conn = match Connection::open(file)
+        let mut conn = match Connection::open(file)
         {
             Err(_) => panic!("Could not create connection"),
             Ok(c) => c,
         };
 
+        conn.busy_timeout(Duration::from_secs(BUSY_TIMEOUT_SECS)).expect("Setting busy timeout");
+
         conn.execute(
             "create table if not exists users (
                 id              integer primary key autoincrement,
@@ -240,10 +247,30 @@ impl DataBase
         )
         .expect("creating tournament_lookup");
 
+        // Everything added to the schema after the tables above lives in
+        // server_migrations.rs
+        migrate(&mut conn).expect("Migrating database");
+
+        DataBase {
+            conn,
+        }
+    }
 
+    // A read only connection to a database that has already been initialized
+    pub fn init_reader(file: &str) -> Self
+    {
+        let conn = match Connection::open_with_flags(
+            file,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        {
+            Err(_) => panic!("Could not create connection"),
+            Ok(c) => c,
+        };
+        conn.busy_timeout(Duration::from_secs(BUSY_TIMEOUT_SECS)).expect("Setting busy timeout");
 
         DataBase {
-            conn: conn
+            conn,
         }
     }
 }
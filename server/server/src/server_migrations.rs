@@ -0,0 +1,501 @@
+use rusqlite::Connection;
+use server_core::types::{ServerError, ServerResult};
+
+// The schema version is stored in sqlite's `user_version`, a database made
+// before migrations existed has version 0. Migrations are only ever added to
+// the end of this list, and a migration that has been released is never
+// changed.
+pub struct Migration
+{
+    pub version:     i64,
+    pub description: &'static str,
+    pub sql:         &'static str,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version:     1,
+        description: "Rating deviation and volatility",
+        sql:         "
+            alter table users add column deviation float default 350.0;
+            alter table users add column volatility float default 0.06;
+            alter table matches add column winner_deviation float default 350.0;
+            alter table matches add column loser_deviation float default 350.0;
+            alter table matches add column winner_volatility float default 0.06;
+            alter table matches add column loser_volatility float default 0.06;
+
+            -- The deviation and volatility at the start of each rating period
+            create table rating_periods (
+                id              integer primary key autoincrement,
+                epoch           bigint not null,
+                user            integer,
+                deviation       float not null,
+                volatility      float not null,
+                foreign key(user) references users(id)
+            );",
+    },
+    Migration {
+        version:     2,
+        description: "Per game scores",
+        sql:         "
+            alter table matches add column scores text default '';
+            alter table old_matches add column scores text default '';
+            alter table offseason_matches add column scores text default '';
+            alter table match_notification add column scores text default '';",
+    },
+    Migration {
+        version:     3,
+        description: "Doubles matches",
+        sql:         "
+            create table doubles_matches (
+                id              integer primary key autoincrement,
+                epoch           bigint not null,
+                winner1         integer,
+                winner2         integer,
+                loser1          integer,
+                loser2          integer,
+                elo_diff        float,
+                winner_elo      float,
+                loser_elo       float,
+                winner1_elo     float,
+                winner2_elo     float,
+                loser1_elo      float,
+                loser2_elo      float,
+                scores          text default '',
+                foreign key(winner1) references users(id),
+                foreign key(winner2) references users(id),
+                foreign key(loser1) references users(id),
+                foreign key(loser2) references users(id)
+            );
+
+            create table doubles_notification (
+                id              integer primary key autoincrement,
+                winner1_accept  smallint default 0,
+                winner2_accept  smallint default 0,
+                loser1_accept   smallint default 0,
+                loser2_accept   smallint default 0,
+                epoch           bigint not null,
+                winner1         integer,
+                winner2         integer,
+                loser1          integer,
+                loser2          integer,
+                scores          text default '',
+                foreign key(winner1) references users(id),
+                foreign key(winner2) references users(id),
+                foreign key(loser1) references users(id),
+                foreign key(loser2) references users(id)
+            );
+
+            -- The doubles rating of each player
+            create table doubles_ratings (
+                user            integer primary key,
+                elo             float default 1500.0,
+                deviation       float default 350.0,
+                volatility      float default 0.06,
+                games           integer default 0,
+                foreign key(user) references users(id)
+            );
+
+            -- The rating of two players playing together, player1 is the lowest id
+            create table pairs (
+                player1         integer,
+                player2         integer,
+                elo             float default 1500.0,
+                deviation       float default 350.0,
+                volatility      float default 0.06,
+                games           integer default 0,
+                primary key(player1, player2),
+                foreign key(player1) references users(id),
+                foreign key(player2) references users(id)
+            );",
+    },
+    Migration {
+        version:     4,
+        description: "Salted password hashes",
+        sql:         "
+            -- Existing hashes are unsalted sha256, see password.rs
+            alter table users add column hash_algorithm smallint default 0;
+            alter table users add column must_change_password smallint default 0;
+            alter table new_user_notification add column hash_algorithm smallint default 0;",
+    },
+    Migration {
+        version:     5,
+        description: "Expiring sessions",
+        sql:         "
+            -- One row for every device a user is logged in on, the uuid column
+            -- in users is no longer used as a token
+            create table sessions (
+                id              integer primary key autoincrement,
+                token           varchar(36) not null unique,
+                user            integer not null,
+                device          text default '',
+                created         bigint not null,
+                expires         bigint not null,
+                foreign key(user) references users(id)
+            );",
+    },
+    Migration {
+        version:     6,
+        description: "Tournament match scores",
+        sql:         "alter table tournament_matches add column scores text default '';",
+    },
+    Migration {
+        version:     7,
+        description: "Group stages",
+        sql:         "
+            -- The knockout bracket of a group stage is a tournament of its own,
+            -- `bracket` is 0 until every group game has been played
+            create table tournament_stages (
+                id              integer primary key autoincrement,
+                tournament      integer not null unique,
+                group_count     integer not null,
+                advance         integer not null,
+                knockout        smallint not null,
+                bracket         integer default 0,
+                foreign key(tournament) references tournaments(id)
+            );
+            create table tournament_groups (
+                id              integer primary key autoincrement,
+                tournament      integer not null,
+                player          integer not null,
+                group_index     integer not null,
+                foreign key(tournament) references tournaments(id),
+                foreign key(player) references users(id)
+            );",
+    },
+    Migration {
+        version:     8,
+        description: "Tournament seeding",
+        sql:         "
+            -- Tournaments without a row are seeded at random
+            create table tournament_seedings (
+                id              integer primary key autoincrement,
+                tournament      integer not null unique,
+                mode            smallint not null,
+                rng_seed        bigint not null,
+                -- Player ids from the first seed down, for manual seeding
+                seeds           text default '[]',
+                foreign key(tournament) references tournaments(id)
+            );",
+    },
+    Migration {
+        version:     9,
+        description: "Rated tournaments",
+        sql:         "
+            -- The games of a rated tournament are also stored in matches, and
+            -- `rated_match` is the id of that match or 0
+            alter table tournaments add column rated smallint default 0;
+            alter table tournament_matches add column rated_match integer default 0;",
+    },
+    Migration {
+        version:     10,
+        description: "Tournament match notifications",
+        sql:         "
+            -- A result reported by one of the players of a tournament game,
+            -- registered once the other player accepts it
+            create table tournament_notification (
+                id              integer primary key autoincrement,
+                game            integer not null,
+                winner_accept   smallint default 0,
+                loser_accept    smallint default 0,
+                epoch           bigint not null,
+                winner          integer,
+                loser           integer,
+                scores          text default '',
+                foreign key(game) references tournament_games(id),
+                foreign key(winner) references users(id),
+                foreign key(loser) references users(id)
+            );",
+    },
+    Migration {
+        version:     11,
+        description: "Tournament withdrawals",
+        sql:         "
+            -- Players who have dropped out of a running tournament, every game
+            -- they have left is a walkover for their opponent
+            create table tournament_withdrawals (
+                id              integer primary key autoincrement,
+                tournament      integer not null,
+                player          integer not null,
+                epoch           bigint not null,
+                unique(tournament, player),
+                foreign key(tournament) references tournaments(id),
+                foreign key(player) references users(id)
+            );
+            alter table tournament_matches add column forfeit smallint default 0;",
+    },
+    Migration {
+        version:     12,
+        description: "Tournament registration deadlines",
+        sql:         "
+            -- When a tournament that is not full starts with the players who
+            -- have joined, 0 when it waits until it is full
+            alter table tournaments add column registration_deadline bigint default 0;",
+    },
+    Migration {
+        version:     13,
+        description: "Tournament placements",
+        sql:         "
+            -- A single elimination tournament can have a game between the two
+            -- losing semifinalists, which is bucket -1
+            alter table tournaments add column third_place_game smallint default 0;
+            -- The players who finished second and third, where both losing
+            -- semifinalists are third when there is no third place game
+            create table tournament_placements (
+                id              integer primary key autoincrement,
+                tournament      integer not null,
+                player          integer not null,
+                place           smallint not null,
+                foreign key(tournament) references tournaments(id),
+                foreign key(player) references users(id)
+            );",
+    },
+    Migration {
+        version:     14,
+        description: "Tournament schedules",
+        sql:         "
+            -- When and at which table a tournament game is played
+            create table tournament_schedule (
+                id              integer primary key autoincrement,
+                game            integer not null unique,
+                start           bigint not null,
+                table_number    integer not null,
+                minutes         integer not null,
+                foreign key(game) references tournament_games(id)
+            );",
+    },
+    Migration {
+        version:     15,
+        description: "Admin sql log",
+        sql:         "
+            -- Every statement run from the admin sql console
+            create table admin_sql_log (
+                id              integer primary key autoincrement,
+                user            integer not null,
+                epoch           bigint not null,
+                command         text not null,
+                write           smallint not null,
+                rows            integer not null,
+                error           text not null default '',
+                foreign key(user) references users(id)
+            );",
+    },
+    Migration {
+        version:     16,
+        description: "Audit log",
+        sql:         "
+            -- Who changed what. The actor is 0 for changes made by the server itself.
+            create table audit_log (
+                id              integer primary key autoincrement,
+                actor           integer not null,
+                action          text not null,
+                target          text not null,
+                before          text not null,
+                after           text not null,
+                epoch           bigint not null
+            );
+            create index audit_log_epoch on audit_log(epoch);
+
+            -- Entries are never changed, and only removed once they are older
+            -- than audit_retention_days (variable 7). 0 keeps them forever.
+            create trigger audit_log_no_update before update on audit_log
+            begin
+                select raise(abort, 'The audit log is append only');
+            end;
+            create trigger audit_log_retention before delete on audit_log
+            when coalesce((select value from variables where id = 7), 365) = 0
+                or old.epoch > (julianday('now') - 2440587.5) * 86400000
+                    - coalesce((select value from variables where id = 7), 365) * 86400000
+            begin
+                select raise(abort, 'The audit log is append only');
+            end;",
+    },
+    Migration {
+        version:     17,
+        description: "Match versions",
+        sql:         "
+            -- A match as it was before an admin edited or deleted it. Undoing
+            -- the change puts the match back the way its version has it.
+            create table match_versions (
+                id              integer primary key autoincrement,
+                match           integer not null,
+                epoch           bigint not null,
+                winner          integer not null,
+                loser           integer not null,
+                scores          text default '',
+                deleted         boolean not null,
+                changed_by      integer not null,
+                changed         bigint not null,
+                undone          boolean not null default 0
+            );
+            create index match_versions_match on match_versions(match);",
+    },
+    Migration {
+        version:     18,
+        description: "Match indexes",
+        sql:         "
+            -- Replaying ratings looks up the matches of a player before an epoch
+            create index matches_epoch on matches(epoch);
+            create index matches_winner on matches(winner, epoch);
+            create index matches_loser on matches(loser, epoch);",
+    },
+    Migration {
+        version:     19,
+        description: "Rating system of each season",
+        sql:         "
+            -- The engine a season is rated with, seasons from before this
+            -- migration use the variables
+            alter table seasons add column rating_system integer;
+            alter table seasons add column elo_k integer;",
+    },
+];
+
+pub fn latest_schema_version() -> i64
+{
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+pub fn schema_version(conn: &Connection) -> ServerResult<i64>
+{
+    Ok(conn.query_row("pragma user_version", rusqlite::NO_PARAMS, |row| row.get(0))?)
+}
+
+// Brings the database up to the latest schema version. Each migration runs in
+// its own transaction together with the version bump, so a failing migration
+// leaves the database at the version before it.
+pub fn migrate(conn: &mut Connection) -> ServerResult<()>
+{
+    let version = schema_version(conn)?;
+    if version > latest_schema_version()
+    {
+        return Err(ServerError::Critical(format!(
+            "Database schema version {} is newer than the latest known version {}",
+            version,
+            latest_schema_version()
+        )));
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version)
+    {
+        let tx = conn.transaction()?;
+        if let Err(e) = tx.execute_batch(migration.sql)
+        {
+            return Err(ServerError::Critical(format!(
+                "Migration {} ({}) failed: {}",
+                migration.version, migration.description, e
+            )));
+        }
+        tx.execute_batch(&format!("pragma user_version = {}", migration.version))?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use rusqlite::{params, Connection, NO_PARAMS};
+
+    use super::*;
+    use crate::server::DataBase;
+
+    const BASELINE: &str = include_str!("../fixtures/baseline.sql");
+
+    fn create_baseline(db_file: &str)
+    {
+        let conn = Connection::open(db_file).unwrap();
+        conn.execute_batch(BASELINE).unwrap();
+    }
+
+    #[test]
+    fn test_versions_are_in_order()
+    {
+        for (i, migration) in MIGRATIONS.iter().enumerate()
+        {
+            assert_eq!(migration.version, i as i64 + 1);
+        }
+    }
+
+    #[test]
+    fn test_migrates_baseline_database()
+    {
+        let db_file = "tempM1.db";
+        create_baseline(db_file);
+
+        let s = DataBase::new(db_file);
+        let version = schema_version(&s.conn).unwrap();
+        let user = s.get_user_without_matches("Sivert").unwrap();
+        let history = s.get_history().unwrap();
+        let notification: String = s
+            .conn
+            .query_row("select scores from match_notification", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        let doubles = s.get_doubles_history().unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempM1");
+        assert_eq!(version, latest_schema_version());
+        assert_eq!(user.elo, 1516.0);
+        assert_eq!(user.deviation, 350.0);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].winner, "Sivert");
+        assert_eq!(history[0].scores, "");
+        assert_eq!(notification, "");
+        assert!(doubles.is_empty());
+    }
+
+    #[test]
+    fn test_migrating_twice_does_nothing()
+    {
+        let db_file = "tempM2.db";
+        create_baseline(db_file);
+
+        drop(DataBase::new(db_file));
+        let s = DataBase::new(db_file);
+        let version = schema_version(&s.conn).unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempM2");
+        assert_eq!(version, latest_schema_version());
+    }
+
+    #[test]
+    fn test_failed_migration_is_rolled_back()
+    {
+        let db_file = "tempM3.db";
+        create_baseline(db_file);
+        let mut conn = Connection::open(db_file).unwrap();
+        // Makes the second migration fail halfway through
+        conn.execute("alter table offseason_matches add column scores text", NO_PARAMS)
+            .unwrap();
+
+        let res = migrate(&mut conn);
+        let version = schema_version(&conn).unwrap();
+        let old_matches_scores: i64 = conn
+            .query_row(
+                "select count(*) from pragma_table_info('old_matches') where name = ?1",
+                params!["scores"],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempM3");
+        assert!(res.is_err());
+        assert_eq!(version, 1);
+        assert_eq!(old_matches_scores, 0);
+    }
+
+    #[test]
+    fn test_refuses_newer_schema()
+    {
+        let db_file = "tempM4.db";
+        create_baseline(db_file);
+        let mut conn = Connection::open(db_file).unwrap();
+        conn.execute_batch(&format!("pragma user_version = {}", latest_schema_version() + 1))
+            .unwrap();
+
+        let res = migrate(&mut conn);
+
+        std::fs::remove_file(db_file).expect("Removing file tempM4");
+        assert!(res.is_err());
+    }
+}
@@ -1,41 +1,115 @@
 use std::collections::HashMap;
 
-use elo::EloRank;
 use rusqlite::{named_params, Connection};
 use server_core::types::ServerResult;
 
-use super::{r#match::Match, server::DataBase};
+use super::{
+    r#match::{Match, MatchFootprint},
+    rating::Rating,
+    server::DataBase,
+};
+use crate::SQL_TUPLE_NAMED;
 
 impl DataBase
 {
+    // Replays the ratings of every match from `time` on, or of the whole
+    // season, doubles included, if `time` is negative. The matches keep the
+    // ratings of their players after the match, which is where a replay from
+    // `time` starts.
     pub fn roll_back(&self, time: i64) -> ServerResult<()>
     {
-        let elo = EloRank {
-            k: 32
+        self.atomically(|| self.replay(time))
+    }
+
+    pub(crate) fn get_match_footprint(&self, id: i64) -> ServerResult<Option<MatchFootprint>>
+    {
+        Ok(SQL_TUPLE_NAMED!(
+            self,
+            "select epoch, winner, loser from matches where id = :id",
+            named_params! {":id": id},
+            i64,
+            i64,
+            i64
+        )?
+        .pop()
+        .map(|(epoch, winner, loser)| MatchFootprint {
+            epoch,
+            players: [winner, loser],
+        }))
+    }
+
+    // Replays the ratings after matches were changed, from the earliest epoch
+    // any of them had before or after the change. The replay only reaches the
+    // players who have matches after that epoch, so the players of the changed
+    // matches first get the rating they had right before it. When most of the
+    // season comes after the epoch, a full replay is cheaper than looking up
+    // the rating of every player before it.
+    pub(crate) fn replay_changed_matches(
+        &self,
+        footprints: &[Option<MatchFootprint>],
+    ) -> ServerResult<()>
+    {
+        let footprints: Vec<MatchFootprint> = footprints.iter().flatten().copied().collect();
+        let epoch = match footprints.iter().map(|f| f.epoch).min()
+        {
+            Some(epoch) => epoch,
+            None => return Ok(()),
         };
 
-        // Match, id
-        let mut modified: Vec<(Match, i64)> = Vec::new();
+        self.atomically(|| {
+            let (later, total) = SQL_TUPLE_NAMED!(
+                self,
+                "select count(case when epoch >= :epoch then 1 end), count(*) from matches",
+                named_params! {":epoch": epoch},
+                i64,
+                i64
+            )?
+            .pop()
+            .expect("Counting matches");
+            if later * 2 > total
+            {
+                return self.replay(-1);
+            }
 
-        // Username, elo
-        let mut map: HashMap<String, f64> = HashMap::new();
+            for pid in footprints.iter().flat_map(|f| f.players.iter())
+            {
+                let name = self.get_user_without_matches_by("id", "=", &pid.to_string())?.name;
+                let rating = get_rating_before(&self.conn, &name, epoch)?;
+                self.update_rating(*pid, &rating)?;
+            }
+            self.replay(epoch)
+        })
+    }
+
+    fn replay(&self, time: i64) -> ServerResult<()>
+    {
+        let rating_system = self.get_rating_system()?;
+
+        // Match, id, winner rating, loser rating
+        let mut modified: Vec<(Match, i64, Rating, Rating)> = Vec::new();
+
+        // User id, rating
+        let mut map: HashMap<i64, Rating> = HashMap::new();
 
         let flag = time < 0;
         let time = time.abs();
-        let default_score = |m: &Match, is_winner: bool| -> f64 {
+        let initial_rating = |name: &str| -> ServerResult<Rating> {
             if flag
             {
-                1500.0
+                get_period_rating(&self.conn, name)
             }
             else
             {
-                if is_winner { m.winner_elo - m.elo_diff } else { m.loser_elo + m.elo_diff }
+                get_rating_before(&self.conn, name, time)
             }
         };
 
-        let initial_elo = |name: &String, matches: &Vec<(Match, i64)>| -> f64 {
-            if flag { 1500.0 } else { get_initial_elo(name, matches) }
-        };
+        // Doubles do not depend on the singles matches, they are only replayed
+        // when everything is
+        if flag
+        {
+            self.replay_doubles()?;
+        }
 
         let matches = get_all_matches_before(&self.conn, time)?;
 
@@ -44,31 +118,40 @@ impl DataBase
             return Ok(());
         }
 
-        map.insert(matches[0].0.winner.clone(), initial_elo(&matches[0].0.winner, &matches));
-        map.insert(matches[0].0.loser.clone(), initial_elo(&matches[0].0.loser, &matches));
-
-        for (m, id) in matches
+        for (m, id, [winner_id, loser_id]) in matches
         {
-            let winner_name = m.winner.clone();
-            let loser_name = m.loser.clone();
-            let winner_elo =
-                *map.entry(winner_name.clone()).or_insert_with(|| default_score(&m, true));
-            let loser_elo =
-                *map.entry(loser_name.clone()).or_insert_with(|| default_score(&m, false));
+            let winner = match map.get(&winner_id)
+            {
+                Some(r) => *r,
+                None => initial_rating(&m.winner)?,
+            };
+            let loser = match map.get(&loser_id)
+            {
+                Some(r) => *r,
+                None => initial_rating(&m.loser)?,
+            };
 
-            let (new_winner_elo, new_loser_elo) = elo.calculate(winner_elo, loser_elo);
+            let (new_winner, new_loser) =
+                rating_system.rate_by_margin(&winner, &loser, self.get_margin(&m.scores)?);
 
-            map.insert(winner_name, new_winner_elo);
-            map.insert(loser_name, new_loser_elo);
+            map.insert(winner_id, new_winner);
+            map.insert(loser_id, new_loser);
             modified.push((
-                create_match(m, new_winner_elo, new_loser_elo, new_winner_elo - winner_elo),
+                create_match(
+                    m,
+                    new_winner.rating,
+                    new_loser.rating,
+                    new_winner.rating - winner.rating,
+                ),
                 id,
+                new_winner,
+                new_loser,
             ));
         }
 
-        for (name, elo) in map
+        for (id, rating) in map
         {
-            update_elo(&self.conn, name, elo)?;
+            self.update_rating(id, &rating)?;
         }
 
         for m in modified
@@ -80,48 +163,97 @@ impl DataBase
     }
 }
 
-fn get_initial_elo(name: &str, matches: &[(Match, i64)]) -> f64
+// The rating a player had right before `time`, i.e after their last match
+fn get_rating_before(s: &Connection, name: &str, time: i64) -> ServerResult<Rating>
 {
-    match matches.iter().skip(1).find(|(m, _)| &m.winner == name || &m.loser == name)
+    // Both halves look up the last match through the index on the player
+    let sql = "select rating, deviation, volatility,
+            (select count(*) from matches where winner = :id and epoch < :epoch)
+            + (select count(*) from matches where loser = :id and epoch < :epoch)
+            from (
+                select * from (
+                    select winner_elo as rating, winner_deviation as deviation,
+                    winner_volatility as volatility, epoch, id from matches
+                    where winner = :id and epoch < :epoch
+                    order by epoch desc, id desc limit 1)
+                union all
+                select * from (
+                    select loser_elo, loser_deviation, loser_volatility, epoch, id from matches
+                    where loser = :id and epoch < :epoch
+                    order by epoch desc, id desc limit 1)
+            )
+            order by epoch desc, id desc limit 1;";
+    let id: i64 = s.query_row_named(
+        "select id from users where name = :name",
+        named_params! {":name": name},
+        |row| row.get(0),
+    )?;
+    let mut stmt = s.prepare_cached(sql)?;
+    let last = stmt
+        .query_map_named(named_params! {":id": id, ":epoch": time}, |row| {
+            Ok(Rating {
+                rating:     row.get(0)?,
+                deviation:  row.get(1)?,
+                volatility: row.get(2)?,
+                games:      row.get(3)?,
+            })
+        })?
+        .flatten()
+        .next();
+
+    match last
     {
-        Some((m, _)) =>
-        {
-            if &m.winner == name { m.winner_elo - m.elo_diff } else { m.loser_elo + m.elo_diff }
-        },
+        Some(rating) => Ok(rating),
+        None => get_period_rating(s, name),
+    }
+}
 
-        None =>
-        {
-            if &matches[0].0.winner == name
-            {
-                matches[0].0.winner_elo - matches[0].0.elo_diff
-            }
-            else
-            {
-                matches[0].0.loser_elo + matches[0].0.elo_diff
-            }
+// The rating a player starts the current rating period with
+fn get_period_rating(s: &Connection, name: &str) -> ServerResult<Rating>
+{
+    let sql = "select r.deviation, r.volatility from rating_periods as r
+            inner join users as u on u.id = r.user
+            where u.name = :name
+            order by r.id desc limit 1;";
+    let mut stmt = s.prepare(sql)?;
+    let period = stmt
+        .query_map_named(named_params! {":name": name}, |row| Ok((row.get(0)?, row.get(1)?)))?
+        .flatten()
+        .next();
+
+    Ok(match period
+    {
+        Some((deviation, volatility)) => Rating {
+            deviation, volatility, ..Default::default()
         },
-    }
+        None => Rating::default(),
+    })
 }
 
-fn update_match(s: &Connection, m: (Match, i64)) -> ServerResult<()>
+fn update_match(s: &Connection, m: (Match, i64, Rating, Rating)) -> ServerResult<()>
 {
-    let id = m.1;
-    let m = m.0;
-    let mut stmt = s.prepare(
+    let (m, id, winner, loser) = m;
+    let mut stmt = s.prepare_cached(
         "update matches set
          winner_elo = :w_elo,
          loser_elo = :l_elo,
-         elo_diff = :diff
+         elo_diff = :diff,
+         winner_deviation = :w_dev,
+         loser_deviation = :l_dev,
+         winner_volatility = :w_vol,
+         loser_volatility = :l_vol
          where id = :id",
     )?;
-    stmt.execute_named(named_params!{":w_elo": m.winner_elo, ":l_elo": m.loser_elo, ":diff": m.elo_diff, ":id": id})?;
-    Ok(())
-}
-
-fn update_elo(s: &Connection, name: String, elo: f64) -> ServerResult<()>
-{
-    let mut stmt = s.prepare("update users  set elo = :elo WHERE name like :name")?;
-    stmt.execute_named(named_params! {":elo": elo, ":name": name})?;
+    stmt.execute_named(named_params! {
+        ":w_elo": m.winner_elo,
+        ":l_elo": m.loser_elo,
+        ":diff": m.elo_diff,
+        ":w_dev": winner.deviation,
+        ":l_dev": loser.deviation,
+        ":w_vol": winner.volatility,
+        ":l_vol": loser.volatility,
+        ":id": id
+    })?;
     Ok(())
 }
 
@@ -133,14 +265,16 @@ fn create_match(mut m: Match, winner_new_elo: f64, loser_new_elo: f64, elo_diff:
     m
 }
 
-fn get_all_matches_before(s: &Connection, time: i64) -> ServerResult<Vec<(Match, i64)>>
+// The matches from `time` on, with their id and the ids of the winner and loser
+fn get_all_matches_before(s: &Connection, time: i64) -> ServerResult<Vec<(Match, i64, [i64; 2])>>
 {
-    let zin = "select a.name, b.name, m.id, m.elo_diff, m.winner_elo, m.loser_elo, m.epoch
+    let zin = "select a.name, b.name, m.id, m.elo_diff, m.winner_elo, m.loser_elo, m.epoch,
+            m.scores, m.winner, m.loser
             from matches as m
             inner join users as a on a.id = m.winner
             inner join users as b on b.id = m.loser
             where epoch >= :epoch
-            order by epoch;";
+            order by epoch, m.id;";
     let mut stmt = s.prepare(zin)?;
     let matches = stmt.query_map_named(named_params! {":epoch" : time}, |row| {
         let id: i64 = row.get(2)?;
@@ -152,11 +286,200 @@ fn get_all_matches_before(s: &Connection, time: i64) -> ServerResult<Vec<(Match,
                 winner_elo: row.get(4)?,
                 loser_elo:  row.get(5)?,
                 epoch:      row.get(6)?,
+                scores:     row.get(7)?,
                 season:     -1, // Only used internally, does not need the correct season
             },
             id,
+            [row.get(8)?, row.get(9)?],
         ))
     })?;
 
     Ok(matches.flatten().collect())
 }
+
+
+#[cfg(test)]
+mod test
+{
+    use rusqlite::NO_PARAMS;
+
+    use super::*;
+    use crate::{r#match::DeleteMatchInfo, test_util::*};
+
+    // Every stored rating, of the players and with the matches
+    fn ratings(s: &DataBase) -> (Vec<(f64, f64)>, Vec<(f64, f64, f64)>)
+    {
+        let query = |sql: &str| {
+            s.conn
+                .prepare(sql)
+                .unwrap()
+                .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .unwrap()
+                .map(|row| row.unwrap())
+                .collect::<Vec<(f64, f64, f64)>>()
+        };
+        let users = query("select elo, deviation, 0.0 from users order by id");
+        (
+            users.into_iter().map(|(elo, deviation, _)| (elo, deviation)).collect(),
+            query("select winner_elo, loser_elo, elo_diff from matches order by id"),
+        )
+    }
+
+    // Players take turns beating each other, one match a minute
+    fn play_season(s: &DataBase, players: &[&str], matches: i64)
+    {
+        s.atomically(|| {
+            for n in 0..matches
+            {
+                let winner = players[(n * 7 % players.len() as i64) as usize];
+                let loser = players[((n * 7 + 1 + n % 3) % players.len() as i64) as usize];
+                s.create_rated_match(
+                    n * 60 * 1000 + 1,
+                    &s.get_user_without_matches(winner)?,
+                    &s.get_user_without_matches(loser)?,
+                    "",
+                )?;
+            }
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_replay_from_changed_match_equals_full_replay()
+    {
+        let db_file = "tempH1.db";
+        let s = DataBase::new(db_file);
+        let admin = create_user(&s, "Admin");
+        make_user_admin(&s, "Admin".to_string()).unwrap();
+        let players = ["Sivert", "Lars", "Markus", "Ella", "Bernt"];
+        for player in &players
+        {
+            create_user(&s, player);
+        }
+        play_season(&s, &players, 40);
+        // Ella plays a single match, late in the season
+        let ella = s.get_user_without_matches("Ella").unwrap();
+        s.conn
+            .execute("delete from matches where winner = ?1 or loser = ?1", &[ella.id])
+            .unwrap();
+        s.roll_back(-1).unwrap();
+        s.create_rated_match(
+            30 * 60 * 1000,
+            &ella,
+            &s.get_user_without_matches("Bernt").unwrap(),
+            "",
+        )
+        .unwrap();
+        let ella_match = s.conn.last_insert_rowid();
+
+        s.delete_match(DeleteMatchInfo {
+            id: ella_match, token: admin.clone()
+        })
+        .unwrap();
+        s.delete_match(DeleteMatchInfo {
+            id: 31, token: admin.clone()
+        })
+        .unwrap();
+        let incremental = ratings(&s);
+        s.roll_back(-1).unwrap();
+        let full = ratings(&s);
+        let ella_elo = s.get_user_without_matches("Ella").unwrap().elo;
+        // Replays the whole season
+        s.delete_match(DeleteMatchInfo {
+            id: 2, token: admin
+        })
+        .unwrap();
+        let early = ratings(&s);
+        s.roll_back(-1).unwrap();
+        let early_full = ratings(&s);
+
+        std::fs::remove_file(db_file).expect("Removing file tempH1");
+        assert_eq!(incremental, full);
+        assert_eq!(ella_elo, 1500.0);
+        assert_eq!(early, early_full);
+    }
+
+    #[test]
+    fn test_replay_only_rates_the_players_of_the_matches()
+    {
+        let db_file = "tempH4.db";
+        let s = DataBase::new(db_file);
+        // A name with a wildcard in it
+        create_user(&s, "Lars_1");
+        create_user(&s, "Larsx1");
+        create_user(&s, "Bernt");
+        play_season(&s, &["Lars_1", "Bernt"], 4);
+        s.roll_back(-1).unwrap();
+        let lars = s.get_user_without_matches("Lars_1").unwrap();
+        let other = s.get_user_without_matches("Larsx1").unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempH4");
+        assert_ne!(lars.elo, 1500.0);
+        assert_eq!(other.elo, 1500.0);
+    }
+
+    #[test]
+    fn test_failed_replay_changes_nothing()
+    {
+        let db_file = "tempH2.db";
+        let s = DataBase::new(db_file);
+        let players = ["Sivert", "Lars", "Markus"];
+        for player in &players
+        {
+            create_user(&s, player);
+        }
+        play_season(&s, &players, 10);
+        s.conn.execute("update users set elo = 1000.0", NO_PARAMS).unwrap();
+        s.conn.execute("update matches set winner_elo = 0.0", NO_PARAMS).unwrap();
+        let before = ratings(&s);
+
+        // The last match of the replay fails to be written
+        s.conn
+            .execute_batch(
+                "create temp trigger fail_replay before update on matches when old.id = 10
+                 begin
+                     select raise(abort, 'Injected failure');
+                 end;",
+            )
+            .unwrap();
+        let res = s.roll_back(-1);
+        let after = ratings(&s);
+
+        std::fs::remove_file(db_file).expect("Removing file tempH2");
+        assert!(res.is_err());
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn test_replay_of_longer_season_equals_full_replay()
+    {
+        let db_file = "tempH3.db";
+        let s = DataBase::new(db_file);
+        let admin = create_user(&s, "Admin");
+        make_user_admin(&s, "Admin".to_string()).unwrap();
+        let names: Vec<String> = (0..20).map(|n| format!("Player{}", n)).collect();
+        for name in &names
+        {
+            create_user(&s, name);
+        }
+        let players: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+        play_season(&s, &players, 1_000);
+        s.roll_back(-1).unwrap();
+
+        // Replays the end of the season, then all of it
+        for id in &[980, 20]
+        {
+            s.delete_match(DeleteMatchInfo {
+                id: *id, token: admin.clone()
+            })
+            .unwrap();
+        }
+        let incremental = ratings(&s);
+        s.roll_back(-1).unwrap();
+        let replayed = ratings(&s);
+
+        std::fs::remove_file(db_file).expect("Removing file tempH3");
+        assert_eq!(incremental, replayed);
+    }
+}
@@ -1,23 +1,25 @@
 use chrono::prelude::*;
 use rusqlite::{named_params, params, NO_PARAMS};
-use server_core::{
-    constants::*,
-    types::{ServerError, ServerResult},
-};
+use serde_json::{json, Value};
+use server_core::{constants::*, types::ServerResult};
 
-use super::{season::Season, server::DataBase};
+use super::{
+    rating::{DEFAULT_DEVIATION, DEFAULT_RATING, DEFAULT_VOLATILITY},
+    season::Season,
+    server::DataBase,
+};
 use crate::{GET_OR_CREATE_DB_VAR, SQL_TUPLE};
 
 impl DataBase
 {
     pub fn end_season(&self, stop_season: bool) -> ServerResult<()>
     {
-        self._end_season(stop_season)
+        self.atomically(|| self._end_season(stop_season))
     }
 
     pub fn start_new_season(&self) -> ServerResult<()>
     {
-        self._start_new_season()
+        self.atomically(|| self._start_new_season())
     }
 
     pub fn get_season_length(&self) -> ServerResult<i64>
@@ -27,13 +29,37 @@ impl DataBase
 
     pub fn set_season_length(&self, token: String, new_val: i64) -> ServerResult<()>
     {
-        if !self.get_is_admin(token)?
-        {
-            return Err(ServerError::Unauthorized);
-        }
+        self.atomically(|| {
+            let admin = self.get_admin(&token)?;
+            let before = self.get_season_length()?;
 
-        self._set_season_length(new_val)?;
-        Ok(())
+            self._set_season_length(new_val)?;
+            self.audit(admin.id, "set_season_length", "season", json!(before), json!(new_val))
+        })
+    }
+
+    // Starts, stops or cancels the season for an admin, `change` is one of
+    // START_SEASON, STOP_SEASON and CANCEL_SEASON
+    pub fn change_season(&self, token: String, change: i64) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let admin = self.get_admin(&token)?;
+            let action = match change
+            {
+                START_SEASON =>
+                {
+                    self.start_new_season()?;
+                    "start_season"
+                },
+                STOP_SEASON | CANCEL_SEASON =>
+                {
+                    self.end_season(change == STOP_SEASON)?;
+                    if change == STOP_SEASON { "stop_season" } else { "cancel_season" }
+                },
+                _ => return Ok(()),
+            };
+            self.audit(admin.id, action, "season", Value::Null, Value::Null)
+        })
     }
 
     pub fn get_is_season(&self) -> ServerResult<bool>
@@ -105,6 +131,7 @@ impl DataBase
                 self.clear_matches()?;
                 self.clear_notifications()?;
                 self.reset_elos()?;
+                self.start_rating_period()?;
                 self.set_users_soft_inactive()?;
                 self.set_is_season(false)?;
             }
@@ -126,8 +153,8 @@ impl DataBase
     {
         self.conn.execute(
             "insert into old_matches (epoch, elo_diff, winner_elo, loser_elo, winner, loser, \
-             season)
-             select epoch, elo_diff, winner_elo, loser_elo, winner, loser, seasons.id
+             scores, season)
+             select epoch, elo_diff, winner_elo, loser_elo, winner, loser, scores, seasons.id
              from matches, seasons where seasons.id = (?1)",
             params![season_number],
         )?;
@@ -153,6 +180,7 @@ impl DataBase
             self.archive_offseason()?;
             self.set_users_soft_inactive()?;
             self.reset_elos()?;
+            self.start_rating_period()?;
             self.clear_notifications()?;
             self.clear_matches()?;
             self.set_is_season(true)?;
@@ -163,11 +191,63 @@ impl DataBase
     fn create_new_season(&self) -> ServerResult<()>
     {
         let next_season = self.get_latest_season_number().unwrap_or(0) + 1;
-        self.conn
-            .execute("insert into seasons (id, start_epoch) values (?1, ?2)", params![
+        self.conn.execute(
+            "insert into seasons (id, start_epoch, rating_system, elo_k) values (?1, ?2, ?3, ?4)",
+            params![
                 next_season,
-                Utc::now().timestamp_millis()
-            ])?;
+                Utc::now().timestamp_millis(),
+                self.get_variable("rating_system".to_string())?,
+                self.get_variable("elo_k".to_string())?
+            ],
+        )?;
+        Ok(())
+    }
+
+    // The running season is rated with the configured engine from now on
+    pub(crate) fn set_season_rating_system(&self) -> ServerResult<()>
+    {
+        if self.get_is_season()?
+        {
+            self.conn.execute(
+                "update seasons set rating_system = ?1, elo_k = ?2
+                 where id = (select max(id) from seasons)",
+                params![
+                    self.get_variable("rating_system".to_string())?,
+                    self.get_variable("elo_k".to_string())?
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    // The engine and k of the running season, if it has stored them
+    pub(crate) fn get_season_rating_system(&self) -> ServerResult<Option<(i64, i64)>>
+    {
+        if !self.get_is_season()?
+        {
+            return Ok(None);
+        }
+        Ok(SQL_TUPLE!(
+            self,
+            "select rating_system, elo_k from seasons order by id desc limit 1",
+            Option<i64>,
+            Option<i64>
+        )?
+        .pop()
+        .and_then(|(system, k)| Some((system?, k?))))
+    }
+}
+
+// ~ rating period functions, a season is one rating period
+impl DataBase
+{
+    fn start_rating_period(&self) -> ServerResult<()>
+    {
+        self.conn.execute(
+            "insert into rating_periods (epoch, user, deviation, volatility)
+             select ?1, id, deviation, volatility from users",
+            params![self.epoch()],
+        )?;
         Ok(())
     }
 }
@@ -184,8 +264,8 @@ impl DataBase
     pub fn archive_offseason(&self) -> ServerResult<()>
     {
         self.conn.execute(
-            "insert into offseason_matches (epoch, elo_diff, winner_elo, loser_elo, winner, loser)
-             select epoch, elo_diff, winner_elo, loser_elo, winner, loser from matches",
+            "insert into offseason_matches (epoch, elo_diff, winner_elo, loser_elo, winner, loser, scores)
+             select epoch, elo_diff, winner_elo, loser_elo, winner, loser, scores from matches",
             NO_PARAMS,
         )?;
         Ok(())
@@ -194,12 +274,19 @@ impl DataBase
     pub fn clear_matches(&self) -> ServerResult<()>
     {
         self.conn.execute("delete from matches", NO_PARAMS)?;
+        // The changes to matches of a finished season can not be undone
+        self.conn.execute("delete from match_versions", NO_PARAMS)?;
         Ok(())
     }
 
+    // The deviation goes with the rating, a player starting over at the default
+    // rating can not be as certain of it as before
     pub fn reset_elos(&self) -> ServerResult<()>
     {
-        self.conn.execute("update users set elo = 1500.0", NO_PARAMS)?;
+        self.conn.execute(
+            "update users set elo = ?1, deviation = ?2, volatility = ?3",
+            params![DEFAULT_RATING, DEFAULT_DEVIATION, DEFAULT_VOLATILITY],
+        )?;
         Ok(())
     }
 
@@ -480,6 +567,50 @@ mod test
         assert_eq!(time, start.unwrap())
     }
 
+    #[test]
+    fn test_new_season_resets_the_deviation()
+    {
+        let db_file = "tempL3.db";
+        let s = DataBase::new(db_file);
+        let siv = create_user(&s, "Sivert");
+        create_user(&s, "Markus");
+        make_user_admin(&s, "Sivert".to_string()).unwrap();
+        s.set_variable(siv.clone(), "rating_system".to_string(), 2).unwrap();
+        let play = || {
+            s.register_match("Markus".to_string(), "Sivert".to_string(), siv.clone()).unwrap();
+            let id = s
+                .conn
+                .query_row("select max(id) from match_notification", NO_PARAMS, |row| row.get(0))
+                .unwrap();
+            respond_to_match(&s, "Markus", id);
+            s.get_user("Sivert").unwrap()
+        };
+
+        s.start_new_season().unwrap();
+        let first = play();
+        for _ in 0..10
+        {
+            play();
+        }
+        let played = s.get_user("Sivert").unwrap();
+        s.roll_back(-1).unwrap();
+        let replayed = s.get_user("Sivert").unwrap();
+
+        s.end_season(true).unwrap();
+        let after_season = s.get_user("Sivert").unwrap();
+        s.start_new_season().unwrap();
+        let next = play();
+
+        std::fs::remove_file(db_file).expect("Removing file tempL3");
+        assert!(played.deviation < first.deviation);
+        assert_eq!(played.elo, replayed.elo);
+        assert_eq!(played.deviation, replayed.deviation);
+        assert_eq!(after_season.deviation, DEFAULT_DEVIATION);
+        // The first game of a season moves the rating as much as for a new player
+        assert_eq!(next.elo, first.elo);
+        assert_eq!(next.deviation, first.deviation);
+    }
+
     #[test]
     fn test_can_cancel_season()
     {
@@ -503,4 +634,98 @@ mod test
         assert!(user.elo == 1500.);
         assert!(user.badges.len() == 0);
     }
+
+    #[test]
+    fn test_failed_end_of_season_changes_nothing()
+    {
+        let db_file = "tempL4.db";
+        let s = DataBase::new(db_file);
+        create_user(&s, "Sivert");
+        let m = create_user(&s, "Markus");
+
+        s.start_new_season().unwrap();
+        s.register_match("Markus".to_string(), "Sivert".to_string(), m.clone()).unwrap();
+        respond_to_match(&s, "Sivert", 1);
+        // The notifications are cleared after the badges are given and the matches
+        // archived
+        s.register_match("Markus".to_string(), "Sivert".to_string(), m).unwrap();
+        fail_on(&s, "delete", "match_notification");
+        let res = s.end_season(true);
+        let user = s.get_user(&"Markus".to_string()).unwrap();
+
+        let counts: Vec<i64> = ["badges", "old_matches", "matches", "match_notification"]
+            .iter()
+            .map(|table| get_table_size(&s, table))
+            .collect();
+        let is_season = s.get_is_season().unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempL4");
+        assert!(res.is_err());
+        assert_eq!(counts, vec![0, 0, 1, 1]);
+        assert!(is_season);
+        assert!(user.elo > 1500.);
+        assert!(user.badges.is_empty());
+    }
+
+    #[test]
+    fn test_failed_season_start_changes_nothing()
+    {
+        let db_file = "tempL5.db";
+        let s = DataBase::new(db_file);
+        create_user(&s, "Sivert");
+        let m = create_user(&s, "Markus");
+
+        s.start_new_season().unwrap();
+        s.end_season(true).unwrap();
+        s.register_match("Markus".to_string(), "Sivert".to_string(), m).unwrap();
+        respond_to_match(&s, "Sivert", 1);
+        // The offseason matches are archived before the matches are cleared
+        fail_on(&s, "delete", "matches");
+        let res = s.start_new_season();
+        let user = s.get_user(&"Markus".to_string()).unwrap();
+
+        let counts: Vec<i64> = ["seasons", "offseason_matches", "matches"]
+            .iter()
+            .map(|table| get_table_size(&s, table))
+            .collect();
+        let is_season = s.get_is_season().unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempL5");
+        assert!(res.is_err());
+        assert_eq!(counts, vec![1, 0, 1]);
+        assert!(!is_season);
+        assert!(user.elo > 1500.);
+    }
+
+    #[test]
+    fn test_season_keeps_its_rating_system() -> ServerResult<()>
+    {
+        let db_file = "tempL6.db";
+        let s = DataBase::new(db_file);
+        let token = create_user(&s, "Sivert");
+        create_user(&s, "Lars");
+        make_user_admin(&s, "Sivert".to_string())?;
+
+        s.start_new_season()?;
+        s.end_season(true)?;
+        // Only the next season is rated with the new k
+        s.set_variable(token.clone(), "elo_k".to_string(), 16)?;
+        let offseason = s.get_season_rating_system()?;
+        s.start_new_season()?;
+        s.register_match("Sivert".to_string(), "Lars".to_string(), token.clone())?;
+        respond_to_match(&s, "Lars", 1);
+        let k16 = s.get_user(&"Sivert".to_string())?.elo;
+        // Changing k during a season changes the running season
+        s.set_variable(token, "elo_k".to_string(), 8)?;
+        let k8 = s.get_user(&"Sivert".to_string())?.elo;
+        let seasons =
+            SQL_TUPLE!(s, "select rating_system, elo_k from seasons order by id", i64, i64)?;
+
+        std::fs::remove_file(db_file).expect("Removing file tempL6");
+        assert_eq!(offseason, None);
+        assert_eq!(k16, 1508.0);
+        assert_eq!(k8, 1504.0);
+        assert_eq!(seasons, vec![(0, 32), (0, 8)]);
+        Ok(())
+    }
 }
@@ -0,0 +1,251 @@
+use rusqlite::named_params;
+use serde_json::Value;
+use server_core::{
+    constants::*,
+    types::{ServerError, ServerResult},
+};
+use uuid::Uuid;
+
+use super::{server::DataBase, user::User};
+use crate::SQL_TUPLE_NAMED;
+
+// Every login creates a session with its own token, so a user can be logged in
+// on several devices and log out of them one at a time. A session expires when
+// it has not been used for SESSION_LENGTH_MS.
+impl DataBase
+{
+    pub fn create_session(&self, user_id: i64, device: &str) -> ServerResult<String>
+    {
+        let token = format!("{}", Uuid::new_v4());
+        let now = self.epoch();
+        self.conn.execute_named(
+            "insert into sessions (token, user, device, created, expires)
+             values (:token, :user, :device, :created, :expires)",
+            named_params! {
+                ":token": token,
+                ":user": user_id,
+                ":device": device,
+                ":created": now,
+                ":expires": now + SESSION_LENGTH_MS
+            },
+        )?;
+        Ok(token)
+    }
+
+    // The user owning the token. Using a session pushes its expiry forward,
+    // but only once every SESSION_RENEW_INTERVAL_MS to keep reads cheap.
+    pub fn get_user_from_token(&self, token: &str) -> ServerResult<User>
+    {
+        let now = self.epoch();
+        let session = SQL_TUPLE_NAMED!(
+            self,
+            "select id, user, expires from sessions where token = :token",
+            named_params! {":token": token},
+            i64,
+            i64,
+            i64
+        )?;
+
+        let (id, user_id, expires) = match session.first()
+        {
+            Some(&session) => session,
+            None => return Err(ServerError::InvalidSession),
+        };
+
+        if expires <= now
+        {
+            self.conn
+                .execute_named("delete from sessions where id = :id", named_params! {":id": id})?;
+            return Err(ServerError::InvalidSession);
+        }
+
+        if expires - now < SESSION_LENGTH_MS - SESSION_RENEW_INTERVAL_MS
+        {
+            self.conn.execute_named(
+                "update sessions set expires = :expires where id = :id",
+                named_params! {":expires": now + SESSION_LENGTH_MS, ":id": id},
+            )?;
+        }
+
+        self.get_user_without_matches_by("id", "=", &user_id.to_string())
+    }
+
+    pub fn logout(&self, token: String) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let user = self.get_user_from_token(&token)?;
+            self.conn.execute_named(
+                "delete from sessions where token = :token",
+                named_params! {":token": token},
+            )?;
+            let target = format!("user:{}", user.name);
+            self.audit(user.id, "logout", &target, Value::Null, Value::Null)
+        })
+    }
+
+    pub fn logout_everywhere(&self, token: String) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let user = self.get_user_from_token(&token)?;
+            self.revoke_sessions(user.id)?;
+            let target = format!("user:{}", user.name);
+            self.audit(user.id, "logout_everywhere", &target, Value::Null, Value::Null)
+        })
+    }
+
+    pub(crate) fn revoke_sessions(&self, user_id: i64) -> ServerResult<()>
+    {
+        self.conn.execute_named(
+            "delete from sessions where user = :user",
+            named_params! {":user": user_id},
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn delete_expired_sessions(&self) -> ServerResult<()>
+    {
+        self.conn.execute_named(
+            "delete from sessions where expires <= :now",
+            named_params! {":now": self.epoch()},
+        )?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use rusqlite::named_params;
+
+    use super::*;
+    use crate::test_util::*;
+
+    fn set_expires(s: &DataBase, token: &str, expires: i64)
+    {
+        s.conn
+            .execute_named(
+                "update sessions set expires = :expires where token = :token",
+                named_params! {":expires": expires, ":token": token},
+            )
+            .unwrap();
+    }
+
+    fn get_expires(s: &DataBase, token: &str) -> i64
+    {
+        s.conn
+            .query_row_named(
+                "select expires from sessions where token = :token",
+                named_params! {":token": token},
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_every_login_gets_its_own_session()
+    {
+        let db_file = "tempS1.db";
+        let s = DataBase::new(db_file);
+        create_user(&s, "Sivert");
+
+        let phone = s.login_on_device("Sivert".to_string(), "password".to_string(), "phone");
+        let laptop = s.login_on_device("Sivert".to_string(), "password".to_string(), "laptop");
+        let (phone, laptop) = (phone.unwrap(), laptop.unwrap());
+        let phone_user = s.get_user_from_token(&phone);
+        let laptop_user = s.get_user_from_token(&laptop);
+
+        std::fs::remove_file(db_file).expect("Removing file tempS1");
+        assert_ne!(phone, laptop);
+        assert_eq!(phone_user.unwrap().name, "Sivert");
+        assert_eq!(laptop_user.unwrap().name, "Sivert");
+    }
+
+    #[test]
+    fn test_expired_session_is_rejected()
+    {
+        let db_file = "tempS2.db";
+        let s = DataBase::new(db_file);
+        let token = create_user(&s, "Sivert");
+        set_expires(&s, &token, s.epoch() - 1);
+
+        let res = s.get_user_from_token(&token);
+        let sessions = get_table_size(&s, "sessions");
+
+        std::fs::remove_file(db_file).expect("Removing file tempS2");
+        assert_eq!(res.unwrap_err(), ServerError::InvalidSession);
+        assert_eq!(sessions, 0);
+    }
+
+    #[test]
+    fn test_session_is_renewed_when_used()
+    {
+        let db_file = "tempS3.db";
+        let s = DataBase::new(db_file);
+        let token = create_user(&s, "Sivert");
+        let fresh = get_expires(&s, &token);
+        let old = s.epoch() + SESSION_RENEW_INTERVAL_MS;
+        set_expires(&s, &token, old);
+
+        s.get_user_from_token(&token).unwrap();
+        let renewed = get_expires(&s, &token);
+        s.get_user_from_token(&token).unwrap();
+        let unchanged = get_expires(&s, &token);
+
+        std::fs::remove_file(db_file).expect("Removing file tempS3");
+        assert!(renewed >= fresh);
+        assert_eq!(renewed, unchanged);
+    }
+
+    #[test]
+    fn test_logout_only_ends_one_session()
+    {
+        let db_file = "tempS4.db";
+        let s = DataBase::new(db_file);
+        create_user(&s, "Sivert");
+        let phone = s.login("Sivert".to_string(), "password".to_string()).unwrap();
+        let laptop = s.login("Sivert".to_string(), "password".to_string()).unwrap();
+
+        s.logout(phone.clone()).unwrap();
+        let phone_user = s.get_user_from_token(&phone);
+        let laptop_user = s.get_user_from_token(&laptop);
+
+        std::fs::remove_file(db_file).expect("Removing file tempS4");
+        assert_eq!(phone_user.unwrap_err(), ServerError::InvalidSession);
+        assert!(laptop_user.is_ok());
+    }
+
+    #[test]
+    fn test_logout_everywhere_ends_all_sessions()
+    {
+        let db_file = "tempS5.db";
+        let s = DataBase::new(db_file);
+        let token = create_user(&s, "Sivert");
+        let other = create_user(&s, "Lars");
+        let laptop = s.login("Sivert".to_string(), "password".to_string()).unwrap();
+
+        s.logout_everywhere(token.clone()).unwrap();
+        let res = s.get_user_from_token(&token);
+        let laptop_res = s.get_user_from_token(&laptop);
+        let other_res = s.get_user_from_token(&other);
+
+        std::fs::remove_file(db_file).expect("Removing file tempS5");
+        assert!(res.is_err() && laptop_res.is_err());
+        assert!(other_res.is_ok());
+    }
+
+    #[test]
+    fn test_changing_password_ends_all_sessions()
+    {
+        let db_file = "tempS6.db";
+        let s = DataBase::new(db_file);
+        let token = create_user(&s, "Sivert");
+
+        s.change_password("Sivert".to_string(), "password".to_string(), "new".to_string())
+            .unwrap();
+        let res = s.get_user_from_token(&token);
+
+        std::fs::remove_file(db_file).expect("Removing file tempS6");
+        assert_eq!(res.unwrap_err(), ServerError::InvalidSession);
+    }
+}
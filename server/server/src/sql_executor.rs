@@ -1,42 +1,422 @@
-use crate::server::DataBase;
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
 
-fn get_row_value(row: &rusqlite::Row, index: usize) -> rusqlite::Result<String>
+use rusqlite::{limits::Limit, named_params, params, ErrorCode, NO_PARAMS};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use server_core::{constants::*, types::*};
+
+use crate::{
+    password::verify_password,
+    pool::DataBasePool,
+    server::DataBase,
+    validate::{Validate, Validator},
+    SQL_TUPLE_NAMED,
+};
+
+#[derive(Deserialize)]
+pub struct SqlCommand
+{
+    pub command:  String,
+    // Statements can only read unless write is set, which needs the password
+    // of the admin
+    #[serde(default)]
+    pub write:    bool,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default)]
+    pub limit:    Option<usize>,
+}
+
+impl Validate for SqlCommand
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        let limit = self.limit.unwrap_or(ADMIN_SQL_MAX_ROWS);
+        Validator::new()
+            .not_empty("command", &self.command)
+            .check(!self.write || !self.password.is_empty(), "password", "Is needed to write")
+            .check((1..=ADMIN_SQL_MAX_ROWS).contains(&limit), "limit", "Must be between 1 and 1000")
+            .finish()
+    }
+}
+
+#[derive(Serialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct SqlResult
+{
+    pub columns:   Vec<String>,
+    pub rows:      Vec<Vec<Value>>,
+    // There were more rows than the limit
+    pub truncated: bool,
+    // The rows inserted, updated or deleted by the statement
+    pub changes:   i64,
+}
+
+fn get_row_value(row: &rusqlite::Row, index: usize) -> rusqlite::Result<Value>
 {
     use rusqlite::types::ValueRef::*;
     let res = row.get_raw_checked(index)?;
     Ok(match res
     {
-        Null => "NULL".to_string(),
-        Integer(i) => i.to_string(),
-        Real(f) => f.to_string(),
-        Text(s) => std::str::from_utf8(s).unwrap().to_string(),
-        Blob(b) => std::str::from_utf8(b).unwrap().to_string(),
+        Null => Value::Null,
+        Integer(i) => Value::from(i),
+        Real(f) => Value::from(f),
+        Text(s) => Value::from(String::from_utf8_lossy(s)),
+        Blob(b) => Value::from(base64::encode(b)),
     })
 }
 
+// The error of a statement is shown to the admin who wrote it
+fn command_error(error: rusqlite::Error, timeout: Duration) -> ServerError
+{
+    let message = match &error
+    {
+        rusqlite::Error::SqliteFailure(e, _) if e.code == ErrorCode::OperationInterrupted =>
+        {
+            format!("Stopped after {} ms", timeout.as_millis())
+        },
+        rusqlite::Error::SqliteFailure(e, _) if e.code == ErrorCode::ReadOnly =>
+        {
+            "Only write mode can change the database".to_string()
+        },
+        _ => error.to_string(),
+    };
+    ServerError::InvalidRequest(vec![FieldError::new("command", message)])
+}
+
+impl DataBasePool
+{
+    // A statement that only reads runs on a reader, so a slow one does not
+    // hold up every write until it times out. The writer still checks the
+    // admin, whose session may be renewed, and logs the statement.
+    pub fn execute_sql(&self, token: String, info: SqlCommand) -> ServerResult<SqlResult>
+    {
+        if info.write
+        {
+            return self.write().execute_sql(token, info);
+        }
+
+        let timeout = Duration::from_secs(ADMIN_SQL_TIMEOUT_SECS);
+        let admin = self.write().authorize_sql(&token, &info)?;
+        let result = self.read().run_command(&info, timeout);
+        self.write().log_sql(admin, info, result)
+    }
+}
+
 impl DataBase
 {
-    pub fn execute_sql(&self, string: String) -> rusqlite::Result<String>
+    // Runs one statement for an admin. The connection is made query only, and
+    // can not attach other databases, unless the admin asks to write and gives
+    // their password again. Every statement run is logged, along with its
+    // error if it failed.
+    pub fn execute_sql(&self, token: String, info: SqlCommand) -> ServerResult<SqlResult>
+    {
+        self.execute_sql_with_timeout(token, info, Duration::from_secs(ADMIN_SQL_TIMEOUT_SECS))
+    }
+
+    fn execute_sql_with_timeout(
+        &self,
+        token: String,
+        info: SqlCommand,
+        timeout: Duration,
+    ) -> ServerResult<SqlResult>
+    {
+        let admin = self.authorize_sql(&token, &info)?;
+        let result = self.run_command(&info, timeout);
+        self.log_sql(admin, info, result)
+    }
+
+    // The id of the admin, who has to give their password to write
+    fn authorize_sql(&self, token: &str, info: &SqlCommand) -> ServerResult<i64>
+    {
+        let admin = self.get_user_from_token(token)?;
+        if admin.user_role & USER_ROLE_SUPERUSER != USER_ROLE_SUPERUSER
+        {
+            return Err(ServerError::Unauthorized);
+        }
+        if info.write
+        {
+            let (hash, algorithm) = SQL_TUPLE_NAMED!(
+                self,
+                "select password_hash, hash_algorithm from users where id = :id",
+                named_params! {":id": admin.id},
+                String,
+                u8
+            )?
+            .pop()
+            .ok_or(ServerError::UserNotExist)?;
+            if !verify_password(&info.password, &hash, algorithm)
+            {
+                return Err(ServerError::PasswordNotMatch);
+            }
+        }
+        Ok(admin.id)
+    }
+
+    fn run_command(&self, info: &SqlCommand, timeout: Duration) -> ServerResult<SqlResult>
+    {
+        let limit = info.limit.unwrap_or(ADMIN_SQL_MAX_ROWS);
+        self.conn.execute_batch(
+            if info.write { "pragma query_only = 0" } else { "pragma query_only = 1" },
+        )?;
+        // Query only still lets ATTACH and VACUUM INTO create files, and both
+        // need to attach a database
+        let attached = self.conn.limit(Limit::SQLITE_LIMIT_ATTACHED);
+        if !info.write
+        {
+            self.conn.set_limit(Limit::SQLITE_LIMIT_ATTACHED, 0);
+        }
+        let result = self.run_with_timeout(&info.command, limit, timeout);
+        self.conn.execute_batch("pragma query_only = 0")?;
+        self.conn.set_limit(Limit::SQLITE_LIMIT_ATTACHED, attached);
+
+        // Query only does not stop BEGIN or SAVEPOINT, and a transaction left
+        // open would hide every later write on the connection
+        if !self.conn.is_autocommit()
+        {
+            self.conn.execute_batch("rollback")?;
+            return Err(ServerError::InvalidRequest(vec![FieldError::new(
+                "command",
+                "Can not leave a transaction open",
+            )]));
+        }
+        result.map_err(|e| command_error(e, timeout))
+    }
+
+    fn log_sql(
+        &self,
+        admin: i64,
+        info: SqlCommand,
+        result: ServerResult<SqlResult>,
+    ) -> ServerResult<SqlResult>
     {
-        use rusqlite::NO_PARAMS;
+        let (rows, error) = match &result
+        {
+            Ok(res) => (res.rows.len() as i64, String::new()),
+            Err(ServerError::InvalidRequest(fields)) => (0, fields[0].message.clone()),
+            Err(e) => (0, format!("{:?}", e)),
+        };
+        self.conn.execute(
+            "insert into admin_sql_log (user, epoch, command, write, rows, error)
+             values (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![admin, self.epoch(), info.command, info.write, rows, error],
+        )?;
+        if info.write && result.is_ok()
+        {
+            let after = json!({ "command": info.command });
+            self.audit(admin, "execute_sql", "database", Value::Null, after)?;
+        }
+        result
+    }
 
-        let mut stmt = self.conn.prepare(&string)?;
+    // The statement is interrupted if it is still running after the timeout
+    fn run_with_timeout(
+        &self,
+        command: &str,
+        limit: usize,
+        timeout: Duration,
+    ) -> rusqlite::Result<SqlResult>
+    {
+        let interrupt = self.conn.get_interrupt_handle();
+        let (done, finished) = mpsc::channel::<()>();
+        let watchdog = std::thread::spawn(move || {
+            if finished.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout)
+            {
+                interrupt.interrupt();
+            }
+        });
 
-        let col_count = stmt.column_count();
+        let result = self.run_sql(command, limit);
+        // The watchdog has already stopped if the statement timed out
+        let _ = done.send(());
+        watchdog.join().expect("Joining sql watchdog");
+        result
+    }
+
+    fn run_sql(&self, command: &str, limit: usize) -> rusqlite::Result<SqlResult>
+    {
+        let total_changes = || {
+            self.conn
+                .query_row("select total_changes()", NO_PARAMS, |row| row.get::<_, i64>(0))
+        };
+        let before = total_changes()?;
+
+        let mut stmt = self.conn.prepare(command)?;
+        let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
         let mut rows = stmt.query(NO_PARAMS)?;
 
         let mut output = Vec::new();
+        let mut truncated = false;
         while let Some(row) = rows.next()?
         {
-            let mut row_vec = Vec::new();
-            for i in 0..col_count
+            if output.len() == limit
             {
-                row_vec.push(get_row_value(row, i)?);
+                truncated = true;
+                break;
             }
-            let s = row_vec.join(", ");
-            output.push(s)
+            let values = (0..columns.len())
+                .map(|i| get_row_value(row, i))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            output.push(values);
+        }
+        drop(rows);
+        drop(stmt);
+
+        Ok(SqlResult {
+            columns,
+            rows: output,
+            truncated,
+            changes: total_changes()? - before,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use crate::test_util::*;
+
+    fn command(command: &str, write: bool, password: &str) -> SqlCommand
+    {
+        SqlCommand {
+            command: command.to_string(),
+            write,
+            password: password.to_string(),
+            limit: None,
         }
+    }
+
+    fn log_errors(s: &DataBase) -> Vec<(String, bool)>
+    {
+        s.conn
+            .prepare("select command, error from admin_sql_log order by id")
+            .unwrap()
+            .query_map(NO_PARAMS, |row| {
+                Ok((row.get::<_, String>(0)?, !row.get::<_, String>(1)?.is_empty()))
+            })
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_admin_sql_is_read_only_without_password()
+    {
+        let db_file = "tempK1.db";
+        let s = DataBase::new(db_file);
+        let token = create_user(&s, "Admin");
+        let user_token = create_user(&s, "User");
+        make_user_admin(&s, "Admin".to_string()).unwrap();
+
+        let select = "select id, name, 0.5 as half, null as missing from users order by id";
+        let update = "update users set name = 'Renamed' where name = 'User'";
+        let not_admin = s.execute_sql(user_token, command(select, false, ""));
+        let read = s.execute_sql(token.clone(), command(select, false, "")).unwrap();
+        let read_only = s.execute_sql(token.clone(), command(update, false, ""));
+        // Other writes still work after a read only statement
+        create_user(&s, "Other");
+        let wrong_password = s.execute_sql(token.clone(), command(update, true, "wrong"));
+        let write = s.execute_sql(token.clone(), command(update, true, "password")).unwrap();
+        let mut limited = command("select name from users order by id", false, "");
+        limited.limit = Some(2);
+        let limited = s.execute_sql(token, limited).unwrap();
+        let log = log_errors(&s);
+
+        std::fs::remove_file(db_file).expect("Removing file tempK1");
+        assert_eq!(not_admin, Err(ServerError::Unauthorized));
+        assert_eq!(read.columns, vec!["id", "name", "half", "missing"]);
+        assert_eq!(read.rows[0], vec![json!(1), json!("Admin"), json!(0.5), json!(null)]);
+        assert_eq!(read.changes, 0);
+        assert!(matches!(read_only, Err(ServerError::InvalidRequest(_))));
+        assert_eq!(wrong_password, Err(ServerError::PasswordNotMatch));
+        assert_eq!(write.changes, 1);
+        assert_eq!(limited.rows, vec![vec![json!("Admin")], vec![json!("Renamed")]]);
+        assert!(limited.truncated);
+        // The password is checked before anything is run
+        assert_eq!(log, vec![
+            (select.to_string(), false),
+            (update.to_string(), true),
+            (update.to_string(), false),
+            ("select name from users order by id".to_string(), false),
+        ]);
+    }
+
+    #[test]
+    fn test_admin_sql_stops_after_timeout()
+    {
+        let db_file = "tempK2.db";
+        let s = DataBase::new(db_file);
+        let token = create_user(&s, "Admin");
+        make_user_admin(&s, "Admin".to_string()).unwrap();
+
+        let forever = "with recursive n(x) as (select 1 union all select x + 1 from n)
+                       select count(*) from n";
+        let timeout = Duration::from_millis(100);
+        let stopped =
+            s.execute_sql_with_timeout(token.clone(), command(forever, false, ""), timeout);
+        let after = s
+            .execute_sql_with_timeout(token, command("select 1", false, ""), timeout)
+            .unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempK2");
+        assert_eq!(
+            stopped,
+            Err(ServerError::InvalidRequest(vec![FieldError::new(
+                "command",
+                "Stopped after 100 ms"
+            )]))
+        );
+        assert_eq!(after.rows, vec![vec![json!(1)]]);
+    }
+
+    #[test]
+    fn test_admin_sql_can_not_create_files_without_password()
+    {
+        let db_file = "tempK3.db";
+        let s = DataBase::new(db_file);
+        let token = create_user(&s, "Admin");
+        make_user_admin(&s, "Admin".to_string()).unwrap();
+
+        let attach =
+            s.execute_sql(token.clone(), command("attach 'tempK4.db' as other", false, ""));
+        let vacuum = s.execute_sql(token.clone(), command("vacuum into 'tempK5.db'", false, ""));
+        let created = ["tempK4.db", "tempK5.db"].iter().any(|f| std::path::Path::new(f).exists());
+        let read = s.execute_sql(token, command("select count(*) from users", false, "")).unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempK3");
+        assert!(matches!(attach, Err(ServerError::InvalidRequest(_))));
+        assert!(matches!(vacuum, Err(ServerError::InvalidRequest(_))));
+        assert!(!created);
+        assert_eq!(read.rows, vec![vec![json!(1)]]);
+    }
+
+    #[test]
+    fn test_admin_sql_can_not_leave_a_transaction_open()
+    {
+        let db_file = "tempK6.db";
+        let pool = DataBasePool::new(db_file, 1);
+        let token = create_user(&pool.write(), "Admin");
+        make_user_admin(&pool.write(), "Admin".to_string()).unwrap();
+
+        let read_begin = pool.execute_sql(token.clone(), command("begin", false, ""));
+        let writer_begin = pool.write().execute_sql(token.clone(), command("begin", false, ""));
+        let savepoint =
+            pool.write().execute_sql(token, command("savepoint open", true, "password"));
+        create_user(&pool.write(), "Other");
+        let other = pool.read().get_user_without_matches("Other");
+        let logged = get_table_size(&pool.read(), "admin_sql_log");
 
-        Ok(output.join("\n"))
+        drop(pool);
+        std::fs::remove_file(db_file).expect("Removing file tempK6");
+        assert!(matches!(read_begin, Err(ServerError::InvalidRequest(_))));
+        assert!(matches!(writer_begin, Err(ServerError::InvalidRequest(_))));
+        assert!(matches!(savepoint, Err(ServerError::InvalidRequest(_))));
+        assert!(other.is_ok());
+        assert_eq!(logged, 3);
     }
 }
@@ -37,6 +37,14 @@ pub fn create_user(s: &DataBase, name: &str) -> String
             ],
         )
         .unwrap();
+
+    // Logged in with the uuid as the session token
+    s.conn
+        .execute(
+            "insert into sessions (token, user, created, expires) values (?1, ?2, ?3, ?4)",
+            params![uuid, s.conn.last_insert_rowid(), s.epoch(), s.epoch() + SESSION_LENGTH_MS],
+        )
+        .unwrap();
     uuid
 }
 
@@ -55,7 +63,9 @@ pub fn respond_to_match(s: &DataBase, name: &str, id: i64)
 {
     let mut stmt = s
         .conn
-        .prepare("select uuid from users where name = :name")
+        .prepare(
+            "select token from sessions s inner join users u on s.user = u.id where u.name = :name",
+        )
         .expect("Creating query");
     let token: String = stmt
         .query_map_named(named_params! {":name": name}, |row| {
@@ -98,3 +108,18 @@ pub fn create_tournament_image(s: &DataBase)
 {
     s.conn.execute("insert into images (name) values (?1)", params![format!("3.png")]).unwrap();
 }
+
+// Every statement of the kind, insert, update or delete, on the table fails
+#[cfg(test)]
+pub fn fail_on(s: &DataBase, kind: &str, table: &str)
+{
+    s.conn
+        .execute_batch(&format!(
+            "create temp trigger fail_{0}_{1} before {0} on {1}
+             begin
+                 select raise(abort, 'Injected failure');
+             end;",
+            kind, table
+        ))
+        .unwrap();
+}
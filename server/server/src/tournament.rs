@@ -2,7 +2,7 @@ use std::{collections::HashMap, io::prelude::*};
 
 use rusqlite::params;
 use serde_derive::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use server_core::{
     constants::*,
     types::{FromSql, *},
@@ -11,7 +11,13 @@ use server_macro::Sql;
 
 use crate::{
     _params,
+    score::{scores_to_sql, validate_scores, GameScore},
     server::{DataBase, ParamsType},
+    tournament_groups::{check_group_stage, GroupStageOptions},
+    tournament_placements::{Podium, THIRD_PLACE_BUCKET},
+    tournament_round_robin::Standing,
+    tournament_seeding::{bracket_slots, check_seeding, SeedingOptions},
+    validate::{Validate, Validator},
 };
 
 #[cfg_attr(test, derive(Debug, PartialOrd, Ord, Eq, PartialEq))]
@@ -19,6 +25,9 @@ pub enum TournamentType
 {
     SingleElimination,
     DoubleElimination,
+    RoundRobin,
+    Swiss,
+    GroupStage,
 }
 
 impl From<u8> for TournamentType
@@ -29,11 +38,30 @@ impl From<u8> for TournamentType
         {
             0 => TournamentType::SingleElimination,
             1 => TournamentType::DoubleElimination,
+            2 => TournamentType::RoundRobin,
+            3 => TournamentType::Swiss,
+            4 => TournamentType::GroupStage,
             _ => unreachable!(),
         }
     }
 }
 
+impl TournamentType
+{
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name
+        {
+            "singleElimination" => Some(TournamentType::SingleElimination),
+            "doubleElimination" => Some(TournamentType::DoubleElimination),
+            "roundRobin" => Some(TournamentType::RoundRobin),
+            "swiss" => Some(TournamentType::Swiss),
+            "groupStage" => Some(TournamentType::GroupStage),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub struct GetTournamentOptions
 {
@@ -59,28 +87,117 @@ pub struct TournamentBadge
 #[derive(Deserialize)]
 pub struct JoinTournament
 {
-    pub token: String,
-    pub tid:   i64,
+    pub tid: i64,
 }
 
 #[derive(Deserialize)]
 pub struct CreateTournament
 {
-    organizer_token: String,
-    name:            String,
-    image:           String,
-    player_count:    i64,
-    ttype:           String,
+    #[serde(skip_deserializing)]
+    pub organizer_token:   String,
+    name:                  String,
+    image:                 String,
+    player_count:          i64,
+    ttype:                 String,
+    // Only used by group stage tournaments
+    #[serde(default)]
+    group_stage:           Option<GroupStageOptions>,
+    // Random when left out
+    #[serde(default)]
+    seeding:               Option<SeedingOptions>,
+    // The games of a rated tournament count towards the ratings of the players
+    #[serde(default)]
+    rated:                 bool,
+    // Epoch in milliseconds, the tournament starts with the players who have
+    // joined by then
+    #[serde(default)]
+    registration_deadline: Option<i64>,
+    // A game for third place between the losing semifinalists of a single
+    // elimination bracket
+    #[serde(default)]
+    third_place_game:      bool,
 }
 
 #[derive(Deserialize)]
 #[cfg_attr(test, derive(Debug, Clone, PartialOrd, Ord, Eq, PartialEq))]
 pub struct RegisterTournamentMatch
 {
-    organizer_token: String,
-    winner:          String,
-    loser:           String,
-    tournament_game: i64,
+    #[serde(skip_deserializing)]
+    pub organizer_token: String,
+    pub winner:          String,
+    pub loser:           String,
+    pub tournament_game: i64,
+    #[serde(default)]
+    pub scores:          Vec<GameScore>,
+}
+
+impl Validate for CreateTournament
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        let mut validator = Validator::new();
+        validator
+            .not_empty("name", &self.name)
+            .check(
+                (MIN_TOURNAMENT_PLAYERS..=MAX_TOURNAMENT_PLAYERS).contains(&self.player_count),
+                "player_count",
+                "Must be between 4 and 64",
+            )
+            .check(
+                self.image.is_empty() || decode_image(&self.image).is_some(),
+                "image",
+                "Must be a base64 data url",
+            );
+        if self.ttype == "groupStage"
+        {
+            check_group_stage(&mut validator, self.player_count, self.group_stage.as_ref());
+        }
+        if let Some(seeding) = &self.seeding
+        {
+            check_seeding(&mut validator, seeding);
+        }
+        if let Some(deadline) = self.registration_deadline
+        {
+            validator.check(deadline > 0, "registration_deadline", "Must be positive");
+        }
+        // The bracket of a group stage can also have a third place game
+        let bracket = match self.ttype.as_str()
+        {
+            "groupStage" => self.group_stage.as_ref().map(|options| options.knockout.as_str()),
+            ttype => Some(ttype),
+        };
+        validator.check(
+            !self.third_place_game || bracket == Some("singleElimination"),
+            "third_place_game",
+            "Only single elimination brackets have a third place game",
+        );
+        validator.finish()
+    }
+}
+
+impl Validate for RegisterTournamentMatch
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new()
+            .not_empty("winner", &self.winner)
+            .not_empty("loser", &self.loser)
+            .finish()
+    }
+}
+
+impl Validate for JoinTournament
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new().check(self.tid > 0, "tid", "Must be positive").finish()
+    }
+}
+
+// The image is sent as a data url, `data:image/png;base64,<data>`
+fn decode_image(image: &str) -> Option<Vec<u8>>
+{
+    image.splitn(2, ',').nth(1).and_then(|data| base64::decode(data).ok())
 }
 
 #[repr(u8)]
@@ -124,6 +241,12 @@ struct TournamentGameInfo
     player2:       String,
     bucket:        i64,
     parent_bucket: i64,
+    // Empty until the game has been played
+    winner:        String,
+    forfeit:       bool,
+    // Zero until the game has been scheduled
+    start:         i64,
+    table:         i64,
 }
 
 #[derive(Sql)]
@@ -143,31 +266,48 @@ pub struct TournamentInfo
     tournament: SendTournament,
     data:       TournamentInfoState,
     table:      Option<String>,
+    // Only round robin and swiss tournaments have standings
+    standings:  Option<Vec<Standing>>,
+    // The standings of each group and the knockout bracket of a group stage
+    groups:     Option<Vec<Vec<Standing>>>,
+    bracket:    Option<Box<TournamentInfo>>,
+    // The players who have withdrawn from the tournament
+    withdrawn:  Vec<String>,
 }
 
 #[derive(Sql)]
 pub struct Tournament
 {
-    pub id:           i64,
-    pub name:         String,
-    pub prize:        i64,
-    pub state:        u8,
-    pub ttype:        u8,
-    pub player_count: i64,
-    pub organizer:    i64,
+    pub id:                    i64,
+    pub name:                  String,
+    pub prize:                 i64,
+    pub state:                 u8,
+    pub ttype:                 u8,
+    pub player_count:          i64,
+    pub organizer:             i64,
+    pub rated:                 bool,
+    pub registration_deadline: i64,
+    pub third_place_game:      bool,
 }
 
 #[derive(Serialize)]
 pub struct SendTournament
 {
-    id:             i64,
-    name:           String,
-    prize:          String,
-    player_count:   i64,
-    state:          u8,
-    ttype:          u8,
-    organizer_name: String,
-    winner:         String,
+    id:                    i64,
+    name:                  String,
+    prize:                 String,
+    player_count:          i64,
+    state:                 u8,
+    ttype:                 u8,
+    organizer_name:        String,
+    winner:                String,
+    // Empty until the tournament is done, both losing semifinalists are third
+    // when there is no third place game
+    second_place:          String,
+    third_place:           Vec<String>,
+    rated:                 bool,
+    registration_deadline: i64,
+    third_place_game:      bool,
 }
 
 #[derive(Sql)]
@@ -195,10 +335,14 @@ pub struct TournamentGame
 #[cfg_attr(test, derive(Debug, PartialEq))]
 pub struct TournamentMatch
 {
-    pub id:     i64,
-    pub game:   i64,
-    pub winner: i64,
-    pub loser:  i64,
+    pub id:          i64,
+    pub game:        i64,
+    pub winner:      i64,
+    pub loser:       i64,
+    // The match in `matches` when the tournament is rated, otherwise 0
+    pub rated_match: i64,
+    // A walkover, the loser had withdrawn from the tournament
+    pub forfeit:     bool,
 }
 
 impl TournamentGame
@@ -206,22 +350,22 @@ impl TournamentGame
     fn empty(tid: i64, bucket: i64) -> Self
     {
         TournamentGame {
-            id:         -1, // Will be initialized by sqlite
+            id: -1, // Will be initialized by sqlite
             tournament: tid,
-            player1:    0,
-            player2:    0,
-            bucket:     bucket,
+            player1: 0,
+            player2: 0,
+            bucket,
         }
     }
 
     fn players(tid: i64, bucket: i64, p1: i64, p2: i64) -> Self
     {
         TournamentGame {
-            id:         -1, // Will be initialized by sqlite
+            id: -1, // Will be initialized by sqlite
             tournament: tid,
-            player1:    p1,
-            player2:    p2,
-            bucket:     bucket,
+            player1: p1,
+            player2: p2,
+            bucket,
         }
     }
 
@@ -300,32 +444,59 @@ impl DataBase
 {
     pub fn create_tournament(&self, info: CreateTournament) -> ServerResult<()>
     {
-        let tournament = self
-            .sql_one::<Tournament, _>("select * from tournaments order by id desc limit 1", None)
-            .map(|t| t.id + 1)
-            .unwrap_or(1);
+        self.atomically(|| {
+            let tournament = self
+                .sql_one::<Tournament, _>(
+                    "select * from tournaments order by id desc limit 1",
+                    None,
+                )
+                .map(|t| t.id + 1)
+                .unwrap_or(1);
 
-        // Use default picture
-        let prize = if info.image.is_empty()
-        {
-            self.get_default_prize()?
-        }
-        else
-        {
-            self.create_image_prize(info.image, tournament)?
-        };
+            let organizer_pid = self.get_user_from_token(&info.organizer_token)?.id;
 
-        let organizer_pid =
-            self.get_user_without_matches_by("uuid", "=", &info.organizer_token)?.id;
+            // Use default picture
+            let prize = if info.image.is_empty()
+            {
+                self.get_default_prize()?
+            }
+            else
+            {
+                self.create_image_prize(info.image, tournament)?
+            };
 
-        let ttype = match info.ttype.as_str()
-        {
-            "singleElimination" => TournamentType::SingleElimination,
-            "doubleElimination" => TournamentType::DoubleElimination,
-            _ => return Err(ServerError::Tournament(TournamentError::InvalidTtype)),
-        };
-        self._create_tournament(organizer_pid, info.name, prize, info.player_count, ttype)?;
-        Ok(())
+            let ttype = TournamentType::from_name(&info.ttype)
+                .ok_or(ServerError::Tournament(TournamentError::InvalidTtype))?;
+            let group_stage = match ttype
+            {
+                TournamentType::GroupStage => Some(info.group_stage.ok_or_else(|| {
+                    ServerError::InvalidRequest(vec![FieldError::new(
+                        "group_stage",
+                        "Required for group stage tournaments",
+                    )])
+                })?),
+                _ => None,
+            };
+            let after = json!({"name": info.name, "ttype": info.ttype, "rated": info.rated});
+            self._create_tournament(organizer_pid, info.name, prize, info.player_count, ttype)?;
+            let tid = self.conn.last_insert_rowid();
+            self.set_tournament_rated(tid, info.rated)?;
+            self.set_third_place_game(tid, info.third_place_game)?;
+            if let Some(options) = group_stage
+            {
+                self.create_group_stage(tid, &options)?;
+            }
+            if let Some(options) = info.seeding
+            {
+                self.create_seeding(tid, &options)?;
+            }
+            if let Some(deadline) = info.registration_deadline
+            {
+                self.set_registration_deadline(tid, deadline)?;
+            }
+            let target = format!("tournament:{}", tid);
+            self.audit(organizer_pid, "create_tournament", &target, Value::Null, after)
+        })
     }
 
     fn get_default_prize(&self) -> ServerResult<i64>
@@ -350,13 +521,14 @@ impl DataBase
 
     fn create_image_prize(&self, image: String, tournament: i64) -> ServerResult<i64>
     {
-        let image_name = format!("{}/{}.png", TOURNAMENT_BADGES_PATH, tournament);
-        let mut file = std::fs::File::create(&format!("{}/{}", ASSETS_PATH, &image_name))
-            .expect("creating file");
+        let bin = decode_image(&image).ok_or_else(|| {
+            ServerError::InvalidRequest(vec![FieldError::new("image", "Must be a base64 data url")])
+        })?;
 
-        let bin: Vec<&str> = image.as_str().splitn(2, ',').collect();
-        let bin = base64::decode(bin[1]).unwrap();
-        file.write_all(&bin).expect("Writing into file");
+        let image_name = format!("{}/{}.png", TOURNAMENT_BADGES_PATH, tournament);
+        std::fs::File::create(format!("{}/{}", ASSETS_PATH, &image_name))
+            .and_then(|mut file| file.write_all(&bin))
+            .map_err(|e| ServerError::Critical(format!("Writing {}: {}", image_name, e)))?;
 
         self.create_image_prize_table(image_name)
     }
@@ -369,7 +541,7 @@ impl DataBase
             .map(|t| t.id)
     }
 
-    fn _create_tournament(
+    pub(crate) fn _create_tournament(
         &self,
         pid: i64,
         name: String,
@@ -378,7 +550,7 @@ impl DataBase
         ttype: TournamentType,
     ) -> ServerResult<()>
     {
-        if player_count < 4 || player_count > 64
+        if !(MIN_TOURNAMENT_PLAYERS..=MAX_TOURNAMENT_PLAYERS).contains(&player_count)
         {
             return Err(ServerError::Tournament(TournamentError::WrongTournamentCount));
         }
@@ -393,22 +565,27 @@ impl DataBase
 
     pub fn leave_tournament(&self, token: String, tid: i64) -> ServerResult<()>
     {
-        let tournament = self
-            .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![tid])?;
-        if tournament.state != TournamentState::Created as u8
-        {
-            return Err(ServerError::Tournament(TournamentError::WrongState));
-        }
-        let pid = self.get_user_without_matches_by("uuid", "=", &token)?.id;
+        self.atomically(|| {
+            let tournament = self
+                .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![
+                    tid
+                ])?;
+            if tournament.state != TournamentState::Created as u8
+            {
+                return Err(ServerError::Tournament(TournamentError::WrongState));
+            }
+            let pid = self.get_user_from_token(&token)?.id;
 
-        self.conn.execute(
-            "delete from tournament_lists where tournament = ?1 and player = ?2",
-            params![tid, pid],
-        )?;
-        Ok(())
+            self.conn.execute(
+                "delete from tournament_lists where tournament = ?1 and player = ?2",
+                params![tid, pid],
+            )?;
+            let target = format!("tournament:{}", tid);
+            self.audit(pid, "leave_tournament", &target, Value::Null, Value::Null)
+        })
     }
 
-    fn get_player_ids(&self, tid: i64) -> ServerResult<Vec<i64>>
+    pub(crate) fn get_player_ids(&self, tid: i64) -> ServerResult<Vec<i64>>
     {
         let mut vec: Vec<i64> = Vec::new();
         for game in self
@@ -432,105 +609,126 @@ impl DataBase
 
     pub fn recreate_tournament(&self, token: String, tid: i64) -> ServerResult<i64>
     {
-        let organizer_id = self.get_user_without_matches_by("uuid", "=", &token)?.id;
-
-        let tournament = self
-            .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![tid])?;
-        if organizer_id != tournament.organizer
-        {
-            return Err(ServerError::Tournament(TournamentError::NotOrganizer));
-        }
+        self.atomically(|| {
+            let organizer_id = self.get_user_from_token(&token)?.id;
 
-        let players = self.get_player_ids(tournament.id)?;
-        let regex = regex::Regex::new(r"^(.*?)(\d+)$").unwrap();
-        println!("{}", tournament.name);
+            let tournament = self
+                .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![
+                    tid
+                ])?;
+            if organizer_id != tournament.organizer
+            {
+                return Err(ServerError::Tournament(TournamentError::NotOrganizer));
+            }
 
+            let players = self.get_player_ids(tournament.id)?;
+            let tid_old = tournament.id;
+            let regex = regex::Regex::new(r"^(.*?)(\d+)$").unwrap();
+            println!("{}", tournament.name);
 
 
-        let name = if let Some(capture) = regex.captures(&tournament.name)
-        {
-            match capture.get(1)
+            let name = if let Some(capture) = regex.captures(&tournament.name)
             {
-                Some(s) =>
+                match capture.get(1)
                 {
-                    let s = s.as_str();
-                    let val: i64 = match capture.get(2)
+                    Some(s) =>
                     {
-                        Some(v) => v.as_str().parse::<i64>().unwrap() + 1,
-                        _ => 2,
-                    };
-                    format!("{}{}", s, val)
-                },
-                _ =>
-                {
-                    let s = capture.get(2).unwrap().as_str();
-                    let val: i64 = s.parse::<i64>().unwrap() + 1;
-                    format!("{}", val)
-                },
+                        let s = s.as_str();
+                        let val: i64 = match capture.get(2)
+                        {
+                            Some(v) => v.as_str().parse::<i64>().unwrap() + 1,
+                            _ => 2,
+                        };
+                        format!("{}{}", s, val)
+                    },
+                    _ =>
+                    {
+                        let s = capture.get(2).unwrap().as_str();
+                        let val: i64 = s.parse::<i64>().unwrap() + 1;
+                        format!("{}", val)
+                    },
+                }
             }
-        }
-        else
-        {
-            format!("{}2", tournament.name)
-        };
+            else
+            {
+                format!("{}2", tournament.name)
+            };
 
 
-        self._create_tournament(
-            tournament.organizer,
-            name,
-            tournament.prize,
-            tournament.player_count,
-            tournament.ttype.into(),
-        )?;
+            self._create_tournament(
+                tournament.organizer,
+                name,
+                tournament.prize,
+                tournament.player_count,
+                tournament.ttype.into(),
+            )?;
+            let rated = tournament.rated;
+            let third_place_game = tournament.third_place_game;
 
-        let tournament = self
-            .sql_one::<Tournament, _>("select * from tournaments order by id desc limit 1", None)?;
+            let mut tournament = self.sql_one::<Tournament, _>(
+                "select * from tournaments order by id desc limit 1",
+                None,
+            )?;
 
-        let tid = tournament.id;
-        self.generate_tournament(tournament, players)?;
-        self.update_tournament_state(tid, TournamentState::InProgress)?;
+            let tid = tournament.id;
+            self.set_tournament_rated(tid, rated)?;
+            self.set_third_place_game(tid, third_place_game)?;
+            tournament.third_place_game = third_place_game;
+            self.copy_group_stage(tid_old, tid)?;
+            self.copy_seeding(tid_old, tid)?;
+            self.generate_tournament(tournament, players)?;
+            self.update_tournament_state(tid, TournamentState::InProgress)?;
 
-        Ok(tid)
+            let target = format!("tournament:{}", tid);
+            let after = json!({ "from": tid_old });
+            self.audit(organizer_id, "recreate_tournament", &target, Value::Null, after)?;
+            Ok(tid)
+        })
     }
 
     pub fn join_tournament(&self, token: String, tid: i64) -> ServerResult<bool>
     {
-        let user = self.get_user_without_matches_by("uuid", "=", token.as_str())?;
-        let list = self.sql_many::<TournamentList, _>(
-            "select * from tournament_lists where tournament = ?1",
-            _params![tid],
-        )?;
+        self.atomically(|| {
+            let user = self.get_user_from_token(&token)?;
+            let list = self.sql_many::<TournamentList, _>(
+                "select * from tournament_lists where tournament = ?1",
+                _params![tid],
+            )?;
 
-        let tournament = self
-            .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![tid])?;
-        if tournament.state != TournamentState::Created as u8
-        {
-            return Err(ServerError::Tournament(TournamentError::WrongState));
-        }
+            let tournament = self
+                .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![
+                    tid
+                ])?;
+            if tournament.state != TournamentState::Created as u8
+            {
+                return Err(ServerError::Tournament(TournamentError::WrongState));
+            }
 
-        if list.iter().map(|t| t.player).any(|pid| pid == user.id)
-        {
-            return Err(ServerError::Tournament(TournamentError::AlreadyJoined));
-        }
+            if list.iter().map(|t| t.player).any(|pid| pid == user.id)
+            {
+                return Err(ServerError::Tournament(TournamentError::AlreadyJoined));
+            }
 
 
-        if list.len() + 1 == tournament.player_count as usize
-        {
-            let mut players: Vec<i64> = list.into_iter().map(|tl| tl.player).collect();
-            players.push(user.id);
-            self.generate_tournament(tournament, players)?;
-            self.delete_tourament_list(tid)?;
-            self.update_tournament_state(tid, TournamentState::InProgress)?;
-            Ok(true)
-        }
-        else
-        {
-            self.add_player_to_tournament(tid, user.id)?;
-            Ok(false)
-        }
+            let started = if list.len() + 1 == tournament.player_count as usize
+            {
+                let mut players: Vec<i64> = list.into_iter().map(|tl| tl.player).collect();
+                players.push(user.id);
+                self.begin_tournament(tournament, players)?;
+                true
+            }
+            else
+            {
+                self.add_player_to_tournament(tid, user.id)?;
+                false
+            };
+            let target = format!("tournament:{}", tid);
+            self.audit(user.id, "join_tournament", &target, Value::Null, Value::Null)?;
+            Ok(started)
+        })
     }
 
-    fn get_all_tournament_games(&self, tid: i64) -> ServerResult<Vec<TournamentGame>>
+    pub(crate) fn get_all_tournament_games(&self, tid: i64) -> ServerResult<Vec<TournamentGame>>
     {
         self.sql_many::<TournamentGame, _>(
             "select * from tournament_games where tournament = ?1",
@@ -556,21 +754,9 @@ impl DataBase
         let delete_match = |old: &TournamentMatch| -> ServerResult<()> {
             self.conn
                 .execute("delete from tournament_matches where id = ?1", params![old.id])?;
-            Ok(())
-        };
-        let remove_previous_tournament_winner = || -> ServerResult<()> {
-            self.conn
-                .execute("delete from tournament_winners where tournament = ?1", params![
-                    tournament.id
-                ])?;
-            self.conn
-                .execute("delete from tournament_badges where tid = ?1", params![tournament.id])?;
-
-            // The state of the tournament should now also be not done
-            self.update_tournament_state(tournament.id, TournamentState::InProgress)?;
-
-            Ok(())
+            self.delete_rated_matches(&[old])
         };
+        let remove_previous_tournament_winner = || self.remove_tournament_winner(tournament.id);
 
         let parent_has_played = |bucket: i64| -> ServerResult<bool> {
             let next = self.get_parent_bucket(bucket, tournament);
@@ -592,8 +778,14 @@ impl DataBase
 
         if tournament.ttype == TournamentType::SingleElimination as u8
         {
-            // New tournament winner
-            if game.bucket == 0
+            // The loser of a semifinal has already played for third place
+            if (game.bucket == 1 || game.bucket == 2)
+                && self.get_bucket_match(tournament.id, THIRD_PLACE_BUCKET).is_some()
+            {
+                return Err(ServerError::Tournament(TournamentError::CannotRerun));
+            }
+            // New tournament winner, or a new third place
+            if game.bucket == 0 || game.bucket == THIRD_PLACE_BUCKET
             {
                 remove_previous_tournament_winner()?;
             }
@@ -685,36 +877,127 @@ impl DataBase
                 },
             };
         }
+        else if tournament.ttype == TournamentType::RoundRobin as u8
+        {
+            // Every game counts towards the standings, so the winner is decided again
+            if tournament.state == TournamentState::Done as u8
+            {
+                remove_previous_tournament_winner()?;
+            }
+            delete_match(old)?;
+        }
+        else if tournament.ttype == TournamentType::Swiss as u8
+        {
+            // The next round was paired using this result
+            if game.bucket != self.get_swiss_round(tournament.id)?
+                || tournament.state == TournamentState::Done as u8
+            {
+                return Err(ServerError::Tournament(TournamentError::CannotRerun));
+            }
+            delete_match(old)?;
+        }
+        else if tournament.ttype == TournamentType::GroupStage as u8
+        {
+            // The qualifiers are already in the bracket
+            if self.get_group_stage(tournament.id)?.bracket != 0
+            {
+                return Err(ServerError::Tournament(TournamentError::CannotRerun));
+            }
+            delete_match(old)?;
+        }
         Ok(())
     }
 
+    fn remove_tournament_winner(&self, tid: i64) -> ServerResult<()>
+    {
+        self.conn
+            .execute("delete from tournament_winners where tournament = ?1", params![tid])?;
+        self.conn
+            .execute("delete from tournament_badges where tid = ?1", params![tid])?;
+        self.delete_placements(tid)?;
+
+        // The state of the tournament should now also be not done
+        self.update_tournament_state(tid, TournamentState::InProgress)?;
+
+        // A new bracket winner also has to win the group stage again
+        match self.get_bracket_owner(tid)
+        {
+            Some(owner) => self.remove_tournament_winner(owner.id),
+            None => Ok(()),
+        }
+    }
+
     pub fn register_tournament_match(
         &self,
         register_game: RegisterTournamentMatch,
     ) -> ServerResult<()>
     {
-        let game = self.sql_one::<TournamentGame, _>(
-            "select * from tournament_games where id = ?1",
-            _params![register_game.tournament_game],
-        )?;
+        self.atomically(|| {
+            let game = self.sql_one::<TournamentGame, _>(
+                "select * from tournament_games where id = ?1",
+                _params![register_game.tournament_game],
+            )?;
 
-        let tournament = self
-            .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![
-                game.tournament
-            ])?;
-        let organizer_id = self
-            .get_user_without_matches_by("uuid", "=", &register_game.organizer_token)?
-            .id;
-
-        if organizer_id != tournament.organizer
+            let tournament = self
+                .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![
+                    game.tournament
+                ])?;
+            let user = self.get_user_from_token(&register_game.organizer_token)?;
+            let target = format!("tournament_game:{}", game.id);
+            let after = json!({
+                "winner": register_game.winner,
+                "loser": register_game.loser,
+                "scores": register_game.scores
+            });
+
+            if user.id != tournament.organizer
+            {
+                // The players of the game report it themselves, and the other player
+                // has to confirm it
+                if user.id != game.player1 && user.id != game.player2
+                {
+                    return Err(ServerError::Tournament(TournamentError::NotOrganizer));
+                }
+                self.report_tournament_match(&user, &game, register_game)?;
+                return self.audit(user.id, "report_tournament_match", &target, Value::Null, after);
+            }
+
+            // The organizer overrides whatever the players have reported
+            let before = self.tournament_match_snapshot(game.id)?;
+            self.delete_tournament_notifications(game.id)?;
+            self._register_tournament_match(&game, &tournament, &register_game)?;
+            self.audit(user.id, "register_tournament_match", &target, before, after)
+        })
+    }
+
+    // The result of a tournament game, or null if it has not been played
+    fn tournament_match_snapshot(&self, game_id: i64) -> ServerResult<Value>
+    {
+        let name = |pid: i64| -> ServerResult<String> {
+            Ok(self.get_user_without_matches_by("id", "=", &pid.to_string())?.name)
+        };
+        match self.sql_one::<TournamentMatch, _>(
+            "select * from tournament_matches where game = ?1",
+            _params![game_id],
+        )
         {
-            return Err(ServerError::Tournament(TournamentError::NotOrganizer));
+            Ok(m) => Ok(json!({"winner": name(m.winner)?, "loser": name(m.loser)?})),
+            Err(_) => Ok(Value::Null),
         }
+    }
 
+    pub(crate) fn _register_tournament_match(
+        &self,
+        game: &TournamentGame,
+        tournament: &Tournament,
+        register_game: &RegisterTournamentMatch,
+    ) -> ServerResult<()>
+    {
         if game.player1 == 0 || game.player2 == 0
         {
             return Err(ServerError::Tournament(TournamentError::InvalidGame));
         }
+        validate_scores(&register_game.scores)?;
 
         if let Ok(old) = self.sql_one::<TournamentMatch, _>(
             "select * from tournament_matches where game = ?1",
@@ -722,20 +1005,29 @@ impl DataBase
         )
         {
             // return Err(ServerError::Tournament(TournamentError::GameAlreadyPlayed));
-            self.handle_game_rerun(&game, &old, &tournament)?;
+            self.handle_game_rerun(game, &old, tournament)?;
         }
         match tournament.ttype.into()
         {
             TournamentType::SingleElimination =>
             {
-                self.handle_single_elimination_match(&game, &register_game, &tournament)?
+                self.handle_single_elimination_match(game, register_game, tournament)?
             },
             TournamentType::DoubleElimination =>
             {
-                self.handle_double_elimination_match(&game, &register_game, &tournament)?
+                self.handle_double_elimination_match(game, register_game, tournament)?
+            },
+            TournamentType::RoundRobin =>
+            {
+                self.handle_round_robin_match(game, register_game, tournament)?
+            },
+            TournamentType::Swiss => self.handle_swiss_match(game, register_game, tournament)?,
+            TournamentType::GroupStage =>
+            {
+                self.handle_group_match(game, register_game, tournament)?
             },
         }
-        Ok(())
+        self.play_walkovers(tournament.id)
     }
 
     fn handle_double_elimination_match(
@@ -749,7 +1041,7 @@ impl DataBase
         let winner_id = self.get_user_without_matches(&register_game.winner)?.id;
         let loser_id = self.get_user_without_matches(&register_game.loser)?.id;
 
-        self.create_match_from_game(winner_id, loser_id, game.id)?;
+        self.create_match_from_game(winner_id, loser_id, game.id, &register_game.scores)?;
 
         let biggest_power_of_two =
             ((tournament.player_count as f64).ln() / 2.0_f64.ln()).ceil() as u32;
@@ -782,7 +1074,8 @@ impl DataBase
                 // Tournament is over
                 if winner_id == game.player1
                 {
-                    self.finish_tournament(tournament, winner_id)?;
+                    let podium = self.double_elimination_podium(tournament, winner_id, loser_id);
+                    self.finish_tournament(tournament, &podium)?;
                 }
                 // Loser won, go to game #2
                 else
@@ -797,7 +1090,8 @@ impl DataBase
             // Second final
             n if n == (power + 1) =>
             {
-                self.finish_tournament(tournament, winner_id)?;
+                let podium = self.double_elimination_podium(tournament, winner_id, loser_id);
+                self.finish_tournament(tournament, &podium)?;
             },
             // Normal game
             _ =>
@@ -895,12 +1189,23 @@ impl DataBase
         self.update_bucket(&loser_bracket)
     }
 
-    fn finish_tournament(&self, tournament: &Tournament, winner_id: i64) -> ServerResult<()>
+    pub(crate) fn finish_tournament(
+        &self,
+        tournament: &Tournament,
+        podium: &Podium,
+    ) -> ServerResult<()>
     {
-        self.create_tournament_winner(tournament.id, winner_id)?;
+        self.create_tournament_winner(tournament.id, podium.winner)?;
+        self.create_placements(tournament.id, podium)?;
         self.update_tournament_state(tournament.id, TournamentState::Done)?;
-        self.award_winner_with_prize(tournament.prize, winner_id, tournament.id)?;
-        Ok(())
+
+        // The winner of a knockout bracket wins the group stage it belongs to, which
+        // is the tournament that awards the prize
+        match self.get_bracket_owner(tournament.id)
+        {
+            Some(owner) => self.finish_tournament(&owner, podium),
+            None => self.award_winner_with_prize(tournament.prize, podium.winner, tournament.id),
+        }
     }
 
     fn handle_single_elimination_match(
@@ -914,32 +1219,47 @@ impl DataBase
         let winner_id = self.get_user_without_matches(&register_game.winner)?.id;
         let loser_id = self.get_user_without_matches(&register_game.loser)?.id;
 
-        self.create_match_from_game(winner_id, loser_id, game.id)?;
+        self.create_match_from_game(winner_id, loser_id, game.id, &register_game.scores)?;
         // This was the last game, award some stuff
-        if game.bucket == 0
+        if game.bucket == 0 || game.bucket == THIRD_PLACE_BUCKET
         {
-            // @TODO: Check if double elim
-            self.finish_tournament(tournament, winner_id)?;
+            if let Some(podium) = self.single_elimination_podium(tournament)
+            {
+                self.finish_tournament(tournament, &podium)?;
+            }
         }
         else
         {
             let game_index = games.iter().position(|g| g.bucket == game.bucket).unwrap();
             let parent = self.advance_player(&mut games, game_index, winner_id);
             self.update_bucket(&games[parent])?;
+            self.send_loser_to_third_place_game(game.bucket, loser_id, tournament.id)?;
         }
         Ok(())
     }
 
-    fn create_match_from_game(
+    pub(crate) fn create_match_from_game(
         &self,
         winner_id: i64,
         loser_id: i64,
         game_id: i64,
+        scores: &[GameScore],
     ) -> ServerResult<()>
     {
+        // A walkover is not rated
+        let forfeit = self.is_forfeit(game_id, loser_id)?;
+        let rated_match = if forfeit
+        {
+            0
+        }
+        else
+        {
+            self.rate_tournament_game(winner_id, loser_id, game_id, scores)?
+        };
         self.conn.execute(
-            "insert into tournament_matches (game, winner, loser) values (?1, ?2, ?3)",
-            params![game_id, winner_id, loser_id],
+            "insert into tournament_matches (game, winner, loser, scores, rated_match, forfeit) \
+             values (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![game_id, winner_id, loser_id, scores_to_sql(scores), rated_match, forfeit],
         )?;
         Ok(())
     }
@@ -962,14 +1282,6 @@ impl DataBase
         Ok(())
     }
 
-    pub fn generate_matchups(&self, mut people: Vec<i64>) -> Vec<i64>
-    {
-        use rand::seq::SliceRandom;
-        let mut rng = rand::thread_rng();
-        people.shuffle(&mut rng);
-        people
-    }
-
     fn advance_game(&self, games: &mut Vec<TournamentGame>, i: usize)
     {
         let parent = (i - 1) / 2;
@@ -994,7 +1306,11 @@ impl DataBase
         parent
     }
 
-    fn generate_buckets(&self, tournament: &Tournament, people: &[i64]) -> Vec<TournamentGame>
+    pub(crate) fn generate_buckets(
+        &self,
+        tournament: &Tournament,
+        people: &[i64],
+    ) -> Vec<TournamentGame>
     {
         let biggest_power_of_two = ((people.len() as f32).ln() / 2.0_f32.ln()).ceil() as u32;
         let power = 2_usize.pow(biggest_power_of_two);
@@ -1100,76 +1416,62 @@ impl DataBase
         Ok(matches)
     }
 
-    fn loser_forward_iterator(&self, power: i64) -> impl Iterator<Item = (i64, i64)>
-    {
-        let power = power / 2;
-        let odd_pattern = || {
-            (0..(power / 4)).scan(power / 2 - 1, |acc, _| {
-                *acc += 2;
-                Some(*acc)
-            })
-        };
-
-        let even_pattern = || {
-            (0..(power / 4)).scan(power / 2 - 2, |acc, _| {
-                *acc += 2;
-                Some(*acc)
-            })
-        };
-
-        let easy_pattern = || power..power + power / 2;
-
-        let buckets = || (power..);
-
-        (odd_pattern().chain(even_pattern()).chain(easy_pattern()))
-            .zip(buckets())
-            .map(|(i, j)| (-i, -j))
-    }
-
+    // Makes room for the first round of a bracket that is not a power of two. The
+    // losers of upper games m and m + power / 4 of the first round meet, and the
+    // winner meets the loser of the second round game that would otherwise have
+    // started the losers bracket. Byes in the first round leave these games empty.
     fn forward_games(
         &self,
         mut matches: Vec<TournamentGame>,
-        rest: i64,
+        upper: &[TournamentGame],
         power: i64,
     ) -> Vec<TournamentGame>
     {
-        let mut bucket = -(matches.len() as i64 + 1);
+        let pos = |i: i64| -(i + 1);
+        let half = power / 2;
+        let first = -(matches.len() as i64 + 1);
         let tid = matches[0].tournament;
-        for (p1, p2) in self.loser_forward_iterator(power).take(rest as usize)
-        {
-            matches.insert(0, TournamentGame::players(tid, bucket, p1, p2));
-            bucket -= 1;
-        }
-        let mut seen = Vec::new();
 
-        for game in &mut matches
+        let mut forwarded = Vec::new();
+        for m in 0..half / 2
         {
-            if seen.contains(&game.player1)
-            {
-                game.player1 = 0;
-            }
-            else
+            let bucket = first - m;
+            let next = first - half / 2 - m;
+            let losers: Vec<i64> = vec![half - 1 + m, half - 1 + half / 2 + m]
+                .into_iter()
+                .filter(|&b| upper[b as usize].player1 != 0)
+                .map(pos)
+                .collect();
+            if losers.is_empty()
             {
-                seen.push(game.player1);
+                forwarded.push(TournamentGame::empty(tid, bucket));
+                forwarded.push(TournamentGame::empty(tid, next));
+                continue;
             }
 
-            if seen.contains(&game.player2)
-            {
-                game.player2 = 0;
-            }
-            else
+            // Take the place of the second round loser, odd games go to player1
+            let parent = self.loser_bracket_parent(bucket);
+            let lgame = matches.iter_mut().find(|g| g.bucket == parent).unwrap();
+            let slot = if bucket.abs() & 1 == 1 { &mut lgame.player1 } else { &mut lgame.player2 };
+            let second = std::mem::replace(slot, 0);
+
+            match *losers
             {
-                seen.push(game.player2);
+                [loser] =>
+                {
+                    forwarded.push(TournamentGame::players(tid, bucket, second, loser));
+                    forwarded.push(TournamentGame::empty(tid, next));
+                },
+                _ =>
+                {
+                    forwarded.push(TournamentGame::players(tid, bucket, second, 0));
+                    forwarded.push(TournamentGame::players(tid, next, losers[0], losers[1]));
+                },
             }
         }
-        // Now, we have forwarded each game, just create the remaining dummy games
-        for _ in 0..power / 2 - rest
-        {
-            matches.insert(0, TournamentGame::players(tid, bucket, 0, 0));
-            bucket -= 1;
-        }
-
-        matches
+        forwarded.sort_by_key(|g| g.bucket);
+        forwarded.append(&mut matches);
+        forwarded
     }
 
     fn create_upper_to_lower_table(&self, matches: &[TournamentGame]) -> ServerResult<String>
@@ -1210,62 +1512,83 @@ impl DataBase
     pub fn generate_tournament(&self, tournament: Tournament, people: Vec<i64>)
         -> ServerResult<()>
     {
-        let games = self.generate_buckets(&tournament, &self.generate_matchups(people));
-        if tournament.ttype == TournamentType::DoubleElimination as u8
-        {
-            let biggest_power_of_two =
-                ((tournament.player_count as f64).ln() / 2.0_f64.ln()).ceil() as u32;
-
-            let power = 2_i64.pow(biggest_power_of_two);
-
-            let matches = if tournament.player_count == power
+        self.atomically(|| {
+            let seeds = self.generate_matchups(&tournament, people)?;
+            if tournament.ttype == TournamentType::RoundRobin as u8
             {
-                self.create_losers_bracket(power, tournament.id)?
+                return self.generate_round_robin(&tournament, &seeds);
             }
-            else
+            if tournament.ttype == TournamentType::Swiss as u8
             {
-                let matches = self.create_losers_bracket(power / 2, tournament.id)?;
-                self.forward_games(matches, tournament.player_count - power / 2, power)
-            };
-            let table = self.create_upper_to_lower_table(&matches)?;
-            self.conn.execute(
-                "insert into tournament_lookup (tournament, _table) values (?1, ?2)",
-                params![tournament.id, table],
-            )?;
-
+                return self.generate_swiss_round(&tournament, 0, &seeds);
+            }
+            if tournament.ttype == TournamentType::GroupStage as u8
+            {
+                return self.generate_groups(&tournament, &seeds);
+            }
 
-            for game in matches
+            let double_elimination = tournament.ttype == TournamentType::DoubleElimination as u8;
+            let games = self.generate_buckets(&tournament, &bracket_slots(&seeds));
+            if double_elimination
             {
-                self._create_tournament_game(
-                    game.player1,
-                    game.player2,
-                    game.bucket,
-                    game.tournament,
+                let biggest_power_of_two =
+                    ((tournament.player_count as f64).ln() / 2.0_f64.ln()).ceil() as u32;
+
+                let power = 2_i64.pow(biggest_power_of_two);
+
+                let matches = if tournament.player_count == power
+                {
+                    self.create_losers_bracket(power, tournament.id)?
+                }
+                else
+                {
+                    let matches = self.create_losers_bracket(power / 2, tournament.id)?;
+                    self.forward_games(matches, &games, power)
+                };
+                let table = self.create_upper_to_lower_table(&matches)?;
+                self.conn.execute(
+                    "insert into tournament_lookup (tournament, _table) values (?1, ?2)",
+                    params![tournament.id, table],
                 )?;
-            }
 
 
-            // We will denote the final final match with n, where n is the highest power of
-            // two E.g 16, 32, 8, etc.
+                for game in matches
+                {
+                    self._create_tournament_game(
+                        game.player1,
+                        game.player2,
+                        game.bucket,
+                        game.tournament,
+                    )?;
+                }
+
+
+                // We will denote the final final match with n, where n is the highest power of
+                // two E.g 16, 32, 8, etc.
 
-            self._create_tournament_game(0, 0, power, tournament.id)?;
-            self._create_tournament_game(0, 0, power + 1, tournament.id)?;
-        }
+                self._create_tournament_game(0, 0, power, tournament.id)?;
+                self._create_tournament_game(0, 0, power + 1, tournament.id)?;
+            }
 
-        for bucket in games
-        {
-            self._create_tournament_game(
-                bucket.player1,
-                bucket.player2,
-                bucket.bucket,
-                bucket.tournament,
-            )?;
-        }
+            for bucket in games
+            {
+                self._create_tournament_game(
+                    bucket.player1,
+                    bucket.player2,
+                    bucket.bucket,
+                    bucket.tournament,
+                )?;
+            }
+            if tournament.third_place_game && !double_elimination
+            {
+                self.create_third_place_game(tournament.id)?;
+            }
 
-        Ok(())
+            Ok(())
+        })
     }
 
-    fn _create_tournament_game(&self, u1: i64, u2: i64, bucket: i64, tid: i64) -> ServerResult<()>
+    pub(crate) fn _create_tournament_game(&self, u1: i64, u2: i64, bucket: i64, tid: i64) -> ServerResult<()>
     {
         self.conn.execute(
             "insert into tournament_games (player1, player2, bucket, tournament) values (?1, ?2, \
@@ -1284,14 +1607,18 @@ impl DataBase
         Ok(())
     }
 
-    fn delete_tourament_list(&self, tid: i64) -> ServerResult<()>
+    pub(crate) fn delete_tourament_list(&self, tid: i64) -> ServerResult<()>
     {
         self.conn
             .execute("delete from tournament_lists where tournament = ?1", params![tid])?;
         Ok(())
     }
 
-    fn update_tournament_state(&self, tid: i64, state: TournamentState) -> ServerResult<()>
+    pub(crate) fn update_tournament_state(
+        &self,
+        tid: i64,
+        state: TournamentState,
+    ) -> ServerResult<()>
     {
         self.conn.execute("update tournaments set state = ?1 where id = ?2", params![
             state as u8,
@@ -1330,6 +1657,9 @@ impl DataBase
                     if bucket == -1 { power } else { self.loser_bracket_parent(bucket) }
                 }
             },
+            // The bucket of a round robin, swiss or group game is its round, and no game
+            // leads to another
+            TournamentType::RoundRobin | TournamentType::Swiss | TournamentType::GroupStage => -1,
         }
     }
 
@@ -1352,12 +1682,25 @@ impl DataBase
 
 
 
+        let (winner, forfeit) = self
+            .sql_one::<TournamentMatch, _>(
+                "select * from tournament_matches where game = ?1",
+                _params![tg.id],
+            )
+            .map_or((0, false), |m| (m.winner, m.forfeit));
+        let (scheduled, table_number) =
+            self.get_game_schedule(tg.id).map_or((0, 0), |s| (s.start, s.table_number));
+
         TournamentGameInfo {
-            player1:       h(tg.player1),
-            player2:       h(tg.player2),
-            id:            tg.id,
-            bucket:        tg.bucket,
+            player1: h(tg.player1),
+            player2: h(tg.player2),
+            id: tg.id,
+            bucket: tg.bucket,
             parent_bucket: self.get_parent_bucket(tg.bucket, tournament),
+            winner: h(winner),
+            forfeit,
+            start: scheduled,
+            table: table_number,
         }
     }
 
@@ -1373,17 +1716,23 @@ impl DataBase
         tw: Option<String>,
     ) -> ServerResult<SendTournament>
     {
+        let (second, third) = self.get_placement_names(tournament.id)?;
         Ok(SendTournament {
-            name:           tournament.name,
-            prize:          self.get_image_name(tournament.prize)?,
-            player_count:   tournament.player_count,
-            state:          tournament.state,
-            ttype:          tournament.ttype,
-            id:             tournament.id,
-            organizer_name: self
+            name:                  tournament.name,
+            prize:                 self.get_image_name(tournament.prize)?,
+            player_count:          tournament.player_count,
+            state:                 tournament.state,
+            ttype:                 tournament.ttype,
+            id:                    tournament.id,
+            organizer_name:        self
                 .get_user_without_matches_by("id", "=", &tournament.organizer.to_string())?
                 .name,
-            winner:         tw.unwrap_or(String::from("")),
+            winner:                tw.unwrap_or(String::from("")),
+            second_place:          second,
+            third_place:           third,
+            rated:                 tournament.rated,
+            registration_deadline: tournament.registration_deadline,
+            third_place_game:      tournament.third_place_game,
         })
     }
 
@@ -1418,6 +1767,10 @@ impl DataBase
                 tournament: self.convert_tournament(t, None).unwrap(),
                 data:       TournamentInfoState::Players(players),
                 table:      None,
+                standings:  None,
+                groups:     None,
+                bracket:    None,
+                withdrawn:  Vec::new(),
             }
         }
         else
@@ -1455,16 +1808,47 @@ impl DataBase
             {
                 None
             };
+            let round_standings = match t.ttype.into()
+            {
+                TournamentType::RoundRobin => Some(self.get_round_robin_standings(tid).unwrap()),
+                TournamentType::Swiss => Some(self.get_swiss_standings(tid).unwrap()),
+                _ => None,
+            };
+            let (group_standings, knockout) = if t.ttype == TournamentType::GroupStage as u8
+            {
+                let bracket = self
+                    .sql_one::<Tournament, _>(
+                        "select t.* from tournaments t inner join tournament_stages s on \
+                         s.bracket = t.id where s.tournament = ?1",
+                        _params![tid],
+                    )
+                    .ok()
+                    .map(|bracket| Box::new(self.map_tournament_info(bracket)));
+                (Some(self.get_group_standings(tid).unwrap()), bracket)
+            }
+            else
+            {
+                (None, None)
+            };
             TournamentInfo {
                 tournament: self.convert_tournament(t, tournament_winner).unwrap(),
-                data:       TournamentInfoState::Games(players),
-                table:      table,
+                data: TournamentInfoState::Games(players),
+                table,
+                standings: round_standings,
+                groups: group_standings,
+                bracket: knockout,
+                withdrawn: self.get_withdrawn_names(tid).unwrap(),
             }
         }
     }
 
     fn filter_tournaments(&self, t: &Tournament, info: &GetTournamentOptions) -> bool
     {
+        // The knockout brackets of group stages are shown as part of their group stage
+        if self.get_bracket_owner(t.id).is_some()
+        {
+            return false;
+        }
         if let Some(s) = &info.query
         {
             match s.as_str()
@@ -1571,7 +1955,7 @@ impl DataBase
 
     fn get_is_organizer(&self, token: String, tid: i64) -> ServerResult<bool>
     {
-        let pid = self.get_user_without_matches_by("uuid", "=", &token)?.id;
+        let pid = self.get_user_from_token(&token)?.id;
         let tournament = self
             .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![tid])?;
         Ok(pid == tournament.organizer)
@@ -1579,15 +1963,30 @@ impl DataBase
 
     pub fn delete_tournament(&self, token: String, tid: i64) -> ServerResult<()>
     {
-        if !self.get_is_organizer(token, tid)?
-        {
-            return Err(ServerError::Unauthorized);
-        }
+        self.atomically(|| {
+            let user = self.get_user_from_token(&token)?;
+            if !self.get_is_organizer(token, tid)?
+            {
+                return Err(ServerError::Unauthorized);
+            }
+            // The bracket is deleted together with its group stage
+            if self.get_bracket_owner(tid).is_some()
+            {
+                return Err(ServerError::Tournament(TournamentError::WrongState));
+            }
 
-        self._delete_tournament(tid)
+            let tournament = self
+                .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![
+                    tid
+                ])?;
+            self._delete_tournament(tid)?;
+            let before = json!({"name": tournament.name, "state": tournament.state});
+            let target = format!("tournament:{}", tid);
+            self.audit(user.id, "delete_tournament", &target, before, Value::Null)
+        })
     }
 
-    fn _delete_tournament(&self, tid: i64) -> ServerResult<()>
+    pub(crate) fn _delete_tournament(&self, tid: i64) -> ServerResult<()>
     {
         let tournament = self
             .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![tid])?;
@@ -1608,6 +2007,14 @@ impl DataBase
         }
         else
         {
+            let matches = self.sql_many::<TournamentMatch, _>(
+                "select m.* from tournament_matches m
+                 join tournament_games g on m.game = g.id
+                 where g.tournament = ?1",
+                _params![tid],
+            )?;
+            self.delete_rated_matches(&matches.iter().collect::<Vec<_>>())?;
+
             let games = self.sql_many::<TournamentGame, _>(
                 "select * from tournament_games where tournament = ?1",
                 _params![tid],
@@ -1616,6 +2023,8 @@ impl DataBase
             {
                 self.conn
                     .execute("delete from tournament_matches where game = ?1", params![game.id])?;
+                self.delete_tournament_notifications(game.id)?;
+                self.delete_game_schedule(game.id)?;
             }
             self.conn
                 .execute("delete from tournament_games where tournament = ?1", params![tid])?;
@@ -1625,14 +2034,22 @@ impl DataBase
                 self.conn
                     .execute("delete from tournament_lookup where tournament = ?1", params![tid])?;
             }
+            if tournament.ttype == TournamentType::GroupStage as u8
+            {
+                self.delete_groups(tid)?;
+            }
         }
+        self.conn
+            .execute("delete from tournament_stages where tournament = ?1", params![tid])?;
+        self.conn
+            .execute("delete from tournament_withdrawals where tournament = ?1", params![tid])?;
         delete_tournament(tid)?;
         Ok(())
     }
 }
 
 #[cfg(test)]
-mod test
+pub(crate) mod test
 {
     use super::*;
     use crate::{server::DataBase, test_util::*};
@@ -1752,6 +2169,40 @@ mod test
         assert!(rl.is_err());
     }
 
+    #[test]
+    fn test_failed_join_changes_nothing()
+    {
+        let db_file = "tempT24.db";
+        let s = DataBase::new(db_file);
+        let token_s = create_user(&s, "Sivert");
+        let token_b = create_user(&s, "Bernt");
+        let token_m = create_user(&s, "Markus");
+        let token_e = create_user(&s, "Ella");
+
+        s._create_tournament(1, "Epic".to_string(), 3, 4, TournamentType::SingleElimination)
+            .unwrap();
+
+        s.join_tournament(token_s, 1).unwrap();
+        s.join_tournament(token_b, 1).unwrap();
+        // The join is audited after everything else is written
+        fail_on(&s, "insert", "audit_log");
+        let rm = s.join_tournament(token_m, 1);
+        let list_after_join = get_table_size(&s, "tournament_lists");
+        let re = s.join_tournament(token_e, 1);
+
+        let tournament = s.sql_one::<Tournament, _>("select * from tournaments", None).unwrap();
+        let games = get_table_size(&s, "tournament_games");
+        let list = get_table_size(&s, "tournament_lists");
+        std::fs::remove_file(db_file).expect("Removing file tempT24");
+
+        assert!(rm.is_err());
+        assert_eq!(list_after_join, 2);
+        assert!(re.is_err());
+        assert_eq!(tournament.state, TournamentState::Created as u8);
+        assert_eq!(games, 0);
+        assert_eq!(list, 2);
+    }
+
     #[test]
     fn test_generate_bucket_power_of_two()
     {
@@ -1764,13 +2215,16 @@ mod test
         let vec16: Vec<i64> = (1..=16).collect();
         let vec = vec![vec4, vec8, vec16];
         let tournament = Tournament {
-            id:           0,
-            state:        0,
-            ttype:        0,
-            player_count: 0,
-            name:         String::new(),
-            prize:        0,
-            organizer:    0,
+            id:                    0,
+            state:                 0,
+            ttype:                 0,
+            player_count:          0,
+            name:                  String::new(),
+            prize:                 0,
+            organizer:             0,
+            rated:                 false,
+            registration_deadline: 0,
+            third_place_game:      false,
         };
 
         let vec_ok = |vec: Vec<i64>| {
@@ -1792,13 +2246,16 @@ mod test
         std::fs::remove_file(db_file).expect("Removing file tempH");
 
         let tournament = Tournament {
-            id:           0,
-            state:        0,
-            ttype:        0,
-            player_count: 0,
-            name:         String::new(),
-            prize:        0,
-            organizer:    0,
+            id:                    0,
+            state:                 0,
+            ttype:                 0,
+            player_count:          0,
+            name:                  String::new(),
+            prize:                 0,
+            organizer:             0,
+            rated:                 false,
+            registration_deadline: 0,
+            third_place_game:      false,
         };
         let tid = tournament.id;
 
@@ -1918,7 +2375,7 @@ mod test
         assert_func(last);
     }
 
-    fn reg_tournament_match_from_tournament_game(
+    pub(crate) fn reg_tournament_match_from_tournament_game(
         s: &DataBase,
         game: &TournamentGame,
         token: String,
@@ -1938,6 +2395,7 @@ mod test
             winner:          winner_name,
             loser:           loser_name,
             organizer_token: token,
+            scores:          Vec::new(),
         }
     }
     #[test]
@@ -2013,6 +2471,7 @@ mod test
             winner:          first_game.winner.clone(),
             loser:           second_game.winner.clone(),
             organizer_token: token_s.clone(),
+            scores:          Vec::new(),
         };
 
         let res1 = s.register_tournament_match(first_game);
@@ -2099,6 +2558,7 @@ mod test
             winner:          String::from(""),
             loser:           String::from(""),
             organizer_token: token_s.clone(),
+            scores:          Vec::new(),
         };
 
         let res1 = s.register_tournament_match(invalid.clone());
@@ -2222,7 +2682,7 @@ mod test
 
                     use crate::SQL_TUPLE;
                     let res: Vec<_> =
-                        SQL_TUPLE!(s, &format!("select uuid from users where id = {}", n), String)
+                        SQL_TUPLE!(s, &format!("select token from sessions where user = {}", n), String)
                             .unwrap();
                     Ok(res.first().unwrap().0.clone())
                 };
@@ -2238,11 +2698,16 @@ mod test
 
             let token = users.first().clone().unwrap();
             let create_tournament = CreateTournament {
-                organizer_token: token.clone(),
-                name:            player_count.to_string(),
-                image:           "".to_string(),
-                player_count:    player_count,
-                ttype:           "doubleElimination".to_string(),
+                organizer_token:       token.clone(),
+                name:                  player_count.to_string(),
+                image:                 "".to_string(),
+                player_count:          player_count,
+                ttype:                 "doubleElimination".to_string(),
+                group_stage:           None,
+                seeding:               None,
+                rated:                 false,
+                registration_deadline: None,
+                third_place_game:      false,
             };
 
             s.create_tournament(create_tournament).expect("Creating tournament");
@@ -2329,11 +2794,16 @@ mod test
 
         let token = users.first().clone().unwrap();
         let create_tournament = CreateTournament {
-            organizer_token: token.clone(),
-            name:            "Epic".to_string(),
-            image:           "".to_string(),
-            player_count:    player_count,
-            ttype:           "doubleElimination".to_string(),
+            organizer_token:       token.clone(),
+            name:                  "Epic".to_string(),
+            image:                 "".to_string(),
+            player_count:          player_count,
+            ttype:                 "doubleElimination".to_string(),
+            group_stage:           None,
+            seeding:               None,
+            rated:                 false,
+            registration_deadline: None,
+            third_place_game:      false,
         };
 
         s.create_tournament(create_tournament).expect("Creating tournament");
@@ -2360,11 +2830,16 @@ mod test
 
         let token = users.first().clone().unwrap();
         let create_tournament = CreateTournament {
-            organizer_token: token.clone(),
-            name:            "Epic".to_string(),
-            image:           "".to_string(),
-            player_count:    player_count,
-            ttype:           "doubleElimination".to_string(),
+            organizer_token:       token.clone(),
+            name:                  "Epic".to_string(),
+            image:                 "".to_string(),
+            player_count:          player_count,
+            ttype:                 "doubleElimination".to_string(),
+            group_stage:           None,
+            seeding:               None,
+            rated:                 false,
+            registration_deadline: None,
+            third_place_game:      false,
         };
 
         s.create_tournament(create_tournament).expect("Creating tournament");
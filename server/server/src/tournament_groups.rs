@@ -0,0 +1,507 @@
+use rusqlite::{named_params, params};
+use serde_derive::Deserialize;
+use server_core::{constants::*, types::*};
+use server_macro::Sql;
+
+use crate::{
+    _params,
+    server::{DataBase, ParamsType},
+    tournament::{
+        RegisterTournamentMatch, Tournament, TournamentGame, TournamentState, TournamentType,
+    },
+    tournament_round_robin::{rank_players, Standing},
+    tournament_seeding::SeedingMode,
+    validate::Validator,
+};
+
+// How a group stage tournament is split up, sent with the tournament when it is
+// created
+#[derive(Deserialize)]
+pub struct GroupStageOptions
+{
+    pub groups:   i64,
+    // The number of players from each group that make it to the bracket
+    pub advance:  i64,
+    pub knockout: String,
+}
+
+#[derive(Sql)]
+pub struct TournamentStage
+{
+    pub id:          i64,
+    pub tournament:  i64,
+    pub group_count: i64,
+    pub advance:     i64,
+    pub knockout:    u8,
+    // The knockout tournament, 0 until the groups are done
+    pub bracket:     i64,
+}
+
+#[derive(Sql)]
+struct TournamentGroup
+{
+    #[allow(dead_code)]
+    id:          i64,
+    #[allow(dead_code)]
+    tournament:  i64,
+    player:      i64,
+    group_index: i64,
+}
+
+pub(crate) fn check_group_stage(
+    validator: &mut Validator,
+    player_count: i64,
+    options: Option<&GroupStageOptions>,
+)
+{
+    let options = match options
+    {
+        Some(options) => options,
+        None =>
+        {
+            validator.check(false, "group_stage", "Required for group stage tournaments");
+            return;
+        },
+    };
+
+    let smallest_group = if options.groups > 0 { player_count / options.groups } else { 0 };
+    let knockout = TournamentType::from_name(&options.knockout);
+    validator
+        .check(smallest_group >= 2, "group_stage.groups", "Every group needs at least 2 players")
+        .check(
+            (1..=smallest_group).contains(&options.advance),
+            "group_stage.advance",
+            "Must be between 1 and the size of the smallest group",
+        )
+        .check(
+            (MIN_TOURNAMENT_PLAYERS..=MAX_TOURNAMENT_PLAYERS)
+                .contains(&options.groups.saturating_mul(options.advance)),
+            "group_stage.advance",
+            "Between 4 and 64 players must advance",
+        )
+        .check(
+            matches!(
+                knockout,
+                Some(TournamentType::SingleElimination) | Some(TournamentType::DoubleElimination)
+            ),
+            "group_stage.knockout",
+            "Must be singleElimination or doubleElimination",
+        );
+}
+
+impl DataBase
+{
+    pub(crate) fn create_group_stage(
+        &self,
+        tid: i64,
+        options: &GroupStageOptions,
+    ) -> ServerResult<()>
+    {
+        let knockout = match TournamentType::from_name(&options.knockout)
+        {
+            Some(ttype @ TournamentType::SingleElimination)
+            | Some(ttype @ TournamentType::DoubleElimination) => ttype,
+            _ => return Err(ServerError::Tournament(TournamentError::InvalidTtype)),
+        };
+
+        self.conn.execute(
+            "insert into tournament_stages (tournament, group_count, advance, knockout) values \
+             (?1, ?2, ?3, ?4)",
+            params![tid, options.groups, options.advance, knockout as u8],
+        )?;
+        Ok(())
+    }
+
+    // A recreated group stage is split up the same way as the old one
+    pub(crate) fn copy_group_stage(&self, from: i64, to: i64) -> ServerResult<()>
+    {
+        self.conn.execute(
+            "insert into tournament_stages (tournament, group_count, advance, knockout)
+             select ?2, group_count, advance, knockout from tournament_stages where tournament = \
+             ?1",
+            params![from, to],
+        )?;
+        Ok(())
+    }
+
+    // A group stage that starts before it is full must still split up into
+    // its groups
+    pub(crate) fn check_group_stage_players(&self, tid: i64, player_count: i64)
+        -> ServerResult<()>
+    {
+        let stage = self.get_group_stage(tid)?;
+        let knockout = match stage.knockout.into()
+        {
+            TournamentType::DoubleElimination => "doubleElimination",
+            _ => "singleElimination",
+        };
+        let options = GroupStageOptions {
+            groups:   stage.group_count,
+            advance:  stage.advance,
+            knockout: knockout.to_string(),
+        };
+        let mut validator = Validator::new();
+        check_group_stage(&mut validator, player_count, Some(&options));
+        validator.finish()
+    }
+
+    pub(crate) fn get_group_stage(&self, tid: i64) -> ServerResult<TournamentStage>
+    {
+        self.sql_one::<TournamentStage, _>(
+            "select * from tournament_stages where tournament = ?1",
+            _params![tid],
+        )
+    }
+
+    // The group stage a knockout tournament is the bracket of, if any
+    pub(crate) fn get_bracket_owner(&self, tid: i64) -> Option<Tournament>
+    {
+        self.sql_one::<Tournament, _>(
+            "select t.* from tournaments t inner join tournament_stages s on s.tournament = t.id \
+             where s.bracket = ?1",
+            _params![tid],
+        )
+        .ok()
+    }
+
+    // The players are dealt out to the groups, and every group plays a round
+    // robin where the bucket of each game is its round
+    pub(crate) fn generate_groups(
+        &self,
+        tournament: &Tournament,
+        people: &[i64],
+    ) -> ServerResult<()>
+    {
+        let stage = self.get_group_stage(tournament.id)?;
+        for group in 0..stage.group_count
+        {
+            let players: Vec<i64> = people
+                .iter()
+                .copied()
+                .skip(group as usize)
+                .step_by(stage.group_count as usize)
+                .collect();
+            for pid in &players
+            {
+                self.conn.execute(
+                    "insert into tournament_groups (tournament, player, group_index) values (?1, \
+                     ?2, ?3)",
+                    params![tournament.id, pid, group],
+                )?;
+            }
+            self.generate_round_robin(tournament, &players)?;
+        }
+        Ok(())
+    }
+
+    fn get_groups(&self, tid: i64) -> ServerResult<Vec<Vec<i64>>>
+    {
+        let mut groups: Vec<Vec<i64>> = Vec::new();
+        for row in self.sql_many::<TournamentGroup, _>(
+            "select * from tournament_groups where tournament = ?1 order by group_index, id",
+            _params![tid],
+        )?
+        {
+            match groups.get_mut(row.group_index as usize)
+            {
+                Some(group) => group.push(row.player),
+                None => groups.push(vec![row.player]),
+            }
+        }
+        Ok(groups)
+    }
+
+    pub(crate) fn handle_group_match(
+        &self,
+        game: &TournamentGame,
+        register_game: &RegisterTournamentMatch,
+        tournament: &Tournament,
+    ) -> ServerResult<()>
+    {
+        self.create_match_between_players(game, register_game)?;
+
+        // The bracket is created when every group game has been played
+        let results = self.get_tournament_results(tournament.id)?;
+        let games: i64 = self.conn.query_row_named(
+            "select count(*) from tournament_games where tournament = :tid",
+            named_params! {":tid": tournament.id},
+            |row| row.get(0),
+        )?;
+        if results.len() as i64 == games
+        {
+            self.generate_knockout(tournament)?;
+        }
+        Ok(())
+    }
+
+    // Creates the bracket from the best players of each group, using the
+    // single or double elimination code of any other tournament. The group
+    // winners are the first seeds, then the runners up and so on.
+    fn generate_knockout(&self, tournament: &Tournament) -> ServerResult<()>
+    {
+        let stage = self.get_group_stage(tournament.id)?;
+        let results = self.get_tournament_results(tournament.id)?;
+        // Players who have withdrawn do not advance
+        let ranked: Vec<Vec<i64>> = self
+            .get_groups(tournament.id)?
+            .iter()
+            .map(|group| self.withdrawn_last(tournament.id, rank_players(group, &results)))
+            .collect::<ServerResult<_>>()?;
+        let qualifiers: Vec<i64> = (0..stage.advance as usize)
+            .flat_map(|place| ranked.iter().map(move |group| group[place]))
+            .collect();
+
+        self._create_tournament(
+            tournament.organizer,
+            tournament.name.clone(),
+            tournament.prize,
+            qualifiers.len() as i64,
+            stage.knockout.into(),
+        )?;
+        let mut bracket = self
+            .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![self
+                .conn
+                .last_insert_rowid()])?;
+        self.conn.execute(
+            "update tournament_stages set bracket = ?1 where tournament = ?2",
+            params![bracket.id, tournament.id],
+        )?;
+        self.insert_seeding(bracket.id, SeedingMode::Manual, rand::random(), &qualifiers)?;
+        self.set_tournament_rated(bracket.id, tournament.rated)?;
+        self.set_third_place_game(bracket.id, tournament.third_place_game)?;
+        bracket.third_place_game = tournament.third_place_game;
+
+        let bracket_id = bracket.id;
+        self.generate_tournament(bracket, qualifiers)?;
+        self.update_tournament_state(bracket_id, TournamentState::InProgress)
+    }
+
+    pub(crate) fn delete_groups(&self, tid: i64) -> ServerResult<()>
+    {
+        let stage = self.get_group_stage(tid)?;
+        if stage.bracket != 0
+        {
+            self._delete_tournament(stage.bracket)?;
+        }
+        self.conn
+            .execute("delete from tournament_groups where tournament = ?1", params![tid])?;
+        Ok(())
+    }
+
+    pub fn get_group_standings(&self, tid: i64) -> ServerResult<Vec<Vec<Standing>>>
+    {
+        let results = self.get_tournament_results(tid)?;
+        self.get_groups(tid)?
+            .iter()
+            .map(|group| self.create_standings(rank_players(group, &results), &results, |_| None))
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        test_util::*,
+        tournament::{test::reg_tournament_match_from_tournament_game, CreateTournament},
+        validate::Validate,
+        GetTournamentOptions,
+    };
+
+    fn group_stage(player_count: i64, groups: i64, advance: i64, knockout: &str)
+        -> CreateTournament
+    {
+        serde_json::from_value(json!({
+            "name": "Lunch",
+            "image": "",
+            "player_count": player_count,
+            "ttype": "groupStage",
+            "group_stage": {"groups": groups, "advance": advance, "knockout": knockout}
+        }))
+        .unwrap()
+    }
+
+    fn create_group_stage(s: &DataBase, token: &str, groups: i64, knockout: &str)
+    {
+        let mut info = group_stage(8, groups, 2, knockout);
+        info.organizer_token = token.to_string();
+        s.create_tournament(info).unwrap();
+    }
+
+    fn play_games(s: &DataBase, token: &str, tid: i64)
+    {
+        for game in s.get_all_tournament_games(tid).unwrap()
+        {
+            let register = reg_tournament_match_from_tournament_game(s, &game, token.to_string());
+            s.register_tournament_match(register).unwrap();
+        }
+    }
+
+    // Plays bracket games as soon as both players are known, player1 always wins
+    fn play_bracket(s: &DataBase, token: &str, tid: i64)
+    {
+        loop
+        {
+            let game = s
+                .sql_one::<TournamentGame, _>(
+                    "select * from tournament_games g where g.tournament = ?1 and g.player1 > 0 \
+                     and g.player2 > 0 and not exists (select * from tournament_matches m where \
+                     m.game = g.id)",
+                    _params![tid],
+                )
+                .ok();
+            let done = s
+                .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![tid])
+                .unwrap()
+                .state
+                == TournamentState::Done as u8;
+            match game
+            {
+                Some(game) if !done =>
+                {
+                    let register =
+                        reg_tournament_match_from_tournament_game(s, &game, token.to_string());
+                    s.register_tournament_match(register).unwrap();
+                },
+                _ => break,
+            }
+        }
+    }
+
+    #[test]
+    fn test_group_stage_options_are_checked()
+    {
+        let field_errors = |info: CreateTournament| match info.validate()
+        {
+            Err(ServerError::InvalidRequest(errors)) =>
+            {
+                errors.into_iter().map(|e| e.field).collect::<Vec<_>>()
+            },
+            _ => Vec::new(),
+        };
+        let mut missing: CreateTournament = serde_json::from_value(json!({
+            "name": "Lunch",
+            "image": "",
+            "player_count": 8,
+            "ttype": "groupStage"
+        }))
+        .unwrap();
+        missing.organizer_token = String::new();
+
+        assert!(group_stage(8, 2, 2, "singleElimination").validate().is_ok());
+        assert_eq!(field_errors(missing), vec!["group_stage"]);
+        assert_eq!(field_errors(group_stage(8, 5, 2, "doubleElimination")), vec![
+            "group_stage.groups",
+            "group_stage.advance"
+        ]);
+        assert_eq!(field_errors(group_stage(8, 2, 1, "swiss")), vec![
+            "group_stage.advance",
+            "group_stage.knockout"
+        ]);
+    }
+
+    #[test]
+    fn test_best_of_each_group_meet_in_bracket()
+    {
+        let db_file = "tempG1.db";
+        let s = DataBase::new(db_file);
+        let tokens: Vec<String> =
+            (1..=8).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+        let organizer = &tokens[0];
+
+        create_group_stage(&s, organizer, 2, "doubleElimination");
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+        play_games(&s, organizer, 1);
+        let standings = s.get_group_standings(1).unwrap();
+        let stage = s.get_group_stage(1).unwrap();
+        let mut qualifiers = s.get_player_ids(stage.bracket).unwrap();
+        let first_game = s.get_all_tournament_games(1).unwrap().remove(0);
+        let rerun = s.register_tournament_match(reg_tournament_match_from_tournament_game(
+            &s,
+            &first_game,
+            organizer.clone(),
+        ));
+        let listed = s.get_tournaments(GetTournamentOptions {
+            query: None
+        });
+        let info = serde_json::to_value(s.get_tournament_from_id(1).unwrap()).unwrap();
+
+        play_bracket(&s, organizer, stage.bracket);
+        let tournament = s.sql_one::<Tournament, _>("select * from tournaments", None).unwrap();
+        let badges = get_table_size(&s, "tournament_badges");
+        let mut best: Vec<i64> = standings
+            .iter()
+            .flat_map(|group| group.iter().take(2))
+            .map(|standing| s.get_user_without_matches(&standing.name).unwrap().id)
+            .collect();
+        best.sort_unstable();
+
+        std::fs::remove_file(db_file).expect("Removing file tempG1");
+        assert_eq!(standings.len(), 2);
+        assert!(standings.iter().all(|group| group.len() == 4));
+        qualifiers.sort_unstable();
+        assert_eq!(qualifiers, best);
+        assert_eq!(stage.knockout, TournamentType::DoubleElimination as u8);
+        assert_eq!(rerun, Err(ServerError::Tournament(TournamentError::CannotRerun)));
+        assert_eq!(listed.unwrap().len(), 1);
+        assert_eq!(info["groups"].as_array().unwrap().len(), 2);
+        assert_eq!(info["bracket"]["tournament"]["player_count"], 4);
+        assert!(info["bracket"]["table"].is_string());
+        assert_eq!(tournament.state, TournamentState::Done as u8);
+        assert_eq!(badges, 1);
+    }
+
+    #[test]
+    fn test_rerun_of_bracket_final_changes_group_stage_winner()
+    {
+        let db_file = "tempG2.db";
+        let s = DataBase::new(db_file);
+        let tokens: Vec<String> =
+            (1..=8).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+        let organizer = &tokens[0];
+
+        create_group_stage(&s, organizer, 4, "singleElimination");
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+        play_games(&s, organizer, 1);
+        let bracket = s.get_group_stage(1).unwrap().bracket;
+        play_bracket(&s, organizer, bracket);
+        let first = s.get_tournament_from_id(1).map(|info| serde_json::to_value(info).unwrap());
+
+        let final_game = s
+            .sql_one::<TournamentGame, _>(
+                "select * from tournament_games where tournament = ?1 and bucket = 0",
+                _params![bracket],
+            )
+            .unwrap();
+        let won = reg_tournament_match_from_tournament_game(&s, &final_game, organizer.clone());
+        let mut lost = won.clone();
+        lost.winner = won.loser;
+        lost.loser = won.winner;
+        s.register_tournament_match(lost).unwrap();
+        let second = s.get_tournament_from_id(1).map(|info| serde_json::to_value(info).unwrap());
+        let winners = get_table_size(&s, "tournament_winners");
+        let badges = get_table_size(&s, "tournament_badges");
+        let deleted = s.delete_tournament(organizer.clone(), bracket);
+
+        std::fs::remove_file(db_file).expect("Removing file tempG2");
+        let first = first.unwrap();
+        let second = second.unwrap();
+        assert_eq!(first["tournament"]["state"], TournamentState::Done as u8);
+        assert_eq!(second["tournament"]["state"], TournamentState::Done as u8);
+        assert_ne!(first["tournament"]["winner"], second["tournament"]["winner"]);
+        assert_eq!(second["tournament"]["winner"], second["bracket"]["tournament"]["winner"]);
+        assert_eq!(winners, 2);
+        assert_eq!(badges, 1);
+        assert_eq!(deleted, Err(ServerError::Tournament(TournamentError::WrongState)));
+    }
+}
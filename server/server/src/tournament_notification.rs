@@ -0,0 +1,344 @@
+use rusqlite::params;
+use serde_json::{json, Value};
+use server_core::{constants::*, types::*};
+
+use crate::{
+    _named_params, _params,
+    notification::{TournamentNotification, TournamentNotificationTable},
+    score::{scores_from_sql, scores_to_sql, validate_scores},
+    server::{DataBase, ParamsType},
+    tournament::{RegisterTournamentMatch, Tournament, TournamentGame, TournamentMatch},
+    user::User,
+};
+
+// The players of a tournament game can report the result themselves, the same
+// way as a normal match: the result is registered once the other player has
+// accepted it. A declined result waits for the organizer, who can accept it,
+// throw it away or register the game themselves.
+impl DataBase
+{
+    pub(crate) fn report_tournament_match(
+        &self,
+        user: &User,
+        game: &TournamentGame,
+        report: RegisterTournamentMatch,
+    ) -> ServerResult<()>
+    {
+        if game.player1 == 0 || game.player2 == 0
+        {
+            return Err(ServerError::Tournament(TournamentError::InvalidGame));
+        }
+        validate_scores(&report.scores)?;
+
+        let winner = self.get_user_without_matches(&report.winner)?.id;
+        let loser = self.get_user_without_matches(&report.loser)?.id;
+        let players = [game.player1, game.player2];
+        if winner == loser || !players.contains(&winner) || !players.contains(&loser)
+        {
+            return Err(ServerError::InvalidRequest(vec![FieldError::new(
+                "winner",
+                "The winner and loser must be the two players of the game",
+            )]));
+        }
+
+        // Only the organizer can change a result
+        if self
+            .sql_one::<TournamentMatch, _>(
+                "select * from tournament_matches where game = ?1",
+                _params![game.id],
+            )
+            .is_ok()
+        {
+            return Err(ServerError::Tournament(TournamentError::GameAlreadyPlayed));
+        }
+
+        let scores = scores_to_sql(&report.scores);
+        // Both players reporting the same result is the same as accepting it
+        if let Ok(n) = self.sql_one::<TournamentNotificationTable, _>(
+            "select * from tournament_notification
+             where game = ?1 and winner = ?2 and loser = ?3 and scores = ?4",
+            _params![game.id, winner, loser, scores],
+        )
+        {
+            return self.respond_to_tournament_match(n.id, ACCEPT_REQUEST, report.organizer_token);
+        }
+
+        // A new result replaces the one reported before it
+        self.delete_tournament_notifications(game.id)?;
+        let accept = |id: i64| if id == user.id { ACCEPT_REQUEST } else { MATCH_NO_ANS };
+        self.conn.execute(
+            "insert into tournament_notification (game, epoch, winner, loser, scores, \
+             winner_accept, loser_accept) values (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![game.id, self.epoch(), winner, loser, scores, accept(winner), accept(loser)],
+        )?;
+        Ok(())
+    }
+
+    pub fn respond_to_tournament_match(&self, id: i64, ans: u8, token: String) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let user = self.get_user_from_token(&token)?;
+            let mut n = self.sql_one::<TournamentNotificationTable, _>(
+                "select * from tournament_notification where id = ?1",
+                _params![id],
+            )?;
+            let game = self.sql_one::<TournamentGame, _>(
+                "select * from tournament_games where id = ?1",
+                _params![n.game],
+            )?;
+            let tournament = self
+                .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![
+                    game.tournament
+                ])?;
+            let action = "respond_to_tournament_match";
+            let target = format!("tournament_game:{}", game.id);
+            let answer = json!({ "answer": ans });
+
+            if user.id == n.winner
+            {
+                n.winner_accept = ans;
+            }
+            else if user.id == n.loser
+            {
+                n.loser_accept = ans;
+            }
+            else if user.id == tournament.organizer
+            {
+                // The organizer settles it, so the result is gone either way
+                if ans == ACCEPT_REQUEST
+                {
+                    self.register_tournament_notification(&n, &game, &tournament)?;
+                }
+                self.delete_tournament_notifications(game.id)?;
+                return self.audit(user.id, action, &target, Value::Null, answer);
+            }
+            else
+            {
+                return Err(ServerError::Unauthorized);
+            }
+
+            if n.winner_accept == ACCEPT_REQUEST && n.loser_accept == ACCEPT_REQUEST
+            {
+                self.register_tournament_notification(&n, &game, &tournament)?;
+                self.delete_tournament_notifications(game.id)?;
+            }
+            else
+            {
+                self.conn.execute(
+                    "update tournament_notification set winner_accept = ?1, loser_accept = ?2
+                     where id = ?3",
+                    params![n.winner_accept, n.loser_accept, n.id],
+                )?;
+            }
+            self.audit(user.id, action, &target, Value::Null, answer)
+        })
+    }
+
+    pub fn get_tournament_notifications(
+        &self,
+        token: String,
+    ) -> ServerResult<Vec<TournamentNotification>>
+    {
+        let user = self.get_user_from_token(&token)?;
+        let sql = "select n.id, g.tournament, n.game as tournament_game, a.name as winner,
+            b.name as loser, n.epoch, n.scores,
+            (n.winner_accept = :declined or n.loser_accept = :declined) as disputed
+            from tournament_notification n
+            join tournament_games g on n.game = g.id
+            join tournaments t on g.tournament = t.id
+            join users a on n.winner = a.id
+            join users b on n.loser = b.id
+            where (n.winner = :id and n.winner_accept = 0)
+            or (n.loser = :id and n.loser_accept = 0)
+            or (t.organizer = :id and (n.winner_accept = :declined or n.loser_accept = :declined))
+            order by n.epoch";
+
+        self.sql_many(sql, _named_params! {":id": user.id, ":declined": DECLINE_REQUEST})
+    }
+
+    pub(crate) fn delete_tournament_notifications(&self, game_id: i64) -> ServerResult<()>
+    {
+        self.conn
+            .execute("delete from tournament_notification where game = ?1", params![game_id])?;
+        Ok(())
+    }
+
+    fn register_tournament_notification(
+        &self,
+        n: &TournamentNotificationTable,
+        game: &TournamentGame,
+        tournament: &Tournament,
+    ) -> ServerResult<()>
+    {
+        let name = |pid: i64| -> ServerResult<String> {
+            Ok(self.get_user_without_matches_by("id", "=", &pid.to_string())?.name)
+        };
+        let register = RegisterTournamentMatch {
+            organizer_token: String::new(),
+            winner:          name(n.winner)?,
+            loser:           name(n.loser)?,
+            tournament_game: n.game,
+            scores:          scores_from_sql(&n.scores),
+        };
+        self._register_tournament_match(game, tournament, &register)
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        notification::NotificationAns, test_util::*, tournament::CreateTournament, TournamentState,
+    };
+
+    fn create_round_robin(s: &DataBase, token: &str)
+    {
+        let mut info: CreateTournament = serde_json::from_value(json!({
+            "name": "Lunch",
+            "image": "",
+            "player_count": 4,
+            "ttype": "roundRobin"
+        }))
+        .unwrap();
+        info.organizer_token = token.to_string();
+        s.create_tournament(info).unwrap();
+    }
+
+    fn report(s: &DataBase, token: &str, winner: i64, loser: i64, game: i64) -> ServerResult<()>
+    {
+        let name =
+            |pid: i64| s.get_user_without_matches_by("id", "=", &pid.to_string()).unwrap().name;
+        s.register_tournament_match(RegisterTournamentMatch {
+            organizer_token: token.to_string(),
+            winner:          name(winner),
+            loser:           name(loser),
+            tournament_game: game,
+            scores:          Vec::new(),
+        })
+    }
+
+    fn answer(s: &DataBase, token: &str, id: i64, ans: u8)
+    {
+        s.respond_to_notification(NotificationAns::Tournament(id, token.to_string(), ans))
+            .unwrap();
+    }
+
+    // The organizer does not play, players[i] has the id i + 2
+    fn setup(s: &DataBase) -> (String, Vec<String>, Vec<TournamentGame>)
+    {
+        create_tournament_image(s);
+        let organizer = create_user(s, "Organizer");
+        let players: Vec<String> =
+            (1..=4).map(|n| create_user(s, &format!("Player{}", n))).collect();
+        create_round_robin(s, &organizer);
+        for token in &players
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+        (organizer, players, s.get_all_tournament_games(1).unwrap())
+    }
+
+    fn winners(s: &DataBase) -> Vec<(i64, i64)>
+    {
+        s.sql_many::<TournamentMatch, _>("select * from tournament_matches", None)
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.game, m.winner))
+            .collect()
+    }
+
+    #[test]
+    fn test_players_report_and_confirm_tournament_games()
+    {
+        let db_file = "tempN1.db";
+        let s = DataBase::new(db_file);
+        let (_, players, games) = setup(&s);
+        let token = |pid: i64| players[pid as usize - 2].clone();
+        let game = &games[0];
+        let outsider = [2, 3, 4, 5]
+            .iter()
+            .copied()
+            .find(|pid| *pid != game.player1 && *pid != game.player2)
+            .unwrap();
+
+        let not_in_game = report(&s, &token(outsider), game.player1, game.player2, game.id);
+        let wrong_players = report(&s, &token(game.player1), game.player1, outsider, game.id);
+        report(&s, &token(game.player1), game.player1, game.player2, game.id).unwrap();
+        let reported = winners(&s);
+        let reporter = s.get_tournament_notifications(token(game.player1)).unwrap();
+        let opponent = s.get_tournament_notifications(token(game.player2)).unwrap();
+        answer(&s, &token(game.player2), opponent[0].id, ACCEPT_REQUEST);
+        let confirmed = winners(&s);
+        let again = report(&s, &token(game.player2), game.player2, game.player1, game.id);
+
+        // Reporting the same result as the other player confirms it
+        let game = &games[1];
+        report(&s, &token(game.player2), game.player2, game.player1, game.id).unwrap();
+        report(&s, &token(game.player1), game.player2, game.player1, game.id).unwrap();
+        let both = winners(&s);
+        let left = get_table_size(&s, "tournament_notification");
+
+        std::fs::remove_file(db_file).expect("Removing file tempN1");
+        assert!(matches!(not_in_game, Err(ServerError::Tournament(TournamentError::NotOrganizer))));
+        assert!(matches!(wrong_players, Err(ServerError::InvalidRequest(_))));
+        assert!(reported.is_empty());
+        assert!(reporter.is_empty());
+        assert_eq!(opponent.len(), 1);
+        assert_eq!(opponent[0].tournament_game, games[0].id);
+        assert!(!opponent[0].disputed);
+        assert_eq!(confirmed, vec![(games[0].id, games[0].player1)]);
+        assert!(matches!(again, Err(ServerError::Tournament(TournamentError::GameAlreadyPlayed))));
+        assert_eq!(both[1], (games[1].id, games[1].player2));
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn test_organizer_resolves_disputes()
+    {
+        let db_file = "tempN2.db";
+        let s = DataBase::new(db_file);
+        let (organizer, players, games) = setup(&s);
+        let token = |pid: i64| players[pid as usize - 2].clone();
+
+        // The loser does not agree, so the organizer has the last word
+        let game = &games[0];
+        report(&s, &token(game.player1), game.player1, game.player2, game.id).unwrap();
+        let id = s.get_tournament_notifications(token(game.player2)).unwrap()[0].id;
+        answer(&s, &token(game.player2), id, DECLINE_REQUEST);
+        let opponent = s.get_tournament_notifications(token(game.player2)).unwrap();
+        let disputes = s.get_tournament_notifications(organizer.clone()).unwrap();
+        answer(&s, &organizer, id, ACCEPT_REQUEST);
+        let resolved = winners(&s);
+
+        // The organizer registering the game throws away what was reported
+        let game = &games[1];
+        report(&s, &token(game.player1), game.player1, game.player2, game.id).unwrap();
+        report(&s, &organizer, game.player2, game.player1, game.id).unwrap();
+        let overridden = winners(&s);
+        let left = get_table_size(&s, "tournament_notification");
+
+        for game in &games[2..]
+        {
+            report(&s, &token(game.player1), game.player1, game.player2, game.id).unwrap();
+            let id = s.get_tournament_notifications(token(game.player2)).unwrap()[0].id;
+            answer(&s, &token(game.player2), id, ACCEPT_REQUEST);
+        }
+        let state = s
+            .sql_one::<Tournament, _>("select * from tournaments where id = 1", None)
+            .unwrap()
+            .state;
+
+        std::fs::remove_file(db_file).expect("Removing file tempN2");
+        assert!(opponent.is_empty());
+        assert_eq!(disputes.len(), 1);
+        assert!(disputes[0].disputed);
+        assert_eq!(resolved, vec![(games[0].id, games[0].player1)]);
+        assert_eq!(overridden[1], (games[1].id, games[1].player2));
+        assert_eq!(left, 0);
+        assert_eq!(state, TournamentState::Done as u8);
+    }
+}
@@ -0,0 +1,399 @@
+use rusqlite::params;
+use server_core::{constants::*, types::*};
+use server_macro::Sql;
+
+use crate::{
+    _params,
+    badge::Badge,
+    server::{DataBase, ParamsType},
+    tournament::{Tournament, TournamentMatch},
+};
+
+// The bucket of the game between the losing semifinalists, buckets 1 and 2, of
+// a single elimination bracket
+pub(crate) const THIRD_PLACE_BUCKET: i64 = -1;
+
+// The players who finished first, second and third. Both losing semifinalists
+// share third place when there is no third place game.
+pub(crate) struct Podium
+{
+    pub winner: i64,
+    pub second: i64,
+    pub third:  Vec<i64>,
+}
+
+impl Podium
+{
+    // The top three of a ranking from first place down
+    pub(crate) fn from_ranking(ranked: &[i64]) -> Self
+    {
+        Podium {
+            winner: ranked[0],
+            second: ranked.get(1).copied().unwrap_or(0),
+            third:  ranked.get(2).copied().into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Sql)]
+struct TournamentPlacement
+{
+    id:         i64,
+    tournament: i64,
+    player:     i64,
+    place:      u8,
+}
+
+impl DataBase
+{
+    pub(crate) fn set_third_place_game(&self, tid: i64, third_place_game: bool)
+        -> ServerResult<()>
+    {
+        self.conn.execute(
+            "update tournaments set third_place_game = ?1 where id = ?2",
+            params![third_place_game, tid],
+        )?;
+        Ok(())
+    }
+
+    pub(crate) fn create_third_place_game(&self, tid: i64) -> ServerResult<()>
+    {
+        self._create_tournament_game(0, 0, THIRD_PLACE_BUCKET, tid)
+    }
+
+    // The loser of semifinal n is player n of the third place game, if there is
+    // one
+    pub(crate) fn send_loser_to_third_place_game(
+        &self,
+        bucket: i64,
+        loser_id: i64,
+        tid: i64,
+    ) -> ServerResult<()>
+    {
+        if bucket != 1 && bucket != 2
+        {
+            return Ok(());
+        }
+        let sql = format!(
+            "update tournament_games set player{} = ?1 where tournament = ?2 and bucket = ?3",
+            bucket
+        );
+        self.conn.execute(&sql, params![loser_id, tid, THIRD_PLACE_BUCKET])?;
+        Ok(())
+    }
+
+    pub(crate) fn get_bucket_match(&self, tid: i64, bucket: i64) -> Option<TournamentMatch>
+    {
+        self.sql_one::<TournamentMatch, _>(
+            "select m.* from tournament_matches m
+             join tournament_games g on m.game = g.id
+             where g.tournament = ?1 and g.bucket = ?2",
+            _params![tid, bucket],
+        )
+        .ok()
+    }
+
+    // The final and the third place game of a single elimination bracket can be
+    // played in any order, the tournament is over once both have been played
+    pub(crate) fn single_elimination_podium(&self, tournament: &Tournament) -> Option<Podium>
+    {
+        let last = self.get_bucket_match(tournament.id, 0)?;
+        let third = if tournament.third_place_game
+        {
+            vec![self.get_bucket_match(tournament.id, THIRD_PLACE_BUCKET)?.winner]
+        }
+        else
+        {
+            [1, 2]
+                .iter()
+                .filter_map(|bucket| self.get_bucket_match(tournament.id, *bucket))
+                .map(|m| m.loser)
+                .collect()
+        };
+        Some(Podium {
+            winner: last.winner,
+            second: last.loser,
+            third,
+        })
+    }
+
+    // The loser of the losers bracket final is third in double elimination
+    pub(crate) fn double_elimination_podium(
+        &self,
+        tournament: &Tournament,
+        winner: i64,
+        second: i64,
+    ) -> Podium
+    {
+        Podium {
+            winner,
+            second,
+            third: self.get_bucket_match(tournament.id, -1).map(|m| m.loser).into_iter().collect(),
+        }
+    }
+
+    pub(crate) fn create_placements(&self, tid: i64, podium: &Podium) -> ServerResult<()>
+    {
+        let places =
+            std::iter::once((podium.second, 2)).chain(podium.third.iter().map(|p| (*p, 3)));
+        for (player, place) in places.filter(|(player, _)| *player > 0)
+        {
+            self.conn.execute(
+                "insert into tournament_placements (tournament, player, place) values (?1, ?2, ?3)",
+                params![tid, player, place],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn delete_placements(&self, tid: i64) -> ServerResult<()>
+    {
+        self.conn
+            .execute("delete from tournament_placements where tournament = ?1", params![tid])?;
+        Ok(())
+    }
+
+    // The names of the players who finished second and third
+    pub(crate) fn get_placement_names(&self, tid: i64) -> ServerResult<(String, Vec<String>)>
+    {
+        let mut second = String::new();
+        let mut third = Vec::new();
+        for placement in self.sql_many::<TournamentPlacement, _>(
+            "select * from tournament_placements where tournament = ?1 order by id",
+            _params![tid],
+        )?
+        {
+            let name =
+                self.get_user_without_matches_by("id", "=", &placement.player.to_string())?.name;
+            if placement.place == 2
+            {
+                second = name;
+            }
+            else
+            {
+                third.push(name);
+            }
+        }
+        Ok((second, third))
+    }
+
+    // The knockout bracket of a group stage has the same placements as the group
+    // stage, so only the group stage gives badges
+    pub(crate) fn get_placement_badges(&self, pid: i64) -> ServerResult<Vec<Badge>>
+    {
+        self.sql_many::<TournamentPlacement, _>(
+            "select * from tournament_placements p where p.player = ?1
+             and not exists (select * from tournament_stages s where s.bracket = p.tournament)
+             order by p.id",
+            _params![pid],
+        )?
+        .into_iter()
+        .map(|placement| {
+            let tournament = self
+                .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![
+                    placement.tournament
+                ])?;
+            Ok(Badge {
+                id:      placement.id,
+                tooltip: tournament.name,
+                name:    BADGES[placement.place as usize - 1].to_string(),
+            })
+        })
+        .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use rusqlite::NO_PARAMS;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        test_util::*,
+        tournament::{
+            test::reg_tournament_match_from_tournament_game, CreateTournament, TournamentGame,
+            TournamentState,
+        },
+        validate::Validate,
+    };
+
+    fn tournament(ttype: &str, player_count: i64, third_place_game: bool) -> CreateTournament
+    {
+        serde_json::from_value(json!({
+            "name": "Lunch",
+            "image": "",
+            "player_count": player_count,
+            "ttype": ttype,
+            "third_place_game": third_place_game
+        }))
+        .unwrap()
+    }
+
+    fn create(s: &DataBase, tokens: &[String], mut info: CreateTournament) -> i64
+    {
+        info.organizer_token = tokens[0].clone();
+        s.create_tournament(info).unwrap();
+        let tid = s
+            .conn
+            .query_row("select max(id) from tournaments", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        for token in tokens
+        {
+            s.join_tournament(token.clone(), tid).unwrap();
+        }
+        tid
+    }
+
+    fn get_game(s: &DataBase, tid: i64, bucket: i64) -> TournamentGame
+    {
+        s.sql_one::<TournamentGame, _>(
+            "select * from tournament_games where tournament = ?1 and bucket = ?2",
+            _params![tid, bucket],
+        )
+        .unwrap()
+    }
+
+    // Player1 of the game wins
+    fn play(s: &DataBase, token: &str, game: &TournamentGame)
+    {
+        let register = reg_tournament_match_from_tournament_game(s, game, token.to_string());
+        s.register_tournament_match(register).unwrap();
+    }
+
+    // Player1 of every game wins until the tournament is done
+    fn play_out(s: &DataBase, token: &str, tid: i64)
+    {
+        let state = || {
+            s.sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![tid])
+                .unwrap()
+                .state
+        };
+        while state() != TournamentState::Done as u8
+        {
+            let game = s
+                .sql_one::<TournamentGame, _>(
+                    "select * from tournament_games g
+                     where g.tournament = ?1 and g.player1 > 0 and g.player2 > 0
+                     and not exists (select * from tournament_matches m where m.game = g.id)",
+                    _params![tid],
+                )
+                .unwrap();
+            play(s, token, &game);
+        }
+    }
+
+    fn placements(s: &DataBase, tid: i64) -> serde_json::Value
+    {
+        let info = serde_json::to_value(s.get_tournament_from_id(tid).unwrap()).unwrap();
+        json!([info["tournament"]["second_place"], info["tournament"]["third_place"]])
+    }
+
+    #[test]
+    fn test_third_place_game_decides_third_place()
+    {
+        let db_file = "tempE1.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=4).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+        let name =
+            |pid: i64| s.get_user_without_matches_by("id", "=", &pid.to_string()).unwrap().name;
+
+        let tid = create(&s, &tokens, tournament("singleElimination", 4, true));
+        let semifinals = [get_game(&s, tid, 1), get_game(&s, tid, 2)];
+        for game in &semifinals
+        {
+            play(&s, &tokens[0], game);
+        }
+        let third_place_game = get_game(&s, tid, THIRD_PLACE_BUCKET);
+        // The final is played first, but the tournament waits for the third place
+        // game
+        let last = get_game(&s, tid, 0);
+        play(&s, &tokens[0], &last);
+        let waiting = placements(&s, tid);
+        play(&s, &tokens[0], &third_place_game);
+        let done = placements(&s, tid);
+        let badges = s.get_user(&name(third_place_game.player1)).unwrap().badges;
+        let fourth = s.get_user(&name(third_place_game.player2)).unwrap().badges;
+
+        std::fs::remove_file(db_file).expect("Removing file tempE1");
+        assert_eq!(third_place_game.player1, semifinals[0].player2);
+        assert_eq!(third_place_game.player2, semifinals[1].player2);
+        assert_eq!(waiting, json!(["", []]));
+        assert_eq!(done, json!([name(last.player2), [name(third_place_game.player1)]]));
+        assert_eq!(badges.len(), 1);
+        assert_eq!(badges[0].name, BADGES[2]);
+        assert_eq!(badges[0].tooltip, "Lunch");
+        assert!(fourth.is_empty());
+    }
+
+    #[test]
+    fn test_semifinal_cannot_be_rerun_after_third_place_game()
+    {
+        let db_file = "tempE3.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=4).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+
+        let tid = create(&s, &tokens, tournament("singleElimination", 4, true));
+        play(&s, &tokens[0], &get_game(&s, tid, 1));
+        play(&s, &tokens[0], &get_game(&s, tid, 2));
+        // The first semifinal can be rerun until the third place game is played
+        let mut semifinal = get_game(&s, tid, 1);
+        std::mem::swap(&mut semifinal.player1, &mut semifinal.player2);
+        play(&s, &tokens[0], &semifinal);
+        let third_place_game = get_game(&s, tid, THIRD_PLACE_BUCKET);
+        play(&s, &tokens[0], &third_place_game);
+
+        std::mem::swap(&mut semifinal.player1, &mut semifinal.player2);
+        let register = reg_tournament_match_from_tournament_game(&s, &semifinal, tokens[0].clone());
+        let rerun = s.register_tournament_match(register);
+        let third_place = s.get_bucket_match(tid, THIRD_PLACE_BUCKET).unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempE3");
+        assert_eq!(third_place_game.player1, semifinal.player1);
+        assert_eq!(rerun, Err(ServerError::Tournament(TournamentError::CannotRerun)));
+        assert_eq!(third_place.winner, semifinal.player1);
+    }
+
+    #[test]
+    fn test_placements_for_every_tournament_type()
+    {
+        let db_file = "tempE2.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=8).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+        let name =
+            |pid: i64| s.get_user_without_matches_by("id", "=", &pid.to_string()).unwrap().name;
+
+        let invalid = tournament("doubleElimination", 8, true).validate();
+        let single = create(&s, &tokens, tournament("singleElimination", 8, false));
+        let semifinals = [get_game(&s, single, 1), get_game(&s, single, 2)];
+        play_out(&s, &tokens[0], single);
+        let semifinals: Vec<TournamentGame> =
+            semifinals.iter().map(|g| get_game(&s, single, g.bucket)).collect();
+        let single = placements(&s, single);
+
+        let double = create(&s, &tokens[..4], tournament("doubleElimination", 4, false));
+        play_out(&s, &tokens[0], double);
+        let losers_final = s.get_bucket_match(double, -1).unwrap();
+        let double = placements(&s, double);
+
+        let round_robin = create(&s, &tokens[..4], tournament("roundRobin", 4, false));
+        play_out(&s, &tokens[0], round_robin);
+        let standings = s.get_round_robin_standings(round_robin).unwrap();
+        let round_robin = placements(&s, round_robin);
+
+        std::fs::remove_file(db_file).expect("Removing file tempE2");
+        assert!(matches!(invalid, Err(ServerError::InvalidRequest(_))));
+        // Both losing semifinalists are third
+        assert_eq!(single[1], json!([name(semifinals[0].player2), name(semifinals[1].player2)]));
+        assert_eq!(double[1], json!([name(losers_final.loser)]));
+        assert_eq!(round_robin, json!([standings[1].name, [standings[2].name]]));
+    }
+}
@@ -0,0 +1,189 @@
+use rusqlite::params;
+use server_core::types::*;
+
+use crate::{
+    _params,
+    score::{scores_to_sql, GameScore},
+    server::{DataBase, ParamsType},
+    tournament::{Tournament, TournamentMatch},
+};
+
+impl DataBase
+{
+    pub(crate) fn set_tournament_rated(&self, tid: i64, rated: bool) -> ServerResult<()>
+    {
+        self.conn
+            .execute("update tournaments set rated = ?1 where id = ?2", params![rated, tid])?;
+        Ok(())
+    }
+
+    // Creates a match in `matches` for a game of a rated tournament, returns the
+    // id of the match or 0 when the game is not rated
+    pub(crate) fn rate_tournament_game(
+        &self,
+        winner_id: i64,
+        loser_id: i64,
+        game_id: i64,
+        scores: &[GameScore],
+    ) -> ServerResult<i64>
+    {
+        let tournament = self.sql_one::<Tournament, _>(
+            "select t.* from tournaments t
+             join tournament_games g on g.tournament = t.id
+             where g.id = ?1",
+            _params![game_id],
+        )?;
+        // A bye is not a game
+        if !tournament.rated || loser_id == 0
+        {
+            return Ok(0);
+        }
+
+        let user = |pid: i64| self.get_user_without_matches_by("id", "=", &pid.to_string());
+        self.create_rated_match(
+            self.epoch(),
+            &user(winner_id)?,
+            &user(loser_id)?,
+            &scores_to_sql(scores),
+        )
+    }
+
+    // Deletes the rated matches of the tournament matches and replays the
+    // ratings from the earliest of them
+    pub(crate) fn delete_rated_matches(&self, matches: &[&TournamentMatch]) -> ServerResult<()>
+    {
+        let mut footprints = Vec::new();
+        for m in matches.iter().filter(|m| m.rated_match != 0)
+        {
+            footprints.push(self.get_match_footprint(m.rated_match)?);
+            self.conn.execute("delete from matches where id = ?1", params![m.rated_match])?;
+        }
+        self.replay_changed_matches(&footprints)
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        rating::DEFAULT_RATING,
+        test_util::*,
+        tournament::{
+            test::reg_tournament_match_from_tournament_game, CreateTournament,
+            RegisterTournamentMatch,
+        },
+    };
+
+    fn create_round_robin(s: &DataBase, token: &str, rated: bool)
+    {
+        let mut info: CreateTournament = serde_json::from_value(json!({
+            "name": "Lunch",
+            "image": "",
+            "player_count": 4,
+            "ttype": "roundRobin",
+            "rated": rated
+        }))
+        .unwrap();
+        info.organizer_token = token.to_string();
+        s.create_tournament(info).unwrap();
+    }
+
+    fn elo(s: &DataBase, name: &str) -> f64
+    {
+        s.get_user_without_matches(name).unwrap().elo
+    }
+
+    #[test]
+    fn test_only_rated_tournament_games_are_rated()
+    {
+        let db_file = "tempQ1.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=4).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+
+        create_round_robin(&s, &tokens[0], false);
+        create_round_robin(&s, &tokens[0], true);
+        let mut histories = Vec::new();
+        for tid in 1..=2
+        {
+            for token in &tokens
+            {
+                s.join_tournament(token.clone(), tid).unwrap();
+            }
+            for game in &s.get_all_tournament_games(tid).unwrap()
+            {
+                let register =
+                    reg_tournament_match_from_tournament_game(&s, game, tokens[0].clone());
+                s.register_tournament_match(register).unwrap();
+            }
+            histories.push(s.get_history().unwrap().len());
+        }
+        let rated_matches: Vec<i64> = s
+            .sql_many::<TournamentMatch, _>("select * from tournament_matches", None)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.rated_match)
+            .collect();
+        let tournament = s
+            .sql_one::<Tournament, _>("select * from tournaments where id = 2", None)
+            .unwrap();
+        let elos: Vec<f64> = (1..=4).map(|n| elo(&s, &format!("Player{}", n))).collect();
+
+        std::fs::remove_file(db_file).expect("Removing file tempQ1");
+        assert_eq!(histories, vec![0, 6]);
+        assert!(rated_matches[..6].iter().all(|&id| id == 0));
+        assert!(rated_matches[6..].iter().all(|&id| id != 0));
+        assert!(tournament.rated);
+        assert!(elos.iter().all(|&elo| elo != DEFAULT_RATING));
+    }
+
+    #[test]
+    fn test_rerun_and_delete_reverse_ratings()
+    {
+        let db_file = "tempQ2.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=4).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+
+        create_round_robin(&s, &tokens[0], true);
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+        let game = &s.get_all_tournament_games(1).unwrap()[0];
+        let won = reg_tournament_match_from_tournament_game(&s, game, tokens[0].clone());
+        let (winner, loser) = (won.winner.clone(), won.loser.clone());
+        s.register_tournament_match(won).unwrap();
+        let first = (elo(&s, &winner), elo(&s, &loser));
+
+        // The loser won after all
+        let mut lost: RegisterTournamentMatch = serde_json::from_value(json!({
+            "winner": loser,
+            "loser": winner,
+            "tournament_game": game.id
+        }))
+        .unwrap();
+        lost.organizer_token = tokens[0].clone();
+        s.register_tournament_match(lost).unwrap();
+        let history = s.get_history().unwrap();
+        let second = (elo(&s, &winner), elo(&s, &loser));
+
+        s.delete_tournament(tokens[0].clone(), 1).unwrap();
+        let deleted = s.get_history().unwrap();
+        let third = (elo(&s, &winner), elo(&s, &loser));
+
+        std::fs::remove_file(db_file).expect("Removing file tempQ2");
+        assert!(first.0 > first.1);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].winner, loser);
+        assert_eq!(second, (first.1, first.0));
+        assert!(deleted.is_empty());
+        assert_eq!(third, (DEFAULT_RATING, DEFAULT_RATING));
+    }
+}
@@ -0,0 +1,398 @@
+use rusqlite::named_params;
+use serde_derive::Serialize;
+use server_core::types::*;
+
+use crate::{
+    score::scores_from_sql,
+    server::DataBase,
+    tournament::{RegisterTournamentMatch, Tournament, TournamentGame},
+    tournament_placements::Podium,
+    SQL_TUPLE_NAMED,
+};
+
+// A row in the table of a round robin or swiss tournament, from best to worst
+#[derive(Serialize)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct Standing
+{
+    pub name:             String,
+    pub played:           i64,
+    pub wins:             i64,
+    pub losses:           i64,
+    pub point_difference: i64,
+    // Only used by swiss tournaments
+    pub buchholz:         Option<i64>,
+}
+
+// A played game, `points` is the point difference from the winner's point of
+// view. A bye is a win against player 0.
+pub(crate) struct GameResult
+{
+    pub(crate) winner: i64,
+    pub(crate) loser:  i64,
+    pub(crate) points: i64,
+}
+
+// Every player meets every other player once. The first player stays in place
+// while the rest rotate around them, one round per rotation. With an odd number
+// of players one of them sits out each round.
+pub fn round_robin_schedule(players: &[i64]) -> Vec<Vec<(i64, i64)>>
+{
+    let mut circle = players.to_vec();
+    if circle.len() % 2 == 1
+    {
+        circle.push(0);
+    }
+    let n = circle.len();
+    if n < 2
+    {
+        return Vec::new();
+    }
+
+    (0..n - 1)
+        .map(|_| {
+            let round = (0..n / 2)
+                .map(|i| (circle[i], circle[n - 1 - i]))
+                .filter(|&(p1, p2)| p1 != 0 && p2 != 0)
+                .collect();
+            circle[1..].rotate_right(1);
+            round
+        })
+        .collect()
+}
+
+pub(crate) fn wins(pid: i64, results: &[GameResult]) -> i64
+{
+    results.iter().filter(|r| r.winner == pid).count() as i64
+}
+
+pub(crate) fn point_difference(pid: i64, results: &[GameResult]) -> i64
+{
+    results
+        .iter()
+        .map(|r| {
+            if r.winner == pid
+            {
+                r.points
+            }
+            else if r.loser == pid
+            {
+                -r.points
+            }
+            else
+            {
+                0
+            }
+        })
+        .sum()
+}
+
+// Orders the players by wins. Players with the same number of wins are
+// ordered by how many games they won against each other, and then by point
+// difference.
+pub(crate) fn rank_players(players: &[i64], results: &[GameResult]) -> Vec<i64>
+{
+    let wins = |pid: i64| wins(pid, results);
+    let head_to_head = |pid: i64, tied: &[i64]| {
+        results.iter().filter(|r| r.winner == pid && tied.contains(&r.loser)).count()
+    };
+
+    let mut ranked = players.to_vec();
+    ranked.sort_by(|&a, &b| {
+        let tied: Vec<i64> = players.iter().copied().filter(|&p| wins(p) == wins(a)).collect();
+        wins(b)
+            .cmp(&wins(a))
+            .then_with(|| head_to_head(b, &tied).cmp(&head_to_head(a, &tied)))
+            .then_with(|| point_difference(b, results).cmp(&point_difference(a, results)))
+    });
+    ranked
+}
+
+impl DataBase
+{
+    // The whole schedule is created up front, the bucket of each game is its round
+    pub(crate) fn generate_round_robin(
+        &self,
+        tournament: &Tournament,
+        people: &[i64],
+    ) -> ServerResult<()>
+    {
+        for (round, games) in round_robin_schedule(people).into_iter().enumerate()
+        {
+            for (p1, p2) in games
+            {
+                self._create_tournament_game(p1, p2, round as i64, tournament.id)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn handle_round_robin_match(
+        &self,
+        game: &TournamentGame,
+        register_game: &RegisterTournamentMatch,
+        tournament: &Tournament,
+    ) -> ServerResult<()>
+    {
+        self.create_match_between_players(game, register_game)?;
+
+        // The tournament is over when every game has been played
+        let results = self.get_tournament_results(tournament.id)?;
+        let games: i64 = self.conn.query_row_named(
+            "select count(*) from tournament_games where tournament = :tid",
+            named_params! {":tid": tournament.id},
+            |row| row.get(0),
+        )?;
+        if results.len() as i64 == games
+        {
+            let players = self.get_player_ids(tournament.id)?;
+            let ranked = self.withdrawn_last(tournament.id, rank_players(&players, &results))?;
+            self.finish_tournament(tournament, &Podium::from_ranking(&ranked))?;
+        }
+        Ok(())
+    }
+
+    // Stores the result of a game where the winner and loser have to be the
+    // players of the game
+    pub(crate) fn create_match_between_players(
+        &self,
+        game: &TournamentGame,
+        register_game: &RegisterTournamentMatch,
+    ) -> ServerResult<()>
+    {
+        let winner_id = self.get_user_without_matches(&register_game.winner)?.id;
+        let loser_id = self.get_user_without_matches(&register_game.loser)?.id;
+        if winner_id == loser_id
+            || ![winner_id, loser_id].iter().all(|p| *p == game.player1 || *p == game.player2)
+        {
+            return Err(ServerError::Tournament(TournamentError::InvalidGame));
+        }
+
+        self.create_match_from_game(winner_id, loser_id, game.id, &register_game.scores)
+    }
+
+    pub(crate) fn get_tournament_results(&self, tid: i64) -> ServerResult<Vec<GameResult>>
+    {
+        let results = SQL_TUPLE_NAMED!(
+            self,
+            "select m.winner, m.loser, m.scores from tournament_matches m
+             inner join tournament_games g on g.id = m.game
+             where g.tournament = :tid",
+            named_params! {":tid": tid},
+            i64,
+            i64,
+            String
+        )?;
+
+        Ok(results
+            .into_iter()
+            .map(|(winner, loser, scores)| GameResult {
+                winner,
+                loser,
+                points: scores_from_sql(&scores).iter().map(|g| g[0] as i64 - g[1] as i64).sum(),
+            })
+            .collect())
+    }
+
+    pub fn get_round_robin_standings(&self, tid: i64) -> ServerResult<Vec<Standing>>
+    {
+        let results = self.get_tournament_results(tid)?;
+        let players = self.get_player_ids(tid)?;
+
+        self.create_standings(rank_players(&players, &results), &results, |_| None)
+    }
+
+    pub(crate) fn create_standings(
+        &self,
+        ranked: Vec<i64>,
+        results: &[GameResult],
+        buchholz: impl Fn(i64) -> Option<i64>,
+    ) -> ServerResult<Vec<Standing>>
+    {
+        ranked
+            .into_iter()
+            .map(|pid| {
+                let wins = wins(pid, results);
+                let losses = results.iter().filter(|r| r.loser == pid).count() as i64;
+                Ok(Standing {
+                    name: self.get_user_without_matches_by("id", "=", &pid.to_string())?.name,
+                    played: wins + losses,
+                    wins,
+                    losses,
+                    point_difference: point_difference(pid, results),
+                    buchholz: buchholz(pid),
+                })
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        test_util::*,
+        tournament::{test::reg_tournament_match_from_tournament_game, CreateTournament},
+        TournamentState,
+    };
+
+    fn create_round_robin(s: &DataBase, token: &str, player_count: i64)
+    {
+        let mut info: CreateTournament = serde_json::from_value(json!({
+            "name": "Lunch",
+            "image": "",
+            "player_count": player_count,
+            "ttype": "roundRobin"
+        }))
+        .unwrap();
+        info.organizer_token = token.to_string();
+        s.create_tournament(info).unwrap();
+    }
+
+    fn result(winner: i64, loser: i64, points: i64) -> GameResult
+    {
+        GameResult {
+            winner,
+            loser,
+            points,
+        }
+    }
+
+    #[test]
+    fn test_everyone_meets_everyone_once()
+    {
+        for n in 4..=9
+        {
+            let players: Vec<i64> = (1..=n).collect();
+            let schedule = round_robin_schedule(&players);
+            let mut games: Vec<(i64, i64)> =
+                schedule.iter().flatten().map(|&(a, b)| (a.min(b), a.max(b))).collect();
+            games.sort_unstable();
+            games.dedup();
+
+            assert_eq!(games.len() as i64, n * (n - 1) / 2);
+            assert_eq!(schedule.iter().flatten().count(), games.len());
+            for round in &schedule
+            {
+                let mut seen: Vec<i64> = round.iter().flat_map(|&(a, b)| vec![a, b]).collect();
+                seen.sort_unstable();
+                seen.dedup();
+                assert_eq!(seen.len(), round.len() * 2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ties_are_broken_by_head_to_head_then_points()
+    {
+        // 1, 2 and 3 all have two wins and one win against each other, 2 has the
+        // best point difference
+        let results = vec![
+            result(1, 2, 5),
+            result(2, 3, 9),
+            result(3, 1, 2),
+            result(1, 4, 1),
+            result(2, 4, 1),
+            result(3, 4, 1),
+        ];
+        assert_eq!(rank_players(&[1, 2, 3, 4], &results), vec![2, 1, 3, 4]);
+
+        // 2 and 3 have two wins and 3 beat 2, even though 2 has more points
+        let results = vec![
+            result(3, 2, 1),
+            result(2, 1, 20),
+            result(2, 4, 20),
+            result(3, 1, 1),
+            result(4, 3, 1),
+            result(1, 4, 1),
+        ];
+        assert_eq!(rank_players(&[1, 2, 3, 4], &results), vec![3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn test_can_run_round_robin_tournament()
+    {
+        let db_file = "tempR1.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=5).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+
+        create_round_robin(&s, &tokens[0], 5);
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+
+        let games = s.get_all_tournament_games(1).unwrap();
+        let mut states = Vec::new();
+        for game in &games
+        {
+            let register = reg_tournament_match_from_tournament_game(&s, game, tokens[0].clone());
+            s.register_tournament_match(register).unwrap();
+            states
+                .push(s.sql_one::<Tournament, _>("select * from tournaments", None).unwrap().state);
+        }
+        let standings = s.get_round_robin_standings(1).unwrap();
+        let winner = s.get_user(&standings[0].name).unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempR1");
+        assert_eq!(games.len(), 10);
+        assert_eq!(states.pop(), Some(TournamentState::Done as u8));
+        assert!(states.iter().all(|&state| state == TournamentState::InProgress as u8));
+        assert_eq!(standings.len(), 5);
+        assert!(standings.iter().all(|s| s.played == 4));
+        assert!(standings.windows(2).all(|w| w[0].wins >= w[1].wins));
+        assert_eq!(winner.badges.len(), 1);
+    }
+
+    #[test]
+    fn test_rerun_after_last_game_picks_new_winner()
+    {
+        let db_file = "tempR2.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=4).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+
+        create_round_robin(&s, &tokens[0], 4);
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+        let games = s.get_all_tournament_games(1).unwrap();
+        for game in &games
+        {
+            let register = reg_tournament_match_from_tournament_game(&s, game, tokens[0].clone());
+            s.register_tournament_match(register).unwrap();
+        }
+        let first = s.get_round_robin_standings(1).unwrap();
+
+        // The winner loses every game they won instead
+        let winner_id = s.get_user_without_matches(&first[0].name).unwrap().id;
+        for game in games.iter().filter(|g| g.player1 == winner_id)
+        {
+            let won = reg_tournament_match_from_tournament_game(&s, game, tokens[0].clone());
+            let mut lost: RegisterTournamentMatch = serde_json::from_value(json!({
+                "winner": won.loser,
+                "loser": won.winner,
+                "tournament_game": game.id
+            }))
+            .unwrap();
+            lost.organizer_token = tokens[0].clone();
+            s.register_tournament_match(lost).unwrap();
+        }
+        let second = s.get_round_robin_standings(1).unwrap();
+        let winners = get_table_size(&s, "tournament_winners");
+        let badges = get_table_size(&s, "tournament_badges");
+
+        std::fs::remove_file(db_file).expect("Removing file tempR2");
+        assert_ne!(first[0].name, second[0].name);
+        assert_eq!(winners, 1);
+        assert_eq!(badges, 1);
+    }
+}
@@ -0,0 +1,599 @@
+use std::collections::HashMap;
+
+use chrono::prelude::*;
+use rusqlite::params;
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use server_core::types::*;
+use server_macro::Sql;
+
+use crate::{
+    _named_params, _params,
+    server::{DataBase, ParamsType},
+    tournament::{Tournament, TournamentGame, TournamentMatch, TournamentState},
+    validate::{Validate, Validator},
+};
+
+const MINUTE_MS: i64 = 60 * 1000;
+const MAX_GAME_MINUTES: i64 = 24 * 60;
+const MAX_TABLES: i64 = 64;
+
+fn default_minutes() -> i64
+{
+    30
+}
+
+#[derive(Deserialize)]
+pub struct ScheduleGame
+{
+    pub game:    i64,
+    // Epoch in milliseconds
+    pub start:   i64,
+    pub table:   i64,
+    #[serde(default = "default_minutes")]
+    pub minutes: i64,
+}
+
+// Schedules every game of a tournament where both players are known, from
+// `start` and on across `tables` tables
+#[derive(Deserialize)]
+pub struct ScheduleTournament
+{
+    pub tid:     i64,
+    pub start:   i64,
+    pub tables:  i64,
+    #[serde(default = "default_minutes")]
+    pub minutes: i64,
+}
+
+impl Validate for ScheduleGame
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new()
+            .check(self.game > 0, "game", "Must be positive")
+            .check(self.start > 0, "start", "Must be positive")
+            .check((1..=MAX_TABLES).contains(&self.table), "table", "Must be between 1 and 64")
+            .check(
+                (1..=MAX_GAME_MINUTES).contains(&self.minutes),
+                "minutes",
+                "Must be between 1 and 1440",
+            )
+            .finish()
+    }
+}
+
+impl Validate for ScheduleTournament
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new()
+            .check(self.tid > 0, "tid", "Must be positive")
+            .check(self.start > 0, "start", "Must be positive")
+            .check((1..=MAX_TABLES).contains(&self.tables), "tables", "Must be between 1 and 64")
+            .check(
+                (1..=MAX_GAME_MINUTES).contains(&self.minutes),
+                "minutes",
+                "Must be between 1 and 1440",
+            )
+            .finish()
+    }
+}
+
+// A scheduled game together with its players
+#[derive(Sql)]
+pub(crate) struct ScheduledGame
+{
+    #[allow(dead_code)]
+    pub id:           i64,
+    pub game:         i64,
+    pub start:        i64,
+    pub table_number: i64,
+    pub minutes:      i64,
+    pub tournament:   i64,
+    pub player1:      i64,
+    pub player2:      i64,
+}
+
+const SCHEDULED_GAMES: &str = "select s.*, g.tournament, g.player1, g.player2
+    from tournament_schedule s join tournament_games g on s.game = g.id";
+
+impl ScheduledGame
+{
+    fn has_player(&self, pid: i64) -> bool
+    {
+        pid > 0 && (self.player1 == pid || self.player2 == pid)
+    }
+
+    fn end(&self) -> i64
+    {
+        self.start + self.minutes * MINUTE_MS
+    }
+
+    fn snapshot(&self) -> Value
+    {
+        json!({"start": self.start, "table": self.table_number, "minutes": self.minutes})
+    }
+}
+
+// Escapes the text of an iCalendar property
+fn ics_text(text: &str) -> String
+{
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+// Folds a content line into lines of at most 75 octets, where each line after
+// the first starts with a space
+fn ics_fold(line: &str) -> String
+{
+    let mut folded = String::new();
+    let mut octets = 0;
+    for c in line.chars()
+    {
+        if octets + c.len_utf8() > 75
+        {
+            folded.push_str("\r\n ");
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+    folded
+}
+
+fn ics_time(epoch: i64) -> String
+{
+    Utc.timestamp_millis(epoch).format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// Organizers give each game a start time and a table, either by hand or by
+// letting the scheduler put the games in the first slot where both players and
+// a table are free. A player is never booked for two games at once, even in
+// different tournaments, while the tables belong to a tournament.
+impl DataBase
+{
+    pub fn schedule_game(&self, token: String, info: ScheduleGame) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let game = self.sql_one::<TournamentGame, _>(
+                "select * from tournament_games where id = ?1",
+                _params![info.game],
+            )?;
+            let tournament = self.get_organized_tournament(&token, game.tournament)?;
+            if self
+                .sql_one::<TournamentMatch, _>(
+                    "select * from tournament_matches where game = ?1",
+                    _params![game.id],
+                )
+                .is_ok()
+            {
+                return Err(ServerError::Tournament(TournamentError::GameAlreadyPlayed));
+            }
+
+            let busy = self.get_overlapping_games(game.id, info.start, info.minutes)?;
+            let mut validator = Validator::new();
+            validator
+                .check(
+                    !busy.iter().any(|s| s.has_player(game.player1) || s.has_player(game.player2)),
+                    "start",
+                    "A player already has a game at this time",
+                )
+                .check(
+                    !busy
+                        .iter()
+                        .any(|s| s.tournament == tournament.id && s.table_number == info.table),
+                    "table",
+                    "The table is taken at this time",
+                );
+            validator.finish()?;
+
+            let before = self.get_game_schedule(game.id).map_or(Value::Null, |s| s.snapshot());
+            self.insert_schedule(game.id, info.start, info.table, info.minutes)?;
+            let after = json!({"start": info.start, "table": info.table, "minutes": info.minutes});
+            let target = format!("tournament_game:{}", game.id);
+            self.audit(tournament.organizer, "schedule_game", &target, before, after)
+        })
+    }
+
+    pub fn schedule_tournament(&self, token: String, info: ScheduleTournament) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let tournament = self.get_organized_tournament(&token, info.tid)?;
+            if tournament.state != TournamentState::InProgress as u8
+            {
+                return Err(ServerError::Tournament(TournamentError::WrongState));
+            }
+
+            let games = self.sql_many::<TournamentGame, _>(
+                "select * from tournament_games g
+                 where g.tournament = ?1 and g.player1 > 0 and g.player2 > 0
+                 and not exists (select * from tournament_matches m where m.game = g.id)
+                 and not exists (select * from tournament_schedule s where s.game = g.id)
+                 order by g.id",
+                _params![tournament.id],
+            )?;
+            // When each table and player is free, after the games already booked
+            let booked = self.get_games_ending_after(info.start)?;
+            let mut tables: Vec<i64> = (1..=info.tables)
+                .map(|table| {
+                    booked
+                        .iter()
+                        .filter(|s| s.tournament == tournament.id && s.table_number == table)
+                        .fold(info.start, |free, s| free.max(s.end()))
+                })
+                .collect();
+            let mut players: HashMap<i64, i64> = HashMap::new();
+            for s in &booked
+            {
+                for pid in vec![s.player1, s.player2].into_iter().filter(|&pid| pid > 0)
+                {
+                    let free = players.entry(pid).or_insert(info.start);
+                    *free = (*free).max(s.end());
+                }
+            }
+
+            let length = info.minutes * MINUTE_MS;
+            let mut slots = Vec::new();
+            for game in games
+            {
+                let free = |pid: i64| players.get(&pid).copied().unwrap_or(info.start);
+                let ready = free(game.player1).max(free(game.player2));
+                let (table, at) = tables
+                    .iter()
+                    .enumerate()
+                    .map(|(table, &free)| (table, free.max(ready)))
+                    .min_by_key(|&(table, at)| (at, table))
+                    .unwrap();
+                // Games start on the slots from `start` and on
+                let start = info.start + (at - info.start + length - 1) / length * length;
+                tables[table] = start + length;
+                players.insert(game.player1, start + length);
+                players.insert(game.player2, start + length);
+                slots.push((game.id, start, table as i64 + 1));
+            }
+            for (game, start, table) in slots
+            {
+                self.insert_schedule(game, start, table, info.minutes)?;
+            }
+            let after =
+                json!({"start": info.start, "tables": info.tables, "minutes": info.minutes});
+            let target = format!("tournament:{}", tournament.id);
+            self.audit(tournament.organizer, "schedule_tournament", &target, Value::Null, after)
+        })
+    }
+
+    pub(crate) fn get_game_schedule(&self, game_id: i64) -> Option<ScheduledGame>
+    {
+        self.sql_one::<ScheduledGame, _>(
+            format!("{} where s.game = ?1", SCHEDULED_GAMES),
+            _params![game_id],
+        )
+        .ok()
+    }
+
+    pub(crate) fn delete_game_schedule(&self, game_id: i64) -> ServerResult<()>
+    {
+        self.conn
+            .execute("delete from tournament_schedule where game = ?1", params![game_id])?;
+        Ok(())
+    }
+
+    // The games of a group stage and of its knockout bracket
+    pub fn get_tournament_calendar(&self, tid: i64) -> ServerResult<String>
+    {
+        let games = self.sql_many::<ScheduledGame, _>(
+            format!(
+                "{} where g.tournament = :tid
+                 or g.tournament = (select bracket from tournament_stages where tournament = :tid)
+                 order by s.start",
+                SCHEDULED_GAMES
+            ),
+            _named_params! {":tid": tid},
+        )?;
+        self.create_calendar(&games)
+    }
+
+    pub fn get_player_calendar(&self, name: &str) -> ServerResult<String>
+    {
+        let pid = self.get_user_without_matches(name)?.id;
+        let games = self.sql_many::<ScheduledGame, _>(
+            format!(
+                "{} where g.player1 = :pid or g.player2 = :pid order by s.start",
+                SCHEDULED_GAMES
+            ),
+            _named_params! {":pid": pid},
+        )?;
+        self.create_calendar(&games)
+    }
+
+    fn get_organized_tournament(&self, token: &str, tid: i64) -> ServerResult<Tournament>
+    {
+        let organizer_id = self.get_user_from_token(token)?.id;
+        let tournament = self
+            .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![tid])?;
+        if organizer_id != tournament.organizer
+        {
+            return Err(ServerError::Tournament(TournamentError::NotOrganizer));
+        }
+        Ok(tournament)
+    }
+
+    // The unplayed games other than `game_id` that are going on some time between
+    // `start` and `minutes` later
+    fn get_overlapping_games(
+        &self,
+        game_id: i64,
+        start: i64,
+        minutes: i64,
+    ) -> ServerResult<Vec<ScheduledGame>>
+    {
+        self.sql_many::<ScheduledGame, _>(
+            format!(
+                "{} where s.game != :game and s.start < :end and s.start + s.minutes * :minute > \
+                 :start
+                 and not exists (select * from tournament_matches m where m.game = g.id)",
+                SCHEDULED_GAMES
+            ),
+            _named_params! {
+                ":game": game_id,
+                ":start": start,
+                ":end": start + minutes * MINUTE_MS,
+                ":minute": MINUTE_MS,
+            },
+        )
+    }
+
+    // The unplayed games that are still going on at `time` or start later
+    fn get_games_ending_after(&self, time: i64) -> ServerResult<Vec<ScheduledGame>>
+    {
+        self.sql_many::<ScheduledGame, _>(
+            format!(
+                "{} where s.start + s.minutes * :minute > :time
+                 and not exists (select * from tournament_matches m where m.game = g.id)",
+                SCHEDULED_GAMES
+            ),
+            _named_params! {":time": time, ":minute": MINUTE_MS},
+        )
+    }
+
+    fn insert_schedule(
+        &self,
+        game_id: i64,
+        start: i64,
+        table: i64,
+        minutes: i64,
+    ) -> ServerResult<()>
+    {
+        self.conn.execute(
+            "insert or replace into tournament_schedule (game, start, table_number, minutes) \
+             values (?1, ?2, ?3, ?4)",
+            params![game_id, start, table, minutes],
+        )?;
+        Ok(())
+    }
+
+    fn create_calendar(&self, games: &[ScheduledGame]) -> ServerResult<String>
+    {
+        let name = |pid: i64| -> ServerResult<String> {
+            if pid > 0
+            {
+                Ok(self.get_user_without_matches_by("id", "=", &pid.to_string())?.name)
+            }
+            else
+            {
+                Ok(String::from("TBD"))
+            }
+        };
+
+        let now = ics_time(self.epoch());
+        let mut lines = vec![
+            String::from("BEGIN:VCALENDAR"),
+            String::from("VERSION:2.0"),
+            String::from("PRODID:-//Rankzter//Tournaments//EN"),
+        ];
+        for game in games
+        {
+            let tournament = self
+                .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![
+                    game.tournament
+                ])?;
+            lines.extend(vec![
+                String::from("BEGIN:VEVENT"),
+                format!("UID:tournament-game-{}@rankzter", game.game),
+                format!("DTSTAMP:{}", now),
+                format!("DTSTART:{}", ics_time(game.start)),
+                format!("DTEND:{}", ics_time(game.start + game.minutes * MINUTE_MS)),
+                format!(
+                    "SUMMARY:{}",
+                    ics_text(&format!("{} vs {}", name(game.player1)?, name(game.player2)?))
+                ),
+                format!("DESCRIPTION:{}", ics_text(&tournament.name)),
+                format!("LOCATION:{}", ics_text(&format!("Table {}", game.table_number))),
+                String::from("END:VEVENT"),
+            ]);
+        }
+        lines.push(String::from("END:VCALENDAR"));
+        // iCalendar lines end with CRLF
+        Ok(lines.iter().map(|line| format!("{}\r\n", ics_fold(line))).collect())
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use rusqlite::NO_PARAMS;
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        test_util::*,
+        tournament::{test::reg_tournament_match_from_tournament_game, CreateTournament},
+    };
+
+    // 2020-09-13 12:26:40 UTC
+    const START: i64 = 1_600_000_000_000;
+
+    fn create(s: &DataBase, tokens: &[String])
+    {
+        let mut info: CreateTournament = serde_json::from_value(json!({
+            "name": "Lunch, round robin",
+            "image": "",
+            "player_count": tokens.len(),
+            "ttype": "roundRobin"
+        }))
+        .unwrap();
+        info.organizer_token = tokens[0].clone();
+        s.create_tournament(info).unwrap();
+        let tid = s
+            .conn
+            .query_row("select max(id) from tournaments", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        for token in tokens
+        {
+            s.join_tournament(token.clone(), tid).unwrap();
+        }
+    }
+
+    fn schedule(game: i64, start: i64, table: i64) -> ScheduleGame
+    {
+        ScheduleGame {
+            game,
+            start,
+            table,
+            minutes: 30,
+        }
+    }
+
+    fn get_schedule(s: &DataBase) -> Vec<ScheduledGame>
+    {
+        s.sql_many::<ScheduledGame, _>(format!("{} order by s.start", SCHEDULED_GAMES), None)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_schedule_tournament_on_tables()
+    {
+        let db_file = "tempB1.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=6).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+        create(&s, &tokens);
+
+        let games = s.get_all_tournament_games(1).unwrap();
+        let played = reg_tournament_match_from_tournament_game(&s, &games[0], tokens[0].clone());
+        s.register_tournament_match(played).unwrap();
+        let info = |tables: i64| ScheduleTournament {
+            tid: 1,
+            start: START,
+            tables,
+            minutes: 30,
+        };
+        let not_organizer = s.schedule_tournament(tokens[1].clone(), info(2));
+        // The scheduler works around a game that is already booked
+        s.schedule_game(tokens[0].clone(), schedule(games[1].id, START + 30 * MINUTE_MS, 1))
+            .unwrap();
+        s.schedule_tournament(tokens[0].clone(), info(2)).unwrap();
+        let scheduled = get_schedule(&s);
+        let already_played = s.schedule_game(tokens[0].clone(), schedule(games[0].id, START, 1));
+
+        std::fs::remove_file(db_file).expect("Removing file tempB1");
+        assert!(matches!(
+            not_organizer,
+            Err(ServerError::Tournament(TournamentError::NotOrganizer))
+        ));
+        assert!(matches!(
+            already_played,
+            Err(ServerError::Tournament(TournamentError::GameAlreadyPlayed))
+        ));
+        // Every game but the played one is scheduled
+        assert_eq!(scheduled.len(), 14);
+        assert!(scheduled.iter().all(|g| g.game != games[0].id));
+        assert!(scheduled.iter().all(|g| g.table_number == 1 || g.table_number == 2));
+        for (i, a) in scheduled.iter().enumerate()
+        {
+            assert_eq!((a.start - START) % (30 * MINUTE_MS), 0);
+            for b in scheduled[i + 1..].iter().filter(|b| b.start == a.start)
+            {
+                assert_ne!(a.table_number, b.table_number);
+                assert!(!b.has_player(a.player1) && !b.has_player(a.player2));
+            }
+        }
+        // 14 games on 2 tables take at least 7 slots
+        assert!(scheduled.last().unwrap().start >= START + 6 * 30 * MINUTE_MS);
+    }
+
+    #[test]
+    fn test_schedule_game_by_hand_and_calendars()
+    {
+        let db_file = "tempB2.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=4).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+        create(&s, &tokens);
+
+        let games = s.get_all_tournament_games(1).unwrap();
+        let shares_player = |a: &TournamentGame, b: &TournamentGame| {
+            [a.player1, a.player2].iter().any(|p| *p == b.player1 || *p == b.player2)
+        };
+        let first = &games[0];
+        let clash = games[1..].iter().find(|g| shares_player(first, g)).unwrap();
+        let other = games[1..].iter().find(|g| !shares_player(first, g)).unwrap();
+
+        s.schedule_game(tokens[0].clone(), schedule(first.id, START, 1)).unwrap();
+        // Overlaps the first game by ten minutes
+        let player_busy =
+            s.schedule_game(tokens[0].clone(), schedule(clash.id, START + 20 * MINUTE_MS, 2));
+        let table_busy = s.schedule_game(tokens[0].clone(), schedule(other.id, START, 1));
+        s.schedule_game(tokens[0].clone(), schedule(other.id, START, 2)).unwrap();
+        s.schedule_game(tokens[0].clone(), schedule(clash.id, START + 30 * MINUTE_MS, 1))
+            .unwrap();
+        let info = serde_json::to_value(s.get_tournament_from_id(1).unwrap()).unwrap();
+        let tournament = s.get_tournament_calendar(1).unwrap();
+        let player = s.get_player_calendar("Player1").unwrap();
+        let name =
+            |pid: i64| s.get_user_without_matches_by("id", "=", &pid.to_string()).unwrap().name;
+        let summary = format!("SUMMARY:{} vs {}\r\n", name(first.player1), name(first.player2));
+
+        std::fs::remove_file(db_file).expect("Removing file tempB2");
+        assert!(matches!(player_busy, Err(ServerError::InvalidRequest(_))));
+        assert!(matches!(table_busy, Err(ServerError::InvalidRequest(_))));
+        let first_info =
+            info["data"]["Games"].as_array().unwrap().iter().find(|g| g["id"] == first.id);
+        assert_eq!(first_info.unwrap()["start"], START);
+        assert_eq!(first_info.unwrap()["table"], 1);
+
+        assert!(tournament.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(tournament.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(tournament.matches("BEGIN:VEVENT\r\n").count(), 3);
+        assert!(tournament.contains(&format!("UID:tournament-game-{}@rankzter\r\n", first.id)));
+        assert!(tournament.contains("DTSTART:20200913T122640Z\r\nDTEND:20200913T125640Z\r\n"));
+        assert!(tournament.contains(&summary));
+        assert!(tournament.contains("DESCRIPTION:Lunch\\, round robin\r\n"));
+        assert!(tournament.contains("LOCATION:Table 2\r\n"));
+        // Only the games Player1 plays in
+        let playing = games.iter().filter(|g| g.player1 == 1 || g.player2 == 1);
+        let scheduled = [first.id, clash.id, other.id];
+        assert_eq!(
+            player.matches("BEGIN:VEVENT").count(),
+            playing.filter(|g| scheduled.contains(&g.id)).count()
+        );
+    }
+
+    #[test]
+    fn test_long_calendar_lines_are_folded()
+    {
+        let line = format!("DESCRIPTION:{}", "Lunch tournament \u{f8}".repeat(10));
+        let folded = ics_fold(&line);
+
+        assert_eq!(ics_fold("END:VEVENT"), "END:VEVENT");
+        assert!(folded.split("\r\n").all(|part| part.len() <= 75));
+        assert!(folded.split("\r\n").skip(1).all(|part| part.starts_with(' ')));
+        assert_eq!(folded.replace("\r\n ", ""), line);
+    }
+}
@@ -0,0 +1,420 @@
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use rusqlite::params;
+use serde_derive::Deserialize;
+use server_core::types::*;
+use server_macro::Sql;
+
+use crate::{
+    _params,
+    server::{DataBase, ParamsType},
+    tournament::Tournament,
+    validate::Validator,
+};
+
+#[repr(u8)]
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub enum SeedingMode
+{
+    Random,
+    Rating,
+    Manual,
+}
+
+impl From<u8> for SeedingMode
+{
+    fn from(n: u8) -> Self
+    {
+        match n
+        {
+            0 => SeedingMode::Random,
+            1 => SeedingMode::Rating,
+            2 => SeedingMode::Manual,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl SeedingMode
+{
+    pub fn from_name(name: &str) -> Option<Self>
+    {
+        match name
+        {
+            "random" => Some(SeedingMode::Random),
+            "rating" => Some(SeedingMode::Rating),
+            "manual" => Some(SeedingMode::Manual),
+            _ => None,
+        }
+    }
+}
+
+// How the players of a tournament are seeded, sent with the tournament when it
+// is created
+#[derive(Deserialize)]
+pub struct SeedingOptions
+{
+    pub mode:     String,
+    // Usernames from the first seed down, for manual seeding. Players that are
+    // not listed come after the ones that are.
+    #[serde(default)]
+    pub players:  Vec<String>,
+    // Picked at random when left out
+    #[serde(default)]
+    pub rng_seed: Option<i64>,
+}
+
+#[derive(Sql)]
+struct TournamentSeeding
+{
+    #[allow(dead_code)]
+    id:         i64,
+    #[allow(dead_code)]
+    tournament: i64,
+    mode:       u8,
+    rng_seed:   i64,
+    seeds:      String,
+}
+
+pub(crate) fn check_seeding(validator: &mut Validator, options: &SeedingOptions)
+{
+    validator.check(
+        SeedingMode::from_name(&options.mode).is_some(),
+        "seeding.mode",
+        "Must be random, rating or manual",
+    );
+    for name in &options.players
+    {
+        validator.username("seeding.players", name);
+    }
+}
+
+// The seeds in the order they meet in the first round of a bracket of `size`,
+// so that the best seeds meet as late as possible: 1 vs 8, 4 vs 5, 2 vs 7 and
+// 3 vs 6
+pub fn bracket_order(size: usize) -> Vec<usize>
+{
+    let mut order = vec![1];
+    while order.len() < size
+    {
+        let sum = order.len() * 2 + 1;
+        order = order.iter().flat_map(|&seed| vec![seed, sum - seed]).collect();
+    }
+    order
+}
+
+// Lays out the seeds the way `generate_buckets` expects them, where
+// people[i] meets people[i + power / 2] in the first round and 0 is a bye. The
+// seeds that are missing from a full bracket are byes for the best seeds.
+pub(crate) fn bracket_slots(seeds: &[i64]) -> Vec<i64>
+{
+    let seed = |s: usize| seeds.get(s - 1).copied().unwrap_or(0);
+    let games: Vec<(i64, i64)> = bracket_order(seeds.len().next_power_of_two())
+        .chunks(2)
+        .map(|pair| (seed(pair[0]), seed(pair[1])))
+        .collect();
+    games.iter().map(|g| g.0).chain(games.iter().map(|g| g.1)).collect()
+}
+
+impl DataBase
+{
+    pub(crate) fn create_seeding(&self, tid: i64, options: &SeedingOptions) -> ServerResult<()>
+    {
+        let mode = SeedingMode::from_name(&options.mode).ok_or_else(|| {
+            ServerError::InvalidRequest(vec![FieldError::new(
+                "seeding.mode",
+                "Must be random, rating or manual",
+            )])
+        })?;
+        let seeds = options
+            .players
+            .iter()
+            .map(|name| self.get_user_without_matches(name).map(|u| u.id))
+            .collect::<ServerResult<Vec<i64>>>()?;
+
+        self.insert_seeding(tid, mode, options.rng_seed.unwrap_or_else(rand::random), &seeds)
+    }
+
+    pub(crate) fn insert_seeding(
+        &self,
+        tid: i64,
+        mode: SeedingMode,
+        rng_seed: i64,
+        seeds: &[i64],
+    ) -> ServerResult<()>
+    {
+        self.conn.execute(
+            "insert into tournament_seedings (tournament, mode, rng_seed, seeds) values (?1, ?2, \
+             ?3, ?4)",
+            params![tid, mode as u8, rng_seed, serde_json::to_string(seeds).unwrap()],
+        )?;
+        Ok(())
+    }
+
+    // A recreated tournament is seeded the same way, but gets a new draw
+    pub(crate) fn copy_seeding(&self, from: i64, to: i64) -> ServerResult<()>
+    {
+        self.conn.execute(
+            "insert into tournament_seedings (tournament, mode, rng_seed, seeds)
+             select ?2, mode, ?3, seeds from tournament_seedings where tournament = ?1",
+            params![from, to, rand::random::<i64>()],
+        )?;
+        Ok(())
+    }
+
+    // Orders the players from the first seed down. Players the seeding does not
+    // tell apart are ordered by the random number generator of the tournament,
+    // so the same seed always gives the same order.
+    pub fn generate_matchups(
+        &self,
+        tournament: &Tournament,
+        mut people: Vec<i64>,
+    ) -> ServerResult<Vec<i64>>
+    {
+        let seeding = self
+            .sql_one::<TournamentSeeding, _>(
+                "select * from tournament_seedings where tournament = ?1",
+                _params![tournament.id],
+            )
+            .unwrap_or(TournamentSeeding {
+                id:         0,
+                tournament: tournament.id,
+                mode:       SeedingMode::Random as u8,
+                rng_seed:   rand::random(),
+                seeds:      String::from("[]"),
+            });
+
+        people.sort_unstable();
+        people.shuffle(&mut StdRng::seed_from_u64(seeding.rng_seed as u64));
+        match seeding.mode.into()
+        {
+            SeedingMode::Random => (),
+            SeedingMode::Rating =>
+            {
+                let elo = |pid: i64| -> ServerResult<f64> {
+                    Ok(self.get_user_without_matches_by("id", "=", &pid.to_string())?.elo)
+                };
+                let mut rated = people
+                    .iter()
+                    .map(|&pid| Ok((pid, elo(pid)?)))
+                    .collect::<ServerResult<Vec<_>>>()?;
+                rated.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+                people = rated.into_iter().map(|(pid, _)| pid).collect();
+            },
+            SeedingMode::Manual =>
+            {
+                let seeds: Vec<i64> = serde_json::from_str(&seeding.seeds).unwrap_or_default();
+                people
+                    .sort_by_key(|pid| seeds.iter().position(|s| s == pid).unwrap_or(seeds.len()));
+            },
+        }
+        Ok(people)
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        test_util::*,
+        tournament::{
+            test::reg_tournament_match_from_tournament_game, CreateTournament, TournamentGame,
+        },
+    };
+
+    fn create_seeded(s: &DataBase, token: &str, ttype: &str, seeding: serde_json::Value)
+    {
+        let mut info: CreateTournament = serde_json::from_value(json!({
+            "name": "Lunch",
+            "image": "",
+            "player_count": 8,
+            "ttype": ttype,
+            "seeding": seeding
+        }))
+        .unwrap();
+        info.organizer_token = token.to_string();
+        s.create_tournament(info).unwrap();
+    }
+
+    // The games of the first round, as the ids of the two players
+    fn first_round(s: &DataBase, tid: i64) -> Vec<(i64, i64)>
+    {
+        let mut games: Vec<TournamentGame> = s.get_all_tournament_games(tid).unwrap();
+        games.sort_by_key(|g| g.bucket);
+        games
+            .into_iter()
+            .filter(|g| (3..7).contains(&g.bucket))
+            .map(|g| (g.player1, g.player2))
+            .collect()
+    }
+
+    #[test]
+    fn test_best_seeds_meet_last()
+    {
+        assert_eq!(bracket_order(2), vec![1, 2]);
+        assert_eq!(bracket_order(8), vec![1, 8, 4, 5, 2, 7, 3, 6]);
+        assert!(bracket_order(16).chunks(2).all(|pair| pair[0] + pair[1] == 17));
+        // 1 and 2 are in different halves, and so are 3 and 4 in each half
+        assert_eq!(&bracket_order(16)[..8].iter().filter(|&&s| s <= 4).count(), &2);
+    }
+
+    #[test]
+    fn test_byes_go_to_best_seeds()
+    {
+        let seeds: Vec<i64> = (1..=6).collect();
+        // 1 vs bye, 4 vs 5, 2 vs bye and 3 vs 6
+        assert_eq!(bracket_slots(&seeds), vec![1, 4, 2, 3, 0, 5, 0, 6]);
+
+        let s = DataBase::new("tempU0.db");
+        std::fs::remove_file("tempU0.db").expect("Removing file tempU0");
+        let tournament = Tournament {
+            id:                    0,
+            state:                 0,
+            ttype:                 0,
+            player_count:          6,
+            name:                  String::new(),
+            prize:                 0,
+            organizer:             0,
+            rated:                 false,
+            registration_deadline: 0,
+            third_place_game:      false,
+        };
+        let games = s.generate_buckets(&tournament, &bracket_slots(&seeds));
+        let players = |g: &TournamentGame| (g.player1, g.player2);
+        // The byes put 1 and 2 straight into the semifinals, on different sides
+        assert_eq!(players(&games[1]), (1, 0));
+        assert_eq!(players(&games[2]), (2, 0));
+        assert_eq!(players(&games[4]), (4, 5));
+        assert_eq!(players(&games[6]), (3, 6));
+    }
+
+    #[test]
+    fn test_double_elimination_byes_keep_the_seeding()
+    {
+        let db_file = "tempU3.db";
+        let s = DataBase::new(db_file);
+        let tokens: Vec<String> =
+            (1..=6).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+        let mut info: CreateTournament = serde_json::from_value(json!({
+            "name": "Lunch",
+            "image": "",
+            "player_count": 6,
+            "ttype": "doubleElimination",
+            "seeding": {"mode": "manual", "players": ["Player1", "Player2", "Player3"]}
+        }))
+        .unwrap();
+        info.organizer_token = tokens[0].clone();
+        s.create_tournament(info).unwrap();
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+        let games = s.get_all_tournament_games(1).unwrap();
+        let semifinal = |bucket: i64| games.iter().find(|g| g.bucket == bucket).unwrap().player1;
+        let semifinals = (semifinal(1), semifinal(2));
+
+        // Player1 of every game wins, so every other player loses twice
+        let mut played = 0;
+        while let Ok(game) = s.sql_one::<TournamentGame, _>(
+            "select * from tournament_games g
+             where g.tournament = 1 and g.player1 > 0 and g.player2 > 0
+             and not exists (select * from tournament_matches m where m.game = g.id)",
+            None,
+        )
+        {
+            let register = reg_tournament_match_from_tournament_game(&s, &game, tokens[0].clone());
+            s.register_tournament_match(register).unwrap();
+            played += 1;
+        }
+        let info = serde_json::to_value(s.get_tournament_from_id(1).unwrap()).unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempU3");
+        // The two best seeds get the byes, on different sides of the bracket
+        assert_eq!(semifinals, (1, 2));
+        assert_eq!(played, 2 * 5);
+        assert_eq!(info["tournament"]["winner"], "Player1");
+    }
+
+    #[test]
+    fn test_seeding_by_rating_and_by_hand()
+    {
+        let db_file = "tempU1.db";
+        let s = DataBase::new(db_file);
+        let tokens: Vec<String> =
+            (1..=8).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+        // Player8 has the best rating and Player1 the worst
+        for pid in 1..=8
+        {
+            s.conn
+                .execute("update users set elo = ?1 where id = ?2", params![
+                    1500.0 + pid as f64,
+                    pid
+                ])
+                .unwrap();
+        }
+
+        create_seeded(&s, &tokens[0], "singleElimination", json!({"mode": "rating"}));
+        create_seeded(
+            &s,
+            &tokens[0],
+            "doubleElimination",
+            json!({
+                "mode": "manual",
+                "players": ["Player3", "Player1"]
+            }),
+        );
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+            s.join_tournament(token.clone(), 2).unwrap();
+        }
+        let rating = first_round(&s, 1);
+        let manual = first_round(&s, 2);
+
+        std::fs::remove_file(db_file).expect("Removing file tempU1");
+        assert_eq!(rating, vec![(8, 1), (5, 4), (7, 2), (6, 3)]);
+        assert_eq!(manual[0].0, 3);
+        assert_eq!(manual[2].0, 1);
+    }
+
+    #[test]
+    fn test_same_rng_seed_gives_same_bracket()
+    {
+        let db_file = "tempU2.db";
+        let s = DataBase::new(db_file);
+        let tokens: Vec<String> =
+            (1..=8).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+
+        for tid in 1..=3
+        {
+            let rng_seed = if tid == 3 { 7 } else { 42 };
+            create_seeded(
+                &s,
+                &tokens[0],
+                "singleElimination",
+                json!({
+                    "mode": "random",
+                    "rng_seed": rng_seed
+                }),
+            );
+            // Players joining in another order does not change the draw
+            let mut order = tokens.clone();
+            if tid == 2
+            {
+                order.reverse();
+            }
+            for token in &order
+            {
+                s.join_tournament(token.clone(), tid).unwrap();
+            }
+        }
+        let brackets: Vec<_> = (1..=3).map(|tid| first_round(&s, tid)).collect();
+
+        std::fs::remove_file(db_file).expect("Removing file tempU2");
+        assert_eq!(brackets[0], brackets[1]);
+        assert_ne!(brackets[0], brackets[2]);
+    }
+}
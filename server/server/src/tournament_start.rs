@@ -0,0 +1,304 @@
+use rusqlite::params;
+use serde_json::Value;
+use server_core::{constants::*, types::*};
+
+use crate::{
+    _params,
+    server::{DataBase, ParamsType},
+    tournament::{Tournament, TournamentList, TournamentState, TournamentType},
+};
+
+// A tournament starts by itself once it is full. The organizer can also close
+// the registration and start it with the players who have joined so far, which
+// happens on its own when the registration deadline of the tournament passes.
+// The bracket is drawn for the players who joined, with byes where it is not
+// full.
+impl DataBase
+{
+    pub(crate) fn set_registration_deadline(&self, tid: i64, deadline: i64) -> ServerResult<()>
+    {
+        self.conn
+            .execute("update tournaments set registration_deadline = ?1 where id = ?2", params![
+                deadline, tid
+            ])?;
+        Ok(())
+    }
+
+    pub fn start_tournament(&self, token: String, tid: i64) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let organizer_id = self.get_user_from_token(&token)?.id;
+            let tournament = self
+                .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![
+                    tid
+                ])?;
+            if organizer_id != tournament.organizer
+            {
+                return Err(ServerError::Tournament(TournamentError::NotOrganizer));
+            }
+            self.start_with_joined(tournament)?;
+            let target = format!("tournament:{}", tid);
+            self.audit(organizer_id, "start_tournament", &target, Value::Null, Value::Null)
+        })
+    }
+
+    // Called from the background thread. A tournament that can not start when
+    // its deadline passes loses the deadline, and is left to the organizer.
+    pub fn start_due_tournaments(&self) -> ServerResult<()>
+    {
+        let due = self.sql_many::<Tournament, _>(
+            "select * from tournaments
+             where state = ?1 and registration_deadline > 0 and registration_deadline <= ?2",
+            _params![TournamentState::Created as u8, self.epoch()],
+        )?;
+        for tournament in due
+        {
+            let tid = tournament.id;
+            // A tournament that fails to start is left as it was
+            if self.atomically(|| self.start_with_joined(tournament)).is_err()
+            {
+                self.set_registration_deadline(tid, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Draws the games of a tournament and closes its registration
+    pub(crate) fn begin_tournament(
+        &self,
+        tournament: Tournament,
+        players: Vec<i64>,
+    ) -> ServerResult<()>
+    {
+        let tid = tournament.id;
+        self.generate_tournament(tournament, players)?;
+        self.delete_tourament_list(tid)?;
+        self.update_tournament_state(tid, TournamentState::InProgress)
+    }
+
+    fn start_with_joined(&self, mut tournament: Tournament) -> ServerResult<()>
+    {
+        if tournament.state != TournamentState::Created as u8
+        {
+            return Err(ServerError::Tournament(TournamentError::WrongState));
+        }
+
+        let players: Vec<i64> = self
+            .sql_many::<TournamentList, _>(
+                "select * from tournament_lists where tournament = ?1",
+                _params![tournament.id],
+            )?
+            .into_iter()
+            .map(|tl| tl.player)
+            .collect();
+        let player_count = players.len() as i64;
+        if player_count < MIN_TOURNAMENT_PLAYERS
+        {
+            return Err(ServerError::Tournament(TournamentError::WrongTournamentCount));
+        }
+        if tournament.ttype == TournamentType::GroupStage as u8
+        {
+            self.check_group_stage_players(tournament.id, player_count)?;
+        }
+
+        // The bracket is drawn from the player count
+        self.conn
+            .execute("update tournaments set player_count = ?1 where id = ?2", params![
+                player_count,
+                tournament.id
+            ])?;
+        tournament.player_count = player_count;
+        self.begin_tournament(tournament, players)
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        test_util::*,
+        tournament::{
+            test::reg_tournament_match_from_tournament_game, CreateTournament, TournamentGame,
+        },
+    };
+
+    fn create(s: &DataBase, token: &str, info: serde_json::Value)
+    {
+        let mut info: CreateTournament = serde_json::from_value(info).unwrap();
+        info.organizer_token = token.to_string();
+        s.create_tournament(info).unwrap();
+    }
+
+    fn get_tournament(s: &DataBase, tid: i64) -> Tournament
+    {
+        s.sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![tid])
+            .unwrap()
+    }
+
+    // Player1 of every game wins until the tournament is done
+    fn play_out(s: &DataBase, token: &str, tid: i64)
+    {
+        while get_tournament(s, tid).state != TournamentState::Done as u8
+        {
+            let game = s
+                .sql_one::<TournamentGame, _>(
+                    "select * from tournament_games g
+                     where g.tournament = ?1 and g.player1 > 0 and g.player2 > 0
+                     and not exists (select * from tournament_matches m where m.game = g.id)",
+                    _params![tid],
+                )
+                .unwrap();
+            let register = reg_tournament_match_from_tournament_game(s, &game, token.to_string());
+            s.register_tournament_match(register).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_organizer_starts_tournament_before_it_is_full()
+    {
+        let db_file = "tempY1.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=7).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+
+        let tournament = |ttype: &str, advance: i64| {
+            json!({
+                "name": "Lunch",
+                "image": "",
+                "player_count": 8,
+                "ttype": ttype,
+                "group_stage": {"groups": 2, "advance": advance, "knockout": "singleElimination"}
+            })
+        };
+        create(&s, &tokens[0], tournament("doubleElimination", 2));
+        create(&s, &tokens[0], tournament("groupStage", 2));
+        create(&s, &tokens[0], tournament("groupStage", 3));
+        let join = |players: &[String]| {
+            for token in players
+            {
+                for tid in 1..=3
+                {
+                    s.join_tournament(token.clone(), tid).unwrap();
+                }
+            }
+        };
+        join(&tokens[..3]);
+        let too_few = s.start_tournament(tokens[0].clone(), 1);
+        join(&tokens[3..5]);
+        let not_organizer = s.start_tournament(tokens[1].clone(), 1);
+        s.start_tournament(tokens[0].clone(), 1).unwrap();
+        // Groups of 2 and 3 players can send 2 players on each, but not 3
+        s.start_tournament(tokens[0].clone(), 2).unwrap();
+        let small_groups = s.start_tournament(tokens[0].clone(), 3);
+        let started = get_tournament(&s, 1);
+        let group_stage = get_tournament(&s, 2);
+        let again = s.start_tournament(tokens[0].clone(), 1);
+        let list = get_table_size(&s, "tournament_lists");
+        play_out(&s, &tokens[0], 1);
+        let done = get_tournament(&s, 1).state;
+
+        std::fs::remove_file(db_file).expect("Removing file tempY1");
+        assert!(matches!(
+            too_few,
+            Err(ServerError::Tournament(TournamentError::WrongTournamentCount))
+        ));
+        assert!(matches!(
+            not_organizer,
+            Err(ServerError::Tournament(TournamentError::NotOrganizer))
+        ));
+        assert_eq!(started.state, TournamentState::InProgress as u8);
+        assert_eq!(started.player_count, 5);
+        assert_eq!(group_stage.state, TournamentState::InProgress as u8);
+        assert!(matches!(small_groups, Err(ServerError::InvalidRequest(_))));
+        assert!(matches!(again, Err(ServerError::Tournament(TournamentError::WrongState))));
+        // Only the tournament that could not start is still open
+        assert_eq!(list, 5);
+        assert_eq!(done, TournamentState::Done as u8);
+    }
+
+    #[test]
+    fn test_registration_deadline_starts_tournament()
+    {
+        let db_file = "tempY2.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=5).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+
+        let tournament = |deadline: i64| {
+            json!({
+                "name": "Lunch",
+                "image": "",
+                "player_count": 8,
+                "ttype": "roundRobin",
+                "registration_deadline": deadline
+            })
+        };
+        let future = s.epoch() + 60 * 60 * 1000;
+        create(&s, &tokens[0], tournament(1));
+        create(&s, &tokens[0], tournament(future));
+        create(&s, &tokens[0], tournament(1));
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+            s.join_tournament(token.clone(), 2).unwrap();
+        }
+        // Too few players to start
+        for token in &tokens[..2]
+        {
+            s.join_tournament(token.clone(), 3).unwrap();
+        }
+        s.start_due_tournaments().unwrap();
+        let tournaments: Vec<Tournament> = (1..=3).map(|tid| get_tournament(&s, tid)).collect();
+        let games = s.get_all_tournament_games(1).unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempY2");
+        assert_eq!(tournaments[0].state, TournamentState::InProgress as u8);
+        assert_eq!(tournaments[0].player_count, 5);
+        // Every player meets every other player once
+        assert_eq!(games.iter().filter(|g| g.player1 > 0 && g.player2 > 0).count(), 10);
+        assert_eq!(tournaments[1].state, TournamentState::Created as u8);
+        assert_eq!(tournaments[1].registration_deadline, future);
+        assert_eq!(tournaments[2].state, TournamentState::Created as u8);
+        assert_eq!(tournaments[2].registration_deadline, 0);
+    }
+
+    #[test]
+    fn test_failed_start_leaves_tournament_open()
+    {
+        let db_file = "tempY3.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=4).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+
+        let tournament = json!({
+            "name": "Lunch",
+            "image": "",
+            "player_count": 4,
+            "ttype": "singleElimination"
+        });
+        create(&s, &tokens[0], tournament);
+        for token in &tokens[..3]
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+        // The state is set after the games are made and the list is cleared
+        fail_on(&s, "update", "tournaments");
+        let last_join = s.join_tournament(tokens[3].clone(), 1);
+        let tournament = get_tournament(&s, 1);
+        let games = get_table_size(&s, "tournament_games");
+        let list = get_table_size(&s, "tournament_lists");
+
+        std::fs::remove_file(db_file).expect("Removing file tempY3");
+        assert!(last_join.is_err());
+        assert_eq!(tournament.state, TournamentState::Created as u8);
+        assert_eq!(games, 0);
+        assert_eq!(list, 3);
+    }
+}
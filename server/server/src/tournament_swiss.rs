@@ -0,0 +1,517 @@
+use rusqlite::named_params;
+use serde_json::{json, Value};
+use server_core::types::*;
+
+use crate::{
+    _params,
+    server::{DataBase, ParamsType},
+    tournament::{
+        RegisterTournamentMatch, Tournament, TournamentGame, TournamentState, TournamentType,
+    },
+    tournament_placements::Podium,
+    tournament_round_robin::{point_difference, wins, GameResult, Standing},
+};
+
+// As many rounds as a knockout tournament of the same size, which is enough
+// for a single player to win every game
+pub fn swiss_rounds(player_count: i64) -> i64
+{
+    ((player_count as f64).log2().ceil() as i64).max(1)
+}
+
+fn opponents(pid: i64, results: &[GameResult]) -> Vec<i64>
+{
+    results
+        .iter()
+        .filter_map(|r| {
+            if r.winner == pid
+            {
+                Some(r.loser)
+            }
+            else if r.loser == pid
+            {
+                Some(r.winner)
+            }
+            else
+            {
+                None
+            }
+        })
+        .filter(|&opponent| opponent != 0)
+        .collect()
+}
+
+// The sum of the wins of everyone the player has met, byes do not count
+pub(crate) fn buchholz(pid: i64, results: &[GameResult]) -> i64
+{
+    opponents(pid, results)
+        .into_iter()
+        .map(|opponent| wins(opponent, results))
+        .sum()
+}
+
+// Orders the players by wins, then by buchholz, and then by point difference
+fn rank_swiss(players: &[i64], results: &[GameResult]) -> Vec<i64>
+{
+    let mut ranked = players.to_vec();
+    ranked.sort_by(|&a, &b| {
+        wins(b, results)
+            .cmp(&wins(a, results))
+            .then_with(|| buchholz(b, results).cmp(&buchholz(a, results)))
+            .then_with(|| point_difference(b, results).cmp(&point_difference(a, results)))
+    });
+    ranked
+}
+
+// The most steps one search for the pairings of a score group can take
+const MAX_PAIRING_STEPS: usize = 10_000;
+
+// The pairs of a score group, and the players who float down from it
+type GroupPairing = (Vec<(i64, i64)>, Vec<i64>);
+
+// Pairs the first player with the best ranked player they have not met, and
+// goes back to try the next opponent when the rest can not be paired. Exactly
+// `floats` players are left unpaired, the lowest ranked ones where possible.
+fn pair_without_rematches(
+    players: &[i64],
+    floats: usize,
+    results: &[GameResult],
+    steps: &mut usize,
+) -> Option<GroupPairing>
+{
+    let (&first, rest) = match players.split_first()
+    {
+        Some(split) => split,
+        None if floats == 0 => return Some((Vec::new(), Vec::new())),
+        None => return None,
+    };
+    if *steps == 0 || floats > players.len()
+    {
+        return None;
+    }
+    *steps -= 1;
+
+    let met = opponents(first, results);
+    for (i, &opponent) in rest.iter().enumerate().filter(|(_, p)| !met.contains(p))
+    {
+        let mut others = rest.to_vec();
+        others.remove(i);
+        if let Some((mut pairs, floaters)) = pair_without_rematches(&others, floats, results, steps)
+        {
+            pairs.insert(0, (first, opponent));
+            return Some((pairs, floaters));
+        }
+    }
+
+    let (pairs, mut floaters) =
+        pair_without_rematches(rest, floats.checked_sub(1)?, results, steps)?;
+    floaters.insert(0, first);
+    Some((pairs, floaters))
+}
+
+// Pairs the score groups from the top, the players a group can not pair float
+// down to the next group. A group floats more players down when the groups
+// below can not be paired otherwise.
+fn pair_score_groups(
+    groups: &[Vec<i64>],
+    floaters: Vec<i64>,
+    results: &[GameResult],
+    steps: &mut usize,
+) -> Option<Vec<(i64, i64)>>
+{
+    let (group, lower) = match groups.split_first()
+    {
+        Some(split) => split,
+        None if floaters.is_empty() => return Some(Vec::new()),
+        None => return None,
+    };
+    let mut bracket = floaters;
+    bracket.extend(group);
+
+    let most = if lower.is_empty() { 0 } else { bracket.len() };
+    for floats in (bracket.len() % 2..=most).step_by(2)
+    {
+        if let Some((mut pairs, down)) = pair_without_rematches(&bracket, floats, results, steps)
+        {
+            if let Some(rest) = pair_score_groups(lower, down, results, steps)
+            {
+                pairs.extend(rest);
+                return Some(pairs);
+            }
+        }
+    }
+    None
+}
+
+// Pairs each player with the best ranked player left they have not met, or
+// with the next player when they have met everyone left
+fn pair_in_order(players: &[i64], results: &[GameResult]) -> Vec<(i64, i64)>
+{
+    let mut left = players.to_vec();
+    let mut pairs = Vec::new();
+    while left.len() >= 2
+    {
+        let first = left.remove(0);
+        let met = opponents(first, results);
+        let i = left.iter().position(|p| !met.contains(p)).unwrap_or(0);
+        pairs.push((first, left.remove(i)));
+    }
+    pairs
+}
+
+// Pairs players with the same score where possible, `ranked` is ordered from
+// best to worst. The search for pairings without rematches is limited, after
+// that the players are paired in order. With an odd number of players, the
+// lowest ranked player that has not had a bye yet sits out the round.
+pub(crate) fn swiss_pairings(
+    ranked: &[i64],
+    results: &[GameResult],
+) -> (Vec<(i64, i64)>, Option<i64>)
+{
+    let mut players = ranked.to_vec();
+    let bye = if players.len() % 2 == 1
+    {
+        let had_bye = |pid: i64| results.iter().any(|r| r.winner == pid && r.loser == 0);
+        let i = players.iter().rposition(|&p| !had_bye(p)).unwrap_or(players.len() - 1);
+        Some(players.remove(i))
+    }
+    else
+    {
+        None
+    };
+
+    let mut groups: Vec<Vec<i64>> = Vec::new();
+    for &pid in &players
+    {
+        match groups.last_mut()
+        {
+            Some(group) if wins(group[0], results) == wins(pid, results) => group.push(pid),
+            _ => groups.push(vec![pid]),
+        }
+    }
+
+    let mut steps = MAX_PAIRING_STEPS;
+    let mut pairs = pair_score_groups(&groups, Vec::new(), results, &mut steps)
+        .unwrap_or_else(|| pair_in_order(&players, results));
+    pairs.sort_by_key(|&(pid, _)| players.iter().position(|&p| p == pid));
+    (pairs, bye)
+}
+
+impl DataBase
+{
+    // Rounds are created one at a time, the bucket of each game is its round. A
+    // bye is a game against player 0 that is won as soon as it is created.
+    pub(crate) fn generate_swiss_round(
+        &self,
+        tournament: &Tournament,
+        round: i64,
+        ranked: &[i64],
+    ) -> ServerResult<()>
+    {
+        let results = self.get_tournament_results(tournament.id)?;
+        let (pairs, bye) = swiss_pairings(ranked, &results);
+        for (p1, p2) in pairs
+        {
+            self._create_tournament_game(p1, p2, round, tournament.id)?;
+        }
+
+        if let Some(pid) = bye
+        {
+            self._create_tournament_game(pid, 0, round, tournament.id)?;
+            self.create_match_from_game(pid, 0, self.conn.last_insert_rowid(), &[])?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn handle_swiss_match(
+        &self,
+        game: &TournamentGame,
+        register_game: &RegisterTournamentMatch,
+        tournament: &Tournament,
+    ) -> ServerResult<()>
+    {
+        if game.bucket != self.get_swiss_round(tournament.id)?
+        {
+            return Err(ServerError::Tournament(TournamentError::CannotRerun));
+        }
+        self.create_match_between_players(game, register_game)
+    }
+
+    pub(crate) fn get_swiss_round(&self, tid: i64) -> ServerResult<i64>
+    {
+        Ok(self.conn.query_row_named(
+            "select coalesce(max(bucket), 0) from tournament_games where tournament = :tid",
+            named_params! {":tid": tid},
+            |row| row.get(0),
+        )?)
+    }
+
+    // Ends the current round of a swiss tournament, and either pairs the
+    // players for the next round or finishes the tournament
+    pub fn close_tournament_round(&self, token: String, tid: i64) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let organizer_id = self.get_user_from_token(&token)?.id;
+            let tournament = self
+                .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![
+                    tid
+                ])?;
+            if organizer_id != tournament.organizer
+            {
+                return Err(ServerError::Tournament(TournamentError::NotOrganizer));
+            }
+            if tournament.ttype != TournamentType::Swiss as u8
+                || tournament.state != TournamentState::InProgress as u8
+            {
+                return Err(ServerError::Tournament(TournamentError::WrongState));
+            }
+
+            let round = self.get_swiss_round(tid)?;
+            let unplayed: i64 = self.conn.query_row_named(
+                "select count(*) from tournament_games g where g.tournament = :tid and g.bucket = \
+                 :round
+                 and not exists (select * from tournament_matches m where m.game = g.id)",
+                named_params! {":tid": tid, ":round": round},
+                |row| row.get(0),
+            )?;
+            if unplayed > 0
+            {
+                return Err(ServerError::Tournament(TournamentError::RoundNotFinished));
+            }
+
+            let results = self.get_tournament_results(tid)?;
+            let ranked =
+                self.withdrawn_last(tid, rank_swiss(&self.get_player_ids(tid)?, &results))?;
+            if round + 1 >= swiss_rounds(tournament.player_count)
+            {
+                self.finish_tournament(&tournament, &Podium::from_ranking(&ranked))?;
+            }
+            else
+            {
+                // Players who have withdrawn are not paired again
+                let withdrawn = self.get_withdrawn(tid)?;
+                let playing: Vec<i64> =
+                    ranked.into_iter().filter(|pid| !withdrawn.contains(pid)).collect();
+                self.generate_swiss_round(&tournament, round + 1, &playing)?;
+            }
+            let target = format!("tournament:{}", tid);
+            let after = json!({ "round": round });
+            self.audit(organizer_id, "close_tournament_round", &target, Value::Null, after)
+        })
+    }
+
+    pub fn get_swiss_standings(&self, tid: i64) -> ServerResult<Vec<Standing>>
+    {
+        let results = self.get_tournament_results(tid)?;
+        let players = self.get_player_ids(tid)?;
+
+        self.create_standings(rank_swiss(&players, &results), &results, |pid| {
+            Some(buchholz(pid, &results))
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::{test_util::*, tournament::CreateTournament};
+
+    fn result(winner: i64, loser: i64) -> GameResult
+    {
+        GameResult {
+            winner,
+            loser,
+            points: 0,
+        }
+    }
+
+    fn create_swiss(s: &DataBase, token: &str, player_count: i64)
+    {
+        let mut info: CreateTournament = serde_json::from_value(json!({
+            "name": "Lunch",
+            "image": "",
+            "player_count": player_count,
+            "ttype": "swiss"
+        }))
+        .unwrap();
+        info.organizer_token = token.to_string();
+        s.create_tournament(info).unwrap();
+    }
+
+    // Lets player1 win every game of the current round that has two players
+    fn play_round(s: &DataBase, token: &str, tid: i64)
+    {
+        let round = s.get_swiss_round(tid).unwrap();
+        let games = s.get_all_tournament_games(tid).unwrap();
+        for game in games.iter().filter(|g| g.bucket == round && g.player2 != 0)
+        {
+            let name =
+                |id: i64| s.get_user_without_matches_by("id", "=", &id.to_string()).unwrap().name;
+            let mut register: RegisterTournamentMatch = serde_json::from_value(json!({
+                "winner": name(game.player1),
+                "loser": name(game.player2),
+                "tournament_game": game.id
+            }))
+            .unwrap();
+            register.organizer_token = token.to_string();
+            s.register_tournament_match(register).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_pairs_by_score_without_rematches()
+    {
+        // 1 has met both 2 and 3, so only 4 is left for them
+        let results = vec![result(1, 2), result(3, 4), result(1, 3), result(2, 4)];
+        let (pairs, bye) = swiss_pairings(&[1, 3, 2, 4], &results);
+        assert_eq!(pairs, vec![(1, 4), (3, 2)]);
+        assert_eq!(bye, None);
+
+        // Nobody has met, so the ranking decides
+        let (pairs, _) = swiss_pairings(&[4, 3, 2, 1], &[]);
+        assert_eq!(pairs, vec![(4, 3), (2, 1)]);
+    }
+
+    #[test]
+    fn test_score_groups_float_down_to_avoid_rematches()
+    {
+        // 5 is left over in the winners group and floats down
+        let results = vec![result(1, 2), result(3, 4), result(5, 6)];
+        let (pairs, _) = swiss_pairings(&[1, 3, 5, 2, 4, 6], &results);
+        assert_eq!(pairs, vec![(1, 3), (5, 2), (4, 6)]);
+
+        // 2 and 4 have met, so both winners float down to play them
+        let results = vec![result(5, 1), result(2, 4), result(3, 0), result(3, 1)];
+        let (pairs, _) = swiss_pairings(&[5, 3, 2, 4], &results);
+        assert_eq!(pairs, vec![(5, 2), (3, 4)]);
+    }
+
+    #[test]
+    fn test_pairing_search_is_limited()
+    {
+        // The first 13 players have all met each other, so some of them have to
+        // meet again, which takes the search through every pairing
+        let mut results = Vec::new();
+        for winner in 1..=13
+        {
+            for loser in winner + 1..=13
+            {
+                results.push(result(winner, loser));
+            }
+        }
+        let ranked: Vec<i64> = (1..=24).collect();
+        let (pairs, bye) = swiss_pairings(&ranked, &results);
+
+        let mut paired: Vec<i64> = pairs.iter().flat_map(|&(a, b)| vec![a, b]).collect();
+        paired.sort_unstable();
+        assert_eq!(paired, ranked);
+        assert_eq!(bye, None);
+    }
+
+    #[test]
+    fn test_bye_goes_to_lowest_player_without_one()
+    {
+        let (pairs, bye) = swiss_pairings(&[1, 2, 3, 4, 5], &[]);
+        assert_eq!(bye, Some(5));
+        assert_eq!(pairs.len(), 2);
+
+        // 5 already had a bye
+        let (_, bye) = swiss_pairings(&[1, 2, 3, 4, 5], &[result(5, 0)]);
+        assert_eq!(bye, Some(4));
+    }
+
+    #[test]
+    fn test_buchholz_counts_opponents_wins()
+    {
+        let results = vec![result(1, 2), result(3, 4), result(1, 3), result(2, 4), result(5, 0)];
+        assert_eq!(buchholz(1, &results), 1 + 1);
+        assert_eq!(buchholz(4, &results), 1 + 1);
+        assert_eq!(buchholz(5, &results), 0);
+        assert_eq!(rank_swiss(&[4, 3, 2, 1], &results), vec![1, 3, 2, 4]);
+    }
+
+    #[test]
+    fn test_can_run_swiss_tournament()
+    {
+        let db_file = "tempW1.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=5).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+        let organizer = &tokens[0];
+
+        create_swiss(&s, organizer, 5);
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+
+        let early = s.close_tournament_round(organizer.clone(), 1);
+        let mut rounds = Vec::new();
+        for _ in 0..swiss_rounds(5)
+        {
+            play_round(&s, organizer, 1);
+            rounds.push(s.get_all_tournament_games(1).unwrap());
+            s.close_tournament_round(organizer.clone(), 1).unwrap();
+        }
+        let games = s.get_all_tournament_games(1).unwrap();
+        let standings = s.get_swiss_standings(1).unwrap();
+        let tournament = s.sql_one::<Tournament, _>("select * from tournaments", None).unwrap();
+        let closed = s.close_tournament_round(organizer.clone(), 1);
+        let winner = s.get_user(&standings[0].name).unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempW1");
+        assert_eq!(early, Err(ServerError::Tournament(TournamentError::RoundNotFinished)));
+        assert_eq!(rounds.iter().map(|r| r.len()).collect::<Vec<_>>(), vec![3, 6, 9]);
+        let mut pairs: Vec<(i64, i64)> = games
+            .iter()
+            .filter(|g| g.player2 != 0)
+            .map(|g| (g.player1.min(g.player2), g.player1.max(g.player2)))
+            .collect();
+        pairs.sort_unstable();
+        pairs.dedup();
+        assert_eq!(pairs.len(), 6);
+        assert_eq!(games.iter().filter(|g| g.player2 == 0).count(), 3);
+        assert!(standings.iter().all(|s| s.played == 3 && s.buchholz.is_some()));
+        assert_eq!(tournament.state, TournamentState::Done as u8);
+        assert_eq!(closed, Err(ServerError::Tournament(TournamentError::WrongState)));
+        assert_eq!(winner.badges.len(), 1);
+    }
+
+    #[test]
+    fn test_cannot_change_closed_round()
+    {
+        let db_file = "tempW2.db";
+        let s = DataBase::new(db_file);
+        let tokens: Vec<String> =
+            (1..=4).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+        let organizer = &tokens[0];
+
+        create_swiss(&s, organizer, 4);
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+        play_round(&s, organizer, 1);
+        s.close_tournament_round(organizer.clone(), 1).unwrap();
+
+        let first = s.get_all_tournament_games(1).unwrap().remove(0);
+        let name =
+            |id: i64| s.get_user_without_matches_by("id", "=", &id.to_string()).unwrap().name;
+        let mut register: RegisterTournamentMatch = serde_json::from_value(json!({
+            "winner": name(first.player2),
+            "loser": name(first.player1),
+            "tournament_game": first.id
+        }))
+        .unwrap();
+        register.organizer_token = organizer.clone();
+        let res = s.register_tournament_match(register);
+
+        std::fs::remove_file(db_file).expect("Removing file tempW2");
+        assert_eq!(res, Err(ServerError::Tournament(TournamentError::CannotRerun)));
+    }
+}
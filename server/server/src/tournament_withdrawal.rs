@@ -0,0 +1,338 @@
+use rusqlite::{named_params, params};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use server_core::types::*;
+
+use crate::{
+    _params,
+    server::{DataBase, ParamsType},
+    tournament::{
+        RegisterTournamentMatch, Tournament, TournamentGame, TournamentState, TournamentType,
+    },
+    validate::{Validate, Validator},
+    SQL_TUPLE_NAMED,
+};
+
+#[derive(Deserialize)]
+pub struct WithdrawPlayer
+{
+    pub tid:    i64,
+    pub player: String,
+}
+
+impl Validate for WithdrawPlayer
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new()
+            .check(self.tid > 0, "tid", "Must be positive")
+            .username("player", &self.player)
+            .finish()
+    }
+}
+
+// A player who drops out of a running tournament loses every game they have
+// left as a walkover. The games are registered as soon as the opponent is
+// known, and are stored as forfeits that do not count towards any rating.
+impl DataBase
+{
+    pub fn withdraw_player(&self, token: String, tid: i64, name: String) -> ServerResult<()>
+    {
+        self.atomically(|| {
+            let organizer_id = self.get_user_from_token(&token)?.id;
+            // Players withdraw from a group stage, not from its bracket
+            let tournament = match self.get_bracket_owner(tid)
+            {
+                Some(owner) => owner,
+                None => self.sql_one::<Tournament, _>(
+                    "select * from tournaments where id = ?1",
+                    _params![tid],
+                )?,
+            };
+            if organizer_id != tournament.organizer
+            {
+                return Err(ServerError::Tournament(TournamentError::NotOrganizer));
+            }
+            if tournament.state != TournamentState::InProgress as u8
+            {
+                return Err(ServerError::Tournament(TournamentError::WrongState));
+            }
+
+            let pid = self.get_user_without_matches(&name)?.id;
+            let players = self.get_player_ids(tournament.id)?;
+            let withdrawn = self.get_withdrawn(tournament.id)?;
+            if !players.contains(&pid)
+            {
+                return Err(ServerError::InvalidRequest(vec![FieldError::new(
+                    "player",
+                    "Is not playing in the tournament",
+                )]));
+            }
+            if players.iter().all(|p| *p == pid || withdrawn.contains(p))
+            {
+                return Err(ServerError::InvalidRequest(vec![FieldError::new(
+                    "player",
+                    "Is the last player left in the tournament",
+                )]));
+            }
+
+            self.conn.execute(
+                "insert or ignore into tournament_withdrawals (tournament, player, epoch) values \
+                 (?1, ?2, ?3)",
+                params![tournament.id, pid, self.epoch()],
+            )?;
+            self.play_walkovers(tournament.id)?;
+            let target = format!("tournament:{}", tournament.id);
+            self.audit(
+                organizer_id,
+                "withdraw_player",
+                &target,
+                Value::Null,
+                json!({ "player": name }),
+            )
+        })
+    }
+
+    // The knockout bracket of a group stage shares the withdrawals of its group
+    // stage
+    pub(crate) fn get_withdrawn(&self, tid: i64) -> ServerResult<Vec<i64>>
+    {
+        let players = SQL_TUPLE_NAMED!(
+            self,
+            "select player from tournament_withdrawals where tournament = :tid
+             or tournament = (select tournament from tournament_stages where bracket = :tid)",
+            named_params! {":tid": tid},
+            i64
+        )?;
+        Ok(players.into_iter().map(|(pid,)| pid).collect())
+    }
+
+    pub(crate) fn get_withdrawn_names(&self, tid: i64) -> ServerResult<Vec<String>>
+    {
+        self.get_withdrawn(tid)?
+            .into_iter()
+            .map(|pid| Ok(self.get_user_without_matches_by("id", "=", &pid.to_string())?.name))
+            .collect()
+    }
+
+    // Moves the players who have withdrawn to the bottom of a ranking, so they
+    // can not win or advance
+    pub(crate) fn withdrawn_last(&self, tid: i64, mut ranked: Vec<i64>) -> ServerResult<Vec<i64>>
+    {
+        let withdrawn = self.get_withdrawn(tid)?;
+        ranked.sort_by_key(|pid| withdrawn.contains(pid));
+        Ok(ranked)
+    }
+
+    pub(crate) fn is_forfeit(&self, game_id: i64, loser_id: i64) -> ServerResult<bool>
+    {
+        let game = self.sql_one::<TournamentGame, _>(
+            "select * from tournament_games where id = ?1",
+            _params![game_id],
+        )?;
+        Ok(self.get_withdrawn(game.tournament)?.contains(&loser_id))
+    }
+
+    // Registers the next game against a player who has withdrawn. Registering a
+    // game plays the walkovers after it, so this goes on until there are none
+    // left.
+    pub(crate) fn play_walkovers(&self, tid: i64) -> ServerResult<()>
+    {
+        let tournament = self
+            .sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![tid])?;
+        if tournament.state != TournamentState::InProgress as u8
+        {
+            return Ok(());
+        }
+
+        let withdrawn = self.get_withdrawn(tid)?;
+        let walkover = self
+            .sql_many::<TournamentGame, _>(
+                "select * from tournament_games g
+                 where g.tournament = ?1 and g.player1 > 0 and g.player2 > 0
+                 and not exists (select * from tournament_matches m where m.game = g.id)
+                 order by g.id",
+                _params![tid],
+            )?
+            .into_iter()
+            .find(|g| withdrawn.contains(&g.player1) || withdrawn.contains(&g.player2));
+
+        let game = match walkover
+        {
+            Some(game) => game,
+            None if tournament.ttype == TournamentType::GroupStage as u8 =>
+            {
+                let bracket = self.get_group_stage(tid)?.bracket;
+                return if bracket != 0 { self.play_walkovers(bracket) } else { Ok(()) };
+            },
+            None => return Ok(()),
+        };
+
+        // When both players have withdrawn one of them still has to go on
+        let (winner, loser) = if withdrawn.contains(&game.player1)
+            && !withdrawn.contains(&game.player2)
+        {
+            (game.player2, game.player1)
+        }
+        else
+        {
+            (game.player1, game.player2)
+        };
+        let name = |pid: i64| -> ServerResult<String> {
+            Ok(self.get_user_without_matches_by("id", "=", &pid.to_string())?.name)
+        };
+        let register = RegisterTournamentMatch {
+            organizer_token: String::new(),
+            winner:          name(winner)?,
+            loser:           name(loser)?,
+            tournament_game: game.id,
+            scores:          Vec::new(),
+        };
+        self.delete_tournament_notifications(game.id)?;
+        self._register_tournament_match(&game, &tournament, &register)
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        test_util::*,
+        tournament::{
+            test::reg_tournament_match_from_tournament_game, CreateTournament, TournamentMatch,
+        },
+    };
+
+    fn create(s: &DataBase, token: &str, ttype: &str, player_count: i64, rated: bool)
+    {
+        let mut info: CreateTournament = serde_json::from_value(json!({
+            "name": "Lunch",
+            "image": "",
+            "player_count": player_count,
+            "ttype": ttype,
+            "rated": rated
+        }))
+        .unwrap();
+        info.organizer_token = token.to_string();
+        s.create_tournament(info).unwrap();
+    }
+
+    // Player1 of every game wins until the tournament is done
+    fn play_out(s: &DataBase, token: &str, tid: i64)
+    {
+        let done = || {
+            s.sql_one::<Tournament, _>("select * from tournaments where id = ?1", _params![tid])
+                .unwrap()
+                .state
+                == TournamentState::Done as u8
+        };
+        while !done()
+        {
+            let game = s
+                .sql_one::<TournamentGame, _>(
+                    "select * from tournament_games g
+                     where g.tournament = ?1 and g.player1 > 0 and g.player2 > 0
+                     and not exists (select * from tournament_matches m where m.game = g.id)",
+                    _params![tid],
+                )
+                .unwrap();
+            let register = reg_tournament_match_from_tournament_game(s, &game, token.to_string());
+            s.register_tournament_match(register).unwrap();
+        }
+    }
+
+    fn forfeits(s: &DataBase) -> Vec<TournamentMatch>
+    {
+        s.sql_many::<TournamentMatch, _>("select * from tournament_matches where forfeit = 1", None)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_withdrawn_player_gives_walkovers_in_both_brackets()
+    {
+        let db_file = "tempX1.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=8).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+
+        create(&s, &tokens[0], "doubleElimination", 8, false);
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+        s.withdraw_player(tokens[0].clone(), 1, "Player3".to_string()).unwrap();
+        let first = forfeits(&s);
+        play_out(&s, &tokens[0], 1);
+        let all = forfeits(&s);
+        let winner: i64 = s
+            .conn
+            .query_row("select player from tournament_winners", rusqlite::NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+        let info = s.get_tournament_from_id(1).unwrap();
+        let info = serde_json::to_value(&info).unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempX1");
+        // Player3 loses their first game straight away, and the one in the losers
+        // bracket once the opponent is known
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].loser, 3);
+        assert_eq!(all.len(), 2);
+        assert!(all.iter().all(|m| m.loser == 3));
+        assert_ne!(winner, 3);
+        assert_eq!(info["withdrawn"], json!(["Player3"]));
+    }
+
+    #[test]
+    fn test_withdrawn_player_forfeits_round_robin_games()
+    {
+        let db_file = "tempX2.db";
+        let s = DataBase::new(db_file);
+        create_tournament_image(&s);
+        let tokens: Vec<String> =
+            (1..=4).map(|n| create_user(&s, &format!("Player{}", n))).collect();
+
+        create(&s, &tokens[0], "roundRobin", 4, true);
+        create(&s, &tokens[0], "roundRobin", 4, false);
+        for token in &tokens
+        {
+            s.join_tournament(token.clone(), 1).unwrap();
+        }
+        let not_organizer = s.withdraw_player(tokens[1].clone(), 1, "Player2".to_string());
+        let not_started = s.withdraw_player(tokens[0].clone(), 2, "Player2".to_string());
+        create_user(&s, "Outsider");
+        let not_playing = s.withdraw_player(tokens[0].clone(), 1, "Outsider".to_string());
+
+        // Player1 drops out before playing a game
+        s.withdraw_player(tokens[0].clone(), 1, "Player1".to_string()).unwrap();
+        let walkovers = forfeits(&s);
+        let history = s.get_history().unwrap();
+        play_out(&s, &tokens[0], 1);
+        let rated = s.get_history().unwrap();
+        let winner: i64 = s
+            .conn
+            .query_row("select player from tournament_winners", rusqlite::NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap();
+
+        std::fs::remove_file(db_file).expect("Removing file tempX2");
+        assert!(matches!(
+            not_organizer,
+            Err(ServerError::Tournament(TournamentError::NotOrganizer))
+        ));
+        assert!(matches!(not_started, Err(ServerError::Tournament(TournamentError::WrongState))));
+        assert!(matches!(not_playing, Err(ServerError::InvalidRequest(_))));
+        assert_eq!(walkovers.len(), 3);
+        assert!(walkovers.iter().all(|m| m.loser == 1 && m.rated_match == 0));
+        assert!(history.is_empty());
+        assert_eq!(rated.len(), 3);
+        assert_ne!(winner, 1);
+    }
+}
@@ -1,14 +1,21 @@
 use serde_derive::{Deserialize, Serialize};
-use server_core::types::FromSql;
+use server_core::types::{FromSql, ServerResult};
 use server_macro::Sql;
 
-use super::{badge::*, r#match::Match};
+use super::{
+    badge::*,
+    r#match::Match,
+    validate::{Validate, Validator},
+};
 
 #[derive(Debug, Serialize, Sql)]
 pub struct User
 {
     pub id:            i64,
     pub elo:           f64,
+    pub deviation:     f64,
+    #[serde(skip)]
+    pub volatility:    f64,
     pub name:          String,
     pub user_role:     u8,
     pub match_history: Vec<Match>,
@@ -42,7 +49,6 @@ pub struct EditUsersInfo
 {
     pub users:  Vec<String>,
     pub action: String,
-    pub token:  String,
 }
 
 #[derive(Deserialize)]
@@ -52,8 +58,84 @@ pub struct StatsUsers
     pub user2: String,
 }
 
+#[derive(Deserialize)]
+pub struct MultipleUsersInfo
+{
+    pub users:  Vec<String>,
+    pub season: Option<u32>,
+}
+
 #[derive(Deserialize)]
 pub struct AdminToken
 {
     pub token: String,
 }
+
+impl Validate for LoginInfo
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new()
+            .username("username", &self.username)
+            .not_empty("password", &self.password)
+            .finish()
+    }
+}
+
+impl Validate for ChangePasswordInfo
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new()
+            .username("username", &self.username)
+            .not_empty("password", &self.password)
+            .not_empty("new_password", &self.new_password)
+            .finish()
+    }
+}
+
+impl Validate for RequestResetPassword
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new().username("name", &self.name).finish()
+    }
+}
+
+impl Validate for EditUsersInfo
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        let mut validator = Validator::new();
+        validator.check(!self.users.is_empty(), "users", "Can not be empty");
+        for name in &self.users
+        {
+            validator.username("users", name);
+        }
+        validator.not_empty("action", &self.action).finish()
+    }
+}
+
+impl Validate for StatsUsers
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        Validator::new()
+            .username("user1", &self.user1)
+            .username("user2", &self.user2)
+            .finish()
+    }
+}
+
+impl Validate for MultipleUsersInfo
+{
+    fn validate(&self) -> ServerResult<()>
+    {
+        let mut validator = Validator::new();
+        for name in &self.users
+        {
+            validator.username("users", name);
+        }
+        validator.finish()
+    }
+}
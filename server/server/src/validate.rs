@@ -0,0 +1,104 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use server_core::types::{FieldError, ServerError, ServerResult};
+
+lazy_static! {
+    static ref USERNAME: Regex = Regex::new(r"^[a-zåA-ZæøåÆØÅ0-9_-]+$").unwrap();
+}
+
+// Checks the parts of a request that can be checked without the database, so
+// that every problem with the request is reported at once
+pub trait Validate
+{
+    fn validate(&self) -> ServerResult<()>;
+}
+
+pub fn valid_username(name: &str) -> bool
+{
+    USERNAME.is_match(name)
+}
+
+// Collects the problems with each field of a request
+#[derive(Default)]
+pub struct Validator
+{
+    errors: Vec<FieldError>,
+}
+
+impl Validator
+{
+    pub fn new() -> Self
+    {
+        Validator::default()
+    }
+
+    pub fn check(&mut self, ok: bool, field: &str, message: &str) -> &mut Self
+    {
+        if !ok
+        {
+            self.errors.push(FieldError::new(field, message));
+        }
+        self
+    }
+
+    pub fn username(&mut self, field: &str, name: &str) -> &mut Self
+    {
+        self.check(
+            valid_username(name),
+            field,
+            "Can only contain letters, numbers, _ and -, and can not be empty",
+        )
+    }
+
+    pub fn not_empty(&mut self, field: &str, value: &str) -> &mut Self
+    {
+        self.check(!value.trim().is_empty(), field, "Can not be empty")
+    }
+
+    pub fn finish(&mut self) -> ServerResult<()>
+    {
+        if self.errors.is_empty()
+        {
+            Ok(())
+        }
+        else
+        {
+            Err(ServerError::InvalidRequest(std::mem::take(&mut self.errors)))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+
+    #[test]
+    fn test_reports_every_invalid_field()
+    {
+        let res = Validator::new()
+            .username("winner", "Sivert")
+            .username("loser", "Sivert Johansen")
+            .not_empty("name", " ")
+            .finish();
+
+        let fields: Vec<_> = match res
+        {
+            Err(ServerError::InvalidRequest(errors)) =>
+            {
+                errors.into_iter().map(|e| e.field).collect()
+            },
+            _ => Vec::new(),
+        };
+        assert_eq!(fields, vec!["loser", "name"]);
+    }
+
+    #[test]
+    fn test_usernames()
+    {
+        assert!(valid_username("Sivert_Æ-2"));
+        assert!(!valid_username(""));
+        assert!(!valid_username("Robert'); drop table users;--"));
+    }
+}
@@ -5,8 +5,7 @@ pub static BADGES: &'static [&'static str] =
 #[allow(dead_code)]
 pub const MATCH_NO_ANS: u8 = 0;
 pub const ACCEPT_REQUEST: u8 = 1;
-#[allow(dead_code)]
-const DECLINE_REQUEST: u8 = 2;
+pub const DECLINE_REQUEST: u8 = 2;
 
 pub const STOP_SEASON: i64 = -1;
 pub const START_SEASON: i64 = -2;
@@ -15,15 +14,34 @@ pub const CANCEL_SEASON: i64 = -3;
 pub const N_SEASON_ID: u32 = 1;
 pub const IS_SEASON_ID: u32 = 2;
 pub const REQUIRE_CONFIRMATION_ID: u32 = 3;
+pub const RATING_SYSTEM_ID: u32 = 4;
+pub const ELO_K_ID: u32 = 5;
+pub const MARGIN_OF_VICTORY_ID: u32 = 6;
+pub const AUDIT_RETENTION_DAYS_ID: u32 = 7;
 
 pub const USER_ROLE_REGULAR: u8 = 0;
 pub const USER_ROLE_SUPERUSER: u8 = 1 << 1;
 pub const USER_ROLE_INACTIVE: u8 = 1 << 2;
 pub const USER_ROLE_SOFT_INACTIVE: u8 = 1 << 4;
 
+pub const HASH_SHA256: u8 = 0;
+pub const HASH_ARGON2ID: u8 = 1;
+
+// A session expires after 30 days without use, it is renewed at most once a day
+pub const SESSION_LENGTH_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+pub const SESSION_RENEW_INTERVAL_MS: i64 = 24 * 60 * 60 * 1000;
+
 pub const DATABASE_FILE: &'static str = "db.db";
 
+// The admin sql console returns at most this many rows, and stops statements
+// that run for longer than the timeout
+pub const ADMIN_SQL_MAX_ROWS: usize = 1000;
+pub const ADMIN_SQL_TIMEOUT_SECS: u64 = 5;
+
 
 pub const ASSETS_PATH: &'static str = if cfg!(debug_assertions) { "assets" } else { "./db/assets" };
 
+pub const MIN_TOURNAMENT_PLAYERS: i64 = 4;
+pub const MAX_TOURNAMENT_PLAYERS: i64 = 64;
+
 pub const TOURNAMENT_BADGES_PATH: &'static str = "tournament_badges";
@@ -12,6 +12,25 @@ pub enum TournamentError
     GameAlreadyPlayed,
     InvalidTtype,
     CannotRerun,
+    RoundNotFinished,
+}
+
+// A problem with one field of a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldError
+{
+    pub field:   String,
+    pub message: String,
+}
+
+impl FieldError
+{
+    pub fn new(field: &str, message: impl Into<String>) -> Self
+    {
+        FieldError {
+            field: field.to_string(), message: message.into()
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -28,6 +47,13 @@ pub enum ServerError
     InactiveUser,
     ResetPasswordDuplicate,
     InvalidUsername,
+    InvalidScore,
+    InvalidTeam,
+    PasswordChangeRequired,
+    InvalidSession,
+    // The match, notification or other row asked for does not exist
+    NotFound,
+    InvalidRequest(Vec<FieldError>),
     Tournament(TournamentError),
 }
 
@@ -50,3 +76,242 @@ impl From<ServerError> for rusqlite::Error
         }
     }
 }
+
+// What is sent to the client for each error. The matches are exhaustive, so a
+// new variant does not compile until it has a code, a status and a message.
+impl ServerError
+{
+    // Stable identifier clients can match on
+    pub fn code(&self) -> &'static str
+    {
+        match self
+        {
+            ServerError::Rusqlite(_) => "database_error",
+            ServerError::Critical(_) => "internal_error",
+            ServerError::UserNotExist => "user_not_found",
+            ServerError::UsernameTaken => "username_taken",
+            ServerError::WrongUsernameOrPassword => "wrong_username_or_password",
+            ServerError::PasswordNotMatch => "wrong_password",
+            ServerError::Unauthorized => "forbidden",
+            ServerError::WaitingForAdmin => "waiting_for_admin",
+            ServerError::InactiveUser => "inactive_user",
+            ServerError::ResetPasswordDuplicate => "reset_password_already_requested",
+            ServerError::InvalidUsername => "invalid_username",
+            ServerError::InvalidScore => "invalid_score",
+            ServerError::InvalidTeam => "invalid_team",
+            ServerError::PasswordChangeRequired => "password_change_required",
+            ServerError::InvalidSession => "invalid_session",
+            ServerError::NotFound => "not_found",
+            ServerError::InvalidRequest(_) => "invalid_request",
+            ServerError::Tournament(t) => t.code(),
+        }
+    }
+
+    // The HTTP status code of the response
+    pub fn status(&self) -> u16
+    {
+        match self
+        {
+            ServerError::Rusqlite(_) | ServerError::Critical(_) => 500,
+            ServerError::InvalidRequest(_) => 400,
+            ServerError::WrongUsernameOrPassword
+            | ServerError::PasswordNotMatch
+            | ServerError::InvalidSession => 401,
+            ServerError::Unauthorized
+            | ServerError::WaitingForAdmin
+            | ServerError::InactiveUser
+            | ServerError::PasswordChangeRequired => 403,
+            ServerError::UserNotExist | ServerError::NotFound => 404,
+            ServerError::UsernameTaken | ServerError::ResetPasswordDuplicate => 409,
+            ServerError::InvalidUsername | ServerError::InvalidScore | ServerError::InvalidTeam =>
+            {
+                422
+            },
+            ServerError::Tournament(t) => t.status(),
+        }
+    }
+
+    pub fn message(&self) -> String
+    {
+        match self
+        {
+            // Database errors can contain the query, so they are only logged
+            ServerError::Rusqlite(_) => "Database error".into(),
+            ServerError::Critical(message) => message.clone(),
+            ServerError::UserNotExist => "User does not exist".into(),
+            ServerError::UsernameTaken => "Username is already taken".into(),
+            ServerError::WrongUsernameOrPassword => "Incorrect username or password".into(),
+            ServerError::PasswordNotMatch => "Wrong password".into(),
+            ServerError::Unauthorized => "Not allowed to do this".into(),
+            ServerError::WaitingForAdmin => "Waiting for approval by an admin".into(),
+            ServerError::InactiveUser => "User is inactive".into(),
+            ServerError::ResetPasswordDuplicate =>
+            {
+                "Password reset has already been requested".into()
+            },
+            ServerError::InvalidUsername =>
+            {
+                "Invalid username, it can only contain letters, numbers, _ and -".into()
+            },
+            ServerError::InvalidScore => "The scores are not a valid match".into(),
+            ServerError::InvalidTeam => "A player can not be in a match twice".into(),
+            ServerError::PasswordChangeRequired => "The password must be changed".into(),
+            ServerError::InvalidSession => "The session is invalid or has expired".into(),
+            ServerError::NotFound => "Not found".into(),
+            ServerError::InvalidRequest(_) => "The request is invalid".into(),
+            ServerError::Tournament(t) => t.message().into(),
+        }
+    }
+
+    // The fields of the request that caused the error
+    pub fn details(&self) -> Vec<FieldError>
+    {
+        let field = |field: &str| vec![FieldError::new(field, self.message())];
+        match self
+        {
+            ServerError::InvalidRequest(details) => details.clone(),
+            ServerError::UsernameTaken | ServerError::InvalidUsername => field("username"),
+            ServerError::PasswordNotMatch => field("password"),
+            ServerError::InvalidScore => field("scores"),
+            ServerError::InvalidTeam => field("players"),
+            ServerError::Tournament(TournamentError::InvalidTtype) => field("ttype"),
+            ServerError::Tournament(TournamentError::WrongTournamentCount) => field("player_count"),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl TournamentError
+{
+    pub fn code(&self) -> &'static str
+    {
+        match self
+        {
+            TournamentError::WrongState => "tournament_wrong_state",
+            TournamentError::NoTournament => "tournament_not_found",
+            TournamentError::NotOrganizer => "not_organizer",
+            TournamentError::InvalidGame => "invalid_tournament_game",
+            TournamentError::WrongTournamentCount => "wrong_tournament_count",
+            TournamentError::AlreadyJoined => "already_joined",
+            TournamentError::GameAlreadyPlayed => "game_already_played",
+            TournamentError::InvalidTtype => "invalid_tournament_type",
+            TournamentError::CannotRerun => "cannot_rerun_tournament",
+            TournamentError::RoundNotFinished => "round_not_finished",
+        }
+    }
+
+    pub fn status(&self) -> u16
+    {
+        match self
+        {
+            TournamentError::NoTournament => 404,
+            TournamentError::NotOrganizer => 403,
+            TournamentError::WrongState
+            | TournamentError::AlreadyJoined
+            | TournamentError::GameAlreadyPlayed
+            | TournamentError::CannotRerun
+            | TournamentError::RoundNotFinished => 409,
+            TournamentError::InvalidGame
+            | TournamentError::WrongTournamentCount
+            | TournamentError::InvalidTtype => 422,
+        }
+    }
+
+    pub fn message(&self) -> &'static str
+    {
+        match self
+        {
+            TournamentError::WrongState => "The tournament has the wrong state",
+            TournamentError::NoTournament => "Tournament not found",
+            TournamentError::NotOrganizer => "Only the organizer can do this",
+            TournamentError::InvalidGame => "The game does not have two players yet",
+            TournamentError::WrongTournamentCount => "The player count must be between 4 and 64",
+            TournamentError::AlreadyJoined => "Already joined the tournament",
+            TournamentError::GameAlreadyPlayed => "The game has already been played",
+            TournamentError::InvalidTtype => "Unknown tournament type",
+            TournamentError::CannotRerun =>
+            {
+                "The result can not change after the next game is played"
+            },
+            TournamentError::RoundNotFinished => "Every game in the round must be played first",
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test
+{
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn all_errors() -> Vec<ServerError>
+    {
+        use TournamentError::*;
+        let mut errors = vec![
+            ServerError::Rusqlite(rusqlite::Error::InvalidQuery),
+            ServerError::Critical("critical".into()),
+            ServerError::UserNotExist,
+            ServerError::UsernameTaken,
+            ServerError::WrongUsernameOrPassword,
+            ServerError::PasswordNotMatch,
+            ServerError::Unauthorized,
+            ServerError::WaitingForAdmin,
+            ServerError::InactiveUser,
+            ServerError::ResetPasswordDuplicate,
+            ServerError::InvalidUsername,
+            ServerError::InvalidScore,
+            ServerError::InvalidTeam,
+            ServerError::PasswordChangeRequired,
+            ServerError::InvalidSession,
+            ServerError::NotFound,
+            ServerError::InvalidRequest(vec![FieldError::new("field", "message")]),
+        ];
+        errors.extend(
+            vec![
+                WrongState,
+                NoTournament,
+                NotOrganizer,
+                InvalidGame,
+                WrongTournamentCount,
+                AlreadyJoined,
+                GameAlreadyPlayed,
+                InvalidTtype,
+                CannotRerun,
+                RoundNotFinished,
+            ]
+            .into_iter()
+            .map(ServerError::Tournament),
+        );
+        errors
+    }
+
+    #[test]
+    fn test_error_codes_are_unique()
+    {
+        let errors = all_errors();
+        let codes: HashSet<_> = errors.iter().map(|e| e.code()).collect();
+
+        assert_eq!(codes.len(), errors.len());
+    }
+
+    #[test]
+    fn test_only_server_faults_are_500()
+    {
+        for e in all_errors()
+        {
+            let server_fault = matches!(e, ServerError::Rusqlite(_) | ServerError::Critical(_));
+            assert_eq!(e.status() == 500, server_fault, "{:?}", e);
+            assert!(!e.message().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_database_errors_are_not_shown()
+    {
+        let e = ServerError::Rusqlite(rusqlite::Error::InvalidColumnName("secret".into()));
+
+        assert!(!e.message().contains("secret"));
+    }
+}
@@ -1,73 +1,95 @@
 use std::{
     convert::TryFrom,
-    sync::{Arc, Mutex},
+    future::{ready, Ready},
+    sync::Arc,
 };
 
 use actix_cors::Cors;
 use actix_files::Files;
-use actix_web::{get, post, web, App, HttpResponse, HttpServer};
+use actix_web::{
+    dev::Payload,
+    error::InternalError,
+    get,
+    http::{header, StatusCode},
+    post, web, App, FromRequest, HttpRequest, HttpResponse, HttpServer,
+};
 use serde::Serialize;
 use serde_derive::Deserialize;
 use serde_json::json;
 use server::{
-    spawn_season_checker, ChangePasswordInfo, CreateTournament, DataBase, DeleteMatchInfo,
-    EditUsersInfo, GetTournamentOptions, JoinTournament, LoginInfo, MatchInfo, NewEditMatchInfo,
-    NotificationAns, NotificationInfo, NotificationType, RegisterTournamentMatch,
-    RequestResetPassword, StatsUsers,
+    spawn_season_checker, spawn_tournament_starter, AuditLogQuery, ChangePasswordInfo,
+    CreateTournament, DataBasePool, DeleteMatchInfo, DoublesMatchInfo, EditUsersInfo,
+    GetTournamentOptions, JoinTournament, LoginInfo, MatchInfo, MultipleUsersInfo,
+    NewEditMatchInfo, NotificationAns, NotificationInfo, NotificationType, RegisterTournamentMatch,
+    RequestResetPassword, ScheduleGame, ScheduleTournament, SqlCommand, StatsUsers, UndoMatchInfo,
+    Validate, WithdrawPlayer,
 };
 use server_core::{
     constants::{CANCEL_SEASON, START_SEASON, STOP_SEASON},
-    types::{ServerError, TournamentError::*},
+    types::{FieldError, ServerError},
 };
 
 const PORT: u32 = 58642;
 pub const DATABASE_FILE: &str = "db.db";
+const READ_CONNECTIONS: usize = 4;
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+const CALENDAR_CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
 
 
 macro_rules! DATABASE {
     ($data:expr) => {
-        match $data.get_ref().lock()
-        {
-            Ok(guard) => guard,
-            Err(p_err) => p_err.into_inner(),
-        }
+        $data.get_ref().write()
+    };
+}
+
+// Only for handlers that never write, these run on a read only connection
+macro_rules! READ_DATABASE {
+    ($data:expr) => {
+        $data.get_ref().read()
     };
 }
 
-fn response_code(e: ServerError) -> u8
+// The session token from the `Authorization: Bearer <token>` header. A request
+// without one gets an empty token, which no session matches.
+struct Token(String);
+
+impl FromRequest for Token
 {
-    println!("{:?}", e);
-    match e
+    type Config = ();
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future
     {
-        ServerError::Critical(_) => 1,
-        ServerError::UserNotExist => 2,
-        ServerError::UsernameTaken => 3,
-        ServerError::WrongUsernameOrPassword => 4,
-        ServerError::PasswordNotMatch => 5,
-        ServerError::Unauthorized => 6,
-        ServerError::WaitingForAdmin => 7,
-        ServerError::InactiveUser => 8,
-        ServerError::ResetPasswordDuplicate => 9,
-        ServerError::InvalidUsername => 10,
-        ServerError::Tournament(t) => match t
-        {
-            WrongState => 11,
-            NoTournament => 12,
-            NotOrganizer => 13,
-            InvalidGame => 14,
-            WrongTournamentCount => 15,
-            AlreadyJoined => 16,
-            GameAlreadyPlayed => 17,
-            InvalidTtype => 18,
-            CannotRerun => 19,
-        },
-        _ => 69,
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .unwrap_or("");
+        ready(Ok(Token(token.trim().to_string())))
     }
 }
 
-fn response_error(e: ServerError) -> serde_json::Value
+// Every error has the same shape, `details` lists the fields of the request
+// that caused it:
+// {"error": {"code": "invalid_score", "message": "...", "details": [...]}}
+fn response_error(e: ServerError) -> HttpResponse
 {
-    json!({ "status": response_code(e) })
+    let status = StatusCode::from_u16(e.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    if status.is_server_error()
+    {
+        eprintln!("{:?}", e);
+    }
+
+    let details: Vec<_> = e
+        .details()
+        .into_iter()
+        .map(|d| json!({"field": d.field, "message": d.message}))
+        .collect();
+    HttpResponse::build(status).json(json!({
+        "error": {"code": e.code(), "message": e.message(), "details": details}
+    }))
 }
 
 fn response_ok_with<T>(item: T) -> serde_json::Value
@@ -84,13 +106,16 @@ fn response_ok() -> serde_json::Value
 
 
 #[post("api/create-user")]
-async fn create_user(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn create_user(data: web::Data<Arc<DataBasePool>>, info: web::Json<LoginInfo>)
+    -> HttpResponse
 {
-    let info: LoginInfo = serde_json::from_str(&info).unwrap();
-    match DATABASE!(data).create_user(info.username, info.password)
+    let info = info.into_inner();
+    match info
+        .validate()
+        .and_then(|_| DATABASE!(data).create_user(info.username, info.password))
     {
         Ok(s) => HttpResponse::Ok().json(response_ok_with(s)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
@@ -101,14 +126,15 @@ struct Variable
 }
 
 #[post("api/admin/get-variable")]
-async fn get_variable(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn get_variable(data: web::Data<Arc<DataBasePool>>, info: web::Json<Variable>)
+    -> HttpResponse
 {
-    let info: Variable = serde_json::from_str(&info).unwrap();
+    let info = info.into_inner();
 
     match DATABASE!(data).get_variable(info.variable)
     {
         Ok(val) => HttpResponse::Ok().json(response_ok_with(val.to_string())),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
@@ -117,535 +143,776 @@ struct EditVariable
 {
     variable: String,
     new_val:  String,
-    token:    String,
 }
 
 #[post("api/admin/set-variable")]
-async fn set_variable(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn set_variable(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<EditVariable>,
+) -> HttpResponse
 {
-    let info: EditVariable = serde_json::from_str(&info).unwrap();
-    let new_val: i64 = info.new_val.parse().unwrap();
-    match DATABASE!(data).set_variable(info.token, info.variable, new_val)
+    let info = info.into_inner();
+    let new_val = info.new_val.parse::<i64>().map_err(|_| {
+        ServerError::InvalidRequest(vec![FieldError::new("new_val", "Must be a whole number")])
+    });
+    match new_val.and_then(|new_val| DATABASE!(data).set_variable(token, info.variable, new_val))
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 
 #[post("api/edit-users")]
-async fn edit_users(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn edit_users(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<EditUsersInfo>,
+) -> HttpResponse
 {
-    let info: EditUsersInfo = serde_json::from_str(&info).unwrap();
+    let info = info.into_inner();
 
-    match DATABASE!(data).edit_users(info.users, info.action, info.token)
+    match info
+        .validate()
+        .and_then(|_| DATABASE!(data).edit_users(info.users, info.action, token))
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 
 #[post("api/request-reset-password")]
-async fn request_reset_password(data: web::Data<Arc<Mutex<DataBase>>>, info: String)
-    -> HttpResponse
+async fn request_reset_password(
+    data: web::Data<Arc<DataBasePool>>,
+    info: web::Json<RequestResetPassword>,
+) -> HttpResponse
 {
-    let info: RequestResetPassword = serde_json::from_str(&info).unwrap();
-    match DATABASE!(data).request_reset_password(info.name)
+    let info = info.into_inner();
+    match info.validate().and_then(|_| DATABASE!(data).request_reset_password(info.name))
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 
 #[post("api/register-match")]
-async fn register_match(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn register_match(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<MatchInfo>,
+) -> HttpResponse
 {
-    let info: MatchInfo = serde_json::from_str(&info).unwrap();
+    let info = info.into_inner();
 
-    match DATABASE!(data).register_match(info.winner, info.loser, info.token)
+    match info.validate().and_then(|_| {
+        DATABASE!(data).register_match_with_scores(info.winner, info.loser, info.scores, token)
+    })
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
+    }
+}
+
+#[post("api/register-doubles-match")]
+async fn register_doubles_match(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<DoublesMatchInfo>,
+) -> HttpResponse
+{
+    let info = info.into_inner();
+
+    match info.validate().and_then(|_| {
+        DATABASE!(data).register_doubles_match(info.winners, info.losers, info.scores, token)
+    })
+    {
+        Ok(_) => HttpResponse::Ok().json(response_ok()),
+        Err(e) => response_error(e),
     }
 }
 
 #[post("api/login")]
-async fn login(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn login(
+    data: web::Data<Arc<DataBasePool>>,
+    req: HttpRequest,
+    info: web::Json<LoginInfo>,
+) -> HttpResponse
 {
-    let info: LoginInfo = serde_json::from_str(&info).unwrap();
+    let info = info.into_inner();
+    // Lets the user tell their sessions apart
+    let device = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+
+    match info
+        .validate()
+        .and_then(|_| DATABASE!(data).login_on_device(info.username, info.password, device))
+    {
+        Ok(token) => HttpResponse::Ok().json(response_ok_with(token)),
+        Err(e) => response_error(e),
+    }
+}
+
+#[post("api/logout")]
+async fn logout(data: web::Data<Arc<DataBasePool>>, Token(token): Token) -> HttpResponse
+{
+    match DATABASE!(data).logout(token)
+    {
+        Ok(_) => HttpResponse::Ok().json(response_ok()),
+        Err(e) => response_error(e),
+    }
+}
 
-    match DATABASE!(data).login(info.username, info.password)
+#[post("api/logout-everywhere")]
+async fn logout_everywhere(data: web::Data<Arc<DataBasePool>>, Token(token): Token)
+    -> HttpResponse
+{
+    match DATABASE!(data).logout_everywhere(token)
     {
-        Ok(uuid) => HttpResponse::Ok().json(response_ok_with(uuid)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Ok(_) => HttpResponse::Ok().json(response_ok()),
+        Err(e) => response_error(e),
     }
 }
 
 #[post("api/change-password")]
-async fn change_password(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn change_password(
+    data: web::Data<Arc<DataBasePool>>,
+    info: web::Json<ChangePasswordInfo>,
+) -> HttpResponse
 {
-    let info: ChangePasswordInfo = serde_json::from_str(&info).unwrap();
+    let info = info.into_inner();
 
-    match DATABASE!(data).change_password(info.username, info.password, info.new_password)
+    match info.validate().and_then(|_| {
+        DATABASE!(data).change_password(info.username, info.password, info.new_password)
+    })
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 
 #[get("api/active-users")]
-async fn get_active_users(data: web::Data<Arc<Mutex<DataBase>>>) -> HttpResponse
+async fn get_active_users(data: web::Data<Arc<DataBasePool>>) -> HttpResponse
 {
-    match DATABASE!(data).get_non_inactive_users()
+    match READ_DATABASE!(data).get_non_inactive_users()
     {
         Ok(data) => HttpResponse::Ok().json(response_ok_with(data)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 #[get("api/users")]
-async fn get_users(data: web::Data<Arc<Mutex<DataBase>>>) -> HttpResponse
+async fn get_users(data: web::Data<Arc<DataBasePool>>) -> HttpResponse
 {
-    match DATABASE!(data).get_users()
+    match READ_DATABASE!(data).get_users()
     {
         Ok(data) => HttpResponse::Ok().json(response_ok_with(data)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 
-#[get("api/all-users/{token}")]
-async fn get_all_users(
-    data: web::Data<Arc<Mutex<DataBase>>>,
-    web::Path(token): web::Path<String>,
-) -> HttpResponse
+#[get("api/all-users")]
+async fn get_all_users(data: web::Data<Arc<DataBasePool>>, Token(token): Token) -> HttpResponse
 {
     match DATABASE!(data).get_all_users(token)
     {
         Ok(data) => HttpResponse::Ok().json(response_ok_with(data)),
-        Err(_) => HttpResponse::NotFound().finish(),
+        Err(e) => response_error(e),
     }
 }
 
 #[get("api/notifications")]
 async fn get_notifications(
-    data: web::Data<Arc<Mutex<DataBase>>>,
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
     info: web::Query<NotificationInfo>,
 ) -> HttpResponse
 {
     let _type = info.r#type.clone();
-    let token = info.token.clone();
 
     NotificationType::try_from(_type.clone()).map_or(
-        HttpResponse::Ok().json(
-            json!({"status": 69, "result": format!("no notification type matching {}", _type)}),
-        ),
+        response_error(ServerError::InvalidRequest(vec![FieldError::new(
+            "type",
+            format!("No notification type matching {}", _type),
+        )])),
         |t| match DATABASE!(data).get_notifications(t, token)
         {
             Ok(data) => HttpResponse::Ok().json(response_ok_with(data)),
-            Err(e) => HttpResponse::Ok().json(response_error(e)),
+            Err(e) => response_error(e),
         },
     )
 }
 
 #[post("api/notifications")]
 async fn respond_to_notification(
-    data: web::Data<Arc<Mutex<DataBase>>>,
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
     info: String,
 ) -> HttpResponse
 {
-    NotificationAns::try_from(info).map_or(
-        HttpResponse::Ok().json(json!({"status": 69, "result": "invalid notification type"})),
+    NotificationAns::try_from((info, token)).map_or(
+        response_error(ServerError::InvalidRequest(vec![FieldError::new(
+            "type",
+            "Invalid notification answer",
+        )])),
         |notification_ans| match DATABASE!(data).respond_to_notification(notification_ans)
         {
-            Ok(_) => HttpResponse::Ok().json(response_ok()),
-            Err(e) => HttpResponse::Ok().json(response_error(e)),
+            Ok(Some(password)) => HttpResponse::Ok().json(response_ok_with(password)),
+            Ok(None) => HttpResponse::Ok().json(response_ok()),
+            Err(e) => response_error(e),
         },
     )
 }
 
 #[get("api/history")]
-async fn get_history(data: web::Data<Arc<Mutex<DataBase>>>) -> HttpResponse
+async fn get_history(data: web::Data<Arc<DataBasePool>>) -> HttpResponse
+{
+    match READ_DATABASE!(data).get_history()
+    {
+        Ok(data) => HttpResponse::Ok().json(response_ok_with(data)),
+        Err(e) => response_error(e),
+    }
+}
+
+#[get("api/doubles/history")]
+async fn get_doubles_history(data: web::Data<Arc<DataBasePool>>) -> HttpResponse
 {
-    match DATABASE!(data).get_history()
+    match READ_DATABASE!(data).get_doubles_history()
     {
         Ok(data) => HttpResponse::Ok().json(response_ok_with(data)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
+    }
+}
+
+#[get("api/doubles/pairs")]
+async fn get_doubles_pairs(data: web::Data<Arc<DataBasePool>>) -> HttpResponse
+{
+    match READ_DATABASE!(data).get_doubles_pairs()
+    {
+        Ok(data) => HttpResponse::Ok().json(response_ok_with(data)),
+        Err(e) => response_error(e),
+    }
+}
+
+#[get("api/doubles/players")]
+async fn get_doubles_players(data: web::Data<Arc<DataBasePool>>) -> HttpResponse
+{
+    match READ_DATABASE!(data).get_doubles_players()
+    {
+        Ok(data) => HttpResponse::Ok().json(response_ok_with(data)),
+        Err(e) => response_error(e),
     }
 }
 
 #[post("api/stats")]
-async fn get_stats(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn get_stats(data: web::Data<Arc<DataBasePool>>, info: web::Json<StatsUsers>)
+    -> HttpResponse
 {
-    let info: StatsUsers = serde_json::from_str(&info).unwrap();
-    match DATABASE!(data).get_stats(info)
+    let info = info.into_inner();
+    match info.validate().and_then(|_| READ_DATABASE!(data).get_stats(info))
     {
         Ok(data) => HttpResponse::Ok().json(response_ok_with(data)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 #[post("api/delete-match")]
-async fn delete_match(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn delete_match(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<DeleteMatchInfo>,
+) -> HttpResponse
 {
-    let info: DeleteMatchInfo = serde_json::from_str(&info).unwrap();
-    match DATABASE!(data).delete_match(info)
+    let mut info = info.into_inner();
+    info.token = token;
+    match info.validate().and_then(|_| DATABASE!(data).delete_match(info))
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 #[post("api/edit-match")]
-async fn edit_match(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn edit_match(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<NewEditMatchInfo>,
+) -> HttpResponse
 {
-    let info: NewEditMatchInfo = serde_json::from_str(&info).unwrap();
-    match DATABASE!(data).edit_match(info)
+    let mut info = info.into_inner();
+    info.token = token;
+    match info.validate().and_then(|_| DATABASE!(data).edit_match(info))
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
+    }
+}
+
+#[post("api/admin/undo-match-change")]
+async fn undo_match_change(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<UndoMatchInfo>,
+) -> HttpResponse
+{
+    let mut info = info.into_inner();
+    info.token = token;
+    match info.validate().and_then(|_| DATABASE!(data).undo_match_change(info))
+    {
+        Ok(_) => HttpResponse::Ok().json(response_ok()),
+        Err(e) => response_error(e),
+    }
+}
+
+#[get("api/admin/match-changes")]
+async fn get_match_changes(data: web::Data<Arc<DataBasePool>>, Token(token): Token)
+    -> HttpResponse
+{
+    match DATABASE!(data).get_match_changes(token)
+    {
+        Ok(changes) => HttpResponse::Ok().json(response_ok_with(changes)),
+        Err(e) => response_error(e),
     }
 }
 
 #[get("api/edit-history")]
-async fn get_edit_history(data: web::Data<Arc<Mutex<DataBase>>>) -> HttpResponse
+async fn get_edit_history(data: web::Data<Arc<DataBasePool>>) -> HttpResponse
 {
-    match DATABASE!(data).get_edit_match_history()
+    match READ_DATABASE!(data).get_edit_match_history()
     {
         Ok(data) => HttpResponse::Ok().json(response_ok_with(data)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 #[get("api/user/{name}")]
 async fn get_profile(
-    data: web::Data<Arc<Mutex<DataBase>>>,
+    data: web::Data<Arc<DataBasePool>>,
     web::Path(name): web::Path<String>,
 ) -> HttpResponse
 {
-    match DATABASE!(data).get_user(&name)
+    match READ_DATABASE!(data).get_user(&name)
     {
         Ok(data) => HttpResponse::Ok().json(response_ok_with(data)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
-#[derive(Deserialize)]
-struct Users
-{
-    users: Vec<String>,
-    season: Option<u32>,
-}
-
 #[post("api/get-multiple-users")]
-async fn get_multiple_users(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn get_multiple_users(
+    data: web::Data<Arc<DataBasePool>>,
+    info: web::Json<MultipleUsersInfo>,
+) -> HttpResponse
 {
-    println!("{}", info);
-    let info: Users = serde_json::from_str(&info).unwrap();
-    match DATABASE!(data).get_multiple_users(info.users, info.season)
+    let info = info.into_inner();
+    match info
+        .validate()
+        .and_then(|_| READ_DATABASE!(data).get_multiple_users(info.users, info.season))
     {
         Ok(users) => HttpResponse::Ok().json(response_ok_with(users)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 
 #[post("api/create-tournament")]
-async fn create_tournament(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn create_tournament(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<CreateTournament>,
+) -> HttpResponse
 {
-    let info: CreateTournament = serde_json::from_str(&info).unwrap();
-    match DATABASE!(data).create_tournament(info)
+    let mut info = info.into_inner();
+    info.organizer_token = token;
+    match info.validate().and_then(|_| DATABASE!(data).create_tournament(info))
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 #[post("api/recreate-tournament")]
-async fn recreate_tournament(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn recreate_tournament(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<JoinTournament>,
+) -> HttpResponse
 {
-    #[derive(Deserialize)]
-    struct RecreateTournament
-    {
-        token: String,
-        tid:   i64,
-    }
-
-    let info: RecreateTournament = serde_json::from_str(&info).unwrap();
-    match DATABASE!(data).recreate_tournament(info.token, info.tid)
+    let info = info.into_inner();
+    match info
+        .validate()
+        .and_then(|_| DATABASE!(data).recreate_tournament(token, info.tid))
     {
         Ok(id) => HttpResponse::Ok().json(response_ok_with(id)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 #[post("api/join-tournament")]
-async fn join_tournament(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn join_tournament(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<JoinTournament>,
+) -> HttpResponse
 {
-    let info: JoinTournament = serde_json::from_str(&info).unwrap();
+    let info = info.into_inner();
 
-    match DATABASE!(data).join_tournament(info.token, info.tid)
+    match info.validate().and_then(|_| DATABASE!(data).join_tournament(token, info.tid))
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 #[post("api/register-tournament-match")]
 async fn register_tournament_match(
-    data: web::Data<Arc<Mutex<DataBase>>>,
-    info: String,
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<RegisterTournamentMatch>,
 ) -> HttpResponse
 {
-    let info: RegisterTournamentMatch = serde_json::from_str(&info).unwrap();
+    let mut info = info.into_inner();
+    info.organizer_token = token;
 
-    match DATABASE!(data).register_tournament_match(info)
+    match info.validate().and_then(|_| DATABASE!(data).register_tournament_match(info))
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 #[post("api/delete-tournament")]
-async fn delete_tournament(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn delete_tournament(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<JoinTournament>,
+) -> HttpResponse
 {
-    #[derive(Deserialize)]
-    struct DeleteTournament
+    let info = info.into_inner();
+    match info.validate().and_then(|_| DATABASE!(data).delete_tournament(token, info.tid))
     {
-        tid:   i64,
-        token: String,
+        Ok(_) => HttpResponse::Ok().json(response_ok()),
+        Err(e) => response_error(e),
     }
+}
+
+#[post("api/leave-tournament")]
+async fn leave_tournament(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<JoinTournament>,
+) -> HttpResponse
+{
+    let info = info.into_inner();
 
-    let info: DeleteTournament = serde_json::from_str(&info).unwrap();
-    match DATABASE!(data).delete_tournament(info.token, info.tid)
+    match info.validate().and_then(|_| DATABASE!(data).leave_tournament(token, info.tid))
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
-#[post("api/leave-tournament")]
-async fn leave_tournament(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+#[post("api/close-tournament-round")]
+async fn close_tournament_round(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<JoinTournament>,
+) -> HttpResponse
 {
-    let info: JoinTournament = serde_json::from_str(&info).unwrap();
+    let info = info.into_inner();
 
-    match DATABASE!(data).leave_tournament(info.token, info.tid)
+    match info
+        .validate()
+        .and_then(|_| DATABASE!(data).close_tournament_round(token, info.tid))
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
+    }
+}
+
+#[post("api/start-tournament")]
+async fn start_tournament(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<JoinTournament>,
+) -> HttpResponse
+{
+    let info = info.into_inner();
+
+    match info.validate().and_then(|_| DATABASE!(data).start_tournament(token, info.tid))
+    {
+        Ok(_) => HttpResponse::Ok().json(response_ok()),
+        Err(e) => response_error(e),
+    }
+}
+
+#[post("api/withdraw-player")]
+async fn withdraw_player(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<WithdrawPlayer>,
+) -> HttpResponse
+{
+    let info = info.into_inner();
+
+    match info
+        .validate()
+        .and_then(|_| DATABASE!(data).withdraw_player(token, info.tid, info.player))
+    {
+        Ok(_) => HttpResponse::Ok().json(response_ok()),
+        Err(e) => response_error(e),
+    }
+}
+
+#[post("api/schedule-game")]
+async fn schedule_game(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<ScheduleGame>,
+) -> HttpResponse
+{
+    let info = info.into_inner();
+
+    match info.validate().and_then(|_| DATABASE!(data).schedule_game(token, info))
+    {
+        Ok(_) => HttpResponse::Ok().json(response_ok()),
+        Err(e) => response_error(e),
+    }
+}
+
+#[post("api/schedule-tournament")]
+async fn schedule_tournament(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<ScheduleTournament>,
+) -> HttpResponse
+{
+    let info = info.into_inner();
+
+    match info.validate().and_then(|_| DATABASE!(data).schedule_tournament(token, info))
+    {
+        Ok(_) => HttpResponse::Ok().json(response_ok()),
+        Err(e) => response_error(e),
+    }
+}
+
+#[get("api/tournament-calendar/{id}")]
+async fn get_tournament_calendar(
+    data: web::Data<Arc<DataBasePool>>,
+    web::Path(id): web::Path<i64>,
+) -> HttpResponse
+{
+    match READ_DATABASE!(data).get_tournament_calendar(id)
+    {
+        Ok(calendar) => HttpResponse::Ok().content_type(CALENDAR_CONTENT_TYPE).body(calendar),
+        Err(e) => response_error(e),
+    }
+}
+
+#[get("api/player-calendar/{name}")]
+async fn get_player_calendar(
+    data: web::Data<Arc<DataBasePool>>,
+    web::Path(name): web::Path<String>,
+) -> HttpResponse
+{
+    match READ_DATABASE!(data).get_player_calendar(&name)
+    {
+        Ok(calendar) => HttpResponse::Ok().content_type(CALENDAR_CONTENT_TYPE).body(calendar),
+        Err(e) => response_error(e),
     }
 }
 
 #[get("api/tournament-infos")]
 async fn get_tournament_infos(
-    data: web::Data<Arc<Mutex<DataBase>>>,
+    data: web::Data<Arc<DataBasePool>>,
     info: web::Query<GetTournamentOptions>,
 ) -> HttpResponse
 {
     let info: GetTournamentOptions = info.into_inner();
-    match DATABASE!(data).get_tournament_infos(info)
+    match READ_DATABASE!(data).get_tournament_infos(info)
     {
         Ok(tournaments) => HttpResponse::Ok().json(response_ok_with(tournaments)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 #[get("api/tournament-table/{id}")]
 async fn get_tournament_table(
-    data: web::Data<Arc<Mutex<DataBase>>>,
+    data: web::Data<Arc<DataBasePool>>,
     web::Path(id): web::Path<i64>,
 ) -> HttpResponse
 {
-    match DATABASE!(data).get_upper_to_lower_table(id)
+    match READ_DATABASE!(data).get_upper_to_lower_table(id)
     {
         Ok(tournaments) => HttpResponse::Ok().json(response_ok_with(tournaments)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 #[get("api/tournament/{id}")]
 async fn get_tournament(
-    data: web::Data<Arc<Mutex<DataBase>>>,
+    data: web::Data<Arc<DataBasePool>>,
     web::Path(id): web::Path<i64>,
 ) -> HttpResponse
 {
-    match DATABASE!(data).get_tournament_from_id(id)
+    match READ_DATABASE!(data).get_tournament_from_id(id)
     {
         Ok(tournaments) => HttpResponse::Ok().json(response_ok_with(tournaments)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
-#[get("api/is-admin/{token}")]
-async fn get_is_admin(
-    data: web::Data<Arc<Mutex<DataBase>>>,
-    web::Path(token): web::Path<String>,
-) -> HttpResponse
+#[get("api/is-admin")]
+async fn get_is_admin(data: web::Data<Arc<DataBasePool>>, Token(token): Token) -> HttpResponse
 {
     match DATABASE!(data).get_is_admin(token)
     {
         Ok(val) => HttpResponse::Ok().json(response_ok_with(val)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
-#[get("api/admin/roll-back/{token}")]
-async fn roll_back(
-    data: web::Data<Arc<Mutex<DataBase>>>,
-    web::Path(token): web::Path<String>,
-) -> HttpResponse
+#[get("api/admin/roll-back")]
+async fn roll_back(data: web::Data<Arc<DataBasePool>>, Token(token): Token) -> HttpResponse
 {
     match DATABASE!(data).admin_rollback(token)
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 #[get("api/season_length")]
-async fn get_season_length(data: web::Data<Arc<Mutex<DataBase>>>) -> HttpResponse
+async fn get_season_length(data: web::Data<Arc<DataBasePool>>) -> HttpResponse
 {
-    match DATABASE!(data).get_season_length()
+    match READ_DATABASE!(data).get_season_length()
     {
         Ok(n_months) => HttpResponse::Ok().json(response_ok_with(n_months)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
 #[derive(Deserialize)]
 struct EditSeasonLength
 {
-    token:   String,
     new_val: i64,
 }
 
 
 #[post("api/season_length")]
-async fn set_season_length(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn set_season_length(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<EditSeasonLength>,
+) -> HttpResponse
 {
-    let info: EditSeasonLength = serde_json::from_str(&info).unwrap();
-    match DATABASE!(data).set_season_length(info.token, info.new_val)
+    let info = info.into_inner();
+    match DATABASE!(data).set_season_length(token, info.new_val)
     {
         Ok(_) => HttpResponse::Ok().json(response_ok()),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
-#[derive(Deserialize)]
-struct Token
+fn change_season(data: web::Data<Arc<DataBasePool>>, token: String, val: i64) -> HttpResponse
 {
-    token: String,
-}
-
-fn change_season(data: web::Data<Arc<Mutex<DataBase>>>, info: String, val: i64) -> HttpResponse
-{
-    let info: Token = serde_json::from_str(&info).unwrap();
-
-    let res = DATABASE!(data).get_is_admin(info.token);
-    match res
+    match DATABASE!(data).change_season(token, val)
     {
-        Ok(true) =>
-        {
-            match val
-            {
-                START_SEASON =>
-                {
-                    DATABASE!(data).start_new_season().expect("Starting new season");
-                },
-                STOP_SEASON | CANCEL_SEASON =>
-                {
-                    DATABASE!(data).end_season(val == STOP_SEASON).expect("Ending season");
-                },
-                _ =>
-                {},
-            };
-
-            HttpResponse::Ok().json(response_ok())
-        },
-        Ok(false) => HttpResponse::Ok().json(response_error(ServerError::Unauthorized)),
-
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Ok(_) => HttpResponse::Ok().json(response_ok()),
+        Err(e) => response_error(e),
     }
 }
 
 #[post("api/start_season")]
-async fn start_season(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn start_season(data: web::Data<Arc<DataBasePool>>, Token(token): Token) -> HttpResponse
 {
-    change_season(data, info, START_SEASON)
+    change_season(data, token, START_SEASON)
 }
 
 #[post("api/stop_season")]
-async fn stop_season(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn stop_season(data: web::Data<Arc<DataBasePool>>, Token(token): Token) -> HttpResponse
 {
-    change_season(data, info, STOP_SEASON)
+    change_season(data, token, STOP_SEASON)
 }
 
 #[post("api/cancel_season")]
-async fn cancel_season(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+async fn cancel_season(data: web::Data<Arc<DataBasePool>>, Token(token): Token) -> HttpResponse
 {
-    change_season(data, info, CANCEL_SEASON)
+    change_season(data, token, CANCEL_SEASON)
 }
 
 #[get("api/leaderboard_info")]
-async fn get_leaderboard_info(data: web::Data<Arc<Mutex<DataBase>>>) -> HttpResponse
+async fn get_leaderboard_info(data: web::Data<Arc<DataBasePool>>) -> HttpResponse
 {
-    let s = DATABASE!(data);
+    let s = READ_DATABASE!(data);
     match (s.get_users(), s.get_is_season(), s.get_latest_season_number())
     {
         (Ok(users), Ok(is_season), Ok(len)) => HttpResponse::Ok().json(response_ok_with(
             json!({"users": users, "is_season": is_season, "season_number": len}),
         )),
-        _ => HttpResponse::InternalServerError().finish(),
+        (Err(e), ..) | (_, Err(e), _) | (.., Err(e)) => response_error(e),
     }
 }
 
 #[get("api/season_start")]
-async fn get_season_start_date(data: web::Data<Arc<Mutex<DataBase>>>) -> HttpResponse
+async fn get_season_start_date(data: web::Data<Arc<DataBasePool>>) -> HttpResponse
 {
-    match DATABASE!(data).get_season_start()
+    match READ_DATABASE!(data).get_season_start()
     {
         Ok(date) => HttpResponse::Ok().json(response_ok_with(date)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
-#[derive(Deserialize)]
-struct SqlCommand
+#[post("api/admin/execute-sql")]
+async fn execute_sql(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    info: web::Json<SqlCommand>,
+) -> HttpResponse
 {
-    token:   String,
-    command: String,
+    let info = info.into_inner();
+
+    // Takes the writer only to write, a statement that reads runs on a reader
+    match info.validate().and_then(|_| data.get_ref().execute_sql(token, info))
+    {
+        Ok(result) => HttpResponse::Ok().json(response_ok_with(result)),
+        Err(e) => response_error(e),
+    }
 }
 
-#[post("api/admin/execute-sql")]
-async fn execute_sql(data: web::Data<Arc<Mutex<DataBase>>>, info: String) -> HttpResponse
+#[get("api/admin/audit-log")]
+async fn get_audit_log(
+    data: web::Data<Arc<DataBasePool>>,
+    Token(token): Token,
+    query: web::Query<AuditLogQuery>,
+) -> HttpResponse
 {
-    let info: SqlCommand = serde_json::from_str(&info).unwrap();
-    let s = DATABASE!(data);
+    let query = query.into_inner();
 
-    match s.get_is_admin(info.token.clone())
+    match query.validate().and_then(|_| DATABASE!(data).get_audit_log(token, query))
     {
-        Ok(true) => match s.execute_sql(info.command)
-        {
-            Ok(string) => HttpResponse::Ok().json(response_ok_with(string)),
-            Err(e) => HttpResponse::Ok().json(response_ok_with(&format!("{}", e))),
-        },
-        Ok(false) => HttpResponse::InternalServerError().json(json!({"status": 5, "result": ""})),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Ok(page) => HttpResponse::Ok().json(response_ok_with(page)),
+        Err(e) => response_error(e),
     }
 }
 
 #[get("api/get-seasons")]
-async fn get_seasons(data: web::Data<Arc<Mutex<DataBase>>>) -> HttpResponse
+async fn get_seasons(data: web::Data<Arc<DataBasePool>>) -> HttpResponse
 {
-    match DATABASE!(data).get_seasons()
+    match READ_DATABASE!(data).get_seasons()
     {
         Ok(v) => HttpResponse::Ok().json(response_ok_with(v)),
-        Err(e) => HttpResponse::Ok().json(response_error(e)),
+        Err(e) => response_error(e),
     }
 }
 
-fn handle_args(data: &Arc<Mutex<DataBase>>)
+fn handle_args(data: &Arc<DataBasePool>)
 {
     let vec = std::env::args().collect::<Vec<String>>();
     match vec.as_slice()
@@ -655,7 +922,7 @@ fn handle_args(data: &Arc<Mutex<DataBase>>)
             // Make user admin
             if flag.as_str() == "-a"
             {
-                data.lock().unwrap().create_superuser(name.clone()).unwrap();
+                data.write().create_superuser(name.clone()).unwrap();
             }
         },
         _ =>
@@ -663,13 +930,92 @@ fn handle_args(data: &Arc<Mutex<DataBase>>)
     }
 }
 
+// Malformed requests get the same error shape as everything else
+fn bad_request(field: &str, message: String) -> actix_web::Error
+{
+    let response =
+        response_error(ServerError::InvalidRequest(vec![FieldError::new(field, message.clone())]));
+    InternalError::from_response(message, response).into()
+}
+
+fn routes(cfg: &mut web::ServiceConfig)
+{
+    // The website does not set a json content type, so any content type is
+    // accepted. The limit leaves room for tournament badges.
+    cfg.app_data(
+        web::JsonConfig::default()
+            .limit(MAX_BODY_SIZE)
+            .content_type(|_| true)
+            .error_handler(|e, _| bad_request("body", e.to_string())),
+    )
+    .app_data(web::QueryConfig::default().error_handler(|e, _| bad_request("query", e.to_string())))
+    .app_data(web::PathConfig::default().error_handler(|e, _| bad_request("path", e.to_string())))
+    .service(create_user)
+    .service(edit_users)
+    .service(edit_match)
+    .service(delete_match)
+    .service(get_profile)
+    .service(get_users)
+    .service(get_all_users)
+    .service(register_match)
+    .service(get_history)
+    .service(register_doubles_match)
+    .service(get_doubles_history)
+    .service(get_doubles_pairs)
+    .service(get_doubles_players)
+    .service(get_edit_history)
+    .service(undo_match_change)
+    .service(get_match_changes)
+    .service(get_is_admin)
+    .service(login)
+    .service(logout)
+    .service(logout_everywhere)
+    .service(change_password)
+    .service(request_reset_password)
+    .service(roll_back)
+    .service(get_active_users)
+    .service(get_season_length)
+    .service(set_season_length)
+    .service(stop_season)
+    .service(start_season)
+    .service(cancel_season)
+    .service(get_leaderboard_info)
+    .service(get_stats)
+    .service(get_multiple_users)
+    .service(execute_sql)
+    .service(get_audit_log)
+    .service(get_variable)
+    .service(set_variable)
+    .service(get_season_start_date)
+    .service(get_notifications)
+    .service(respond_to_notification)
+    .service(create_tournament)
+    .service(join_tournament)
+    .service(leave_tournament)
+    .service(close_tournament_round)
+    .service(start_tournament)
+    .service(withdraw_player)
+    .service(schedule_game)
+    .service(schedule_tournament)
+    .service(get_tournament_calendar)
+    .service(get_player_calendar)
+    .service(register_tournament_match)
+    .service(delete_tournament)
+    .service(get_tournament_infos)
+    .service(get_tournament)
+    .service(get_tournament_table)
+    .service(recreate_tournament)
+    .service(get_seasons);
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()>
 {
-    let data = Arc::new(Mutex::new(DataBase::new(DATABASE_FILE)));
+    let data = Arc::new(DataBasePool::new(DATABASE_FILE, READ_CONNECTIONS));
     handle_args(&data);
 
     spawn_season_checker(data.clone());
+    spawn_tournament_starter(data.clone());
 
     let assets_path = if cfg!(debug_assertions) { "assets" } else { "./db/assets" };
 
@@ -678,48 +1024,207 @@ async fn main() -> std::io::Result<()>
             .data(data.clone())
             .wrap(Cors::default().allow_any_header().allow_any_origin().allow_any_method())
             .service(Files::new("api/assets", assets_path).show_files_listing())
-            .service(create_user)
-            .service(edit_users)
-            .service(edit_match)
-            .service(delete_match)
-            .service(get_profile)
-            .service(get_users)
-            .service(get_all_users)
-            .service(register_match)
-            .service(get_history)
-            .service(get_edit_history)
-            .service(get_is_admin)
-            .service(login)
-            .service(change_password)
-            .service(request_reset_password)
-            .service(roll_back)
-            .service(get_active_users)
-            .service(get_season_length)
-            .service(set_season_length)
-            .service(stop_season)
-            .service(start_season)
-            .service(cancel_season)
-            .service(get_leaderboard_info)
-            .service(get_stats)
-            .service(get_multiple_users)
-            .service(execute_sql)
-            .service(get_variable)
-            .service(set_variable)
-            .service(get_season_start_date)
-            .service(get_notifications)
-            .service(respond_to_notification)
-            .service(create_tournament)
-            .service(join_tournament)
-            .service(leave_tournament)
-            .service(register_tournament_match)
-            .service(delete_tournament)
-            .service(get_tournament_infos)
-            .service(get_tournament)
-            .service(get_tournament_table)
-            .service(recreate_tournament)
-            .service(get_seasons)
+            .configure(routes)
     })
     .bind(format!("0.0.0.0:{}", PORT))?
     .run()
     .await
 }
+
+
+#[cfg(test)]
+mod test
+{
+    use actix_web::{dev::ServiceResponse, test, App};
+    use server_core::constants::{SESSION_RENEW_INTERVAL_MS, USER_ROLE_SUPERUSER};
+
+    use super::*;
+
+    // Every endpoint that reads a json body
+    const JSON_ENDPOINTS: &[&str] = &[
+        "/api/create-user",
+        "/api/admin/get-variable",
+        "/api/admin/set-variable",
+        "/api/edit-users",
+        "/api/request-reset-password",
+        "/api/register-match",
+        "/api/register-doubles-match",
+        "/api/login",
+        "/api/change-password",
+        "/api/notifications",
+        "/api/stats",
+        "/api/delete-match",
+        "/api/edit-match",
+        "/api/admin/undo-match-change",
+        "/api/get-multiple-users",
+        "/api/create-tournament",
+        "/api/recreate-tournament",
+        "/api/join-tournament",
+        "/api/register-tournament-match",
+        "/api/delete-tournament",
+        "/api/leave-tournament",
+        "/api/close-tournament-round",
+        "/api/start-tournament",
+        "/api/withdraw-player",
+        "/api/schedule-game",
+        "/api/schedule-tournament",
+        "/api/season_length",
+        "/api/admin/execute-sql",
+    ];
+
+    const MALFORMED_BODIES: &[&str] = &[
+        "",
+        "{",
+        "null",
+        "[]",
+        "42",
+        "\"text\"",
+        "{}",
+        "{\"username\": 1, \"winner\": [], \"tid\": \"one\"}",
+        "{\"new_val\": \"not a number\", \"variable\": \"elo_k\"}",
+        "{\"winner\": \"\", \"loser\": \"\", \"users\": [], \"id\": -1}",
+        "{\"tid\": 99999999999999999999999}",
+        "\u{0}\u{1}\u{ff}",
+        "{\"image\": \"no comma\", \"name\": \"\", \"player_count\": 2, \"ttype\": \"\"}",
+    ];
+
+    const MALFORMED_URLS: &[&str] = &[
+        "/api/notifications",
+        "/api/notifications?type=",
+        "/api/notifications?type=nothing",
+        "/api/tournament/one",
+        "/api/tournament-table/1.5",
+        "/api/tournament-calendar/one",
+        "/api/admin/audit-log?page=minus",
+    ];
+
+    async fn error_code(resp: ServiceResponse) -> String
+    {
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        body["error"]["code"].as_str().unwrap_or("").to_string()
+    }
+
+    #[actix_rt::test]
+    async fn test_malformed_requests_do_not_panic()
+    {
+        let db_file = "tempF1.db";
+        let pool = Arc::new(DataBasePool::new(db_file, 1));
+        let mut app = test::init_service(App::new().data(pool.clone()).configure(routes)).await;
+
+        let mut responses = Vec::new();
+        for uri in JSON_ENDPOINTS
+        {
+            for body in MALFORMED_BODIES
+            {
+                let req = test::TestRequest::post().uri(uri).set_payload(*body).to_request();
+                let resp = test::call_service(&mut app, req).await;
+                responses.push((uri, body.to_string(), resp.status(), error_code(resp).await));
+            }
+        }
+        for uri in MALFORMED_URLS
+        {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&mut app, req).await;
+            responses.push((uri, String::new(), resp.status(), error_code(resp).await));
+        }
+
+        drop(app);
+        drop(pool);
+        std::fs::remove_file(db_file).expect("Removing file tempF1");
+        for (uri, body, status, code) in responses
+        {
+            assert!(status.is_client_error(), "{} {:?} gave {}", uri, body, status);
+            assert!(!code.is_empty(), "{} {:?} gave no error code", uri, body);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_invalid_fields_are_listed()
+    {
+        let db_file = "tempF2.db";
+        let pool = Arc::new(DataBasePool::new(db_file, 0));
+        let mut app = test::init_service(App::new().data(pool.clone()).configure(routes)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/create-tournament")
+            .set_json(&json!({"name": " ", "image": "", "player_count": 65, "ttype": "x"}))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let status = resp.status();
+        let body: serde_json::Value = test::read_body_json(resp).await;
+
+        drop(app);
+        drop(pool);
+        std::fs::remove_file(db_file).expect("Removing file tempF2");
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_request");
+        assert_eq!(body["error"]["details"][0]["field"], "name");
+        assert_eq!(body["error"]["details"][1]["field"], "player_count");
+    }
+
+    #[actix_rt::test]
+    async fn test_unknown_ids_are_not_found()
+    {
+        let db_file = "tempF3.db";
+        let pool = Arc::new(DataBasePool::new(db_file, 1));
+        let mut app = test::init_service(App::new().data(pool.clone()).configure(routes)).await;
+        pool.write().create_user("Sivert".to_string(), "password".to_string()).unwrap();
+        let token = pool.write().login("Sivert".to_string(), "password".to_string()).unwrap();
+
+        let not_found = response_error(ServerError::NotFound);
+        let req = test::TestRequest::post()
+            .uri("/api/notifications")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .set_json(&json!({"id": 42, "type": "match", "ans": 1}))
+            .to_request();
+        let notification = test::call_service(&mut app, req).await;
+        let notification = (notification.status(), error_code(notification).await);
+
+        drop(app);
+        drop(pool);
+        std::fs::remove_file(db_file).expect("Removing file tempF3");
+        assert_eq!(not_found.status(), StatusCode::NOT_FOUND);
+        assert_eq!(notification, (StatusCode::NOT_FOUND, "not_found".to_string()));
+    }
+
+    #[actix_rt::test]
+    async fn test_admin_pages_renew_sessions()
+    {
+        let db_file = "tempF4.db";
+        let pool = Arc::new(DataBasePool::new(db_file, 1));
+        let mut app = test::init_service(App::new().data(pool.clone()).configure(routes)).await;
+        pool.write().create_user("Sivert".to_string(), "password".to_string()).unwrap();
+        let token = pool.write().login("Sivert".to_string(), "password".to_string()).unwrap();
+        pool.write()
+            .conn
+            .execute_batch(&format!(
+                "update users set user_role = user_role | {}",
+                USER_ROLE_SUPERUSER
+            ))
+            .unwrap();
+
+        let mut statuses = Vec::new();
+        for uri in &["/api/admin/audit-log", "/api/admin/match-changes"]
+        {
+            // An old session is renewed, which needs the writer
+            let age = format!(
+                "update sessions set expires = expires - {}",
+                2 * SESSION_RENEW_INTERVAL_MS
+            );
+            pool.write().conn.execute_batch(&age).unwrap();
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .to_request();
+            statuses.push((uri, test::call_service(&mut app, req).await.status()));
+        }
+
+        drop(app);
+        drop(pool);
+        std::fs::remove_file(db_file).expect("Removing file tempF4");
+        for (uri, status) in statuses
+        {
+            assert_eq!(status, StatusCode::OK, "{}", uri);
+        }
+    }
+}
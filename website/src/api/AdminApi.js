@@ -1,62 +1,65 @@
 import * as BaseApi from './BaseApi'
 
-export const isAdmin = (token) => BaseApi.get('is-admin/' + token)
+export const isAdmin = () => BaseApi.get('is-admin')
 
 export const getAllUsers = () =>
-  BaseApi.get('all-users/' + localStorage.getItem('token'))
+  BaseApi.get('all-users')
 
 export const editUsers = (users, action) =>
   BaseApi.post('edit-users', {
     users: users,
     action: action,
-    token: localStorage.getItem('token') ?? '',
   })
 
 export const rollBack = () =>
-  BaseApi.get('admin/roll-back/' + localStorage.getItem('token'))
+  BaseApi.get('admin/roll-back')
 
 export const editMatch = (newWinner, newLoser, epoch, id) =>
   BaseApi.post('edit-match', {
     winner: newWinner,
     loser: newLoser,
     epoch: epoch,
-    token: localStorage.getItem('token'),
     id: id,
   })
 
 export const deleteMatch = (id) =>
   BaseApi.post('delete-match', {
     id: id,
-    token: localStorage.getItem('token'),
   })
 
+// Undoes the latest edit or deletion of the match
+export const undoMatchChange = (id) =>
+  BaseApi.post('admin/undo-match-change', {
+    id: id,
+  })
+
+export const getMatchChanges = () => BaseApi.get('admin/match-changes')
+
 export const setSeasonLength = (num) =>
   BaseApi.post('season_length', {
     new_val: num,
-    token: localStorage.getItem('token'),
   })
 
 export const stopSeason = () =>
-  BaseApi.post('stop_season', {
-    token: localStorage.getItem('token'),
-  })
+  BaseApi.post('stop_season', {})
 
 export const startSeason = () =>
-  BaseApi.post('start_season', {
-    token: localStorage.getItem('token'),
-  })
+  BaseApi.post('start_season', {})
 
 export const cancelSeason = () =>
-    BaseApi.post('cancel_season', {
-        token: localStorage.getItem('token'),
-    })
+  BaseApi.post('cancel_season', {})
 
-export const executeSql = (str) =>
+export const executeSql = (str, write = false, password = '') =>
   BaseApi.post('admin/execute-sql', {
-    token: localStorage.getItem('token'),
     command: str,
+    write: write,
+    password: password,
   })
 
+// Filters are given as { actor, action, target, from, to, page, page_size }
+export const getAuditLog = (filters = {}) =>
+  BaseApi.get('admin/audit-log?' + new URLSearchParams(filters).toString())
+
 export const getVariable = (variable) =>
   BaseApi.post('admin/get-variable', {
     variable: variable,
@@ -66,5 +69,4 @@ export const setVariable = (variable, val) =>
   BaseApi.post('admin/set-variable', {
     variable: variable,
     new_val: val,
-    token: localStorage.getItem('token'),
   })
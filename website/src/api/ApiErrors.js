@@ -1,27 +1,14 @@
 export class ApiError extends Error {
-  constructor(type, message) {
+  constructor(type, message, details = []) {
     super(message)
+    // The error code from the server, e.g. 'invalid_score'
     this.type = type
+    this.details = details
   }
 }
 
-export const errorMap = {
-  1: 'Critical error',
-  2: 'User does not exist.',
-  3: 'Username is already taken.',
-  4: 'Incorrect username or password',
-  5: 'Wrong password',
-  6: 'Admin privileges required.',
-  7: 'Waiting for approval by an admin.',
-  8: 'User is inactive.',
-  9: 'Password reset has already been requested.',
-  10: "Invalid username. Must be valid by the following regex: '^[a-zåA-ZæøåÆØÅ0-9_-]*$'",
-  11: 'Tournament has wrong state',
-  12: 'Tournament with that id not found',
-  13: 'User is not organizer',
-  14: 'Game is invalid',
-  15: 'Wrong tournament count',
-  16: 'Already joined tournament',
-  17: 'Game already played',
-  // TODO: Get the error message from response
-}
+// Errors are sent as { error: { code, message, details } } with a matching http status
+export const fromResponse = (response, body) =>
+  body?.error
+    ? new ApiError(body.error.code, body.error.message, body.error.details)
+    : new ApiError(response.status, response.statusText)
@@ -1,4 +1,4 @@
-import { ApiError, errorMap } from './ApiErrors'
+import { ApiError, fromResponse } from './ApiErrors'
 import useFetch from 'use-http'
 
 const url = process.env.REACT_APP_URL ?? 'http://localhost'
@@ -19,8 +19,8 @@ export const GetHook = (url) => {
   const { loading, data = [] } = useFetch(apiUrl + url, {}, [])
 
   if (!loading) {
-    if (data.status !== 0) {
-      throw new ApiError(data.status, errorMap[data.status])
+    if (data.error) {
+      throw new ApiError(data.error.code, data.error.message, data.error.details)
     } else {
       return [false, data.result]
     }
@@ -29,24 +29,36 @@ export const GetHook = (url) => {
   }
 }
 
-const parseResponse = (response) => {
-  if (response.ok)
-    return response.json().then((res) => {
-      if (res.status === 0) return res.result
-      throw new ApiError(res.status, errorMap[res.status])
-    })
-
-  throw new ApiError(response.status, response.statusText)
+// The session token is sent as a header, never in urls or bodies
+const authHeaders = () => {
+  const token = localStorage.getItem('token')
+  return token ? { Authorization: 'Bearer ' + token } : {}
 }
 
+const parseResponse = (response) =>
+  response
+    .json()
+    .catch(() => undefined)
+    .then((body) => {
+      if (response.ok && body) return body.result
+      const error = fromResponse(response, body)
+      if (error.type === 'invalid_session') localStorage.removeItem('token')
+      throw error
+    })
+
 export const get = (url) =>
-  fetch(apiUrl + url).then((res) => parseResponse(res))
+  fetch(apiUrl + url, { headers: authHeaders() }).then((res) =>
+    parseResponse(res),
+  )
 
 export const getImageUrl = (url) => apiUrl + 'assets/' + url
 
+export const getApiUrl = (url) => apiUrl + url
+
 const _post = (url, body) =>
   fetch(apiUrl + url, {
     method: 'POST',
+    headers: { 'Content-Type': 'application/json', ...authHeaders() },
     body: JSON.stringify(body),
   }).then((res) => parseResponse(res))
 
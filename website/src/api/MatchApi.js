@@ -4,11 +4,10 @@ export const getHistory = () => BaseApi.get('history')
 
 export const getEditHistory = () => BaseApi.get('edit-history')
 
-export const registerMatch = (winner, loser, token) =>
+export const registerMatch = (winner, loser) =>
   BaseApi.post('register-match', {
     winner: winner,
     loser: loser,
-    token: token,
   })
 
 export const getSeasonLength = () => BaseApi.get('season_length')
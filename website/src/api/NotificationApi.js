@@ -1,31 +1,35 @@
 import * as BaseApi from './BaseApi'
 
 export const getNotifications = () =>
-  BaseApi.get('notifications?type=match&token=' + localStorage.getItem('token'))
+  BaseApi.get('notifications?type=match')
+
+export const getTournamentNotifications = () =>
+  BaseApi.get('notifications?type=tournament')
 
 export const getAdminNotifications = () =>
-  BaseApi.get('notifications?type=admin&token=' + localStorage.getItem('token'))
+  BaseApi.get('notifications?type=admin')
 
-export const registerMatch = (winner, loser, token) =>
+export const registerMatch = (winner, loser) =>
   BaseApi.post('register-match', {
     winner: winner,
     loser: loser,
-    token: token,
   })
 
-const respondBase = (id, token, ans, type) => 
+const respondBase = (id, ans, type) => 
   BaseApi.post('notifications', {
     id: id,
     ans: ans,
-    token: token,
     type: type
   })
 
-export const replyToMatch = (id, token, ans) => 
-    respondBase(id, token, ans, "match")
+export const replyToMatch = (id, ans) => 
+    respondBase(id, ans, "match")
+
+export const replyToTournamentMatch = (id, ans) =>
+    respondBase(id, ans, "tournament")
 
-export const replyToResetPassword = (id, token, ans) =>
-    respondBase(id, token, ans, "reset_password")
+export const replyToResetPassword = (id, ans) =>
+    respondBase(id, ans, "reset_password")
 
-export const replyToNewUser = (id, token, ans) =>
-    respondBase(id, token, ans, "new_user")
+export const replyToNewUser = (id, ans) =>
+    respondBase(id, ans, "new_user")
@@ -9,13 +9,27 @@ export const getTournamentInfosToggle = (arg = 'active') =>
 
 export const getTournament = (id) => BaseApi.get('tournament/' + id)
 
-export const createTournament = (name, playerCount, image, type) =>
+export const createTournament = (
+  name,
+  playerCount,
+  image,
+  type,
+  groupStage,
+  seeding,
+  rated,
+  registrationDeadline,
+  thirdPlaceGame,
+) =>
   BaseApi.postImage('create-tournament', {
     name: name,
-    organizer_token: localStorage.getItem('token') ?? '',
     player_count: parseInt(playerCount),
     image: image,
     ttype: type,
+    group_stage: groupStage,
+    seeding: seeding,
+    rated: rated,
+    registration_deadline: registrationDeadline,
+    third_place_game: thirdPlaceGame,
   })
 
 export const getTournaments = (arg = '') =>
@@ -24,30 +38,63 @@ export const getTournaments = (arg = '') =>
 export const joinTournament = (id) =>
   BaseApi.post('join-tournament', {
     tid: id,
-    token: localStorage.getItem('token') ?? '',
   })
 
 export const leaveTournament = (id) =>
   BaseApi.post('leave-tournament', {
     tid: id,
-    token: localStorage.getItem('token') ?? '',
   })
 
 export const registerTournamentMatch = (winner, loser, tournament_game) =>
   BaseApi.post('register-tournament-match', {
-    organizer_token: localStorage.getItem('token') ?? '',
     winner: winner,
     loser: loser,
     tournament_game: tournament_game,
   })
 export const deleteTournament = (tid) =>
   BaseApi.post('delete-tournament', {
-    token: localStorage.getItem('token') ?? '',
     tid: tid,
   })
 
+export const startTournament = (tid) =>
+  BaseApi.post('start-tournament', {
+    tid: tid,
+  })
+
+export const closeTournamentRound = (tid) =>
+  BaseApi.post('close-tournament-round', {
+    tid: tid,
+  })
+
+export const withdrawPlayer = (tid, player) =>
+  BaseApi.post('withdraw-player', {
+    tid: tid,
+    player: player,
+  })
+
 export const recreateTournament = (tid) =>
   BaseApi.post('recreate-tournament', {
-    token: localStorage.getItem('token') ?? '',
     tid: tid,
   })
+
+export const scheduleGame = (game, start, table, minutes) =>
+  BaseApi.post('schedule-game', {
+    game: game,
+    start: start,
+    table: parseInt(table),
+    minutes: parseInt(minutes),
+  })
+
+export const scheduleTournament = (tid, start, tables, minutes) =>
+  BaseApi.post('schedule-tournament', {
+    tid: tid,
+    start: start,
+    tables: parseInt(tables),
+    minutes: parseInt(minutes),
+  })
+
+export const getTournamentCalendarUrl = (tid) =>
+  BaseApi.getApiUrl('tournament-calendar/' + tid)
+
+export const getPlayerCalendarUrl = (name) =>
+  BaseApi.getApiUrl('player-calendar/' + encodeURIComponent(name))
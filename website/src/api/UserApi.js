@@ -29,6 +29,10 @@ export const login = (username, password) =>
     password: password,
   })
 
+export const logout = () => BaseApi.post('logout', {})
+
+export const logoutEverywhere = () => BaseApi.post('logout-everywhere', {})
+
 export const changePassword = (username, password, newPassword) =>
   BaseApi.post('change-password', {
     username: username,
@@ -6,9 +6,8 @@ export default function AdminGuard(props) {
   const [isAdmin, setIsAdmin] = useState(0)
 
   useEffect(() => {
-    const token = localStorage.getItem('token')
-    if (token) {
-      AdminApi.isAdmin(token)
+    if (localStorage.getItem('token')) {
+      AdminApi.isAdmin()
         .then((isAdmin) => {
           if (isAdmin) {
             setIsAdmin(1)
@@ -0,0 +1,75 @@
+import React, { useState } from 'react'
+import * as Api from '../../api/TournamentApi'
+import Button from '../../components/button/Button'
+import Modal from 'react-modal'
+
+// The organizer spreads the games that are ready to be played over a number of
+// tables, no player is given two games at the same time
+export default function ScheduleTournament(props) {
+  const [modalIsOpen, setIsOpen] = useState(false)
+  const [start, setStart] = useState('')
+  const [tables, setTables] = useState(1)
+  const [minutes, setMinutes] = useState(30)
+
+  function closeModal() {
+    setIsOpen(false)
+  }
+
+  function schedule() {
+    if (!start) return
+    Api.scheduleTournament(props.id, new Date(start).getTime(), tables, minutes)
+      .then(() => window.location.reload())
+      .catch((e) => console.warn('Jaha' + e))
+    closeModal()
+  }
+
+  return (
+    <>
+      <div onClick={() => setIsOpen(true)}>
+        <Button placeholder="Schedule games" />
+      </div>
+      <Modal
+        className="Modal"
+        overlayClassName="Overlay"
+        isOpen={modalIsOpen}
+        onRequestClose={closeModal}
+        ariaHideApp={false}
+      >
+        <div className="modal-body">
+          <h3>When and where are the games played?</h3>
+          <label style={{ display: 'block' }}>
+            First game{' '}
+            <input
+              type="datetime-local"
+              value={start}
+              onChange={(e) => setStart(e.target.value)}
+            />
+          </label>
+          <label style={{ display: 'block' }}>
+            Tables{' '}
+            <input
+              type="number"
+              min="1"
+              max="64"
+              value={tables}
+              onChange={(e) => setTables(e.target.value)}
+            />
+          </label>
+          <label style={{ display: 'block' }}>
+            Minutes per game{' '}
+            <input
+              type="number"
+              min="1"
+              max="1440"
+              value={minutes}
+              onChange={(e) => setMinutes(e.target.value)}
+            />
+          </label>
+          <div onClick={schedule}>
+            <Button style={{ marginTop: '2rem' }} placeholder="Schedule" />
+          </div>
+        </div>
+      </Modal>
+    </>
+  )
+}
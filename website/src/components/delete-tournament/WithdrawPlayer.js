@@ -0,0 +1,59 @@
+import React, { useState } from 'react'
+import * as Api from '../../api/TournamentApi'
+import Button from '../../components/button/Button'
+import Modal from 'react-modal'
+import Select from 'react-select'
+
+// The organizer can take a player out of a running tournament, every game
+// they have left is a walkover for their opponent
+export default function WithdrawPlayer(props) {
+  const [modalIsOpen, setIsOpen] = useState(false)
+  const [player, setPlayer] = useState(undefined)
+
+  const names = props.matches
+    .flatMap((m) => [m.player1, m.player2])
+    .filter((name) => name !== '' && !props.withdrawn.includes(name))
+  const options = [...new Set(names)]
+    .sort()
+    .map((name) => ({ value: name, label: name }))
+
+  function closeModal() {
+    setIsOpen(false)
+  }
+
+  function withdraw() {
+    if (!player) return
+    Api.withdrawPlayer(props.id, player)
+      .then(() => window.location.reload())
+      .catch((e) => console.warn('Jaha' + e))
+    closeModal()
+  }
+
+  return (
+    <>
+      <div onClick={() => setIsOpen(true)}>
+        <Button placeholder="Withdraw player" />
+      </div>
+      <Modal
+        className="Modal"
+        overlayClassName="Overlay"
+        isOpen={modalIsOpen}
+        onRequestClose={closeModal}
+        ariaHideApp={false}
+      >
+        <div className="modal-body">
+          <h3>Who is leaving the tournament?</h3>
+          <Select
+            className="black"
+            options={options}
+            placeholder="Select a person"
+            onChange={(event) => setPlayer(event.value)}
+          />
+          <div onClick={withdraw}>
+            <Button style={{ marginTop: '2rem' }} placeholder="Withdraw" />
+          </div>
+        </div>
+      </Modal>
+    </>
+  )
+}
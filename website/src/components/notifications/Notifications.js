@@ -4,9 +4,9 @@ import '../../index.css'
 import './Notifications.css'
 import { getDateTime } from '../../utils/Date'
 
-export const Notifications = (notifications, token) => {
+export const Notifications = (notifications) => {
   const items = notifications.values?.map((not) => {
-    return <NotificationItem key={not.id} values={not} />
+    return <NotificationItem key={rowId(not)} values={not} />
   })
   return (
     <div className="table-container">
@@ -26,20 +26,23 @@ export const Notifications = (notifications, token) => {
   )
 }
 
+// Match and tournament notifications can have the same id
+const rowId = (not) => (not.type ?? 'match') + '-' + not.id
+
 const NotificationItem = (values) => {
   const vals = values.values
   return (
-    <tr id={vals.id} className="tr">
+    <tr id={rowId(vals)} className="tr">
       <th>{vals.winner}</th>
       <th>{vals.loser}</th>
       <th>{getDateTime(vals.epoch)}</th>
       <th>
-        <button onClick={() => clickButton(vals.id, 1)}>
+        <button onClick={() => clickButton(vals, 1)}>
           <span>&#10003;</span>
         </button>
       </th>
       <th>
-        <button onClick={() => clickButton(vals.id, 2)}>
+        <button onClick={() => clickButton(vals, 2)}>
           <span>&#10005;</span>
         </button>
       </th>
@@ -47,11 +50,14 @@ const NotificationItem = (values) => {
   )
 }
 
-const clickButton = (id, ans) => {
-  const token = localStorage.getItem('token')
-  NotificationApi.replyToMatch(id, token, ans)
+const clickButton = (not, ans) => {
+  const reply =
+    not.type === 'tournament'
+      ? NotificationApi.replyToTournamentMatch
+      : NotificationApi.replyToMatch
+  reply(not.id, ans)
     .then(() => {
-      document.getElementById(id).remove()
+      document.getElementById(rowId(not)).remove()
       document.getElementById('notificationCounter').innerHTML -= 1
     })
     .catch((err) => console.warn(err.message))
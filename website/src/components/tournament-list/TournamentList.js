@@ -18,6 +18,7 @@ class TournamentList extends Component {
 
     this.join = this.join.bind(this)
     this.leave = this.leave.bind(this)
+    this.start = this.start.bind(this)
     this.removeUser = this.removeUser.bind(this)
   }
 
@@ -62,6 +63,12 @@ class TournamentList extends Component {
       .catch((e) => this.setState({ color: 'red', info: e.toString() }))
   }
 
+  start(id) {
+    Api.startTournament(id)
+      .then(() => window.location.reload())
+      .catch((e) => this.setState({ color: 'red', info: e.toString() }))
+  }
+
   render() {
     const id = this.state.tournament.id
     const tournamentName = this.state.tournament.name
@@ -69,6 +76,7 @@ class TournamentList extends Component {
     const joined = this.state.users.some((user) => user === this.state.username)
     const organizerName = this.state.tournament.organizer_name
     const name = localStorage.getItem('username')
+    const deadline = this.state.tournament.registration_deadline
 
     const list = this.state.users.map((name, index) => (
       <tr key={name}>
@@ -85,6 +93,11 @@ class TournamentList extends Component {
           {name === organizerName && <DeleteTournament id={id} />}
         </div>
         <h1>'{tournamentName}' Participants:</h1>
+        {deadline > 0 && (
+          <p className="center">
+            Registration closes {new Date(deadline).toLocaleString()}
+          </p>
+        )}
         <div className="table-container">
           <table>
             <tbody>
@@ -110,6 +123,11 @@ class TournamentList extends Component {
         >
           <Button placeholder="Join" />
         </div>
+        {name === organizerName && this.state.users.length >= 4 && (
+          <div className="center" onClick={() => this.start(id)}>
+            <Button placeholder="Start now" />
+          </div>
+        )}
         <div className="center">
           {this.state.info && (
             <h2 style={{ color: this.state.color }}>{this.state.info}</h2>
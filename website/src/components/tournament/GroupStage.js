@@ -0,0 +1,97 @@
+import React from 'react'
+import '../../index.css'
+import './Tournament.css'
+import * as Api from '../../api/TournamentApi'
+
+import { DoubleElimination } from './DoubleElimination'
+import { RoundRobinMatch, Standings } from './RoundRobin'
+import { SingleElimination } from './SingleElimination'
+
+const groupName = (i) => 'Group ' + String.fromCharCode(65 + i)
+
+function Bracket(props) {
+  const bracket = props.bracket
+  // The bracket is a single or double elimination tournament of its own
+  return bracket.tournament.ttype === 1 ? (
+    <DoubleElimination
+      info={bracket.tournament}
+      matches={bracket.data.Games}
+      table={bracket.table}
+    />
+  ) : (
+    <SingleElimination info={bracket.tournament} matches={bracket.data.Games} />
+  )
+}
+
+export const GroupStage = (props) => {
+  const [tournament, setTournament] = React.useState({
+    info: props.info,
+    matches: props.matches,
+    groups: props.groups ?? [],
+    bracket: props.bracket,
+  })
+
+  //matches would not rerender when parent rerender (:
+  if (tournament.info.id !== props.info.id) {
+    setTournament({
+      info: props.info,
+      matches: props.matches,
+      groups: props.groups ?? [],
+      bracket: props.bracket,
+    })
+  }
+
+  const reload = () =>
+    Api.getTournament(tournament.info.id)
+      .then((t) =>
+        setTournament({
+          info: t.tournament,
+          matches: t.data.Games,
+          groups: t.groups ?? [],
+          bracket: t.bracket,
+        }),
+      )
+      .catch((e) => console.warn('Jaha' + e))
+
+  // Players only meet the players of their own group
+  const groupMatches = (standings) => {
+    const names = standings.map((s) => s.name)
+    return tournament.matches
+      .filter((m) => names.includes(m.player1))
+      .sort((a, b) => a.bucket - b.bucket)
+  }
+
+  // Group games can not change once the bracket is made
+  const organizer = tournament.bracket ? '' : tournament.info.organizer_name
+
+  return (
+    <div key="tournament" className="tournament">
+      {tournament.bracket && (
+        <Bracket
+          key={tournament.bracket.tournament.id}
+          bracket={tournament.bracket}
+        />
+      )}
+      {tournament.groups.map((standings, i) => (
+        <div key={'group-' + i}>
+          <Standings title={groupName(i)} standings={standings} />
+          <div className="bracket-container">
+            <div className="bracket">
+              {groupMatches(standings).map((match) => (
+                <div className="match" key={'match-' + match.id}>
+                  <RoundRobinMatch
+                    match={match}
+                    organizer={organizer}
+                    callback={reload}
+                  />
+                </div>
+              ))}
+            </div>
+          </div>
+        </div>
+      ))}
+    </div>
+  )
+}
+
+export default GroupStage
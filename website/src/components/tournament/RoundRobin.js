@@ -0,0 +1,198 @@
+import React from 'react'
+import '../../index.css'
+import './Tournament.css'
+import * as Api from '../../api/TournamentApi'
+
+import Button from '../button/Button'
+import Modal from 'react-modal'
+import Select from 'react-select'
+
+export function RoundRobinMatch(props) {
+  const [selectedClient, setSelectedClient] = React.useState(undefined)
+  const [modalIsOpen, setIsOpen] = React.useState(false)
+  const options = [
+    { value: props.match.player1, label: props.match.player1 },
+    { value: props.match.player2, label: props.match.player2 },
+  ]
+
+  function openModal() {
+    const username = localStorage.getItem('username')
+    // A bye has no second player to win against. The players report their
+    // own game, and the other player has to accept it
+    const canReport =
+      props.organizer === username ||
+      (!props.match.winner &&
+        [props.match.player1, props.match.player2].includes(username))
+    if (canReport && props.match.player2 !== '') setIsOpen(true)
+  }
+  function closeModal() {
+    setIsOpen(false)
+  }
+  function commitMatch() {
+    if (!selectedClient) return
+
+    let winner = selectedClient
+    let loser =
+      props.match.player1 === winner ? props.match.player2 : props.match.player1
+    Api.registerTournamentMatch(winner, loser, props.match.id)
+      .then(() => props.callback())
+      .catch((e) => console.warn('Jaha' + e))
+    closeModal()
+  }
+
+  const color = (player) =>
+    props.match.winner === player ? 'var(--orange)' : ''
+
+  return (
+    <>
+      <div className="match-info" onClick={openModal}>
+        <span style={{ gridColumn: 1, color: color(props.match.player1) }}>
+          {props.match.player1}
+        </span>
+        <span style={{ gridColumn: 2 }}>|</span>
+        <span style={{ gridColumn: 3, color: color(props.match.player2) }}>
+          {props.match.player2}
+        </span>
+      </div>
+
+      <Modal
+        className="Modal"
+        overlayClassName="Overlay"
+        isOpen={modalIsOpen}
+        onRequestClose={closeModal}
+        ariaHideApp={false}
+      >
+        <div className="modal-body">
+          <h3>
+            Editing match. Who won between {props.match.player1} &{' '}
+            {props.match.player2}
+          </h3>
+          <Select
+            className="black"
+            options={options}
+            placeholder="Select a person"
+            onChange={(event) => setSelectedClient(event.value)}
+          />
+          <div onClick={() => commitMatch()}>
+            <Button style={{ marginTop: '2rem' }} placeholder="Submit" />
+          </div>
+        </div>
+      </Modal>
+    </>
+  )
+}
+
+export function Standings(props) {
+  // Only swiss tournaments have buchholz
+  const buchholz = props.standings.some((s) => s.buchholz !== null)
+  return (
+    <div className="bracket-container">
+      <h2>{props.title ?? 'Standings'}</h2>
+      <table>
+        <thead>
+          <tr>
+            <th>Name</th>
+            <th>Played</th>
+            <th>Won</th>
+            <th>Lost</th>
+            <th>+/-</th>
+            {buchholz && <th>Buchholz</th>}
+          </tr>
+        </thead>
+        <tbody>
+          {props.standings.map((s) => (
+            <tr key={s.name}>
+              <td>{s.name}</td>
+              <td>{s.played}</td>
+              <td>{s.wins}</td>
+              <td>{s.losses}</td>
+              <td>{s.point_difference}</td>
+              {buchholz && <td>{s.buchholz}</td>}
+            </tr>
+          ))}
+        </tbody>
+      </table>
+    </div>
+  )
+}
+
+export const RoundRobin = (props) => {
+  const [tournament, setTournament] = React.useState({
+    info: props.info,
+    matches: props.matches,
+    standings: props.standings ?? [],
+  })
+
+  //matches would not rerender when parent rerender (:
+  if (tournament.info.id !== props.info.id) {
+    setTournament({
+      info: props.info,
+      matches: props.matches,
+      standings: props.standings ?? [],
+    })
+  }
+
+  const reload = () =>
+    Api.getTournament(tournament.info.id)
+      .then((t) =>
+        setTournament({
+          info: t.tournament,
+          matches: t.data.Games,
+          standings: t.standings ?? [],
+        }),
+      )
+      .catch((e) => console.warn('Jaha' + e))
+
+  const closeRound = () =>
+    Api.closeTournamentRound(tournament.info.id)
+      .then(() => reload())
+      .catch((e) => alert(e.message))
+
+  const isOrganizer =
+    tournament.info.organizer_name === localStorage.getItem('username')
+
+  // The bucket of a round robin or swiss game is its round
+  const rounds = []
+  tournament.matches.forEach((match) => {
+    rounds[match.bucket] = [...(rounds[match.bucket] ?? []), match]
+  })
+
+  return (
+    <div key="tournament" className="tournament">
+      <Standings standings={tournament.standings} />
+      {props.swiss && isOrganizer && tournament.info.winner === '' && (
+        <div onClick={closeRound}>
+          <Button placeholder="Close round" />
+        </div>
+      )}
+      {rounds.map((matches, i) => (
+        <div className="bracket-container" key={'round-' + i}>
+          <h2>{'Round ' + (i + 1)}</h2>
+          <div className="bracket">
+            {matches.map((match) => (
+              <div className="match" key={'match-' + match.id}>
+                <RoundRobinMatch
+                  match={match}
+                  organizer={tournament.info.organizer_name}
+                  callback={reload}
+                />
+              </div>
+            ))}
+          </div>
+        </div>
+      ))}
+      {tournament.info.winner !== '' && (
+        <div className="bracket-container" key="winner-bracket">
+          <h2>Winner</h2>
+          <div className="bracket">
+            <div className="match">
+              <div className="winner">{tournament.info.winner}</div>
+            </div>
+          </div>
+        </div>
+      )}
+    </div>
+  )
+}
+
+export default RoundRobin
@@ -0,0 +1,40 @@
+import React from 'react'
+import * as Api from '../../api/TournamentApi'
+
+// The scheduled games that have not been played yet, with links to add the
+// tournament or your own games to a calendar
+export default function Schedule(props) {
+  const username = localStorage.getItem('username')
+  const upcoming = props.matches
+    .filter((m) => m.start > 0 && m.winner === '')
+    .sort((a, b) => a.start - b.start)
+
+  return (
+    <div className="schedule">
+      {upcoming.length > 0 && (
+        <table>
+          <tbody>
+            {upcoming.map((m) => (
+              <tr key={m.id}>
+                <td>{new Date(m.start).toLocaleString()}</td>
+                <td>Table {m.table}</td>
+                <td>
+                  {m.player1 || 'TBD'} vs {m.player2 || 'TBD'}
+                </td>
+              </tr>
+            ))}
+          </tbody>
+        </table>
+      )}
+      <a href={Api.getTournamentCalendarUrl(props.id)}>Tournament calendar</a>
+      {username && (
+        <a
+          style={{ marginLeft: '1rem' }}
+          href={Api.getPlayerCalendarUrl(username)}
+        >
+          My calendar
+        </a>
+      )}
+    </div>
+  )
+}
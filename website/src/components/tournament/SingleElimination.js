@@ -7,6 +7,9 @@ import Button from '../button/Button'
 import Modal from 'react-modal'
 import Select from 'react-select'
 
+// The game between the losing semifinalists, see tournament_placements.rs
+const thirdPlaceBucket = -1
+
 const finals = {
   2: 'Final',
   4: 'Semifinals',
@@ -25,7 +28,7 @@ function TournamentMatch(props) {
   ]
 
   function parentNotPlayed(match, matches) {
-    if (match.bucket === 0) {
+    if (match.bucket <= 0) {
       return true
     }
 
@@ -69,7 +72,7 @@ function TournamentMatch(props) {
   }
 
   let winner = props.winner
-  if (props.match.bucket !== 0) {
+  if (props.match.bucket > 0) {
     let parentIndex = Math.trunc((props.match.bucket - 1) / 2)
     let parent = props.matches.find((m) => {
       return parentIndex === m.bucket
@@ -180,6 +183,8 @@ export const SingleElimination = (props) => {
   function handleInputChange(match, winner) {
     if (match.bucket === 0) {
       tournament.winner = winner
+    } else if (match.bucket === thirdPlaceBucket) {
+      tournament.third_place = [winner]
     } else {
       let parent = Math.trunc((match.bucket - 1) / 2)
       const index = matches.findIndex((m) => m.bucket === parent)
@@ -189,10 +194,21 @@ export const SingleElimination = (props) => {
       } else {
         matches[index].player2 = winner
       }
+
+      // The losing semifinalists meet in the third place game
+      const loser = match.player1 === winner ? match.player2 : match.player1
+      const third = matches.findIndex((m) => m.bucket === thirdPlaceBucket)
+      if (third >= 0 && match.bucket === 1) {
+        matches[third].player1 = loser
+      } else if (third >= 0 && match.bucket === 2) {
+        matches[third].player2 = loser
+      }
     }
 
     setMatches([...matches])
   }
+  const bracket = matches.filter((m) => m.bucket !== thirdPlaceBucket)
+  const thirdPlaceGame = matches.find((m) => m.bucket === thirdPlaceBucket)
   let numBrackets = Math.ceil(Math.log2(tournament.player_count))
   let n_matches = Math.pow(2, numBrackets)
   let tournamentBrackets = []
@@ -205,7 +221,7 @@ export const SingleElimination = (props) => {
       <TournamentBracket
         start={start_match}
         stop={start_match + n_matches}
-        matches={matches}
+        matches={bracket}
         callback={handleInputChange}
         organizer={tournament.organizer_name}
         title={finals[competitors]}
@@ -216,6 +232,24 @@ export const SingleElimination = (props) => {
     competitors /= 2
     start_match += n_matches
   }
+  if (thirdPlaceGame) {
+    tournamentBrackets.push(
+      <div className="bracket-container" key="third-place-bracket">
+        <h2>Third place</h2>
+        <div className="bracket">
+          <div className="match">
+            <TournamentMatch
+              match={thirdPlaceGame}
+              matches={matches}
+              callback={handleInputChange}
+              organizer={tournament.organizer_name}
+              winner={tournament.third_place?.[0] ?? ''}
+            />
+          </div>
+        </div>
+      </div>,
+    )
+  }
   if (tournament.winner !== '') {
     tournamentBrackets.push(
       <div className="bracket-container" key="winner-bracket">
@@ -224,6 +258,14 @@ export const SingleElimination = (props) => {
           <div className="match">
             <div className="winner">{tournament.winner}</div>
           </div>
+          {tournament.second_place && (
+            <div className="match">Second: {tournament.second_place}</div>
+          )}
+          {tournament.third_place?.length > 0 && (
+            <div className="match">
+              Third: {tournament.third_place.join(', ')}
+            </div>
+          )}
         </div>
       </div>,
     )
@@ -1,5 +1,7 @@
 import React from 'react'
 import { DoubleElimination } from './DoubleElimination'
+import { GroupStage } from './GroupStage'
+import { RoundRobin } from './RoundRobin'
 import { SingleElimination } from './SingleElimination'
 
 function selectTournament(props) {
@@ -14,6 +16,32 @@ function selectTournament(props) {
           table={props.table}
         />
       )
+    case 2:
+      return (
+        <RoundRobin
+          info={props.info}
+          matches={props.matches}
+          standings={props.standings}
+        />
+      )
+    case 3:
+      return (
+        <RoundRobin
+          info={props.info}
+          matches={props.matches}
+          standings={props.standings}
+          swiss
+        />
+      )
+    case 4:
+      return (
+        <GroupStage
+          info={props.info}
+          matches={props.matches}
+          groups={props.groups}
+          bracket={props.bracket}
+        />
+      )
 
     default:
       return <p>error</p>
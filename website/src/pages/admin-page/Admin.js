@@ -48,16 +48,17 @@ class Admin extends Component {
   }
 
   newUserButton(id, ans) {
-    const token = localStorage.getItem('token')
-    NotificationApi.replyToNewUser(id, token, ans)
+    NotificationApi.replyToNewUser(id, ans)
       .then(() => document.getElementById('New users' + id).remove())
       .catch((error) => console.warn(error.message))
   }
 
   resetPasswordButton(id, ans) {
-    const token = localStorage.getItem('token')
-    NotificationApi.replyToResetPassword(id, token, ans)
-      .then(() => document.getElementById('Password resets' + id).remove())
+    NotificationApi.replyToResetPassword(id, ans)
+      .then((password) => {
+        if (password) alert('Temporary password: ' + password)
+        document.getElementById('Password resets' + id).remove()
+      })
       .catch((error) => console.warn(error.message))
   }
 
@@ -9,20 +9,13 @@ import { getDateTime } from '../../../utils/Date'
 class EditMatches extends Component {
   users = []
   history = []
+  changes = []
   inFocus = -1
   success = ''
   constructor() {
     super()
-    MatchApi.getEditHistory()
-      .then(
-        (history) =>
-          (this.history = history.map((his) => {
-            his.focus = false
-            return his
-          })),
-      )
-      .catch((error) => console.warn(error.message))
-      .finally(() => this.setState({}))
+    this.loadHistory()
+    this.loadChanges()
     AdminApi.getAllUsers()
       .then((users) => {
         this.users = users.map((u) => ({
@@ -37,6 +30,37 @@ class EditMatches extends Component {
     this.submit = this.submit.bind(this)
     this.sanityCheck = this.sanityCheck.bind(this)
     this.toggleTrash = this.toggleTrash.bind(this)
+    this.undo = this.undo.bind(this)
+  }
+
+  loadHistory() {
+    MatchApi.getEditHistory()
+      .then(
+        (history) =>
+          (this.history = history.map((his) => {
+            his.focus = false
+            return his
+          })),
+      )
+      .catch((error) => console.warn(error.message))
+      .finally(() => this.setState({}))
+  }
+
+  loadChanges() {
+    AdminApi.getMatchChanges()
+      .then((changes) => (this.changes = changes))
+      .catch((error) => console.warn(error.message))
+      .finally(() => this.setState({}))
+  }
+
+  undo(matchId) {
+    AdminApi.undoMatchChange(matchId)
+      .then(() => {
+        this.success = 'Undid the change'
+        this.loadHistory()
+        this.loadChanges()
+      })
+      .catch((error) => console.warn(error))
   }
 
   setFocus(id) {
@@ -97,6 +121,7 @@ class EditMatches extends Component {
             this.history.splice(index, 1)
             this.setState({})
           }
+          this.loadChanges()
         })
         .catch((error) => console.warn(error))
       return
@@ -118,6 +143,7 @@ class EditMatches extends Component {
         his.loser = loser
         his.epoch = epoch
         this.setState({})
+        this.loadChanges()
       })
       .catch((error) => console.warn(error))
   }
@@ -232,6 +258,45 @@ class EditMatches extends Component {
           </table>
         </div>
         {this.success && <h2 className="success">{this.success}</h2>}
+        <h2>Undo Changes</h2>
+        <div className="table-container">
+          <table>
+            <tbody>
+              <tr key={0}>
+                <th>Change</th>
+                <th>Winner</th>
+                <th>Loser</th>
+                <th>Time</th>
+                <th>By</th>
+                <th></th>
+              </tr>
+              {this.changes.map((change, index) => (
+                <tr className="table-row" key={change.id}>
+                  <td>{change.deleted ? 'Deleted' : 'Edited'}</td>
+                  <td>{change.winner}</td>
+                  <td>{change.loser}</td>
+                  <td>
+                    <p>{getDateTime(change.epoch)}</p>
+                  </td>
+                  <td>{change.changed_by}</td>
+                  <td>
+                    {/* The changes of a match are undone newest first */}
+                    {this.changes.findIndex(
+                      (c) => c.match_id === change.match_id,
+                    ) === index && (
+                      <button
+                        className="accept button"
+                        onClick={() => this.undo(change.match_id)}
+                      >
+                        <span>&#8630;</span>
+                      </button>
+                    )}
+                  </td>
+                </tr>
+              ))}
+            </tbody>
+          </table>
+        </div>
       </div>
     )
   }
@@ -7,16 +7,68 @@ class Terminal extends Component {
   constructor() {
     super()
     this.sqlExectute = this.sqlExectute.bind(this)
+    this.sqlWrite = this.sqlWrite.bind(this)
+    this.auditLog = this.auditLog.bind(this)
     this.getVariable = this.getVariable.bind(this)
     this.setVariable = this.setVariable.bind(this)
   }
   showMsg = () => 'Hello World'
 
+  printSqlResult(res, print) {
+    print(res.columns.join(', '))
+    res.rows.forEach((row) => print(row.join(', ')))
+    if (res.truncated) print('(only the first ' + res.rows.length + ' rows)')
+    if (res.changes > 0) print(res.changes + ' rows changed')
+  }
+
+  printSqlError(e, print) {
+    const details = e.details?.map((d) => d.message) ?? []
+    print([e.message, ...details].join(': '))
+  }
+
   sqlExectute(str, print) {
     str.shift()
     const command = str.join(' ')
 
-    AdminApi.executeSql(command).then((res) => print(res))
+    AdminApi.executeSql(command)
+      .then((res) => this.printSqlResult(res, print))
+      .catch((e) => this.printSqlError(e, print))
+  }
+
+  // Changing the database needs the password of the admin
+  sqlWrite(str, print) {
+    str.shift()
+    const command = str.join(' ')
+    const password = window.prompt('Password')
+    if (!password) return
+
+    AdminApi.executeSql(command, true, password)
+      .then((res) => this.printSqlResult(res, print))
+      .catch((e) => this.printSqlError(e, print))
+  }
+
+  // Filters are given as key=value, like auditLog action=delete_match page=1
+  auditLog(str, print) {
+    str.shift()
+    const filters = Object.fromEntries(str.map((filter) => filter.split('=')))
+
+    AdminApi.getAuditLog(filters)
+      .then((res) => {
+        res.entries.forEach((e) =>
+          print(
+            [
+              new Date(e.epoch).toLocaleString(),
+              e.actor || 'server',
+              e.action,
+              e.target,
+              JSON.stringify(e.before),
+              JSON.stringify(e.after),
+            ].join(', '),
+          ),
+        )
+        print(res.entries.length + ' of ' + res.total + ' entries')
+      })
+      .catch((e) => this.printSqlError(e, print))
   }
 
   getVariable(str, print) {
@@ -50,6 +102,8 @@ class Terminal extends Component {
             style={{ fontWeight: 'bold', fontSize: '1em' }}
             commands={{
               sql: (str, print) => this.sqlExectute(str, print),
+              sqlWrite: (str, print) => this.sqlWrite(str, print),
+              auditLog: (str, print) => this.auditLog(str, print),
               getVariable: (str, print) => this.getVariable(str, print),
               setVariable: (str, print) => this.setVariable(str, print),
             }}
@@ -58,11 +112,15 @@ class Terminal extends Component {
               show: false,
               clear: false,
               sql:
-                'Exectue a sql command: sql <command>: sql select * from users',
+                'Exectue a read only sql command: sql <command>: sql select * from users',
+              sqlWrite:
+                'Exectue a sql command that changes the database, asks for your password: sqlWrite <command>',
+              auditLog:
+                'Show the changes made to the database, newest first: auditLog actor=<name> action=<action> target=<target> page=<page>',
               getVariable:
-                'Get server variable, avaliable are: \nis_season\nseason_length\nuser_conf',
+                'Get server variable, avaliable are: \nis_season\nseason_length\nuser_conf\nrating_system (0: elo, 1: dynamic elo, 2: glicko2, 3: trueskill)\nelo_k\nmargin_of_victory (0: off, 1: on)\naudit_retention_days (0: keep forever)',
               setVariable:
-                'Set server variable, avaliable are: \nis_season\nseason_length\nuser_conf',
+                'Set server variable, avaliable are: \nis_season\nseason_length\nuser_conf\nrating_system (0: elo, 1: dynamic elo, 2: glicko2, 3: trueskill)\nelo_k\nmargin_of_victory (0: off, 1: on)\naudit_retention_days (0: keep forever)',
             }}
             msg="Help for help"
           />
@@ -7,9 +7,15 @@ import Button from '../../components/button/Button'
 class ChangePasswordPage extends Component {
   error = ''
 
-  constructor() {
-    super()
-    if (!localStorage.getItem('token')) window.location.href = '/'
+  constructor(props) {
+    super(props)
+    // A user with a temporary password comes from the login page, without a
+    // session
+    this.temporary = props.location?.state
+    if (!this.temporary && !localStorage.getItem('token'))
+      window.location.href = '/'
+    this.username = this.temporary?.username || localStorage.getItem('username')
+    this.password = this.temporary?.password
 
     this.onResetPassword = this.onResetPassword.bind(this)
     this.saveOldPassword = this.saveOldPassword.bind(this)
@@ -26,9 +32,15 @@ class ChangePasswordPage extends Component {
     if (this.newPassword !== this.confirmPassword)
       return this.setError('Passwords do not match')
 
-    const username = localStorage.getItem('username')
+    const username = this.username
+    // Changing the password ends every session, including this one
     UserApi.changePassword(username, this.password, this.newPassword)
-      .then((res) => (window.location.href = '/profiles/' + username))
+      .then(() => UserApi.login(username, this.newPassword))
+      .then((token) => {
+        localStorage.setItem('token', token)
+        localStorage.setItem('username', username)
+        window.location.href = '/profiles/' + username
+      })
       .catch((error) => (this.error = error.message))
       .finally(() => this.setState({}))
   }
@@ -54,14 +66,23 @@ class ChangePasswordPage extends Component {
     return (
       <form onSubmit={this.onResetPassword} className="container">
         <h1>Change Password</h1>
+        {this.temporary && (
+          <h2 className="message">
+            Your password is temporary, choose a new one to log in
+          </h2>
+        )}
         <div className="inputs">
-          <input
-            type="password"
-            placeholder="Old Password"
-            onChange={this.saveOldPassword}
-          />
-          <br />
-          <br />
+          {!this.temporary && (
+            <>
+              <input
+                type="password"
+                placeholder="Old Password"
+                onChange={this.saveOldPassword}
+              />
+              <br />
+              <br />
+            </>
+          )}
           <input
             type="password"
             placeholder="New Password"
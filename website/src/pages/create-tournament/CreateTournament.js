@@ -9,7 +9,19 @@ import Select from 'react-select'
 
 const maxSize = 256 * 256
 
-const createTournament = (image, name, numPlayers, type, setInfo, setColor) => {
+const createTournament = (
+  image,
+  name,
+  numPlayers,
+  type,
+  groupStage,
+  seeding,
+  rated,
+  deadline,
+  thirdPlaceGame,
+  setInfo,
+  setColor,
+) => {
   setColor('red')
   if (!name) {
     return setInfo('Tournament need a name')
@@ -17,7 +29,17 @@ const createTournament = (image, name, numPlayers, type, setInfo, setColor) => {
     return setInfo('Tournament player count needs to be at least 4')
   }
 
-  Api.createTournament(name, numPlayers, image, type)
+  Api.createTournament(
+    name,
+    numPlayers,
+    image,
+    type,
+    type === 'groupStage' ? groupStage : undefined,
+    seeding,
+    rated,
+    deadline ? new Date(deadline).getTime() : undefined,
+    thirdPlaceGame,
+  )
     .then(() => {
       setColor('green')
       setInfo('Success!')
@@ -33,6 +55,17 @@ const createTournament = (image, name, numPlayers, type, setInfo, setColor) => {
 const options = [
   { label: 'Single Elimination', value: 'singleElimination' },
   { label: 'Double Elimination', value: 'doubleElimination' },
+  { label: 'Round Robin', value: 'roundRobin' },
+  { label: 'Swiss', value: 'swiss' },
+  { label: 'Group Stage', value: 'groupStage' },
+]
+
+const knockoutOptions = options.slice(0, 2)
+
+const seedingOptions = [
+  { label: 'Random seeding', value: 'random' },
+  { label: 'Seeded by rating', value: 'rating' },
+  { label: 'Seeded by hand', value: 'manual' },
 ]
 
 const customStyles = {
@@ -69,6 +102,16 @@ export default function CreateTournament() {
   const [info, setInfo] = useState('')
   const [color, setColor] = useState('red')
   const [type, setType] = useState('singleElimination')
+  const [groupStage, setGroupStage] = useState({
+    groups: 2,
+    advance: 2,
+    knockout: 'singleElimination',
+  })
+  const [seeding, setSeeding] = useState({ mode: 'random', players: [] })
+  const [rated, setRated] = useState(false)
+  const [deadline, setDeadline] = useState('')
+  const [thirdPlaceGame, setThirdPlaceGame] = useState(false)
+  const bracketType = type === 'groupStage' ? groupStage.knockout : type
 
   return (
     <div className="container center">
@@ -95,6 +138,85 @@ export default function CreateTournament() {
           }}
           options={options}
         />
+        {type === 'groupStage' && (
+          <>
+            <Input
+              type="number"
+              style={{ marginTop: '1rem' }}
+              placeholder="Number of groups"
+              onChange={(groups) =>
+                setGroupStage({ ...groupStage, groups: parseInt(groups) })
+              }
+            />
+            <Input
+              type="number"
+              style={{ margin: '1rem 0' }}
+              placeholder="Players advancing from each group"
+              onChange={(advance) =>
+                setGroupStage({ ...groupStage, advance: parseInt(advance) })
+              }
+            />
+            <Select
+              defaultValue={knockoutOptions[0]}
+              styles={customStyles}
+              onChange={(e) =>
+                setGroupStage({ ...groupStage, knockout: e.value })
+              }
+              options={knockoutOptions}
+            />
+          </>
+        )}
+
+        <div style={{ marginTop: '1rem' }}>
+          <Select
+            defaultValue={seedingOptions[0]}
+            styles={customStyles}
+            onChange={(e) => setSeeding({ ...seeding, mode: e.value })}
+            options={seedingOptions}
+          />
+        </div>
+        {seeding.mode === 'manual' && (
+          <Input
+            type="text"
+            style={{ marginTop: '1rem' }}
+            placeholder="Seeds, best first: name1, name2, ..."
+            onChange={(names) =>
+              setSeeding({
+                ...seeding,
+                players: names
+                  .split(',')
+                  .map((name) => name.trim())
+                  .filter((name) => name),
+              })
+            }
+          />
+        )}
+        <label style={{ display: 'block', marginTop: '1rem' }}>
+          <input
+            type="checkbox"
+            checked={rated}
+            onChange={(e) => setRated(e.target.checked)}
+          />{' '}
+          Count games toward ratings
+        </label>
+        {bracketType === 'singleElimination' && (
+          <label style={{ display: 'block', marginTop: '1rem' }}>
+            <input
+              type="checkbox"
+              checked={thirdPlaceGame}
+              onChange={(e) => setThirdPlaceGame(e.target.checked)}
+            />{' '}
+            Play a game for third place
+          </label>
+        )}
+        <label style={{ display: 'block', marginTop: '1rem' }}>
+          Registration deadline (optional){' '}
+          <input
+            type="datetime-local"
+            value={deadline}
+            onChange={(e) => setDeadline(e.target.value)}
+          />
+        </label>
 
         <h2 style={{ margin: '2rem auto 0.5rem auto' }}>Choose prize</h2>
         <p>Leave empty to use default prize</p>
@@ -102,7 +224,19 @@ export default function CreateTournament() {
         <div
           className="button"
           onClick={() =>
-            createTournament(image, name, numPlayers, type, setInfo, setColor)
+            createTournament(
+              image,
+              name,
+              numPlayers,
+              type,
+              groupStage,
+              seeding,
+              rated,
+              deadline,
+              thirdPlaceGame && bracketType === 'singleElimination',
+              setInfo,
+              setColor,
+            )
           }
         >
           <Button placeholder="Create Tournament" />
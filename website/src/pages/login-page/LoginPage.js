@@ -27,7 +27,15 @@ class LoginPage extends Component {
         localStorage.setItem('username', this.username)
         window.location.href = '/profiles/' + this.username
       })
-      .catch((error) => (this.error = error.message))
+      .catch((error) => {
+        // A temporary password has to be changed before logging in
+        if (error.code === 'password_change_required')
+          return this.props.history.push({
+            pathname: '/change-password',
+            state: { username: this.username, password: this.password },
+          })
+        this.error = error.message
+      })
       .finally(() => {
         this.resetPasswordMessage = false
         this.setState({})
@@ -86,7 +94,7 @@ class LoginPage extends Component {
           <>
             <h2 className="message">Your request has been submitted</h2>
             <h2 className="message">
-              If an admin approves, your password will be reset to '@uit'
+              If an admin approves, they will give you a temporary password
             </h2>
           </>
         )}
@@ -16,6 +16,8 @@ class Profile extends Component {
   constructor(args) {
     super()
     this.changePassword = this.changePassword.bind(this)
+    this.logout = this.logout.bind(this)
+    this.logoutEverywhere = this.logoutEverywhere.bind(this)
 
     UserApi.getUser(args.user)
       .then((user) => (this.user = user))
@@ -23,13 +25,29 @@ class Profile extends Component {
       .finally(() => this.setState({}))
 
     if (localStorage.getItem('username') === args.user)
-      NotificationApi.getNotifications()
-        .then((notifications) => (this.notifications = notifications))
+      Promise.all([
+        NotificationApi.getNotifications(),
+        NotificationApi.getTournamentNotifications(),
+      ])
+        .then(
+          ([matches, tournament]) =>
+            (this.notifications = matches.concat(
+              tournament.map((n) => ({ ...n, type: 'tournament' })),
+            )),
+        )
         .catch((error) => console.warn(error.message))
         .finally(() => this.setState({}))
   }
 
   logout() {
+    UserApi.logout().finally(() => this.clearLogin())
+  }
+
+  logoutEverywhere() {
+    UserApi.logoutEverywhere().finally(() => this.clearLogin())
+  }
+
+  clearLogin() {
     localStorage.removeItem('token')
     localStorage.removeItem('username')
     window.location.href = '/'
@@ -63,10 +81,7 @@ class Profile extends Component {
               </div>
               )
             </h2>
-            <Notifications
-              values={this.notifications}
-              token={localStorage.getItem('token')}
-            />
+            <Notifications values={this.notifications} />
           </div>
         )}
         {Object.keys(this.user).length !== 0 && (
@@ -90,6 +105,10 @@ class Profile extends Component {
               callback={this.changePassword}
             />
             <Button placeholder="Logout" callback={this.logout} />
+            <Button
+              placeholder="Logout everywhere"
+              callback={this.logoutEverywhere}
+            />
           </div>
         )}
       </div>
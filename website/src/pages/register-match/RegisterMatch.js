@@ -34,8 +34,7 @@ class RegisterMatch extends Component {
   }
 
   pressButton(e) {
-    const token = localStorage.getItem('token')
-    if (!token) {
+    if (!localStorage.getItem('token')) {
       this.setErrorLabel('Need to be logged in to register matches')
       return
     }
@@ -45,7 +44,7 @@ class RegisterMatch extends Component {
     if (this.winner === this.loser)
       return this.setErrorLabel('Players cannot be the same')
 
-    MatchApi.registerMatch(this.winner, this.loser, token)
+    MatchApi.registerMatch(this.winner, this.loser)
       .then(() => (window.location.href = '/'))
       .catch((error) => this.setErrorLabel('Something went wrong'))
   }
@@ -7,6 +7,9 @@ import * as Api from '../../api/TournamentApi'
 
 import { default as TournamentComponenet } from '../../components/tournament/Tournament.js'
 import TournamentList from '../../components/tournament-list/TournamentList'
+import WithdrawPlayer from '../../components/delete-tournament/WithdrawPlayer'
+import ScheduleTournament from '../../components/delete-tournament/ScheduleTournament'
+import Schedule from '../../components/tournament/Schedule'
 import { useLocation } from 'react-router'
 
 function TournamentMenu(props) {
@@ -147,11 +150,32 @@ function TournamentMenu(props) {
     <div className={'tournament-container'}>
       {selectedTournament?.tournament.name ? (
         selectedTournament.tournament.state > 0 ? (
-          <TournamentComponenet
-            matches={selectedTournament.data.Games}
-            info={selectedTournament.tournament}
-            table={selectedTournament.table}
-          />
+          <>
+            <TournamentComponenet
+              matches={selectedTournament.data.Games}
+              info={selectedTournament.tournament}
+              table={selectedTournament.table}
+              standings={selectedTournament.standings}
+              groups={selectedTournament.groups}
+              bracket={selectedTournament.bracket}
+            />
+            <Schedule
+              id={selectedTournament.tournament.id}
+              matches={selectedTournament.data.Games}
+            />
+            {selectedTournament.tournament.state === 1 &&
+              selectedTournament.tournament.organizer_name ===
+                localStorage.getItem('username') && (
+                <>
+                  <WithdrawPlayer
+                    id={selectedTournament.tournament.id}
+                    matches={selectedTournament.data.Games}
+                    withdrawn={selectedTournament.withdrawn}
+                  />
+                  <ScheduleTournament id={selectedTournament.tournament.id} />
+                </>
+              )}
+          </>
         ) : (
           <TournamentList
             players={selectedTournament.data.Players}